@@ -0,0 +1,48 @@
+// Compares compiling and running programs with the JIT against interpreting them,
+// to show when generating machine code pays off.
+//
+// Run with `cargo bench --bench jit`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use simple_jit_calculator::{interpret, jit, run, CompiledProgram};
+use std::hint::black_box;
+
+/// The program lengths to measure, in operations
+const LENGTHS: [usize; 3] = [10, 100, 10000];
+
+/// A program of `length` operations, cycling through all of them
+fn program(length: usize) -> String {
+    "++*-^/%+7*3=5".chars().cycle().take(length).collect()
+}
+
+/// Parsing, generating code and running it, against parsing and interpreting
+fn jit_vs_interpret(c: &mut Criterion) {
+    let mut group = c.benchmark_group("jit_vs_interpret");
+    for length in LENGTHS {
+        let program = program(length);
+        group.bench_with_input(BenchmarkId::new("jit_run", length), &program, |b, p| {
+            b.iter(|| unsafe { run(&jit(black_box(p)).unwrap()) })
+        });
+        group.bench_with_input(BenchmarkId::new("interpret", length), &program, |b, p| {
+            b.iter(|| interpret(black_box(p)).unwrap())
+        });
+        // Code which has already been compiled, as it would be if it were cached
+        let compiled = unsafe { CompiledProgram::new(&jit(&program).unwrap()).unwrap() };
+        group.bench_with_input(BenchmarkId::new("call", length), &compiled, |b, c| {
+            b.iter(|| c.call())
+        });
+    }
+    group.finish();
+}
+
+/// What `run` spends before the code starts: allocating memory, copying the code into
+/// it and making it executable. The code itself does almost nothing.
+fn load(c: &mut Criterion) {
+    let machine_code = jit("+").unwrap();
+    c.bench_function("load", |b| {
+        b.iter(|| unsafe { CompiledProgram::new(black_box(&machine_code)).unwrap() })
+    });
+}
+
+criterion_group!(benches, jit_vs_interpret, load);
+criterion_main!(benches);
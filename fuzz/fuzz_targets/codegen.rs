@@ -0,0 +1,60 @@
+// Compiling arbitrary strings with every kind of code generation, without ever running
+// the result. Whatever the parser accepts must compile, and the x86_64 integer code must
+// be made only of instructions the compiler knows it emits, rather than stray bytes.
+// The operations the parser gives must also pass the checks `jit_ops` makes.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use simple_jit_calculator::{
+    disassemble, jit, jit_float, jit_ops, jit_with_options, parse, Options, OverflowBehavior,
+    Signedness, Target,
+};
+
+fuzz_target!(|program: &str| {
+    let Ok(ops) = parse(program) else {
+        return;
+    };
+    assert_eq!(jit_ops(&ops), jit(program), "{program:?}");
+    let x86_64 = Options {
+        target: Target::X86_64,
+        ..Options::default()
+    };
+    let variants = [
+        x86_64,
+        Options {
+            coalesce: true,
+            strength_reduce: true,
+            ..x86_64
+        },
+        Options {
+            signedness: Signedness::Unsigned,
+            ..x86_64
+        },
+        Options {
+            overflow: OverflowBehavior::Checked,
+            ..x86_64
+        },
+        Options {
+            overflow: OverflowBehavior::Saturate,
+            ..x86_64
+        },
+    ];
+    for options in variants {
+        let machine_code = jit_with_options(program, options).unwrap();
+        let listing = disassemble(&machine_code);
+        assert!(
+            !listing.contains("db 0x"),
+            "{program:?} with {options:?}:\n{listing}"
+        );
+    }
+    for options in variants {
+        let options = Options {
+            target: Target::Aarch64,
+            ..options
+        };
+        let machine_code = jit_with_options(program, options).unwrap();
+        assert_eq!(machine_code.len() % 4, 0, "{program:?} with {options:?}");
+    }
+    // Not every program can be compiled to floating point code, but none may panic
+    let _ = jit_float(program);
+});
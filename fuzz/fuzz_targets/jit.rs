@@ -0,0 +1,35 @@
+// Compiling and running arbitrary programs, and checking the JIT gives the same result
+// as the interpreter. Any program they disagree on belongs in the regressions in
+// tests/differential.rs once it's fixed.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use simple_jit_calculator::{compile, interpret, parse, Op, Program};
+
+/// The most operations in a program. Divisions take tens of cycles each, so longer
+/// programs would mostly make each run slower rather than find anything new.
+const MAX_OPS: usize = 64;
+/// The most times a loop runs, which nested loops multiply
+const MAX_LOOP_COUNT: i64 = 8;
+
+fuzz_target!(|program: Program| {
+    if program.ops.len() > MAX_OPS {
+        return;
+    }
+    // Spaces keep an operand from being read as part of the operation before it
+    let program = program
+        .ops
+        .iter()
+        .map(|op| match op {
+            Op::Loop(n) => Op::Loop((n - 1) % MAX_LOOP_COUNT + 1),
+            op => *op,
+        })
+        .map(|op| op.to_string())
+        .collect::<Vec<_>>()
+        .join(" ");
+    if parse(&program).is_err() {
+        return;
+    }
+    let jit = compile(&program).unwrap().call();
+    assert_eq!(jit, interpret(&program).unwrap(), "{program}");
+});
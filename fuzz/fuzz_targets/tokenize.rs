@@ -0,0 +1,13 @@
+// Feeding arbitrary strings to the parser, which must reject bad programs with an error
+// rather than panicking, and then rendering the error against the program it came from.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use simple_jit_calculator::parse;
+
+fuzz_target!(|program: &str| {
+    if let Err(error) = parse(program) {
+        let _ = error.span(program);
+        let _ = error.render(program);
+    }
+});
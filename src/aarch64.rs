@@ -0,0 +1,1285 @@
+// The AArch64 backend, following the AAPCS64 calling convention.
+// The working register is x0, which is also where AAPCS64 expects the return value.
+// Every AArch64 instruction is a 32-bit little endian word.
+// The stack pointer has to stay 16-byte aligned, so each value pushed onto the stack
+// takes 16 bytes.
+
+use crate::expr::{BinOp, Step};
+use crate::ir::Instruction;
+use crate::{
+    exact_log2, literal_pool, Constants, DivRounding, JitError, Options, ReturnType, Signedness,
+    Start, MAX_LOOP_DEPTH,
+};
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Initialize the working register (x0)
+pub(crate) fn prologue(machine_code: &mut Vec<u8>, start: Start) {
+    match start {
+        // Set the working register (x0) to its starting value. Zero takes a single
+        // `mov x0, #0`
+        Start::Constant(n) => push(machine_code, &load_immediate(0, n)),
+        // The first integer argument is passed in x0, which is already the working register
+        Start::Input => {}
+    }
+}
+
+/// Emit the instructions for a single operation
+pub(crate) fn emit(machine_code: &mut Vec<u8>, token: &Instruction, options: &Options) {
+    let mut instructions: Vec<u32> = Vec::new();
+    let unsigned = options.signedness == Signedness::Unsigned;
+    let floor = options.div_rounding == DivRounding::Floor;
+    match token {
+        // Increment the working register by 1
+        // `add x0, x0, #1`
+        Instruction::Inc => instructions.push(0x91000400),
+        // Decrement the working register by 1
+        // `sub x0, x0, #1`
+        Instruction::Dec => instructions.push(0xd1000400),
+        // Multiply the working register by 2
+        // `lsl x0, x0, #1`
+        Instruction::Double => instructions.push(0xd37ff800),
+        // A logical shift right halves unsigned values exactly
+        // `lsr x0, x0, #1`
+        Instruction::Halve | Instruction::ShiftRight if unsigned => instructions.push(0xd341fc00),
+        // `and x0, x0, #1`
+        Instruction::RemTwo if unsigned => instructions.push(0x92400000),
+        // Unsigned values are never negative
+        Instruction::Abs | Instruction::ClampZero if unsigned => {}
+        // Rounding towards negative infinity is just an arithmetic shift right
+        // `asr x0, x0, #1`
+        Instruction::Halve if floor => instructions.push(0x9341fc00),
+        // An arithmetic shift right rounds towards negative infinity, whereas the
+        // x86_64 backend's `idiv` rounds towards zero. Adding the sign bit to the value
+        // before shifting makes odd negative numbers round towards zero too.
+        // `add x1, x0, x0, lsr #63`
+        // `asr x0, x1, #1`
+        Instruction::Halve => instructions.extend_from_slice(&[0x8b40fc01, 0x9341fc20]),
+        // `sdiv` rounds towards zero, so the remainder x0 - (x0 / 2) * 2 has the same
+        // sign as x0, like `%` in C
+        // `mov x2, #2`
+        // `sdiv x1, x0, x2`
+        // `msub x0, x1, x2, x0`
+        Instruction::RemTwo => {
+            instructions.extend_from_slice(&[0xd2800042, 0x9ac20c01, 0x9b028020])
+        }
+        // `mul x0, x0, x0`
+        Instruction::Square => instructions.push(0x9b007c00),
+        // `neg x0, x0`
+        Instruction::Negate => instructions.push(0xcb0003e0),
+        // `mov x0, #0`
+        Instruction::Reset => instructions.push(0xd2800000),
+        // `lsl x0, x0, #1`
+        Instruction::ShiftLeft => instructions.push(0xd37ff800),
+        // `asr x0, x0, #1`
+        Instruction::ShiftRight => instructions.push(0x9341fc00),
+        // Negate the working register if it's negative
+        // `cmp x0, #0`
+        // `cneg x0, x0, mi`
+        Instruction::Abs => instructions.extend_from_slice(&[0xf100001f, 0xda805400]),
+        // Replace the working register with zero if it's negative
+        // `cmp x0, #0`
+        // `csel x0, x0, xzr, ge`
+        Instruction::ClampZero => instructions.extend_from_slice(&[0xf100001f, 0x9a9fa000]),
+        Instruction::AddImm(n) => add(&mut instructions, *n),
+        Instruction::SubImm(n) => add(&mut instructions, n.wrapping_neg()),
+        Instruction::MulImm(n) if options.strength_reduce && exact_log2(*n).is_some() => {
+            let shift = exact_log2(*n).unwrap();
+            // `lsl x0, x0, #shift`, which is an alias of
+            // `ubfm x0, x0, #(64 - shift), #(63 - shift)`
+            instructions.push(0xd3400000 | ((64 - shift) << 16) | ((63 - shift) << 10));
+        }
+        Instruction::MulImm(n) => {
+            instructions.extend(load_immediate(1, *n));
+            // `mul x0, x0, x1`
+            instructions.push(0x9b017c00);
+        }
+        Instruction::DivImm(n)
+            if options.strength_reduce && unsigned && exact_log2(*n).is_some() =>
+        {
+            let shift = exact_log2(*n).unwrap();
+            // `lsr x0, x0, #shift`, which is an alias of `ubfm x0, x0, #shift, #63`
+            instructions.push(0xd340fc00 | (shift << 16));
+        }
+        Instruction::DivImm(n) if unsigned => {
+            instructions.extend(load_immediate(1, *n));
+            // `udiv x0, x0, x1`
+            instructions.push(0x9ac10800);
+        }
+        Instruction::DivImm(n) if options.strength_reduce && *n == 2 => {
+            instructions.extend_from_slice(&[0x8b40fc01, 0x9341fc20])
+        }
+        Instruction::DivImm(n) if options.strength_reduce && exact_log2(*n).is_some() => {
+            let shift = exact_log2(*n).unwrap();
+            // Negative values need n - 1 added first so the shift rounds towards zero.
+            // The sign is spread across x1, and the top `shift` bits are used as the bias.
+            // `asr x1, x0, #63`
+            // `add x0, x0, x1, lsr #(64 - shift)`
+            // `asr x0, x0, #shift`
+            instructions.extend_from_slice(&[
+                0x937ffc01,
+                0x8b410000 | ((64 - shift) << 10),
+                0x9340fc00 | (shift << 16),
+            ]);
+        }
+        Instruction::DivImm(n) => {
+            instructions.extend(load_immediate(1, *n));
+            // `sdiv` rounds towards zero, just like `idiv` on x86_64
+            // `sdiv x0, x0, x1`
+            instructions.push(0x9ac10c00);
+        }
+        Instruction::RemImm(n) => {
+            // The same as `Instruction::RemTwo`, with `n` in place of 2
+            instructions.extend(load_immediate(2, *n));
+            // `sdiv x1, x0, x2` (or `udiv`)
+            // `msub x0, x1, x2, x0`
+            let divide = if unsigned { 0x9ac20801 } else { 0x9ac20c01 };
+            instructions.extend_from_slice(&[divide, 0x9b028020]);
+        }
+        Instruction::LoadConst(n) => instructions.extend(load_immediate(0, *n)),
+        // Push a copy of the working register onto the stack
+        // `str x0, [sp, #-16]!`
+        Instruction::Push => instructions.push(0xf81f0fe0),
+        // Swap the working register with the value on top of the stack
+        // `ldr x1, [sp]`
+        // `str x0, [sp]`
+        // `mov x0, x1`
+        Instruction::Swap => instructions.extend_from_slice(&[0xf94003e1, 0xf90003e0, 0xaa0103e0]),
+        Instruction::SetCounter { .. } | Instruction::CountDown { .. } => {
+            unreachable!("loops are emitted by loop_start and loop_end")
+        }
+        Instruction::Label(_) | Instruction::JumpIfZero(_) => {
+            unreachable!("skips are emitted by skip and patch_skip")
+        }
+    }
+    push(machine_code, &instructions);
+}
+
+/// The registers (x9 to x11) holding the counters of nested loops, outermost first
+const COUNTERS: [u32; MAX_LOOP_DEPTH] = [9, 10, 11];
+
+/// Start a loop running `n` times, nested inside `depth` other loops.
+/// The loop's counter counts down to zero.
+pub(crate) fn loop_start(machine_code: &mut Vec<u8>, depth: usize, n: i64) {
+    push(machine_code, &load_immediate(COUNTERS[depth], n));
+}
+
+/// End a loop nested inside `depth` other loops, jumping back to the start of its body
+/// (at byte offset `body`) until its counter reaches zero.
+/// Fails with [`JitError::CodeTooLarge`] if the body is more than 128 megabytes back.
+pub(crate) fn loop_end(
+    machine_code: &mut Vec<u8>,
+    depth: usize,
+    body: usize,
+) -> Result<(), JitError> {
+    let counter = COUNTERS[depth];
+    // `subs x<counter>, x<counter>, #1`
+    let subs = 0xf1000400 | (counter << 5) | counter;
+    // The branch comes after the `subs`, and its offset counts instructions
+    let distance = (machine_code.len() + 4 - body) / 4;
+    if distance <= 1 << 18 {
+        let offset = -(distance as i32);
+        // `b.ne #offset`
+        push(
+            machine_code,
+            &[subs, 0x54000000 | (((offset as u32) & 0x7ffff) << 5) | NE],
+        );
+    } else {
+        // Conditional branches only reach a megabyte, which a long loop body can be
+        // further back than. An unconditional branch reaches 128 megabytes, which is more
+        // code than any program can have, so the loop jumps over one of those to finish.
+        // `b.eq #8`
+        // `b #offset`
+        let distance = distance + 1;
+        if distance > BRANCH_RANGE {
+            return Err(JitError::CodeTooLarge {
+                size: machine_code.len(),
+                limit: BRANCH_RANGE * 4,
+            });
+        }
+        let offset = -(distance as i32);
+        push(
+            machine_code,
+            &[
+                subs,
+                0x54000040 | EQ,
+                0x14000000 | ((offset as u32) & 0x3ffffff),
+            ],
+        );
+    }
+    Ok(())
+}
+
+/// Jump over the next operation if the working register is zero. Returns where the
+/// jump is, as its offset can't be known until the operation has been emitted.
+pub(crate) fn skip(machine_code: &mut Vec<u8>) -> usize {
+    // `cbz` only reaches a megabyte, which a skipped loop can be longer than, so the
+    // jump is an unconditional branch that a `cbnz` jumps over when there's no skipping
+    // `cbnz x0, #8`
+    // `b #0`
+    push(machine_code, &[0xb5000040, 0x14000000]);
+    machine_code.len() - 4
+}
+
+/// Fill in the offset of the jump at `fixup` (from [`skip`] or [`skip_float`]), so it goes
+/// to the end of the machine code so far.
+/// Fails with [`JitError::CodeTooLarge`] if the end is 128 megabytes or more away.
+pub(crate) fn patch_skip(machine_code: &mut [u8], fixup: usize) -> Result<(), JitError> {
+    // The offset counts instructions from the jump itself
+    let offset = (machine_code.len() - fixup) / 4;
+    // An unconditional branch reaches 128 megabytes, which is more code than any program
+    // can have
+    if offset >= BRANCH_RANGE {
+        return Err(JitError::CodeTooLarge {
+            size: machine_code.len(),
+            limit: BRANCH_RANGE * 4,
+        });
+    }
+    let jump = &mut machine_code[fixup..fixup + 4];
+    let instruction = u32::from_le_bytes(jump.try_into().unwrap()) | offset as u32;
+    jump.copy_from_slice(&instruction.to_le_bytes());
+    Ok(())
+}
+
+/// How many instructions forwards an unconditional branch reaches, with its signed
+/// 26-bit offset
+const BRANCH_RANGE: usize = 1 << 25;
+
+/// How many instructions forwards a load from the literal pool reaches, with its signed
+/// 19-bit offset
+const LOAD_RANGE: usize = 1 << 18;
+
+/// Emit the instructions for a step of an expression.
+/// The stack pointer has to stay 16 byte aligned, so each value pushed takes 16 bytes.
+pub(crate) fn emit_step(machine_code: &mut Vec<u8>, step: &Step) {
+    let instructions = match step {
+        Step::Load(n) => load_immediate(0, *n),
+        // `str x0, [sp, #-16]!`
+        Step::Push => vec![0xf81f0fe0],
+        Step::Apply(op) => {
+            let op = match op {
+                // `add x0, x0, x1`
+                BinOp::Add => 0x8b010000,
+                // `sub x0, x0, x1`
+                BinOp::Sub => 0xcb010000,
+                // `mul x0, x0, x1`
+                BinOp::Mul => 0x9b017c00,
+                // `sdiv x0, x0, x1`
+                BinOp::Div => 0x9ac10c00,
+            };
+            // The right operand moves to x1, making way for the left one
+            // `mov x1, x0`
+            // `ldr x0, [sp], #16`
+            vec![0xaa0003e1, 0xf84107e0, op]
+        }
+        // `neg x0, x0`
+        Step::Negate => vec![0xcb0003e0],
+    };
+    push(machine_code, &instructions);
+}
+
+/// Start a call frame, keeping the stack pointer in x16. Only the veneers the linker
+/// puts in front of calls use x16, and the code doesn't make any calls.
+pub(crate) fn frame_start(machine_code: &mut Vec<u8>) {
+    // `mov x16, sp`
+    push(machine_code, &[0x910003f0]);
+}
+
+/// End the call frame, throwing away whatever was pushed onto the stack since it started
+pub(crate) fn frame_end(machine_code: &mut Vec<u8>) {
+    push(machine_code, &[FRAME_END]);
+}
+
+/// `mov sp, x16`
+const FRAME_END: u32 = 0x9100021f;
+
+/// Stop in the debugger, or raise SIGTRAP when there isn't one
+pub(crate) fn breakpoint(machine_code: &mut Vec<u8>) {
+    // `brk #0`
+    push(machine_code, &[0xd4200000]);
+}
+
+/// Return the working register, converted to `return_type`
+pub(crate) fn epilogue(machine_code: &mut Vec<u8>, return_type: ReturnType) {
+    match return_type {
+        ReturnType::I64 | ReturnType::U64 => {}
+        // Extend the bottom half of the register to the whole of it, like the
+        // x86_64 backend does
+        // `sxtw x0, w0`
+        ReturnType::I32 => push(machine_code, &[0x93407c00]),
+        // Writing to the bottom half of a register clears the top half
+        // `mov w0, w0`
+        ReturnType::U32 => push(machine_code, &[0x2a0003e0]),
+    }
+    // The result is already in the return register (x0)
+    // `ret`
+    push(machine_code, &[0xd65f03c0]);
+}
+
+/// Machine code for a function which returns `value` without doing anything else.
+pub(crate) fn return_constant(value: i64) -> Vec<u8> {
+    let mut machine_code = Vec::new();
+    push(&mut machine_code, &load_immediate(0, value));
+    epilogue(&mut machine_code, ReturnType::I64);
+    machine_code
+}
+
+/// Append instructions to the machine code
+fn push(machine_code: &mut Vec<u8>, instructions: &[u32]) {
+    for instruction in instructions {
+        machine_code.extend_from_slice(&instruction.to_le_bytes());
+    }
+}
+
+/// Like [`emit`], but returns from the function early if the operation overflows.
+/// The flag setting forms of the instructions are used (`adds` rather than `add`),
+/// so that the overflow flag (V) is set when the signed result doesn't fit.
+/// The early return reports which operation overflowed, as one more than its `index`,
+/// and ends the call frame first if there is one (`frame`).
+pub(crate) fn emit_checked(
+    machine_code: &mut Vec<u8>,
+    index: usize,
+    token: &Instruction,
+    options: &Options,
+    frame: bool,
+) {
+    let Some((mut instructions, condition)) = flag_setting(token) else {
+        return emit(machine_code, token, options);
+    };
+    // Report the overflow in the second return register (x1)
+    let mut early_return = load_immediate(1, index as i64 + 1);
+    if frame {
+        early_return.push(FRAME_END);
+    }
+    // `ret`
+    early_return.push(0xd65f03c0);
+    // Skip over the early return when there was no overflow
+    // `b.<condition> #(4 * (early_return.len() + 1))`
+    let length = early_return.len() as u32 + 1;
+    instructions.push(0x54000000 | (length << 5) | condition);
+    instructions.extend(early_return);
+    push(machine_code, &instructions);
+}
+
+/// Like [`emit`], but clamps the working register to `i64::MIN` or `i64::MAX` when the
+/// operation overflows, instead of wrapping around. Overflow is detected the same way
+/// as for [`emit_checked`].
+pub(crate) fn emit_saturating(machine_code: &mut Vec<u8>, token: &Instruction, options: &Options) {
+    let Some((mut instructions, condition)) = flag_setting(token) else {
+        return emit(machine_code, token, options);
+    };
+    let clamp = match token {
+        // Squares are never negative
+        // `mov x0, #0x7fffffffffffffff`
+        Instruction::Square => vec![0x92f00000],
+        // The product is negative when exactly one of the working register and `n` is,
+        // so the sign of their xor is kept in x3 before multiplying. Filling x0 with it
+        // and flipping every other bit gives `i64::MIN` or `i64::MAX`.
+        // `eor x3, x0, x1`
+        // `asr x0, x3, #63`
+        // `eor x0, x0, #0x7fffffffffffffff`
+        Instruction::MulImm(_) => {
+            instructions.insert(instructions.len() - 3, 0xca010003);
+            vec![0x937ffc60, 0xd240f800]
+        }
+        // Otherwise a result which overflowed has the opposite sign from the real one,
+        // so filling x0 with its sign and flipping the top bit gives the right limit
+        // `asr x0, x0, #63`
+        // `eor x0, x0, #0x8000000000000000`
+        _ => vec![0x937ffc00, 0xd2410000],
+    };
+    // Skip over the clamp when there was no overflow
+    // `b.<condition> #(4 * (clamp.len() + 1))`
+    let length = clamp.len() as u32 + 1;
+    instructions.push(0x54000000 | (length << 5) | condition);
+    instructions.extend(clamp);
+    push(machine_code, &instructions);
+}
+
+/// The flag setting instructions for an operation which can overflow, along with the
+/// condition which holds when it didn't overflow. `None` for operations which can't.
+fn flag_setting(token: &Instruction) -> Option<(Vec<u32>, u32)> {
+    let mut instructions: Vec<u32> = Vec::new();
+    let mut condition = VC;
+    match token {
+        // `adds x0, x0, #1`
+        Instruction::Inc => instructions.push(0xb1000400),
+        // `subs x0, x0, #1`
+        Instruction::Dec => instructions.push(0xf1000400),
+        // Doubling is the same as adding the register to itself
+        // `adds x0, x0, x0`
+        Instruction::Double | Instruction::ShiftLeft => instructions.push(0xab000000),
+        // `negs x0, x0`
+        Instruction::Negate => instructions.push(0xeb0003e0),
+        Instruction::Abs => {
+            // `cneg` doesn't set any flags, and the result is only still negative when
+            // it overflowed, given it was `i64::MIN`
+            // `cmp x0, #0`
+            // `cneg x0, x0, mi`
+            // `cmp x0, #0`
+            instructions.extend_from_slice(&[0xf100001f, 0xda805400, 0xf100001f]);
+            condition = PL;
+        }
+        Instruction::AddImm(n) => checked_add(&mut instructions, *n),
+        Instruction::SubImm(n) => checked_add(&mut instructions, n.wrapping_neg()),
+        Instruction::MulImm(n) => {
+            instructions.extend(load_immediate(1, *n));
+            // `mul` doesn't set any flags. The product fits in 64 bits only if the high
+            // half of the full 128-bit product is just the sign extension of the low half.
+            // `smulh x2, x0, x1`
+            // `mul x0, x0, x1`
+            // `cmp x2, x0, asr #63`
+            instructions.extend_from_slice(&[0x9b417c02, 0x9b017c00, 0xeb80fc5f]);
+            condition = EQ;
+        }
+        Instruction::Square => {
+            // Checked the same way as `Instruction::MulImm`
+            // `smulh x2, x0, x0`
+            // `mul x0, x0, x0`
+            // `cmp x2, x0, asr #63`
+            instructions.extend_from_slice(&[0x9b407c02, 0x9b007c00, 0xeb80fc5f]);
+            condition = EQ;
+        }
+        // Division by a positive number, taking the remainder of it, shifting right, and
+        // setting or resetting the working register can't overflow
+        Instruction::Halve
+        | Instruction::DivImm(_)
+        | Instruction::RemImm(_)
+        | Instruction::RemTwo
+        | Instruction::LoadConst(_)
+        | Instruction::Reset
+        | Instruction::ShiftRight
+        | Instruction::ClampZero
+        | Instruction::SetCounter { .. }
+        | Instruction::CountDown { .. }
+        | Instruction::Label(_)
+        | Instruction::JumpIfZero(_)
+        | Instruction::Push
+        | Instruction::Swap => return None,
+    }
+    Some((instructions, condition))
+}
+
+/// Like [`epilogue`], but also reports that nothing overflowed
+pub(crate) fn epilogue_checked(machine_code: &mut Vec<u8>) {
+    // `mov x1, #0`
+    // `ret`
+    push(machine_code, &[0xd2800001, 0xd65f03c0]);
+}
+
+// The floating point code keeps the working value in d0, which is also where a
+// `double` is returned. Operands are loaded into d1 from a literal pool placed after
+// the function's `ret`. The loads of the constants are recorded in `constants`, so
+// their offsets can be filled in once the pool is placed by `epilogue_float`.
+
+/// Initialize the floating point working register (d0) to zero
+pub(crate) fn prologue_float(machine_code: &mut Vec<u8>) {
+    // `fmov d0, xzr`
+    push(machine_code, &[0x9e6703e0]);
+}
+
+/// Emit the floating point instructions for a single operation
+pub(crate) fn emit_float(
+    machine_code: &mut Vec<u8>,
+    token: &Instruction,
+    constants: &mut Constants,
+) {
+    let (op, n) = match token {
+        // `fadd d0, d0, d1`
+        Instruction::Inc => (0x1e612800, 1.0),
+        // `fsub d0, d0, d1`
+        Instruction::Dec => (0x1e613800, 1.0),
+        // `fmul d0, d0, d1`
+        Instruction::Double | Instruction::ShiftLeft => (0x1e610800, 2.0),
+        // `fdiv d0, d0, d1`
+        Instruction::Halve => (0x1e611800, 2.0),
+        Instruction::AddImm(n) => (0x1e612800, *n as f64),
+        Instruction::SubImm(n) => (0x1e613800, *n as f64),
+        Instruction::MulImm(n) => (0x1e610800, *n as f64),
+        Instruction::DivImm(n) => (0x1e611800, *n as f64),
+        Instruction::RemTwo => return float_rem(machine_code, constants, 2.0),
+        Instruction::RemImm(n) => return float_rem(machine_code, constants, *n as f64),
+        // `fmul d0, d0, d0`
+        Instruction::Square => return push(machine_code, &[0x1e600800]),
+        // `fneg d0, d0`
+        Instruction::Negate => return push(machine_code, &[0x1e614000]),
+        // `fabs d0, d0`
+        Instruction::Abs => return push(machine_code, &[0x1e60c000]),
+        // NaN becomes zero too, as `fmaxnm` prefers a number to NaN
+        // `fmaxnm d0, d0, d1`
+        Instruction::ClampZero => (0x1e616800, 0.0),
+        Instruction::Reset => return prologue_float(machine_code),
+        Instruction::ShiftRight => {
+            load_float(machine_code, constants, 1, 2.0);
+            // Halve, then round down like an arithmetic shift does
+            // `fdiv d0, d0, d1`
+            // `frintm d0, d0`
+            return push(machine_code, &[0x1e611800, 0x1e654000]);
+        }
+        Instruction::LoadConst(n) => return load_float(machine_code, constants, 0, *n as f64),
+        // `str d0, [sp, #-16]!`
+        Instruction::Push => return push(machine_code, &[0xfc1f0fe0]),
+        // `ldr d1, [sp]`
+        // `str d0, [sp]`
+        // `fmov d0, d1`
+        Instruction::Swap => return push(machine_code, &[0xfd4003e1, 0xfd0003e0, 0x1e604020]),
+        Instruction::SetCounter { .. } | Instruction::CountDown { .. } => {
+            unreachable!("loops are emitted by loop_start and loop_end")
+        }
+        Instruction::Label(_) | Instruction::JumpIfZero(_) => {
+            unreachable!("skips are emitted by skip_float and patch_skip")
+        }
+    };
+    load_float(machine_code, constants, 1, n);
+    push(machine_code, &[op]);
+}
+
+/// Load `value` from the literal pool into the register d<rt>
+fn load_float(machine_code: &mut Vec<u8>, constants: &mut Constants, rt: u32, value: f64) {
+    constants.push((machine_code.len(), value));
+    // `ldr d<rt>, #0`, until the offset is filled in
+    push(machine_code, &[0x5c000000 | rt]);
+}
+
+/// Replace the floating point working register (x) with its remainder after dividing
+/// by `n`, which is x - trunc(x / n) * n, the same as the x86_64 backend
+fn float_rem(machine_code: &mut Vec<u8>, constants: &mut Constants, n: f64) {
+    load_float(machine_code, constants, 1, n);
+    // `fdiv d2, d0, d1`
+    // `frintz d2, d2`
+    // `fmsub d0, d2, d1, d0`
+    push(machine_code, &[0x1e611802, 0x1e65c042, 0x1f418040]);
+}
+
+/// Like [`skip`], but for the floating point working register. Both zeros are skipped,
+/// which are the only values with no bits set other than the sign bit.
+pub(crate) fn skip_float(machine_code: &mut Vec<u8>) -> usize {
+    // `fmov x1, d0`
+    // `lsl x1, x1, #1`
+    // `cbnz x1, #8`
+    // `b #0`
+    push(
+        machine_code,
+        &[0x9e660001, 0xd37ff821, 0xb5000041, 0x14000000],
+    );
+    machine_code.len() - 4
+}
+
+/// Return the floating point working register, which is already in the return register,
+/// followed by the literal pool.
+/// Fails with [`JitError::CodeTooLarge`] if the code is too long for the first loads to
+/// reach the pool, which is a megabyte away at most.
+pub(crate) fn epilogue_float(
+    machine_code: &mut Vec<u8>,
+    constants: &Constants,
+) -> Result<(), JitError> {
+    // `ret`
+    push(machine_code, &[0xd65f03c0]);
+    for (load, address) in literal_pool(machine_code, constants) {
+        // The offset counts instructions from the load itself
+        let offset = (address - load) / 4;
+        if offset >= LOAD_RANGE {
+            return Err(JitError::CodeTooLarge {
+                size: machine_code.len(),
+                limit: LOAD_RANGE * 4,
+            });
+        }
+        let instruction = &mut machine_code[load..load + 4];
+        let patched = u32::from_le_bytes(instruction.try_into().unwrap()) | ((offset as u32) << 5);
+        instruction.copy_from_slice(&patched.to_le_bytes());
+    }
+    Ok(())
+}
+
+/// Condition code for "equal"
+const EQ: u32 = 0b0000;
+/// Condition code for "not equal"
+const NE: u32 = 0b0001;
+/// Condition code for "positive or zero"
+const PL: u32 = 0b0101;
+/// Condition code for "no overflow"
+const VC: u32 = 0b0111;
+
+/// Add `n` to the working register, setting the flags
+fn checked_add(instructions: &mut Vec<u32>, n: i64) {
+    let op = if n < 0 { 0xf1000000 } else { 0xb1000000 };
+    let magnitude = n.unsigned_abs();
+    if magnitude < 4096 {
+        // `adds x0, x0, #imm12` or `subs x0, x0, #imm12`
+        instructions.push(op | ((magnitude as u32) << 10));
+    } else {
+        instructions.extend(load_immediate(1, n));
+        // `adds x0, x0, x1`
+        instructions.push(0xab010000);
+    }
+}
+
+/// Add `n` to the working register. Negative values are subtracted instead.
+fn add(instructions: &mut Vec<u32>, n: i64) {
+    // Bit 30 selects between `add` and `sub`
+    let op = if n < 0 { 0xd1000000 } else { 0x91000000 };
+    let magnitude = n.unsigned_abs();
+    if magnitude < 4096 {
+        // `add x0, x0, #imm12` or `sub x0, x0, #imm12`
+        instructions.push(op | ((magnitude as u32) << 10));
+    } else {
+        // Larger values are loaded into x1 first
+        instructions.extend(load_immediate(1, n));
+        // `add x0, x0, x1`
+        instructions.push(0x8b010000);
+    }
+}
+
+/// Instructions which set register `rd` to `value`, 16 bits at a time.
+fn load_immediate(rd: u32, value: i64) -> Vec<u32> {
+    let value = value as u64;
+    let mut instructions: Vec<u32> = Vec::new();
+    for hw in 0..4 {
+        let chunk = ((value >> (hw * 16)) & 0xffff) as u32;
+        if chunk == 0 {
+            continue;
+        }
+        if instructions.is_empty() {
+            // The first non-zero chunk also clears the rest of the register
+            // `movz xd, #chunk, lsl #(hw * 16)`
+            instructions.push(0xd2800000 | (hw << 21) | (chunk << 5) | rd);
+        } else {
+            // `movk xd, #chunk, lsl #(hw * 16)`
+            instructions.push(0xf2800000 | (hw << 21) | (chunk << 5) | rd);
+        }
+    }
+    if instructions.is_empty() {
+        // `mov xd, #0`
+        instructions.push(0xd2800000 | rd);
+    }
+    instructions
+}
+
+#[cfg(test)]
+mod test {
+    use crate::expr::{BinOp, Step};
+    use crate::ir::{Instruction, Label};
+    use crate::{
+        codegen, codegen_float, emit_all, jit_with_options, parse, DivRounding, JitError, Op,
+        Options, OverflowBehavior, ReturnType, Signedness, Start, Target,
+    };
+
+    #[test]
+    fn test_encoding() {
+        let options = Options {
+            target: Target::Aarch64,
+            ..Options::default()
+        };
+        assert_eq!(
+            jit_with_options("+", options).unwrap(),
+            [0x00, 0x00, 0x80, 0xd2, 0x00, 0x04, 0x00, 0x91, 0xc0, 0x03, 0x5f, 0xd6]
+        );
+        // `mov x0, #0`
+        // `sub x0, x0, #1`
+        // `ret`
+        assert_eq!(
+            jit_with_options("-", options).unwrap(),
+            [0x00, 0x00, 0x80, 0xd2, 0x00, 0x04, 0x00, 0xd1, 0xc0, 0x03, 0x5f, 0xd6]
+        );
+        // `lsl x0, x0, #1`
+        assert_eq!(
+            jit_with_options("*", options).unwrap(),
+            [0x00, 0x00, 0x80, 0xd2, 0x00, 0xf8, 0x7f, 0xd3, 0xc0, 0x03, 0x5f, 0xd6]
+        );
+        // `add x1, x0, x0, lsr #63`
+        // `asr x0, x1, #1`
+        assert_eq!(
+            jit_with_options("/", options).unwrap(),
+            [
+                0x00, 0x00, 0x80, 0xd2, 0x01, 0xfc, 0x40, 0x8b, 0x20, 0xfc, 0x41, 0x93, 0xc0, 0x03,
+                0x5f, 0xd6
+            ]
+        );
+        // `mov x0, #100`
+        // `add x0, x0, #1`
+        assert_eq!(
+            codegen(&[Op::Plus], Start::Constant(100), &options).unwrap()[..8],
+            [0x80, 0x0c, 0x80, 0xd2, 0x00, 0x04, 0x00, 0x91]
+        );
+        // `mov x2, #5`
+        // `sdiv x1, x0, x2`
+        // `msub x0, x1, x2, x0`
+        assert_eq!(
+            jit_with_options("%5", options).unwrap()[4..16],
+            [0xa2, 0x00, 0x80, 0xd2, 0x01, 0x0c, 0xc2, 0x9a, 0x20, 0x80, 0x02, 0x9b]
+        );
+        // `asr x0, x0, #1`
+        assert_eq!(
+            jit_with_options(">", options).unwrap()[4..8],
+            [0x00, 0xfc, 0x41, 0x93]
+        );
+        // `cmp x0, #0`
+        // `cneg x0, x0, mi`
+        assert_eq!(
+            jit_with_options("|", options).unwrap()[4..12],
+            [0x1f, 0x00, 0x00, 0xf1, 0x00, 0x54, 0x80, 0xda]
+        );
+        // `cmp x0, #0`
+        // `csel x0, x0, xzr, ge`
+        assert_eq!(
+            jit_with_options("_", options).unwrap()[4..12],
+            [0x1f, 0x00, 0x00, 0xf1, 0x00, 0xa0, 0x9f, 0x9a]
+        );
+        // `neg x0, x0`
+        assert_eq!(
+            jit_with_options("~", options).unwrap()[4..8],
+            [0xe0, 0x03, 0x00, 0xcb]
+        );
+    }
+
+    #[test]
+    fn test_unsigned_encoding() {
+        let options = Options {
+            target: Target::Aarch64,
+            signedness: Signedness::Unsigned,
+            ..Options::default()
+        };
+        // `lsr x0, x0, #1`
+        for p in ["/", ">"] {
+            assert_eq!(
+                jit_with_options(p, options).unwrap()[4..8],
+                [0x00, 0xfc, 0x41, 0xd3],
+                "{p}"
+            );
+        }
+        // `and x0, x0, #1`
+        assert_eq!(
+            jit_with_options("%", options).unwrap()[4..8],
+            [0x00, 0x00, 0x40, 0x92]
+        );
+        // `mov x1, #3`
+        // `udiv x0, x0, x1`
+        assert_eq!(
+            jit_with_options("/3", options).unwrap()[4..12],
+            [0x61, 0x00, 0x80, 0xd2, 0x00, 0x08, 0xc1, 0x9a]
+        );
+        // `udiv x1, x0, x2`
+        assert_eq!(
+            jit_with_options("%5", options).unwrap()[8..12],
+            [0x01, 0x08, 0xc2, 0x9a]
+        );
+        // `lsr x0, x0, #3`
+        let options = Options {
+            strength_reduce: true,
+            ..options
+        };
+        assert_eq!(
+            jit_with_options("/8", options).unwrap()[4..8],
+            [0x00, 0xfc, 0x43, 0xd3]
+        );
+        // `|` does nothing
+        assert_eq!(jit_with_options("|", options).unwrap().len(), 8);
+    }
+
+    /// The integer code can be copied anywhere and still run, as it never loads from or
+    /// computes an address relative to itself (with `adr`, `adrp`, or a literal `ldr`),
+    /// and only branches within itself
+    #[test]
+    fn test_position_independent() {
+        let program = "+-*/%^~0<>|+5-5*3*8/3/8%3=-7=2147483647*2147483647 2[+?-]";
+        let mut machine_code = Vec::new();
+        for strength_reduce in [false, true] {
+            for signedness in [Signedness::Signed, Signedness::Unsigned] {
+                let options = Options {
+                    target: Target::Aarch64,
+                    strength_reduce,
+                    signedness,
+                    ..Options::default()
+                };
+                machine_code.extend(jit_with_options(program, options).unwrap());
+            }
+        }
+        for (_, token) in crate::ir::lower(&crate::tokenize(program).unwrap()).unwrap() {
+            let jump = matches!(
+                token,
+                Instruction::SetCounter { .. }
+                    | Instruction::Label(_)
+                    | Instruction::CountDown { .. }
+                    | Instruction::JumpIfZero(_)
+            );
+            if !jump {
+                super::emit_checked(&mut machine_code, 0, &token, &Options::default(), false);
+            }
+        }
+        for word in machine_code.chunks(4) {
+            let word = u32::from_le_bytes(word.try_into().unwrap());
+            // `adr` and `adrp`
+            assert_ne!(word & 0x1f000000, 0x10000000, "{word:#010x}");
+            // `ldr` (literal), for both general purpose and floating point registers
+            assert_ne!(word & 0x3b000000, 0x18000000, "{word:#010x}");
+        }
+    }
+
+    #[test]
+    fn test_checked_encoding() {
+        let mut machine_code = Vec::new();
+        super::emit_checked(
+            &mut machine_code,
+            0,
+            &Instruction::Inc,
+            &Options::default(),
+            false,
+        );
+        // `adds x0, x0, #1`
+        // `b.vc #12`
+        // `mov x1, #1`
+        // `ret`
+        assert_eq!(
+            machine_code,
+            [
+                0x00, 0x04, 0x00, 0xb1, 0x67, 0x00, 0x00, 0x54, 0x21, 0x00, 0x80, 0xd2, 0xc0, 0x03,
+                0x5f, 0xd6
+            ]
+        );
+        // Indices which don't fit in 16 bits take two instructions to load
+        let mut machine_code = Vec::new();
+        super::emit_checked(
+            &mut machine_code,
+            0x12345,
+            &Instruction::Inc,
+            &Options::default(),
+            false,
+        );
+        // `b.vc #16`
+        // `mov x1, #0x2346`
+        // `movk x1, #1, lsl #16`
+        assert_eq!(
+            machine_code[4..16],
+            [0x87, 0x00, 0x00, 0x54, 0xc1, 0x68, 0x84, 0xd2, 0x21, 0x00, 0xa0, 0xf2]
+        );
+    }
+
+    #[test]
+    fn test_saturating_encoding() {
+        let mut machine_code = Vec::new();
+        super::emit_saturating(&mut machine_code, &Instruction::Inc, &Options::default());
+        // `adds x0, x0, #1`
+        // `b.vc #12`
+        // `asr x0, x0, #63`
+        // `eor x0, x0, #0x8000000000000000`
+        assert_eq!(
+            machine_code,
+            [
+                0x00, 0x04, 0x00, 0xb1, 0x67, 0x00, 0x00, 0x54, 0x00, 0xfc, 0x7f, 0x93, 0x00, 0x00,
+                0x41, 0xd2
+            ]
+        );
+        let mut machine_code = Vec::new();
+        super::emit_saturating(
+            &mut machine_code,
+            &Instruction::MulImm(3),
+            &Options::default(),
+        );
+        // `mov x1, #3`
+        // `eor x3, x0, x1`
+        // `smulh x2, x0, x1`
+        assert_eq!(
+            machine_code[..12],
+            [0x61, 0x00, 0x80, 0xd2, 0x03, 0x00, 0x01, 0xca, 0x02, 0x7c, 0x41, 0x9b]
+        );
+        // `b.eq #12`
+        // `asr x0, x3, #63`
+        // `eor x0, x0, #0x7fffffffffffffff`
+        assert_eq!(
+            machine_code[20..],
+            [0x60, 0x00, 0x00, 0x54, 0x60, 0xfc, 0x7f, 0x93, 0x00, 0xf8, 0x40, 0xd2]
+        );
+        // Operations which can't overflow are emitted as usual
+        let options = Options {
+            overflow: OverflowBehavior::Saturate,
+            target: Target::Aarch64,
+            ..Options::default()
+        };
+        let wrapping = Options {
+            target: Target::Aarch64,
+            ..Options::default()
+        };
+        assert_eq!(
+            jit_with_options("=5/%>0", options),
+            jit_with_options("=5/%>0", wrapping)
+        );
+    }
+
+    #[test]
+    fn test_div_rounding_encoding() {
+        let floor = Options {
+            div_rounding: DivRounding::Floor,
+            target: Target::Aarch64,
+            ..Options::default()
+        };
+        // `asr x0, x0, #1`
+        assert_eq!(
+            codegen(&[Op::Slash], Start::Input, &floor).unwrap(),
+            [0x00, 0xfc, 0x41, 0x93, 0xc0, 0x03, 0x5f, 0xd6]
+        );
+        let checked = Options {
+            overflow: OverflowBehavior::Checked,
+            ..floor
+        };
+        assert_eq!(
+            codegen(&[Op::Slash], Start::Input, &checked).unwrap()[..4],
+            [0x00, 0xfc, 0x41, 0x93]
+        );
+    }
+
+    #[test]
+    fn test_stack_encoding() {
+        let options = Options {
+            target: Target::Aarch64,
+            ..Options::default()
+        };
+        // `mov x0, #0`
+        // `mov x16, sp`
+        // `str x0, [sp, #-16]!`
+        // `ldr x1, [sp]`
+        // `str x0, [sp]`
+        // `mov x0, x1`
+        // `mov sp, x16`
+        // `ret`
+        assert_eq!(
+            codegen(&[Op::Dup, Op::Swap], Start::Constant(0), &options).unwrap(),
+            [
+                0x00, 0x00, 0x80, 0xd2, 0xf0, 0x03, 0x00, 0x91, 0xe0, 0x0f, 0x1f, 0xf8, 0xe1, 0x03,
+                0x40, 0xf9, 0xe0, 0x03, 0x00, 0xf9, 0xe0, 0x03, 0x01, 0xaa, 0x1f, 0x02, 0x00, 0x91,
+                0xc0, 0x03, 0x5f, 0xd6
+            ]
+        );
+        // Without anything pushed there's no frame
+        assert_eq!(
+            codegen(&[Op::Plus], Start::Input, &options).unwrap().len(),
+            8
+        );
+        // Returning early restores the stack pointer too
+        let mut machine_code = Vec::new();
+        super::emit_checked(
+            &mut machine_code,
+            0,
+            &Instruction::Inc,
+            &Options::default(),
+            true,
+        );
+        // `b.vc #16`
+        // `mov x1, #1`
+        // `mov sp, x16`
+        // `ret`
+        assert_eq!(
+            machine_code[4..],
+            [
+                0x87, 0x00, 0x00, 0x54, 0x21, 0x00, 0x80, 0xd2, 0x1f, 0x02, 0x00, 0x91, 0xc0, 0x03,
+                0x5f, 0xd6
+            ]
+        );
+    }
+
+    #[test]
+    fn test_breakpoint_encoding() {
+        let options = Options {
+            target: Target::Aarch64,
+            breakpoint: true,
+            ..Options::default()
+        };
+        // `mov x0, #0`
+        // `brk #0`
+        // `add x0, x0, #1`
+        // `ret`
+        assert_eq!(
+            codegen(&[Op::Plus], Start::Constant(0), &options).unwrap(),
+            [
+                0x00, 0x00, 0x80, 0xd2, 0x00, 0x00, 0x20, 0xd4, 0x00, 0x04, 0x00, 0x91, 0xc0, 0x03,
+                0x5f, 0xd6
+            ]
+        );
+    }
+
+    #[test]
+    fn test_long_loop_encoding() {
+        let options = Options {
+            target: Target::Aarch64,
+            ..Options::default()
+        };
+        /// The instructions ending a loop with `n` increments in it
+        fn t(n: usize, options: &Options) -> Vec<u8> {
+            let mut tokens = vec![Op::Loop(2)];
+            tokens.extend(vec![Op::Plus; n]);
+            tokens.push(Op::EndLoop);
+            let machine_code = codegen(&tokens, Start::Input, options).unwrap();
+            machine_code[4 + 4 * n..].to_vec()
+        }
+
+        // The furthest `b.ne` reaches
+        // `subs x9, x9, #1`
+        // `b.ne #-1048576`
+        // `ret`
+        assert_eq!(
+            t((1 << 18) - 1, &options),
+            [0x29, 0x05, 0x00, 0xf1, 0x01, 0x00, 0x80, 0x54, 0xc0, 0x03, 0x5f, 0xd6]
+        );
+        // `subs x9, x9, #1`
+        // `b.eq #8`
+        // `b #-1048584`
+        // `ret`
+        assert_eq!(
+            t(1 << 18, &options),
+            [
+                0x29, 0x05, 0x00, 0xf1, 0x40, 0x00, 0x00, 0x54, 0xfe, 0xff, 0xfb, 0x17, 0xc0, 0x03,
+                0x5f, 0xd6
+            ]
+        );
+    }
+
+    #[test]
+    fn test_branch_out_of_range() {
+        let too_large = |size| JitError::CodeTooLarge {
+            size,
+            limit: super::BRANCH_RANGE * 4,
+        };
+        // The furthest a loop's `b` reaches back, then one instruction further
+        let mut machine_code = vec![0; super::BRANCH_RANGE * 4 - 8];
+        assert_eq!(super::loop_end(&mut machine_code, 0, 0), Ok(()));
+        let mut machine_code = vec![0; super::BRANCH_RANGE * 4 - 4];
+        let size = machine_code.len();
+        assert_eq!(
+            super::loop_end(&mut machine_code, 0, 0),
+            Err(too_large(size))
+        );
+        // The furthest a skip's `b` reaches forwards, then one instruction further
+        let mut machine_code = vec![0; super::BRANCH_RANGE * 4];
+        assert_eq!(super::patch_skip(&mut machine_code, 4), Ok(()));
+        assert_eq!(
+            super::patch_skip(&mut machine_code, 0),
+            Err(too_large(machine_code.len()))
+        );
+        // The error is passed on when a skipped instruction is too long to jump over
+        let instructions = [
+            (0, Instruction::JumpIfZero(Label(0))),
+            (1, Instruction::Inc),
+            (1, Instruction::Label(Label(0))),
+        ];
+        let mut machine_code = Vec::new();
+        let result = emit_all(
+            &mut machine_code,
+            &instructions,
+            Target::Aarch64,
+            |machine_code, _, _| machine_code.resize(machine_code.len() + (128 << 20), 0),
+            super::skip,
+        );
+        assert_eq!(result, Err(too_large(machine_code.len())));
+    }
+
+    #[test]
+    fn test_step_encoding() {
+        /// Tester function
+        fn t(step: Step) -> Vec<u8> {
+            let mut machine_code = Vec::new();
+            super::emit_step(&mut machine_code, &step);
+            machine_code
+        }
+
+        // `str x0, [sp, #-16]!`
+        assert_eq!(t(Step::Push), [0xe0, 0x0f, 0x1f, 0xf8]);
+        // `mov x1, x0`
+        // `ldr x0, [sp], #16`
+        // `sdiv x0, x0, x1`
+        assert_eq!(
+            t(Step::Apply(BinOp::Div)),
+            [0xe1, 0x03, 0x00, 0xaa, 0xe0, 0x07, 0x41, 0xf8, 0x00, 0x0c, 0xc1, 0x9a]
+        );
+        // `neg x0, x0`
+        assert_eq!(t(Step::Negate), [0xe0, 0x03, 0x00, 0xcb]);
+    }
+
+    #[test]
+    fn test_float_encoding() {
+        let mut machine_code = Vec::new();
+        let mut constants = Vec::new();
+        super::emit_float(&mut machine_code, &Instruction::Inc, &mut constants);
+        super::epilogue_float(&mut machine_code, &constants).unwrap();
+        // `ldr d1, #16`
+        // `fadd d0, d0, d1`
+        // `ret`
+        assert_eq!(
+            machine_code[..12],
+            [0x81, 0x00, 0x00, 0x5c, 0x00, 0x28, 0x61, 0x1e, 0xc0, 0x03, 0x5f, 0xd6]
+        );
+        // The literal pool starts at the next multiple of 8 bytes
+        assert_eq!(machine_code[12..16], [0; 4]);
+        assert_eq!(machine_code[16..], 1.0f64.to_le_bytes());
+        let mut machine_code = Vec::new();
+        super::emit_float(&mut machine_code, &Instruction::RemTwo, &mut Vec::new());
+        // `fdiv d2, d0, d1`
+        // `frintz d2, d2`
+        // `fmsub d0, d2, d1, d0`
+        assert_eq!(
+            machine_code[4..],
+            [0x02, 0x18, 0x61, 0x1e, 0x42, 0xc0, 0x65, 0x1e, 0x40, 0x80, 0x41, 0x1f]
+        );
+        let mut machine_code = Vec::new();
+        super::skip_float(&mut machine_code);
+        // `fmov x1, d0`
+        // `lsl x1, x1, #1`
+        // `cbnz x1, #8`
+        // `b #0`
+        assert_eq!(
+            machine_code,
+            [
+                0x01, 0x00, 0x66, 0x9e, 0x21, 0xf8, 0x7f, 0xd3, 0x41, 0x00, 0x00, 0xb5, 0x00, 0x00,
+                0x00, 0x14
+            ]
+        );
+    }
+
+    /// Loads of constants reach a megabyte, so longer floating point code is an error
+    /// rather than loading from the wrong place
+    #[test]
+    fn test_far_literal_pool() {
+        let tokens = parse("100000(+)").unwrap();
+        let machine_code = codegen_float(&tokens, Target::Aarch64).unwrap();
+        assert!(machine_code.len() > 800_000);
+        // The first load, after `fmov d0, xzr`, reaches all the way to the pool
+        let load = u32::from_le_bytes(machine_code[4..8].try_into().unwrap());
+        let offset = ((load >> 5) & 0x7ffff) as usize;
+        let address = 4 + offset * 4;
+        assert_eq!(machine_code[address..address + 8], 1.0f64.to_le_bytes());
+
+        for p in ["200000(+)", "+?2[300000(+)]"] {
+            let tokens = parse(p).unwrap();
+            assert!(matches!(
+                codegen_float(&tokens, Target::Aarch64),
+                Err(JitError::CodeTooLarge {
+                    limit: 0x100000,
+                    ..
+                })
+            ));
+            // x86_64 loads reach much further
+            assert!(codegen_float(&tokens, Target::X86_64).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_loop_encoding() {
+        let options = Options {
+            target: Target::Aarch64,
+            ..Options::default()
+        };
+        // `mov x9, #2`
+        // `add x0, x0, #1`
+        // `subs x9, x9, #1`
+        // `b.ne #-8`
+        assert_eq!(
+            jit_with_options("2[+]", options).unwrap()[4..20],
+            [
+                0x49, 0x00, 0x80, 0xd2, 0x00, 0x04, 0x00, 0x91, 0x29, 0x05, 0x00, 0xf1, 0xc1, 0xff,
+                0xff, 0x54
+            ]
+        );
+    }
+
+    #[test]
+    fn test_skip_encoding() {
+        let options = Options {
+            target: Target::Aarch64,
+            ..Options::default()
+        };
+        // `cbnz x0, #8`
+        // `b #8`
+        // `add x0, x0, #1`
+        assert_eq!(
+            jit_with_options("?+", options).unwrap()[4..16],
+            [0x40, 0x00, 0x00, 0xb5, 0x02, 0x00, 0x00, 0x14, 0x00, 0x04, 0x00, 0x91]
+        );
+    }
+
+    /// A skipped loop can be further than `cbz` reaches, so the jump has to go forwards
+    /// past all of it
+    #[test]
+    fn test_long_skip() {
+        let options = Options {
+            target: Target::Aarch64,
+            ..Options::default()
+        };
+        let machine_code = jit_with_options("+?2[300000(+)]", options).unwrap();
+        assert!(machine_code.len() > 1 << 20);
+        // After `mov x0, #0` and `add x0, x0, #1` comes `cbnz x0, #8`, then the jump
+        assert_eq!(machine_code[8..12], [0x40, 0x00, 0x00, 0xb5]);
+        let jump = u32::from_le_bytes(machine_code[12..16].try_into().unwrap());
+        assert_eq!(jump >> 26, 0b000101);
+        let offset = (jump & 0x3ffffff) as usize;
+        assert!(offset < 1 << 25, "jumps backwards");
+        // The jump lands on the `ret`, just after the loop
+        assert_eq!(12 + offset * 4, machine_code.len() - 4);
+    }
+
+    #[test]
+    fn test_typed_encoding() {
+        /// Tester function
+        fn t(return_type: ReturnType) -> Vec<u8> {
+            let options = Options {
+                target: Target::Aarch64,
+                return_type,
+                ..Options::default()
+            };
+            jit_with_options("+", options).unwrap()[8..].to_vec()
+        }
+
+        // `ret`
+        assert_eq!(t(ReturnType::I64), [0xc0, 0x03, 0x5f, 0xd6]);
+        // `sxtw x0, w0`
+        assert_eq!(t(ReturnType::I32)[..4], [0x00, 0x7c, 0x40, 0x93]);
+        // `mov w0, w0`
+        assert_eq!(t(ReturnType::U32)[..4], [0xe0, 0x03, 0x00, 0x2a]);
+    }
+
+    #[test]
+    fn test_shift_divide_encoding() {
+        let options = Options {
+            target: Target::Aarch64,
+            strength_reduce: true,
+            ..Options::default()
+        };
+        assert_eq!(
+            jit_with_options("/8", options).unwrap()[4..16],
+            [0x01, 0xfc, 0x7f, 0x93, 0x00, 0xf4, 0x41, 0x8b, 0x00, 0xfc, 0x43, 0x93]
+        );
+        // `lsl x0, x0, #3`
+        assert_eq!(
+            jit_with_options("*8", options).unwrap()[4..8],
+            [0x00, 0xf0, 0x7d, 0xd3]
+        );
+    }
+}
+
+// These tests run the generated code, so they need an AArch64 host
+#[cfg(all(test, target_arch = "aarch64"))]
+mod test_execution {
+    use crate::aarch64::return_constant;
+    use crate::{codegen, compile, run, Op, Options, Start};
+
+    #[test]
+    fn test_halving_rounds_towards_zero() {
+        assert_eq!(compile("-/").unwrap().call(), 0);
+        assert_eq!(compile("---/").unwrap().call(), -1);
+        assert_eq!(compile("----/").unwrap().call(), -2);
+    }
+
+    #[test]
+    fn test_return_constant() {
+        for value in [0, 1, -1, 0x1234_0000, i64::MAX, i64::MIN] {
+            assert_eq!(unsafe { run(&return_constant(value)) }, value);
+        }
+    }
+
+    #[test]
+    fn test_add_encodings() {
+        for n in [2, -2, 4095, -4095, 4096, -4096, 1 << 40, -(1 << 40)] {
+            let result = unsafe {
+                run(&codegen(&[Op::Add(n)], Start::Constant(0), &Options::default()).unwrap())
+            };
+            assert_eq!(result, n, "{n}");
+        }
+    }
+}
@@ -0,0 +1,117 @@
+// Making up programs for fuzzing, such as with `cargo fuzz`. The programs are ones
+// `parse` could give, so the fuzzer's time goes on the code generation rather than on
+// programs which are rejected straight away.
+
+use crate::{Op, Program, MAX_LOOP_DEPTH};
+use alloc::vec::Vec;
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+/// At least one operation, with balanced loops nested no more than [`MAX_LOOP_DEPTH`]
+/// deep, operands in the range [`parse`](crate::parse) accepts, and something after each
+/// skip to skip. As with [`parse`](crate::parse), lengthy programs and loops which run up
+/// to `i32::MAX` times are possible, so a fuzzer may want to cap both. The stack can still
+/// be misused, which [`jit_ops`](crate::jit_ops) rejects.
+impl<'a> Arbitrary<'a> for Program {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut ops = Vec::new();
+        let mut depth = 0;
+        for _ in 0..u.arbitrary_len::<u32>()?.max(1) {
+            let op = match u.int_in_range(0..=22)? {
+                0 => Op::Plus,
+                1 => Op::Minus,
+                2 => Op::Star,
+                3 => Op::Slash,
+                4 => Op::Percent,
+                5 => Op::Caret,
+                6 => Op::Tilde,
+                7 => Op::Zero,
+                8 => Op::Less,
+                9 => Op::Greater,
+                10 => Op::Bar,
+                11 => Op::Clamp,
+                12 => Op::Add(operand(u)?),
+                13 => Op::Sub(operand(u)?),
+                14 => Op::Mul(operand(u)?),
+                15 => Op::Div(operand(u)?),
+                16 => Op::Rem(operand(u)?),
+                17 if u.arbitrary()? => Op::Set(-operand(u)?),
+                17 => Op::Set(operand(u)?),
+                18 => Op::Skip,
+                19 => Op::Dup,
+                20 => Op::Swap,
+                21 if depth < MAX_LOOP_DEPTH => {
+                    depth += 1;
+                    Op::Loop(operand(u)?)
+                }
+                _ if depth > 0 => {
+                    depth -= 1;
+                    end_loop(&mut ops);
+                    continue;
+                }
+                _ => Op::Plus,
+            };
+            ops.push(op);
+        }
+        for _ in 0..depth {
+            end_loop(&mut ops);
+        }
+        if ops.last() == Some(&Op::Skip) {
+            ops.push(Op::Plus);
+        }
+        Ok(Program { ops })
+    }
+}
+
+/// End the innermost loop, giving a skip at its end something to skip first
+fn end_loop(ops: &mut Vec<Op>) {
+    if ops.last() == Some(&Op::Skip) {
+        ops.push(Op::Plus);
+    }
+    ops.push(Op::EndLoop);
+}
+
+/// An operand the parser accepts, which is from 1 to `i32::MAX`
+fn operand(u: &mut Unstructured) -> Result<i64> {
+    Ok(i64::from(u.int_in_range(1..=i32::MAX)?))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{jit_ops, parse, JitError, Op, Program};
+    use alloc::string::ToString;
+    use alloc::vec::Vec;
+    use arbitrary::{Arbitrary, Unstructured};
+
+    /// Everything made up is a program `parse` gives back unchanged, unless it misuses
+    /// the stack
+    #[test]
+    fn test_arbitrary_program() {
+        let mut state: u64 = 0x9e3779b97f4a7c15;
+        let mut parsed = 0;
+        for length in 0..2000 {
+            let bytes: Vec<u8> = (0..length % 300)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state as u8
+                })
+                .collect();
+            let program = Program::arbitrary(&mut Unstructured::new(&bytes)).unwrap();
+            assert!(!program.ops.is_empty());
+            match jit_ops(&program.ops) {
+                Err(JitError::EmptyStack { .. } | JitError::StackTooDeep { .. }) => continue,
+                result => assert!(result.is_ok(), "{:?}", program.ops),
+            }
+            let source = program
+                .ops
+                .iter()
+                .map(Op::to_string)
+                .collect::<Vec<_>>()
+                .join(" ");
+            assert_eq!(parse(&source), Ok(program.ops), "{source}");
+            parsed += 1;
+        }
+        assert!(parsed > 1000);
+    }
+}
@@ -0,0 +1,149 @@
+// Timing a program run through the JIT compiler against the same program run through
+// the interpreter, for the binary's `--bench` flag.
+
+use crate::{compile, interpret, CalcError};
+use std::fmt;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+/// How many times each way of running the program goes before timing starts
+const WARMUP_ITERATIONS: u32 = 10;
+
+/// How long running a program many times took with each of the JIT and the interpreter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BenchReport {
+    /// What the program gives, which both ways agree on
+    pub result: i64,
+    /// How many times the program was run each way
+    pub iterations: u32,
+    /// The total time taken to call the compiled program
+    pub jit: Duration,
+    /// The total time taken to interpret the program, including parsing it each time
+    pub interpreter: Duration,
+}
+
+impl BenchReport {
+    /// The average time taken by one call of the compiled program
+    pub fn jit_per_iteration(&self) -> Duration {
+        self.jit / self.iterations
+    }
+
+    /// The average time taken to interpret the program once
+    pub fn interpreter_per_iteration(&self) -> Duration {
+        self.interpreter / self.iterations
+    }
+
+    /// How many times faster the JIT was than the interpreter
+    pub fn speedup(&self) -> f64 {
+        self.interpreter.as_secs_f64() / self.jit.as_secs_f64()
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "result:      {}", self.result)?;
+        writeln!(f, "iterations:  {}", self.iterations)?;
+        writeln!(
+            f,
+            "jit:         {:?} total, {:?} per iteration",
+            self.jit,
+            self.jit_per_iteration()
+        )?;
+        writeln!(
+            f,
+            "interpreter: {:?} total, {:?} per iteration",
+            self.interpreter,
+            self.interpreter_per_iteration()
+        )?;
+        write!(f, "speedup:     {:.2}x", self.speedup())
+    }
+}
+
+/// Compile a program once, then time calling it `iterations` times against interpreting
+/// it `iterations` times. Both are run a few times first to warm up, and the program is
+/// only loaded into executable memory once.
+///
+/// # Panics
+/// Panics if `iterations` is zero, or if the JIT and the interpreter disagree on the
+/// result, which would be a bug in one of them.
+pub fn benchmark(program: &str, iterations: u32) -> Result<BenchReport, CalcError> {
+    assert!(iterations > 0, "a benchmark needs at least one iteration");
+    let compiled = compile(program)?;
+    let result = compiled.call();
+    let interpreted = interpret(program)?;
+    assert_eq!(
+        result, interpreted,
+        "the JIT and the interpreter disagree on {program:?}"
+    );
+
+    for _ in 0..WARMUP_ITERATIONS {
+        black_box(compiled.call());
+        black_box(interpret(black_box(program))?);
+    }
+    let start = Instant::now();
+    for _ in 0..iterations {
+        black_box(compiled.call());
+    }
+    let jit = start.elapsed();
+    let start = Instant::now();
+    for _ in 0..iterations {
+        black_box(interpret(black_box(program))?);
+    }
+    let interpreter = start.elapsed();
+
+    Ok(BenchReport {
+        result,
+        iterations,
+        jit,
+        interpreter,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{benchmark, BenchReport, CalcError, JitError};
+    use std::time::Duration;
+
+    #[test]
+    fn test_benchmark() {
+        for (p, result) in [("++*", 4), ("5[+]*", 10), ("+1000(+)*", 2002)] {
+            let report = benchmark(p, 100).unwrap();
+            assert_eq!(report.result, result, "{p}");
+            assert_eq!(report.iterations, 100);
+        }
+        assert!(matches!(
+            benchmark("+x", 10),
+            Err(CalcError::Jit(JitError::UnknownCharacter { index: 1, .. }))
+        ));
+    }
+
+    #[test]
+    fn test_bench_report() {
+        let report = BenchReport {
+            result: 4,
+            iterations: 1000,
+            jit: Duration::from_micros(20),
+            interpreter: Duration::from_micros(500),
+        };
+        assert_eq!(report.jit_per_iteration(), Duration::from_nanos(20));
+        assert_eq!(
+            report.interpreter_per_iteration(),
+            Duration::from_nanos(500)
+        );
+        assert_eq!(report.speedup(), 25.0);
+        assert_eq!(
+            report.to_string(),
+            "result:      4
+iterations:  1000
+jit:         20µs total, 20ns per iteration
+interpreter: 500µs total, 500ns per iteration
+speedup:     25.00x"
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_benchmark_no_iterations() {
+        let _ = benchmark("+", 0);
+    }
+}
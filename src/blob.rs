@@ -0,0 +1,163 @@
+// Saving compiled programs to run later. The machine code is prefixed by a small header
+// saying which architecture it was compiled for, so it's never run on the wrong one.
+//
+// The header is 6 bytes:
+//  - the magic number `SJCB`
+//  - the format version, currently 1
+//  - the target: 0 for x86_64 and 1 for AArch64, each with its usual calling convention
+// The code after it is a function of type `int64_t f()`, as from `jit`.
+
+use crate::Target;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// The first bytes of every blob
+pub const BLOB_MAGIC: [u8; 4] = *b"SJCB";
+/// The version of the format written by [`save_blob`]
+pub const BLOB_VERSION: u8 = 1;
+/// Size of the header before the machine code
+const HEADER_SIZE: usize = 6;
+
+/// Reasons a blob can't be loaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlobError {
+    /// The data doesn't start with [`BLOB_MAGIC`], or is too short to hold a header
+    NotABlob,
+    /// The blob was written by another version of the format
+    UnsupportedVersion(u8),
+    /// The blob's target byte doesn't name an architecture
+    UnknownTarget(u8),
+    /// The blob holds code for another architecture than the one it's being run on.
+    /// `expected` is `None` when there is no backend for the host at all.
+    WrongTarget {
+        expected: Option<Target>,
+        found: Target,
+    },
+}
+
+impl fmt::Display for BlobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlobError::NotABlob => write!(f, "Not a compiled program"),
+            BlobError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported compiled program version {version}")
+            }
+            BlobError::UnknownTarget(target) => write!(f, "Unknown target {target}"),
+            BlobError::WrongTarget {
+                expected: Some(expected),
+                found,
+            } => write!(
+                f,
+                "The program was compiled for {found:?}, not {expected:?}"
+            ),
+            BlobError::WrongTarget {
+                expected: None,
+                found,
+            } => write!(
+                f,
+                "The program was compiled for {found:?}, which this CPU can't run"
+            ),
+        }
+    }
+}
+
+impl core::error::Error for BlobError {}
+
+/// Prefix machine code compiled for `target` with a header, so it can be written to a
+/// file and later run with [`run_blob`]
+pub fn save_blob(machine_code: &[u8], target: Target) -> Vec<u8> {
+    let mut blob = Vec::with_capacity(HEADER_SIZE + machine_code.len());
+    blob.extend_from_slice(&BLOB_MAGIC);
+    blob.push(BLOB_VERSION);
+    blob.push(match target {
+        Target::X86_64 => 0,
+        Target::Aarch64 => 1,
+    });
+    blob.extend_from_slice(machine_code);
+    blob
+}
+
+/// Read the header written by [`save_blob`], returning the target the code was compiled
+/// for and the machine code itself
+pub fn load_blob(blob: &[u8]) -> Result<(Target, &[u8]), BlobError> {
+    if blob.len() < HEADER_SIZE || blob[..4] != BLOB_MAGIC {
+        return Err(BlobError::NotABlob);
+    }
+    if blob[4] != BLOB_VERSION {
+        return Err(BlobError::UnsupportedVersion(blob[4]));
+    }
+    let target = match blob[5] {
+        0 => Target::X86_64,
+        1 => Target::Aarch64,
+        n => return Err(BlobError::UnknownTarget(n)),
+    };
+    Ok((target, &blob[HEADER_SIZE..]))
+}
+
+#[cfg(feature = "std")]
+/// Run a blob from [`save_blob`], after checking it was compiled for the host.
+/// Panics if the code can't be loaded into executable memory, like [`run`](crate::run).
+///
+/// # Safety
+/// The header only says what the code claims to be. The same requirements as
+/// [`run`](crate::run) apply to the code after it.
+pub unsafe fn run_blob(blob: &[u8]) -> Result<i64, BlobError> {
+    let (target, machine_code) = load_blob(blob)?;
+    if Target::host() != Some(target) {
+        return Err(BlobError::WrongTarget {
+            expected: Target::host(),
+            found: target,
+        });
+    }
+    Ok(crate::run(machine_code))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{jit, load_blob, run, run_blob, save_blob, BlobError, Target, BLOB_MAGIC};
+
+    #[test]
+    fn test_blob() {
+        let path = std::env::temp_dir().join(format!("blob_{}.bin", std::process::id()));
+        for program in ["+", "++*-", "3[+?-]", "+++d*s-", "=-7%3"] {
+            let machine_code = jit(program).unwrap();
+            std::fs::write(&path, save_blob(&machine_code, Target::default())).unwrap();
+            let blob = std::fs::read(&path).unwrap();
+            assert_eq!(blob[..4], BLOB_MAGIC);
+            assert_eq!(load_blob(&blob), Ok((Target::default(), &machine_code[..])));
+            if Target::host().is_some() {
+                let expected = unsafe { run(&machine_code) };
+                assert_eq!(unsafe { run_blob(&blob) }, Ok(expected), "{program}");
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_blob_errors() {
+        let blob = save_blob(&[0xc3], Target::Aarch64);
+        assert_eq!(load_blob(&blob), Ok((Target::Aarch64, &[0xc3][..])));
+
+        assert_eq!(load_blob(&[]), Err(BlobError::NotABlob));
+        assert_eq!(load_blob(&blob[..5]), Err(BlobError::NotABlob));
+        assert_eq!(load_blob(b"\x7fELF\x02\x01"), Err(BlobError::NotABlob));
+        let mut newer = blob.clone();
+        newer[4] = 2;
+        assert_eq!(load_blob(&newer), Err(BlobError::UnsupportedVersion(2)));
+        let mut unknown = blob.clone();
+        unknown[5] = 7;
+        assert_eq!(load_blob(&unknown), Err(BlobError::UnknownTarget(7)));
+
+        let other = match Target::host() {
+            Some(Target::X86_64) => Target::Aarch64,
+            _ => Target::X86_64,
+        };
+        assert_eq!(
+            unsafe { run_blob(&save_blob(&[0xc3], other)) },
+            Err(BlobError::WrongTarget {
+                expected: Target::host(),
+                found: other
+            })
+        );
+    }
+}
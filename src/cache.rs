@@ -0,0 +1,205 @@
+// Keeping compiled programs around, so running the same program again doesn't have to
+// generate its code and allocate executable memory for it a second time.
+
+use crate::{compile, CalcError, CompiledProgram};
+use std::collections::HashMap;
+
+/// Programs compiled with [`compile`], keyed by their program string.
+/// Each program's executable memory stays mapped until it's removed from the cache.
+/// A cache made with [`JitCache::with_capacity`] holds a limited number of programs,
+/// removing the least recently used one to make room for another.
+#[derive(Default)]
+pub struct JitCache {
+    programs: HashMap<String, Entry>,
+    /// The most programs the cache holds, if it's limited
+    capacity: Option<usize>,
+    /// Counts up on every lookup, to order the entries by when they were last used
+    clock: u64,
+    stats: CacheStats,
+}
+
+/// A compiled program in the cache
+struct Entry {
+    program: CompiledProgram,
+    /// The value of the cache's clock when the program was last looked up
+    last_used: u64,
+}
+
+/// How the lookups made through a [`JitCache`] went
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheStats {
+    /// Lookups which found the program already compiled
+    pub hits: u64,
+    /// Lookups which had to compile the program, including ones where compiling failed
+    pub misses: u64,
+    /// Programs removed to make room for others
+    pub evictions: u64,
+}
+
+impl JitCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A cache which holds at most `capacity` programs at once.
+    ///
+    /// # Panics
+    /// Panics if `capacity` is zero, as there would be nowhere to put a program
+    /// while it's being used.
+    pub fn with_capacity(capacity: usize) -> Self {
+        assert!(capacity > 0, "a cache must be able to hold a program");
+        JitCache {
+            capacity: Some(capacity),
+            ..Self::default()
+        }
+    }
+
+    /// Run a program, compiling it first if it isn't already in the cache
+    pub fn run(&mut self, program: &str) -> Result<i64, CalcError> {
+        Ok(self.get_or_compile(program)?.call())
+    }
+
+    /// The compiled program for a program string, compiling it first if it isn't
+    /// already in the cache. Programs which fail to compile aren't cached.
+    pub fn get_or_compile(&mut self, program: &str) -> Result<&CompiledProgram, CalcError> {
+        self.clock += 1;
+        if let Some(entry) = self.programs.get_mut(program) {
+            self.stats.hits += 1;
+            entry.last_used = self.clock;
+        } else {
+            self.stats.misses += 1;
+            let compiled = compile(program)?;
+            if self.capacity.is_some_and(|capacity| self.len() >= capacity) {
+                self.evict();
+            }
+            let entry = Entry {
+                program: compiled,
+                last_used: self.clock,
+            };
+            self.programs.insert(program.to_string(), entry);
+        }
+        Ok(&self.programs[program].program)
+    }
+
+    /// Remove the least recently used program
+    fn evict(&mut self) {
+        let oldest = self
+            .programs
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(program, _)| program.clone());
+        if let Some(program) = oldest {
+            self.programs.remove(&program);
+            self.stats.evictions += 1;
+        }
+    }
+
+    /// How the lookups made so far went
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Whether a program is in the cache, without counting as a use of it
+    pub fn contains(&self, program: &str) -> bool {
+        self.programs.contains_key(program)
+    }
+
+    /// How many programs are in the cache
+    pub fn len(&self) -> usize {
+        self.programs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.programs.is_empty()
+    }
+
+    /// Remove every program from the cache, releasing their memory.
+    /// The stats are kept.
+    pub fn clear(&mut self) {
+        self.programs.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{compile, CacheStats, CalcError, JitCache, JitError};
+
+    #[test]
+    fn test_cache() {
+        let mut cache = JitCache::new();
+        assert!(cache.is_empty());
+        assert_eq!(cache.run("++*").unwrap(), 4);
+        assert_eq!(cache.run("++*").unwrap(), 4);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(
+            cache.run("+8(*)").unwrap(),
+            compile("+8(*)").unwrap().call()
+        );
+        assert_eq!(cache.len(), 2);
+
+        // The same function is called each time
+        let first = cache.get_or_compile("++*").unwrap() as *const _;
+        let second = cache.get_or_compile("++*").unwrap() as *const _;
+        assert_eq!(first, second);
+
+        assert!(matches!(
+            cache.run("+x"),
+            Err(CalcError::Jit(JitError::UnknownCharacter { .. }))
+        ));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 3,
+                misses: 3,
+                evictions: 0
+            }
+        );
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_cache_eviction() {
+        let mut cache = JitCache::with_capacity(2);
+        assert_eq!(cache.run("+").unwrap(), 1);
+        assert_eq!(cache.run("++").unwrap(), 2);
+        // Using `+` again makes `++` the least recently used
+        assert_eq!(cache.run("+").unwrap(), 1);
+        assert_eq!(cache.run("+++").unwrap(), 3);
+        assert_eq!(cache.len(), 2);
+        assert!(cache.contains("+") && cache.contains("+++"));
+        assert!(!cache.contains("++"));
+
+        // `++` is compiled again, this time pushing out `+`
+        assert_eq!(cache.run("++").unwrap(), 2);
+        assert!(!cache.contains("+"));
+        assert_eq!(cache.run("+++").unwrap(), 3);
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 2,
+                misses: 4,
+                evictions: 2
+            }
+        );
+
+        // A program which fails to compile doesn't push anything out
+        assert!(cache.run("+x").is_err());
+        assert!(cache.contains("++") && cache.contains("+++"));
+        assert_eq!(cache.stats().evictions, 2);
+
+        let mut cache = JitCache::with_capacity(1);
+        for _ in 0..3 {
+            assert_eq!(cache.run("--*").unwrap(), -4);
+            assert_eq!(cache.run("++*").unwrap(), 4);
+        }
+        assert_eq!(cache.stats().evictions, 5);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_zero_capacity() {
+        let _ = JitCache::with_capacity(0);
+    }
+}
@@ -0,0 +1,264 @@
+// Helpers for inspecting generated machine code
+
+use crate::{x86_64, Op};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::ops::Range;
+
+/// The bytes of machine code which were emitted for an operation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub bytes: Range<usize>,
+    pub op: Op,
+}
+
+/// Format bytes as space separated two digit hex, e.g. `48 ff c1`
+pub fn hex_dump(machine_code: &[u8]) -> String {
+    let bytes: Vec<String> = machine_code.iter().map(|b| format!("{b:02x}")).collect();
+    bytes.join(" ")
+}
+
+/// Format bytes the way `hexdump -C` does, 16 to a line: the offset of the line, the bytes
+/// in hex with a gap after the eighth, and an ASCII gutter showing the printable ones.
+///
+/// ```text
+/// 00000000  48 31 c0 48 ff c0 c3                              |H1.H...|
+/// ```
+pub fn format_hex(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for (row, chunk) in bytes.chunks(16).enumerate() {
+        write!(out, "{:08x} ", row * 16).unwrap();
+        for i in 0..16 {
+            if i == 8 {
+                out.push(' ');
+            }
+            match chunk.get(i) {
+                Some(byte) => write!(out, " {byte:02x}").unwrap(),
+                None => out.push_str("   "),
+            }
+        }
+        let ascii: String = chunk
+            .iter()
+            .map(|&byte| match byte {
+                b' '..=b'~' => byte as char,
+                _ => '.',
+            })
+            .collect();
+        writeln!(out, "  |{ascii}|").unwrap();
+    }
+    out
+}
+
+/// Compile a program with [`jit`](crate::jit) and write the machine code to `path` as a
+/// flat binary, with no headers around it, so it can be disassembled with other tools:
+///
+/// ```sh
+/// objdump -b binary -m i386:x86-64 -D file.bin
+/// ```
+///
+/// The code is for the host, so that's `-m aarch64` on AArch64. A program which doesn't
+/// compile is an [`InvalidInput`](std::io::ErrorKind::InvalidInput) error.
+#[cfg(feature = "std")]
+pub fn write_code(program: &str, path: &std::path::Path) -> std::io::Result<()> {
+    let machine_code = crate::jit(program)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    std::fs::write(path, machine_code)
+}
+
+/// Format machine code as a hex dump with one line per operation, using the side table
+/// produced by [`jit_with_spans`](crate::jit_with_spans). Each line holds the byte offset,
+/// the bytes and the operation which emitted them. The bytes before the first operation
+/// and after the last one are the prologue and epilogue.
+pub fn annotate(machine_code: &[u8], spans: &[Span]) -> String {
+    let body_start = spans.first().map_or(0, |span| span.bytes.start);
+    let body_end = spans.last().map_or(0, |span| span.bytes.end);
+
+    let mut chunks: Vec<(Range<usize>, String)> = Vec::new();
+    chunks.push((0..body_start, "prologue".to_string()));
+    for span in spans {
+        chunks.push((span.bytes.clone(), format!("{:?}", span.op)));
+    }
+    chunks.push((body_end..machine_code.len(), "epilogue".to_string()));
+
+    let width = chunks
+        .iter()
+        .map(|(span, _)| hex_dump(&machine_code[span.clone()]).len())
+        .max()
+        .unwrap_or(0);
+
+    let mut out = String::new();
+    for (span, label) in chunks {
+        if span.is_empty() {
+            continue;
+        }
+        let bytes = hex_dump(&machine_code[span.clone()]);
+        writeln!(out, "{:04x}:  {bytes:width$}  {label}", span.start).unwrap();
+    }
+    out
+}
+
+/// Disassemble x86_64 machine code generated by this crate, writing one instruction per
+/// line: its byte offset, its bytes, and the instruction in AT&T syntax, the way
+/// [`to_assembly`](crate::to_assembly) writes it. Only the instructions the compiler
+/// emits are understood, and any other byte is shown as `db 0x..` instead. Data, such as
+/// the literal pool after the code from [`jit_float`](crate::jit_float), comes out as
+/// whichever of the two it happens to look like.
+pub fn disassemble(machine_code: &[u8]) -> String {
+    let mut lines: Vec<(usize, usize, String)> = Vec::new();
+    let mut offset = 0;
+    while offset < machine_code.len() {
+        let (len, text) = match x86_64::decode(&machine_code[offset..]) {
+            Some((inst, len)) => (len, inst.to_string()),
+            None => (1, format!("db 0x{:02x}", machine_code[offset])),
+        };
+        lines.push((offset, len, text));
+        offset += len;
+    }
+
+    let width = lines
+        .iter()
+        .map(|(_, len, _)| len * 3 - 1)
+        .max()
+        .unwrap_or(0);
+    let mut out = String::new();
+    for (offset, len, text) in lines {
+        let bytes = hex_dump(&machine_code[offset..offset + len]);
+        writeln!(out, "{offset:04x}:  {bytes:width$}  {text}").unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        annotate, disassemble, format_hex, hex_dump, jit, jit_with_options, jit_with_spans,
+        write_code, JitError, Op, Options, Target,
+    };
+
+    #[test]
+    fn test_hex_dump() {
+        assert_eq!(hex_dump(&[]), "");
+        assert_eq!(hex_dump(&[0x00, 0x0f, 0xff]), "00 0f ff");
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            hex_dump(&jit("+").unwrap()),
+            "48 31 c9 48 ff c1 48 89 c8 c3"
+        );
+    }
+
+    #[test]
+    fn test_format_hex() {
+        assert_eq!(format_hex(&[]), "");
+        assert_eq!(
+            format_hex(&[0x48, 0x31, 0xc0, 0x48, 0xff, 0xc0, 0xc3]),
+            "00000000  48 31 c0 48 ff c0 c3                              |H1.H...|\n"
+        );
+        let bytes: Vec<u8> = (0x1f..0x41).collect();
+        assert_eq!(
+            format_hex(&bytes),
+            "00000000  1f 20 21 22 23 24 25 26  27 28 29 2a 2b 2c 2d 2e  |. !\"#$%&'()*+,-.|
+00000010  2f 30 31 32 33 34 35 36  37 38 39 3a 3b 3c 3d 3e  |/0123456789:;<=>|
+00000020  3f 40                                             |?@|
+"
+        );
+    }
+
+    #[test]
+    fn test_write_code() {
+        let path = std::env::temp_dir().join(format!("write_code_{}.bin", std::process::id()));
+        for program in ["+", "++*-", "3[+?-]", "=7%3"] {
+            write_code(program, &path).unwrap();
+            assert_eq!(
+                std::fs::read(&path).unwrap(),
+                jit(program).unwrap(),
+                "{program}"
+            );
+        }
+        let error = write_code("+x", &path).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(matches!(
+            error.get_ref().unwrap().downcast_ref(),
+            Some(JitError::UnknownCharacter { index: 1, .. })
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_spans() {
+        let (machine_code, spans) = jit_with_spans("+*").unwrap();
+        assert_eq!(machine_code, jit("+*").unwrap());
+        let ops: Vec<Op> = spans.iter().map(|span| span.op).collect();
+        assert_eq!(ops, [Op::Plus, Op::Star]);
+        assert_eq!(spans[0].bytes.end, spans[1].bytes.start);
+
+        let listing = annotate(&machine_code, &spans);
+        let labels: Vec<&str> = listing
+            .lines()
+            .map(|line| line.rsplit(' ').next().unwrap())
+            .collect();
+        assert_eq!(labels, ["prologue", "Plus", "Star", "epilogue"]);
+
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            listing,
+            "0000:  48 31 c9     prologue
+0003:  48 ff c1     Plus
+0006:  48 6b c9 02  Star
+000a:  48 89 c8 c3  epilogue
+"
+        );
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let options = Options {
+            target: Target::X86_64,
+            ..Options::default()
+        };
+        assert_eq!(
+            disassemble(&jit_with_options("+-*/", options).unwrap()),
+            "0000:  48 31 c9              xor %rcx, %rcx
+0003:  48 ff c1              inc %rcx
+0006:  48 ff c9              dec %rcx
+0009:  48 6b c9 02           imul $2, %rcx, %rcx
+000d:  48 89 c8              mov %rcx, %rax
+0010:  49 c7 c0 02 00 00 00  mov $2, %r8
+0017:  48 99                 cqto
+0019:  49 f7 f8              idivq %r8
+001c:  48 89 c1              mov %rax, %rcx
+001f:  48 89 c8              mov %rcx, %rax
+0022:  c3                    ret
+"
+        );
+        assert_eq!(
+            disassemble(&jit_with_options("2[+?-]", options).unwrap()),
+            "0000:  48 31 c9              xor %rcx, %rcx
+0003:  49 c7 c1 02 00 00 00  mov $2, %r9
+000a:  48 ff c1              inc %rcx
+000d:  48 85 c9              test %rcx, %rcx
+0010:  0f 84 03 00 00 00     {disp32} jz .+9
+0016:  48 ff c9              dec %rcx
+0019:  49 ff c9              dec %r9
+001c:  75 ec                 jnz .-18
+001e:  48 89 c8              mov %rcx, %rax
+0021:  c3                    ret
+"
+        );
+        assert_eq!(disassemble(&[]), "");
+        // Bytes which aren't an instruction the compiler emits, including one cut short
+        assert_eq!(
+            disassemble(&[0x0f, 0x0b, 0xc3, 0x48, 0xff]),
+            "0000:  0f  db 0x0f
+0001:  0b  db 0x0b
+0002:  c3  ret
+0003:  48  db 0x48
+0004:  ff  db 0xff
+"
+        );
+        // The literal pool after floating point code isn't made of instructions
+        #[cfg(target_arch = "x86_64")]
+        assert!(disassemble(&crate::jit_float("+").unwrap()).ends_with("  db 0x3f\n"));
+    }
+}
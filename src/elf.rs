@@ -0,0 +1,332 @@
+// Wrapping machine code in an ELF relocatable object file, so it can be linked into
+// other programs. Only the handful of sections a linker needs are written:
+// the code, a symbol table naming it, and the string tables those refer to.
+
+use crate::{jit, JitError, Target};
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Size of the ELF header
+const HEADER_SIZE: usize = 64;
+/// Size of a section header
+const SECTION_HEADER_SIZE: usize = 64;
+/// Size of a symbol table entry
+const SYMBOL_SIZE: usize = 24;
+
+// Section types
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+
+// Section flags
+const SHF_ALLOC: u64 = 0x2;
+const SHF_EXECINSTR: u64 = 0x4;
+
+/// Compile a program with [`jit`] and wrap it in an ELF relocatable object (a `.o` file)
+/// for the host architecture. The object exports one global function named `symbol`,
+/// which can be called from C as `int64_t symbol(void)` after linking the object in.
+///
+/// # Panics
+/// Panics if `symbol` is empty or contains a NUL byte, as it couldn't be named from C.
+pub fn emit_object(program: &str, symbol: &str) -> Result<Vec<u8>, JitError> {
+    assert!(
+        !symbol.is_empty() && !symbol.contains('\0'),
+        "invalid symbol name {symbol:?}"
+    );
+    let machine_code = jit(program)?;
+    Ok(object(&machine_code, symbol, Target::default()))
+}
+
+/// Build the object file. The code needs no relocations, as it never refers to
+/// anything outside of itself.
+fn object(machine_code: &[u8], symbol: &str, target: Target) -> Vec<u8> {
+    let machine: u16 = match target {
+        Target::X86_64 => 62,
+        Target::Aarch64 => 183,
+    };
+
+    // Section names, and the offset of each within the section name table
+    let mut shstrtab = vec![0];
+    let mut name = |name: &str| {
+        let offset = shstrtab.len() as u32;
+        shstrtab.extend_from_slice(name.as_bytes());
+        shstrtab.push(0);
+        offset
+    };
+    let text_name = name(".text");
+    let symtab_name = name(".symtab");
+    let strtab_name = name(".strtab");
+    let shstrtab_name = name(".shstrtab");
+    // Tells the linker the code doesn't need an executable stack
+    let stack_name = name(".note.GNU-stack");
+
+    // The symbol name is the only entry in the string table
+    let mut strtab = vec![0];
+    strtab.extend_from_slice(symbol.as_bytes());
+    strtab.push(0);
+
+    // The first symbol is always the null symbol
+    let mut symtab = vec![0; SYMBOL_SIZE];
+    // st_name: offset in the string table
+    symtab.extend_from_slice(&1u32.to_le_bytes());
+    // st_info: a global (1) function (2)
+    symtab.push(0x12);
+    // st_other: default visibility
+    symtab.push(0);
+    // st_shndx: defined in .text, the first section after the null section
+    symtab.extend_from_slice(&1u16.to_le_bytes());
+    // st_value: the function starts at the beginning of .text
+    symtab.extend_from_slice(&0u64.to_le_bytes());
+    // st_size
+    symtab.extend_from_slice(&(machine_code.len() as u64).to_le_bytes());
+
+    // Lay the section contents out one after another, after the ELF header
+    let mut body = Vec::new();
+    let mut place = |data: &[u8], align: usize| {
+        while !(HEADER_SIZE + body.len()).is_multiple_of(align) {
+            body.push(0);
+        }
+        let offset = (HEADER_SIZE + body.len()) as u64;
+        body.extend_from_slice(data);
+        offset
+    };
+    let text_offset = place(machine_code, 16);
+    let symtab_offset = place(&symtab, 8);
+    let strtab_offset = place(&strtab, 1);
+    let shstrtab_offset = place(&shstrtab, 1);
+    let section_headers_offset = place(&[], 8);
+
+    let sections = [
+        // The null section
+        SectionHeader::default(),
+        SectionHeader {
+            name: text_name,
+            kind: SHT_PROGBITS,
+            flags: SHF_ALLOC | SHF_EXECINSTR,
+            offset: text_offset,
+            size: machine_code.len() as u64,
+            align: 16,
+            ..SectionHeader::default()
+        },
+        SectionHeader {
+            name: symtab_name,
+            kind: SHT_SYMTAB,
+            offset: symtab_offset,
+            size: symtab.len() as u64,
+            // The string table holding the symbol names
+            link: 3,
+            // The index of the first global symbol
+            info: 1,
+            align: 8,
+            entry_size: SYMBOL_SIZE as u64,
+            ..SectionHeader::default()
+        },
+        SectionHeader {
+            name: strtab_name,
+            kind: SHT_STRTAB,
+            offset: strtab_offset,
+            size: strtab.len() as u64,
+            align: 1,
+            ..SectionHeader::default()
+        },
+        SectionHeader {
+            name: shstrtab_name,
+            kind: SHT_STRTAB,
+            offset: shstrtab_offset,
+            size: shstrtab.len() as u64,
+            align: 1,
+            ..SectionHeader::default()
+        },
+        SectionHeader {
+            name: stack_name,
+            kind: SHT_PROGBITS,
+            offset: section_headers_offset,
+            align: 1,
+            ..SectionHeader::default()
+        },
+    ];
+
+    let mut elf = Vec::new();
+    // e_ident: magic number, 64-bit, little endian, version 1, System V ABI
+    elf.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+    elf.extend_from_slice(&[0; 8]);
+    // e_type: relocatable
+    elf.extend_from_slice(&1u16.to_le_bytes());
+    elf.extend_from_slice(&machine.to_le_bytes());
+    // e_version
+    elf.extend_from_slice(&1u32.to_le_bytes());
+    // e_entry and e_phoff: relocatable objects have no entry point or program headers
+    elf.extend_from_slice(&0u64.to_le_bytes());
+    elf.extend_from_slice(&0u64.to_le_bytes());
+    // e_shoff
+    elf.extend_from_slice(&section_headers_offset.to_le_bytes());
+    // e_flags
+    elf.extend_from_slice(&0u32.to_le_bytes());
+    // e_ehsize, e_phentsize, e_phnum
+    elf.extend_from_slice(&(HEADER_SIZE as u16).to_le_bytes());
+    elf.extend_from_slice(&0u16.to_le_bytes());
+    elf.extend_from_slice(&0u16.to_le_bytes());
+    // e_shentsize, e_shnum
+    elf.extend_from_slice(&(SECTION_HEADER_SIZE as u16).to_le_bytes());
+    elf.extend_from_slice(&(sections.len() as u16).to_le_bytes());
+    // e_shstrndx: the section holding the section names
+    elf.extend_from_slice(&4u16.to_le_bytes());
+
+    elf.extend_from_slice(&body);
+    for section in &sections {
+        section.write(&mut elf);
+    }
+    elf
+}
+
+/// The fields of an ELF section header which this module uses
+#[derive(Default)]
+struct SectionHeader {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    align: u64,
+    entry_size: u64,
+}
+
+impl SectionHeader {
+    fn write(&self, elf: &mut Vec<u8>) {
+        elf.extend_from_slice(&self.name.to_le_bytes());
+        elf.extend_from_slice(&self.kind.to_le_bytes());
+        elf.extend_from_slice(&self.flags.to_le_bytes());
+        // sh_addr: sections aren't loaded at any particular address
+        elf.extend_from_slice(&0u64.to_le_bytes());
+        elf.extend_from_slice(&self.offset.to_le_bytes());
+        elf.extend_from_slice(&self.size.to_le_bytes());
+        elf.extend_from_slice(&self.link.to_le_bytes());
+        elf.extend_from_slice(&self.info.to_le_bytes());
+        elf.extend_from_slice(&self.align.to_le_bytes());
+        elf.extend_from_slice(&self.entry_size.to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::elf::object;
+    use crate::{emit_object, jit, Target};
+    use object::{Architecture, Object, ObjectSection, ObjectSymbol, SectionKind, SymbolKind};
+
+    #[test]
+    fn test_emit_object() {
+        let elf = emit_object("++*", "calculate").unwrap();
+        let machine_code = jit("++*").unwrap();
+
+        let file = object::File::parse(&*elf).unwrap();
+        assert_eq!(file.kind(), object::ObjectKind::Relocatable);
+        let symbol = file.symbol_by_name("calculate").unwrap();
+        assert!(symbol.is_global());
+        assert_eq!(symbol.kind(), SymbolKind::Text);
+        assert_eq!(symbol.address(), 0);
+        assert_eq!(symbol.size(), machine_code.len() as u64);
+
+        let text = file
+            .section_by_index(symbol.section_index().unwrap())
+            .unwrap();
+        assert_eq!(text.name(), Ok(".text"));
+        assert_eq!(text.kind(), SectionKind::Text);
+        assert_eq!(text.data(), Ok(&machine_code[..]));
+        assert_eq!(
+            file.section_by_name(".text").unwrap().relocations().count(),
+            0
+        );
+    }
+
+    /// Read the header fields back by hand, without going through the `object` crate
+    #[test]
+    fn test_object_header() {
+        let machine_code = jit("++*").unwrap();
+        let elf = object(&machine_code, "calc", Target::X86_64);
+        let u16_at = |i: usize| u16::from_le_bytes(elf[i..i + 2].try_into().unwrap()) as usize;
+        let u32_at = |i: usize| u32::from_le_bytes(elf[i..i + 4].try_into().unwrap()) as usize;
+        let u64_at = |i: usize| u64::from_le_bytes(elf[i..i + 8].try_into().unwrap()) as usize;
+
+        assert_eq!(elf[..8], [0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+        // e_type, e_machine, e_version
+        assert_eq!((u16_at(16), u16_at(18), u32_at(20)), (1, 62, 1));
+        // e_ehsize, e_shentsize
+        assert_eq!((u16_at(52), u16_at(58)), (64, 64));
+        let section_headers = u64_at(40);
+        let sections = u16_at(60);
+        assert_eq!(elf.len(), section_headers + sections * 64);
+
+        let header = |i: usize| section_headers + i * 64;
+        let shstrtab = header(u16_at(62));
+        let names = &elf[u64_at(shstrtab + 24)..][..u64_at(shstrtab + 32)];
+        let name = |i: usize| {
+            let start = u32_at(header(i));
+            let len = names[start..].iter().position(|&b| b == 0).unwrap();
+            core::str::from_utf8(&names[start..start + len]).unwrap()
+        };
+        let text = (0..sections).find(|&i| name(i) == ".text").unwrap();
+        // sh_type and sh_flags
+        assert_eq!(
+            (u32_at(header(text) + 4), u64_at(header(text) + 8)),
+            (1, 0x6)
+        );
+        let offset = u64_at(header(text) + 24);
+        let size = u64_at(header(text) + 32);
+        assert_eq!(elf[offset..offset + size], machine_code);
+        assert!((0..sections).any(|i| name(i) == ".symtab"));
+    }
+
+    /// Link the object into a C program and call it. Skipped without a C compiler.
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    #[test]
+    fn test_link_object() {
+        use std::process::Command;
+
+        if Command::new("cc").arg("--version").output().is_err() {
+            eprintln!("skipping, cc isn't installed");
+            return;
+        }
+        let dir = std::env::temp_dir().join(format!("emit_object_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("calc.o"), emit_object("+++*-", "calc").unwrap()).unwrap();
+        std::fs::write(
+            dir.join("main.c"),
+            "#include <stdint.h>\n#include <stdio.h>\nint64_t calc(void);\n\
+             int main(void) { printf(\"%lld\\n\", (long long)calc()); }\n",
+        )
+        .unwrap();
+        let status = Command::new("cc")
+            .current_dir(&dir)
+            .args(["main.c", "calc.o", "-o", "main"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let output = Command::new(dir.join("main")).output().unwrap();
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "5\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_object_architecture() {
+        /// Tester function
+        fn t(target: Target) -> Architecture {
+            let elf = object(&[0xc3], "f", target);
+            object::File::parse(&*elf).unwrap().architecture()
+        }
+
+        assert_eq!(t(Target::X86_64), Architecture::X86_64);
+        assert_eq!(t(Target::Aarch64), Architecture::Aarch64);
+        assert!(emit_object("++x", "f").is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_invalid_symbol() {
+        let _ = emit_object("+", "a\0b");
+    }
+}
@@ -0,0 +1,103 @@
+// The machine code the x86_64 backend generates for each operation with the default
+// options, for other backends and tests to check their own code against. The backend
+// itself builds its code from the instructions in x86_64.rs, and the tests below check
+// these constants match what it generates.
+
+/// `xor %rcx, %rcx`, starting the working register at zero
+pub const PROLOGUE: [u8; 3] = [0x48, 0x31, 0xc9];
+/// `mov %rcx, %rax` and `ret`, returning the working register
+pub const EPILOGUE: [u8; 4] = [0x48, 0x89, 0xc8, 0xc3];
+
+/// `+`: `inc %rcx`
+pub const OP_PLUS: [u8; 3] = [0x48, 0xff, 0xc1];
+/// `-`: `dec %rcx`
+pub const OP_MINUS: [u8; 3] = [0x48, 0xff, 0xc9];
+/// `*`: `imul $2, %rcx, %rcx`
+pub const OP_STAR: [u8; 4] = [0x48, 0x6b, 0xc9, 0x02];
+/// `/`: `mov %rcx, %rax`, `mov $2, %r8`, `cqto`, `idivq %r8` and `mov %rax, %rcx`,
+/// as `idiv` divides rdx:rax
+pub const OP_SLASH: [u8; 18] = [
+    0x48, 0x89, 0xc8, 0x49, 0xc7, 0xc0, 0x02, 0x00, 0x00, 0x00, 0x48, 0x99, 0x49, 0xf7, 0xf8, 0x48,
+    0x89, 0xc1,
+];
+/// `%`: the same division as [`OP_SLASH`], keeping the remainder with `mov %rdx, %rcx`
+pub const OP_PERCENT: [u8; 18] = [
+    0x48, 0x89, 0xc8, 0x49, 0xc7, 0xc0, 0x02, 0x00, 0x00, 0x00, 0x48, 0x99, 0x49, 0xf7, 0xf8, 0x48,
+    0x89, 0xd1,
+];
+/// `^`: `imul %rcx, %rcx`
+pub const OP_CARET: [u8; 4] = [0x48, 0x0f, 0xaf, 0xc9];
+/// `~`: `neg %rcx`
+pub const OP_TILDE: [u8; 3] = [0x48, 0xf7, 0xd9];
+/// `0`: `xor %rcx, %rcx`, the same as the prologue
+pub const OP_ZERO: [u8; 3] = PROLOGUE;
+/// `<`: `shl %rcx`
+pub const OP_LESS: [u8; 3] = [0x48, 0xd1, 0xe1];
+/// `>`: `sar %rcx`
+pub const OP_GREATER: [u8; 3] = [0x48, 0xd1, 0xf9];
+/// `|`: `mov %rcx, %rax`, `sar $63, %rax`, `xor %rax, %rcx` and `sub %rax, %rcx`
+pub const OP_BAR: [u8; 13] = [
+    0x48, 0x89, 0xc8, 0x48, 0xc1, 0xf8, 0x3f, 0x48, 0x31, 0xc1, 0x48, 0x29, 0xc1,
+];
+/// `_`: `xor %rax, %rax`, `test %rcx, %rcx` and `cmovs %rax, %rcx`
+pub const OP_CLAMP: [u8; 10] = [0x48, 0x31, 0xc0, 0x48, 0x85, 0xc9, 0x48, 0x0f, 0x48, 0xc8];
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        codegen, jit_with_options, Op, Options, Start, Target, EPILOGUE, OP_BAR, OP_CARET,
+        OP_CLAMP, OP_GREATER, OP_LESS, OP_MINUS, OP_PERCENT, OP_PLUS, OP_SLASH, OP_STAR, OP_TILDE,
+        OP_ZERO, PROLOGUE,
+    };
+
+    #[test]
+    fn test_encodings_match_backend() {
+        let options = Options {
+            target: Target::X86_64,
+            ..Options::default()
+        };
+        let encodings: [(Op, &[u8]); 12] = [
+            (Op::Plus, &OP_PLUS),
+            (Op::Minus, &OP_MINUS),
+            (Op::Star, &OP_STAR),
+            (Op::Slash, &OP_SLASH),
+            (Op::Percent, &OP_PERCENT),
+            (Op::Caret, &OP_CARET),
+            (Op::Tilde, &OP_TILDE),
+            (Op::Zero, &OP_ZERO),
+            (Op::Less, &OP_LESS),
+            (Op::Greater, &OP_GREATER),
+            (Op::Bar, &OP_BAR),
+            (Op::Clamp, &OP_CLAMP),
+        ];
+        for (op, encoding) in encodings {
+            let expected = [&PROLOGUE[..], encoding, &EPILOGUE].concat();
+            assert_eq!(
+                codegen(&[op], Start::Constant(0), &options).unwrap(),
+                expected,
+                "{op}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_encodings_concatenate() {
+        let options = Options {
+            target: Target::X86_64,
+            ..Options::default()
+        };
+        let expected = [&PROLOGUE[..], &OP_PLUS, &OP_PLUS, &OP_STAR, &EPILOGUE].concat();
+        assert_eq!(jit_with_options("++*", options).unwrap(), expected);
+        let expected = [
+            &PROLOGUE[..],
+            &OP_MINUS,
+            &OP_MINUS,
+            &OP_MINUS,
+            &OP_SLASH,
+            &OP_BAR,
+            &EPILOGUE,
+        ]
+        .concat();
+        assert_eq!(jit_with_options("---/|", options).unwrap(), expected);
+    }
+}
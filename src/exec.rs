@@ -0,0 +1,158 @@
+// Loading machine code into executable memory so it can be called. Memory allocated by
+// a structure like Vec<u8> is almost certainly not executable, so the code can't simply
+// be run from the slice it was generated into.
+//
+// Every backend gets the same steps: copy the code into writable memory, make it
+// executable instead, and flush the instruction cache, which only x86 keeps coherent
+// with the data cache by itself.
+
+#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+use crate::macos;
+use crate::RunError;
+#[cfg(not(all(target_os = "macos", target_arch = "aarch64")))]
+use region::Protection;
+
+/// Memory holding code to run. macOS on Apple Silicon has its own way of mapping it.
+#[cfg(not(all(target_os = "macos", target_arch = "aarch64")))]
+pub(crate) type Memory = region::Allocation;
+#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+pub(crate) type Memory = macos::Allocation;
+
+#[cfg(not(all(target_os = "macos", target_arch = "aarch64")))]
+/// Copy machine code into a freshly allocated region of executable memory.
+/// The code stays valid for as long as the returned allocation is alive.
+pub(crate) fn load(machine_code: &[u8]) -> Result<Memory, RunError> {
+    let mut memory =
+        region::alloc(machine_code.len(), Protection::READ_WRITE).map_err(RunError::Alloc)?;
+    fill(&mut memory, machine_code)?;
+    Ok(memory)
+}
+
+#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+/// Copy machine code into a freshly mapped `MAP_JIT` region.
+/// The code stays valid for as long as the returned allocation is alive.
+pub(crate) fn load(machine_code: &[u8]) -> Result<Memory, RunError> {
+    let mut memory = macos::alloc(machine_code.len())?;
+    macos::fill(&mut memory, machine_code);
+    Ok(memory)
+}
+
+#[cfg(not(all(target_os = "macos", target_arch = "aarch64")))]
+/// Replace the code in memory from [`load`] with other code no longer than it, by making
+/// the memory writable again and then filling it
+pub(crate) fn refill(memory: &mut Memory, machine_code: &[u8]) -> Result<(), RunError> {
+    unsafe { region::protect(memory.as_ptr::<u8>(), memory.len(), Protection::READ_WRITE) }
+        .map_err(RunError::Protect)?;
+    fill(memory, machine_code)
+}
+
+#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+/// Replace the code in memory from [`load`] with other code no longer than it
+pub(crate) fn refill(memory: &mut Memory, machine_code: &[u8]) -> Result<(), RunError> {
+    macos::fill(memory, machine_code);
+    Ok(())
+}
+
+#[cfg(not(all(target_os = "macos", target_arch = "aarch64")))]
+/// Copy machine code to the start of writable memory, and then make it executable
+/// instead. The memory must be at least as long as the code.
+fn fill(memory: &mut Memory, machine_code: &[u8]) -> Result<(), RunError> {
+    let code_len = machine_code.len();
+    let slice = unsafe { std::slice::from_raw_parts_mut(memory.as_mut_ptr::<u8>(), memory.len()) };
+    slice[..code_len].copy_from_slice(machine_code);
+
+    // Swap write permission for execute permission. Memory is never writable and
+    // executable at the same time (W^X). A mapping which is both would let any stray write,
+    // in this process or from an exploit, turn straight into code execution. Hardened systems
+    // (SELinux, macOS's hardened runtime, grsecurity) refuse to create such mappings at all.
+    unsafe {
+        region::protect(
+            memory.as_ptr::<u8>(),
+            memory.len(),
+            Protection::READ_EXECUTE,
+        )
+    }
+    .map_err(RunError::Protect)?;
+
+    // The freshly written code may not be what the CPU fetches until the caches are
+    // synchronised
+    unsafe { flush_icache(memory.as_ptr::<u8>(), code_len) };
+    Ok(())
+}
+
+/// Make the instruction cache see the `len` bytes of code written at `start`, which
+/// must be called between writing code and running it.
+/// x86 keeps its instruction cache coherent with the data cache, so there this does
+/// nothing.
+///
+/// # Safety
+/// The `len` bytes from `start` must be mapped memory.
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+pub(crate) unsafe fn flush_icache(_start: *const u8, _len: usize) {}
+
+/// Make the instruction cache see the `len` bytes of code written at `start`, which
+/// must be called between writing code and running it
+///
+/// # Safety
+/// The `len` bytes from `start` must be mapped memory.
+#[cfg(all(
+    target_os = "macos",
+    not(any(target_arch = "x86_64", target_arch = "x86"))
+))]
+pub(crate) unsafe fn flush_icache(start: *const u8, len: usize) {
+    extern "C" {
+        fn sys_icache_invalidate(start: *mut core::ffi::c_void, len: usize);
+    }
+    sys_icache_invalidate(start as *mut core::ffi::c_void, len);
+}
+
+/// Make the instruction cache see the `len` bytes of code written at `start`, which
+/// must be called between writing code and running it. On AArch64 and RISC-V this
+/// cleans the data cache and invalidates the instruction cache over the range, through
+/// a system call where the CPU doesn't allow it from user space.
+///
+/// # Safety
+/// The `len` bytes from `start` must be mapped memory.
+#[cfg(not(any(target_arch = "x86_64", target_arch = "x86", target_os = "macos")))]
+pub(crate) unsafe fn flush_icache(start: *const u8, len: usize) {
+    extern "C" {
+        // Provided by libgcc and compiler-rt.
+        fn __clear_cache(start: *mut core::ffi::c_char, end: *mut core::ffi::c_char);
+    }
+    let start = start as *mut core::ffi::c_char;
+    __clear_cache(start, start.add(len));
+}
+
+#[cfg(test)]
+mod test {
+    use crate::exec::flush_icache;
+
+    /// Flushing works on any mapped memory, including memory which isn't executable
+    #[test]
+    fn test_flush_icache() {
+        let code = [0xc3u8; 64];
+        unsafe {
+            flush_icache(code.as_ptr(), code.len());
+            flush_icache(code.as_ptr(), 0);
+            flush_icache(code[1..].as_ptr(), 3);
+        }
+    }
+
+    #[test]
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    fn test_load_and_refill() {
+        use crate::exec::{load, refill};
+        use crate::jit;
+
+        type Executable = unsafe extern "C" fn() -> i64;
+        let mut memory = load(&jit("+8(*)").unwrap()).unwrap();
+        let f: Executable = unsafe { std::mem::transmute(memory.as_ptr::<u8>()) };
+        assert_eq!(unsafe { f() }, 256);
+        // Shorter code in the same memory runs instead of what was there before
+        for (p, result) in [("++*", 4), ("-", -1), ("+++", 3), ("--*", -4)] {
+            refill(&mut memory, &jit(p).unwrap()).unwrap();
+            let f: Executable = unsafe { std::mem::transmute(memory.as_ptr::<u8>()) };
+            assert_eq!(unsafe { f() }, result, "{p}");
+        }
+    }
+}
@@ -0,0 +1,574 @@
+// Front ends for writing programs the usual way: infix like `(1+2)*3-4/2`, or reverse
+// Polish notation like `3 4 + 2 *`. Infix expressions are parsed by precedence climbing
+// into a tree of literals and operators, which is flattened into steps for a stack
+// machine. Reverse Polish notation already is those steps, more or less. Either way the
+// steps are then compiled by the backends.
+//
+// The working register holds the value computed so far. The left side of an operator
+// is pushed onto the machine stack while its right side is worked out, and popped
+// again to combine the two, so subexpressions can be nested as deeply as the parser allows.
+
+use crate::{line_column, JitError, ReturnType, Target, MAX_PAREN_DEPTH, MAX_STACK_DEPTH};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::iter::Peekable;
+use core::str::CharIndices;
+
+/// A binary operator in an expression
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl BinOp {
+    fn from_char(c: char) -> Option<BinOp> {
+        match c {
+            '+' => Some(BinOp::Add),
+            '-' => Some(BinOp::Sub),
+            '*' => Some(BinOp::Mul),
+            '/' => Some(BinOp::Div),
+            _ => None,
+        }
+    }
+
+    /// How tightly the operator binds. Operators with the same precedence are applied
+    /// from left to right.
+    fn precedence(self) -> u8 {
+        match self {
+            BinOp::Add | BinOp::Sub => 1,
+            BinOp::Mul | BinOp::Div => 2,
+        }
+    }
+}
+
+/// A node of an expression's tree. The nodes are kept in a list rather than boxed, and
+/// refer to their operands by their position in it, so long expressions such as
+/// `1+1+1+...` don't need a deep recursion to lower or drop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Node {
+    Literal(i64),
+    Negate(usize),
+    Binary(BinOp, usize, usize),
+}
+
+/// A step of the stack machine an expression is lowered to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Step {
+    /// Set the working register to a literal
+    Load(i64),
+    /// Push the working register onto the stack
+    Push,
+    /// Pop the left operand off the stack, and combine it with the working register
+    /// (the right operand), leaving the result in the working register
+    Apply(BinOp),
+    /// Negate the working register
+    Negate,
+}
+
+/// Compile an infix expression such as `(1+2)*3-4/2` into machine code for the host
+/// architecture, in the same form as [`jit`](crate::jit): `int64_t f()`.
+///
+/// Expressions are made of decimal numbers (up to `i32::MAX`), `+`, `-`, `*` and `/`,
+/// with the usual precedence, parentheses, and unary minus. Arithmetic wraps around on
+/// overflow, and division rounds towards zero. Dividing by a literal zero is rejected,
+/// but dividing by something which works out to zero does whatever the CPU does: AArch64
+/// gives zero, whereas x86_64 raises an exception (which kills the process).
+pub fn jit_expr(expression: &str) -> Result<Vec<u8>, JitError> {
+    Ok(compile(&lower(&parse(expression)?)))
+}
+
+/// Compile an expression in reverse Polish notation such as `3 4 + 2 *` into machine
+/// code for the host architecture, in the same form as [`jit`](crate::jit): `int64_t f()`.
+///
+/// The expression is made of numbers (up to `i32::MAX`, with a `-` in front for negative
+/// ones) and the operators `+`, `-`, `*` and `/`, separated by whitespace. Numbers are
+/// pushed onto a stack, and each operator pops two and pushes the result. The arithmetic
+/// is the same as [`jit_expr`]. All of this is checked when compiling: every operator
+/// must have two values to work on, and exactly one value, the result, must be left at
+/// the end. Errors about these say which token (counting from 0) went wrong.
+pub fn jit_rpn(expression: &str) -> Result<Vec<u8>, JitError> {
+    Ok(compile(&parse_rpn(expression)?))
+}
+
+/// Compile the steps of an expression, returning the working register once they're done
+fn compile(steps: &[Step]) -> Vec<u8> {
+    let target = Target::default();
+    let mut machine_code = Vec::new();
+    for step in steps {
+        target.emit_step(&mut machine_code, step);
+    }
+    target.epilogue(&mut machine_code, ReturnType::I64);
+    machine_code
+}
+
+/// Parse an expression into its nodes. Operands come before the nodes using them,
+/// so the last node is the root.
+pub(crate) fn parse(expression: &str) -> Result<Vec<Node>, JitError> {
+    let mut parser = Parser {
+        source: expression,
+        chars: expression.char_indices().peekable(),
+        nodes: Vec::new(),
+        depth: 0,
+    };
+    if parser.peek().is_none() {
+        return Err(JitError::EmptyProgram);
+    }
+    parser.expression(0)?;
+    match parser.peek() {
+        None => Ok(parser.nodes),
+        Some((index, ')')) => Err(JitError::UnbalancedBracket { index }),
+        Some((index, c)) => Err(parser.unexpected(index, c)),
+    }
+}
+
+/// Flatten the nodes of an expression into steps. As the nodes are in the order their
+/// values are needed, it's just a matter of pushing each left operand once it's computed.
+pub(crate) fn lower(nodes: &[Node]) -> Vec<Step> {
+    let mut left = vec![false; nodes.len()];
+    for node in nodes {
+        if let Node::Binary(_, l, _) = node {
+            left[*l] = true;
+        }
+    }
+    let mut steps = Vec::new();
+    for (node, left) in nodes.iter().zip(left) {
+        steps.push(match node {
+            Node::Literal(n) => Step::Load(*n),
+            Node::Negate(_) => Step::Negate,
+            Node::Binary(op, _, _) => Step::Apply(*op),
+        });
+        if left {
+            steps.push(Step::Push);
+        }
+    }
+    steps
+}
+
+/// Turn an expression in reverse Polish notation into steps. The top of the stack
+/// lives in the working register, so only the values under it are on the machine stack.
+pub(crate) fn parse_rpn(expression: &str) -> Result<Vec<Step>, JitError> {
+    // The token each value on the stack came from, and whether it's a literal zero
+    let mut stack: Vec<(usize, bool)> = Vec::new();
+    let mut steps = Vec::new();
+    for (token, word) in expression.split_whitespace().enumerate() {
+        let index = word.as_ptr() as usize - expression.as_ptr() as usize;
+        let mut chars = word.chars();
+        if let (Some(op), None) = (chars.next().and_then(BinOp::from_char), chars.next()) {
+            if stack.len() < 2 {
+                return Err(JitError::MissingOperands { token });
+            }
+            let (_, zero) = stack.pop().unwrap();
+            if op == BinOp::Div && zero {
+                return Err(JitError::DivisionByZero { index });
+            }
+            *stack.last_mut().unwrap() = (token, false);
+            steps.push(Step::Apply(op));
+            continue;
+        }
+        let n = rpn_literal(expression, word, index)?;
+        if !stack.is_empty() {
+            if stack.len() > MAX_STACK_DEPTH {
+                return Err(JitError::StackTooDeep { at_op: token });
+            }
+            steps.push(Step::Push);
+        }
+        stack.push((token, n == 0));
+        steps.push(Step::Load(n));
+    }
+    match stack[..] {
+        [] => Err(JitError::EmptyProgram),
+        [_] => Ok(steps),
+        // Point at the value nearest the top which nothing used
+        [.., (token, _), _] => Err(JitError::LeftoverOperands { token }),
+    }
+}
+
+/// Read the number `word`, found at byte offset `index` of `expression`
+fn rpn_literal(expression: &str, word: &str, index: usize) -> Result<i64, JitError> {
+    let digits = word.strip_prefix('-').unwrap_or(word);
+    let start = index + word.len() - digits.len();
+    if let Some((i, c)) = digits.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        let (line, column) = line_column(expression, start + i);
+        return Err(JitError::UnknownCharacter {
+            character: c,
+            index: start + i,
+            line,
+            column,
+        });
+    }
+    match digits.parse::<i32>() {
+        Ok(n) if digits.len() < word.len() => Ok(-(n as i64)),
+        Ok(n) => Ok(n as i64),
+        Err(_) => Err(JitError::OperandOutOfRange { index }),
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    nodes: Vec<Node>,
+    /// How many parentheses are open
+    depth: usize,
+}
+
+impl Parser<'_> {
+    /// The next character other than whitespace, without consuming it
+    fn peek(&mut self) -> Option<(usize, char)> {
+        while self.chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+        self.chars.peek().copied()
+    }
+
+    /// Add a node, returning its position
+    fn push(&mut self, node: Node) -> usize {
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    /// Parse operands joined by operators binding at least as tightly as `min_precedence`.
+    /// Returns the position of the resulting node.
+    fn expression(&mut self, min_precedence: u8) -> Result<usize, JitError> {
+        let mut left = self.operand()?;
+        while let Some((index, op)) = self
+            .peek()
+            .and_then(|(index, c)| Some((index, BinOp::from_char(c)?)))
+        {
+            if op.precedence() < min_precedence {
+                break;
+            }
+            self.chars.next();
+            // Only operators binding more tightly go into the right operand, which
+            // makes operators of the same precedence left associative
+            let right = self.expression(op.precedence() + 1)?;
+            if op == BinOp::Div && self.nodes[right] == Node::Literal(0) {
+                return Err(JitError::DivisionByZero { index });
+            }
+            left = self.push(Node::Binary(op, left, right));
+        }
+        Ok(left)
+    }
+
+    /// Parse a number or a parenthesized expression, with any unary minuses in front
+    fn operand(&mut self) -> Result<usize, JitError> {
+        // Negating twice does nothing, even for `i64::MIN`
+        let mut negate = false;
+        while self.peek().is_some_and(|(_, c)| c == '-') {
+            self.chars.next();
+            negate = !negate;
+        }
+        let operand = match self.peek() {
+            Some((index, '(')) => {
+                if self.depth == MAX_PAREN_DEPTH {
+                    return Err(JitError::ParenTooDeep { index });
+                }
+                self.chars.next();
+                self.depth += 1;
+                let inner = self.expression(0)?;
+                match self.peek() {
+                    Some((_, ')')) => self.chars.next(),
+                    Some((index, c)) => return Err(self.unexpected(index, c)),
+                    None => return Err(JitError::UnbalancedBracket { index }),
+                };
+                self.depth -= 1;
+                inner
+            }
+            Some((index, c)) if c.is_ascii_digit() => {
+                let mut n: i64 = 0;
+                while let Some((_, c)) = self.chars.next_if(|(_, c)| c.is_ascii_digit()) {
+                    n = n * 10 + c.to_digit(10).unwrap() as i64;
+                    if n > i32::MAX as i64 {
+                        return Err(JitError::OperandOutOfRange { index });
+                    }
+                }
+                self.push(Node::Literal(n))
+            }
+            Some((index, c)) if c == ')' || BinOp::from_char(c).is_some() => {
+                return Err(JitError::ExpectedOperand { index });
+            }
+            Some((index, c)) => return Err(self.unexpected(index, c)),
+            None => {
+                return Err(JitError::ExpectedOperand {
+                    index: self.source.len(),
+                })
+            }
+        };
+        Ok(if negate {
+            self.push(Node::Negate(operand))
+        } else {
+            operand
+        })
+    }
+
+    /// The error for finding `c` at byte offset `index` where an operator should be
+    fn unexpected(&self, index: usize, c: char) -> JitError {
+        if c.is_ascii_digit() || c == '(' {
+            return JitError::ExpectedOperator { index };
+        }
+        let (line, column) = line_column(self.source, index);
+        JitError::UnknownCharacter {
+            character: c,
+            index,
+            line,
+            column,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::expr::{lower, parse, parse_rpn, BinOp, Node, Step};
+    use crate::{jit_expr, jit_rpn, JitError, MAX_PAREN_DEPTH, MAX_STACK_DEPTH};
+
+    /// Tester function, which runs the expression on the host
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    fn t(expression: &str) -> i64 {
+        let machine_code = jit_expr(expression).unwrap();
+        // Safety: the code is generated for the host
+        unsafe { crate::run(&machine_code) }
+    }
+
+    #[test]
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    fn test_expr() {
+        assert_eq!(t("(1+2)*3-4/2"), 7);
+        assert_eq!(t("42"), 42);
+        assert_eq!(t(" 1 + 2 "), 3);
+        assert_eq!(t("7/2"), 3);
+        assert_eq!(t("2147483647*2147483647"), 4611686014132420609);
+    }
+
+    #[test]
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    fn test_expr_precedence() {
+        assert_eq!(t("1+2*3"), 7);
+        assert_eq!(t("2*3+1"), 7);
+        assert_eq!(t("10-4-3"), 3);
+        assert_eq!(t("100/10/5"), 2);
+        assert_eq!(t("2*6/4"), 3);
+        assert_eq!(t("6/4*2"), 2);
+        assert_eq!(t("1-2*3+4/2"), -3);
+    }
+
+    #[test]
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    fn test_expr_parentheses() {
+        assert_eq!(t("(1+2)*3"), 9);
+        assert_eq!(t("10-(4-3)"), 9);
+        assert_eq!(t("((((5))))"), 5);
+        assert_eq!(t("(2*(3+(4*(5-1))))"), 38);
+        assert_eq!(t("(1+2)*(3+4)/(5-2)"), 7);
+        let deep = format!(
+            "{}1{}",
+            "(1+".repeat(MAX_PAREN_DEPTH),
+            ")".repeat(MAX_PAREN_DEPTH)
+        );
+        assert_eq!(t(&deep), MAX_PAREN_DEPTH as i64 + 1);
+        let long = format!("1{}", "+1".repeat(100_000));
+        assert_eq!(t(&long), 100_001);
+    }
+
+    #[test]
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    fn test_expr_unary_minus() {
+        assert_eq!(t("-3"), -3);
+        assert_eq!(t("--3"), 3);
+        assert_eq!(t("2*-3"), -6);
+        assert_eq!(t("-(1+2)*3"), -9);
+        assert_eq!(t("1--1"), 2);
+        assert_eq!(t("-7/2"), -3);
+        assert_eq!(t("- - -(4)"), -4);
+    }
+
+    #[test]
+    fn test_expr_errors() {
+        assert_eq!(jit_expr(""), Err(JitError::EmptyProgram));
+        assert_eq!(jit_expr("  "), Err(JitError::EmptyProgram));
+        assert_eq!(jit_expr("1/0"), Err(JitError::DivisionByZero { index: 1 }));
+        assert_eq!(
+            jit_expr("1/(0)"),
+            Err(JitError::DivisionByZero { index: 1 })
+        );
+        assert_eq!(
+            jit_expr("2/(0*3)").map(|_| ()),
+            Ok(()),
+            "computed zeros aren't caught"
+        );
+        assert_eq!(jit_expr("1+"), Err(JitError::ExpectedOperand { index: 2 }));
+        assert_eq!(jit_expr("*2"), Err(JitError::ExpectedOperand { index: 0 }));
+        assert_eq!(jit_expr("()"), Err(JitError::ExpectedOperand { index: 1 }));
+        assert_eq!(
+            jit_expr("1 2"),
+            Err(JitError::ExpectedOperator { index: 2 })
+        );
+        assert_eq!(
+            jit_expr("2(1)"),
+            Err(JitError::ExpectedOperator { index: 1 })
+        );
+        assert_eq!(
+            jit_expr("(1+2"),
+            Err(JitError::UnbalancedBracket { index: 0 })
+        );
+        assert_eq!(
+            jit_expr("1+2)"),
+            Err(JitError::UnbalancedBracket { index: 3 })
+        );
+        assert_eq!(
+            jit_expr("2147483648"),
+            Err(JitError::OperandOutOfRange { index: 0 })
+        );
+        assert_eq!(
+            jit_expr("1+x"),
+            Err(JitError::UnknownCharacter {
+                character: 'x',
+                index: 2,
+                line: 1,
+                column: 3
+            })
+        );
+        let deep = format!(
+            "{}1{}",
+            "(".repeat(MAX_PAREN_DEPTH + 1),
+            ")".repeat(MAX_PAREN_DEPTH + 1)
+        );
+        assert_eq!(
+            jit_expr(&deep),
+            Err(JitError::ParenTooDeep {
+                index: MAX_PAREN_DEPTH
+            })
+        );
+    }
+
+    #[test]
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    fn test_rpn() {
+        /// Tester function
+        fn t(expression: &str) -> i64 {
+            let machine_code = jit_rpn(expression).unwrap();
+            // Safety: the code is generated for the host
+            unsafe { crate::run(&machine_code) }
+        }
+
+        assert_eq!(t("3 4 + 2 *"), 14);
+        assert_eq!(t("42"), 42);
+        assert_eq!(t("-7"), -7);
+        assert_eq!(t("10 3 -"), 7);
+        assert_eq!(t("7 -2 /"), -3);
+        assert_eq!(t("1 2 3 4 + + +"), 10);
+        assert_eq!(t("1 2 + 3 4 + *"), 21);
+        assert_eq!(t(" 5\n1\t2 + 4 * + 3 - "), 14);
+        assert_eq!(t("-0"), 0);
+        let deep = format!(
+            "{}{}",
+            "1 ".repeat(MAX_STACK_DEPTH + 1),
+            "+ ".repeat(MAX_STACK_DEPTH)
+        );
+        assert_eq!(t(&deep), MAX_STACK_DEPTH as i64 + 1);
+    }
+
+    #[test]
+    fn test_rpn_errors() {
+        assert_eq!(jit_rpn(""), Err(JitError::EmptyProgram));
+        assert_eq!(jit_rpn("+"), Err(JitError::MissingOperands { token: 0 }));
+        assert_eq!(jit_rpn("1 +"), Err(JitError::MissingOperands { token: 1 }));
+        assert_eq!(
+            jit_rpn("1 2 + * 3"),
+            Err(JitError::MissingOperands { token: 3 })
+        );
+        assert_eq!(jit_rpn("1 2"), Err(JitError::LeftoverOperands { token: 0 }));
+        assert_eq!(
+            jit_rpn("1 2 3 +"),
+            Err(JitError::LeftoverOperands { token: 0 })
+        );
+        assert_eq!(
+            jit_rpn("1 2 3"),
+            Err(JitError::LeftoverOperands { token: 1 })
+        );
+        assert_eq!(jit_rpn("4 0 /"), Err(JitError::DivisionByZero { index: 4 }));
+        assert_eq!(jit_rpn("4 0 0 * /").map(|_| ()), Ok(()));
+        assert_eq!(
+            jit_rpn("1 2147483648 +"),
+            Err(JitError::OperandOutOfRange { index: 2 })
+        );
+        assert_eq!(
+            jit_rpn("1 2x +"),
+            Err(JitError::UnknownCharacter {
+                character: 'x',
+                index: 3,
+                line: 1,
+                column: 4
+            })
+        );
+        assert_eq!(
+            jit_rpn("1 2 ++"),
+            Err(JitError::UnknownCharacter {
+                character: '+',
+                index: 4,
+                line: 1,
+                column: 5
+            })
+        );
+        let deep = "1 ".repeat(MAX_STACK_DEPTH + 2);
+        assert_eq!(
+            jit_rpn(&deep),
+            Err(JitError::StackTooDeep {
+                at_op: MAX_STACK_DEPTH + 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_rpn_steps() {
+        assert_eq!(
+            parse_rpn("3 4 + 2 *").unwrap(),
+            [
+                Step::Load(3),
+                Step::Push,
+                Step::Load(4),
+                Step::Apply(BinOp::Add),
+                Step::Push,
+                Step::Load(2),
+                Step::Apply(BinOp::Mul),
+            ]
+        );
+        // The same steps as the infix form
+        assert_eq!(parse_rpn("1 2 3 * -"), Ok(lower(&parse("1-2*3").unwrap())));
+    }
+
+    #[test]
+    fn test_expr_lowering() {
+        assert_eq!(
+            parse("1-2*3").unwrap(),
+            [
+                Node::Literal(1),
+                Node::Literal(2),
+                Node::Literal(3),
+                Node::Binary(BinOp::Mul, 1, 2),
+                Node::Binary(BinOp::Sub, 0, 3),
+            ]
+        );
+        assert_eq!(
+            lower(&parse("1-2*3").unwrap()),
+            [
+                Step::Load(1),
+                Step::Push,
+                Step::Load(2),
+                Step::Push,
+                Step::Load(3),
+                Step::Apply(BinOp::Mul),
+                Step::Apply(BinOp::Sub),
+            ]
+        );
+        assert_eq!(
+            lower(&parse("-(1+2)").unwrap()),
+            [
+                Step::Load(1),
+                Step::Push,
+                Step::Load(2),
+                Step::Apply(BinOp::Add),
+                Step::Negate,
+            ]
+        );
+    }
+}
@@ -0,0 +1,742 @@
+// The intermediate representation between the operations a program is made of and the
+// machine code for them. Lowering flattens loops and skips into labels and the jumps
+// between them, so the backends only see straight-line instructions and a few kinds of
+// jump, and don't need to track the nesting themselves. The arithmetic instructions still
+// map one to one onto operations, as `+` and `+1` (or `*` and `<`) compile to different
+// machine code, and the code for each operation is kept exactly as it was.
+
+use crate::{JitError, Op, MAX_LOOP_DEPTH};
+use alloc::vec::Vec;
+
+/// A place in the code which jumps go to. Labels are numbered from zero in the order
+/// they're made, which isn't necessarily the order they're placed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Label(pub(crate) usize);
+
+/// A single instruction for the backends. Apart from the last four, each is the
+/// operation of the same name in [`Op`], with the character it's written as given first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Instruction {
+    /// `+`: Increment the working register
+    Inc,
+    /// `-`: Decrement the working register
+    Dec,
+    /// `*`: Double the working register
+    Double,
+    /// `/`: Halve the working register
+    Halve,
+    /// `%`: Take the remainder after dividing the working register by 2
+    RemTwo,
+    /// `^`: Square the working register
+    Square,
+    /// `~`: Negate the working register
+    Negate,
+    /// `0`: Reset the working register to zero
+    Reset,
+    /// `<`: Shift the working register left by one bit
+    ShiftLeft,
+    /// `>`: Shift the working register right by one bit
+    ShiftRight,
+    /// `|`: Take the absolute value of the working register
+    Abs,
+    /// `_`: Replace the working register with zero if it's negative
+    ClampZero,
+    /// `+n`
+    AddImm(i64),
+    /// `-n`
+    SubImm(i64),
+    /// `*n`
+    MulImm(i64),
+    /// `/n`
+    DivImm(i64),
+    /// `%n`
+    RemImm(i64),
+    /// `=n`: Set the working register to n
+    LoadConst(i64),
+    /// `d`: Push a copy of the working register onto the stack
+    Push,
+    /// `s`: Swap the working register with the value on top of the stack
+    Swap,
+    /// Set the counter of the loop nested inside `depth` others to `n`
+    SetCounter { depth: usize, n: i64 },
+    /// The place the next instruction starts at
+    Label(Label),
+    /// Count down the counter of the loop nested inside `depth` others, jumping back to
+    /// `body` until it reaches zero
+    CountDown { depth: usize, body: Label },
+    /// Jump to the label if the working register is zero
+    JumpIfZero(Label),
+}
+
+/// Lower operations to instructions, each along with the index of the operation it's
+/// for. The instructions for each operation follow on from those for the one before,
+/// so the first instruction with an index is where that operation's code starts.
+///
+/// `n[` sets the loop's counter and places a label for the body, which `]` counts down
+/// to. `?` jumps to a label placed after the next operation at the same depth, so
+/// when that's a loop, the label goes after the loop's end.
+///
+/// Fails with [`JitError::InvalidOperation`] if the loops are unbalanced or nested more
+/// than [`MAX_LOOP_DEPTH`] deep, or if an [`Op::Skip`] has no operation after it to skip,
+/// which neither [`tokenize`](crate::tokenize) nor [`check_ops`](crate::check_ops) let
+/// through.
+pub(crate) fn lower(tokens: &[Op]) -> Result<Vec<(usize, Instruction)>, JitError> {
+    let mut instructions = Vec::new();
+    let mut labels = 0;
+    let mut label = || {
+        labels += 1;
+        Label(labels - 1)
+    };
+    // The labels at the start of the bodies of the loops which are still open, innermost
+    // last, along with the index of each loop
+    let mut loops: Vec<(Label, usize)> = Vec::new();
+    // The labels of the skips which haven't been placed yet, along with how many loops
+    // they're inside and the index of each skip
+    let mut skips: Vec<(Label, usize, usize)> = Vec::new();
+    for (index, token) in tokens.iter().enumerate() {
+        let instruction = match *token {
+            Op::Plus => Instruction::Inc,
+            Op::Minus => Instruction::Dec,
+            Op::Star => Instruction::Double,
+            Op::Slash => Instruction::Halve,
+            Op::Percent => Instruction::RemTwo,
+            Op::Caret => Instruction::Square,
+            Op::Tilde => Instruction::Negate,
+            Op::Zero => Instruction::Reset,
+            Op::Less => Instruction::ShiftLeft,
+            Op::Greater => Instruction::ShiftRight,
+            Op::Bar => Instruction::Abs,
+            Op::Clamp => Instruction::ClampZero,
+            Op::Add(n) => Instruction::AddImm(n),
+            Op::Sub(n) => Instruction::SubImm(n),
+            Op::Mul(n) => Instruction::MulImm(n),
+            Op::Div(n) => Instruction::DivImm(n),
+            Op::Rem(n) => Instruction::RemImm(n),
+            Op::Set(n) => Instruction::LoadConst(n),
+            Op::Dup => Instruction::Push,
+            Op::Swap => Instruction::Swap,
+            Op::Loop(n) => {
+                let depth = loops.len();
+                if depth >= MAX_LOOP_DEPTH {
+                    return Err(JitError::InvalidOperation { at_op: index });
+                }
+                let body = label();
+                instructions.push((index, Instruction::SetCounter { depth, n }));
+                instructions.push((index, Instruction::Label(body)));
+                loops.push((body, index));
+                continue;
+            }
+            Op::EndLoop => {
+                let Some((body, _)) = loops.pop() else {
+                    return Err(JitError::InvalidOperation { at_op: index });
+                };
+                let depth = loops.len();
+                Instruction::CountDown { depth, body }
+            }
+            Op::Skip => {
+                let skip = label();
+                instructions.push((index, Instruction::JumpIfZero(skip)));
+                // A skip right after another is the operation that one skips
+                place_skip(&mut instructions, index, &mut skips, loops.len())?;
+                skips.push((skip, loops.len(), index));
+                continue;
+            }
+        };
+        instructions.push((index, instruction));
+        place_skip(&mut instructions, index, &mut skips, loops.len())?;
+    }
+    if let Some(&(_, at_op)) = loops.last() {
+        return Err(JitError::InvalidOperation { at_op });
+    }
+    if let Some(&(_, _, at_op)) = skips.last() {
+        return Err(JitError::InvalidOperation { at_op });
+    }
+    Ok(instructions)
+}
+
+/// Place the label of the skip waiting for an operation at `depth`, now that operation's
+/// instructions have been added. Fails if the skip is inside a loop which has just
+/// ended, so there was nothing in the loop after it to skip.
+fn place_skip(
+    instructions: &mut Vec<(usize, Instruction)>,
+    index: usize,
+    skips: &mut Vec<(Label, usize, usize)>,
+    depth: usize,
+) -> Result<(), JitError> {
+    if let Some(&(label, skip_depth, at_op)) = skips.last() {
+        if skip_depth > depth {
+            return Err(JitError::InvalidOperation { at_op });
+        }
+        if skip_depth == depth {
+            instructions.push((index, Instruction::Label(label)));
+            skips.pop();
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{lower, Instruction, Label};
+    use crate::{
+        codegen, codegen_float, emit_all, to_assembly, tokenize, JitError, Op, Options,
+        OverflowBehavior, Start, Target,
+    };
+    use alloc::format;
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    fn lowered(program: &str) -> Vec<(usize, Instruction)> {
+        lower(&tokenize(program).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_lower_straight_line() {
+        assert_eq!(
+            lowered("+-*/%^~0<>|_"),
+            [
+                (0, Instruction::Inc),
+                (1, Instruction::Dec),
+                (2, Instruction::Double),
+                (3, Instruction::Halve),
+                (4, Instruction::RemTwo),
+                (5, Instruction::Square),
+                (6, Instruction::Negate),
+                (7, Instruction::Reset),
+                (8, Instruction::ShiftLeft),
+                (9, Instruction::ShiftRight),
+                (10, Instruction::Abs),
+                (11, Instruction::ClampZero),
+            ]
+        );
+        assert_eq!(
+            lowered("+5-6*7/8%9=-3ds"),
+            [
+                (0, Instruction::AddImm(5)),
+                (1, Instruction::SubImm(6)),
+                (2, Instruction::MulImm(7)),
+                (3, Instruction::DivImm(8)),
+                (4, Instruction::RemImm(9)),
+                (5, Instruction::LoadConst(-3)),
+                (6, Instruction::Push),
+                (7, Instruction::Swap),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lower_loops() {
+        assert_eq!(
+            lowered("2[3[+]-]"),
+            [
+                (0, Instruction::SetCounter { depth: 0, n: 2 }),
+                (0, Instruction::Label(Label(0))),
+                (1, Instruction::SetCounter { depth: 1, n: 3 }),
+                (1, Instruction::Label(Label(1))),
+                (2, Instruction::Inc),
+                (
+                    3,
+                    Instruction::CountDown {
+                        depth: 1,
+                        body: Label(1),
+                    },
+                ),
+                (4, Instruction::Dec),
+                (
+                    5,
+                    Instruction::CountDown {
+                        depth: 0,
+                        body: Label(0),
+                    },
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lower_skips() {
+        // The label goes after the operation skipped, inside the loop
+        assert_eq!(
+            lowered("3[+?-]"),
+            [
+                (0, Instruction::SetCounter { depth: 0, n: 3 }),
+                (0, Instruction::Label(Label(0))),
+                (1, Instruction::Inc),
+                (2, Instruction::JumpIfZero(Label(1))),
+                (3, Instruction::Dec),
+                (3, Instruction::Label(Label(1))),
+                (
+                    4,
+                    Instruction::CountDown {
+                        depth: 0,
+                        body: Label(0),
+                    },
+                ),
+            ]
+        );
+        // The first skip only skips the second one's jump
+        assert_eq!(
+            lowered("??+"),
+            [
+                (0, Instruction::JumpIfZero(Label(0))),
+                (1, Instruction::JumpIfZero(Label(1))),
+                (1, Instruction::Label(Label(0))),
+                (2, Instruction::Inc),
+                (2, Instruction::Label(Label(1))),
+            ]
+        );
+        // Skipping a loop skips the whole loop
+        assert_eq!(
+            lowered("?3[+]-"),
+            [
+                (0, Instruction::JumpIfZero(Label(0))),
+                (1, Instruction::SetCounter { depth: 0, n: 3 }),
+                (1, Instruction::Label(Label(1))),
+                (2, Instruction::Inc),
+                (
+                    3,
+                    Instruction::CountDown {
+                        depth: 0,
+                        body: Label(1),
+                    },
+                ),
+                (3, Instruction::Label(Label(0))),
+                (4, Instruction::Dec),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lower_invalid() {
+        let invalid = |at_op| Err(JitError::InvalidOperation { at_op });
+        assert_eq!(lower(&[Op::Plus, Op::Skip]), invalid(1));
+        assert_eq!(lower(&[Op::Loop(2), Op::Skip, Op::EndLoop]), invalid(1));
+        assert_eq!(lower(&[Op::Plus, Op::EndLoop]), invalid(1));
+        assert_eq!(lower(&[Op::Loop(2), Op::Plus]), invalid(0));
+        assert_eq!(lower(&[Op::Loop(2); 4]), invalid(3));
+    }
+
+    #[test]
+    fn test_emit_unplaced_label() {
+        let invalid = |at_op| Err(JitError::InvalidOperation { at_op });
+        for target in [Target::X86_64, Target::Aarch64] {
+            let emit = |instructions: &[(usize, Instruction)]| {
+                emit_all(
+                    &mut Vec::new(),
+                    instructions,
+                    target,
+                    |machine_code, _, instruction| {
+                        target.emit(machine_code, instruction, &Options::default())
+                    },
+                    |machine_code| target.skip(machine_code),
+                )
+            };
+            // A skip to a label which is never placed
+            let skip = [
+                (0, Instruction::JumpIfZero(Label(0))),
+                (1, Instruction::Inc),
+            ];
+            assert_eq!(emit(&skip), invalid(0), "{target:?}");
+            // A loop counting down to a body which is only placed after it
+            let count_down = [
+                (0, Instruction::SetCounter { depth: 0, n: 2 }),
+                (
+                    1,
+                    Instruction::CountDown {
+                        depth: 0,
+                        body: Label(0),
+                    },
+                ),
+                (1, Instruction::Label(Label(0))),
+            ];
+            assert_eq!(emit(&count_down), invalid(1), "{target:?}");
+        }
+    }
+
+    /// The machine code for each program, for each target and overflow behavior, as the
+    /// code generator made it before the IR was added. Since then only the AArch64 skips
+    /// have changed, to reach further with `cbnz` and `b`.
+    const CODE: [(&str, Target, OverflowBehavior, &str); 30] = [
+        (
+            "+-*/%^~0<>|",
+            Target::X86_64,
+            OverflowBehavior::Wrap,
+            "4831c948ffc148ffc9486bc9024889c849c7c002000000489949f7f84889c14889c849c7c0020000\
+             00489949f7f84889d1480fafc948f7d94831c948d1e148d1f94889c848c1f83f4831c14829c14889\
+             c8c3",
+        ),
+        (
+            "+-*/%^~0<>|",
+            Target::X86_64,
+            OverflowBehavior::Checked,
+            "4831c948ffc17106ba01000000c348ffc97106ba02000000c3486bc9027106ba03000000c34889c8\
+             49c7c002000000489949f7f84889c14889c849c7c002000000489949f7f84889d1480fafc97106ba\
+             06000000c348f7d97106ba07000000c34831c948d1e17106ba09000000c348d1f94889c848c1f83f\
+             4831c14829c17106ba0b000000c34889c831d2c3",
+        ),
+        (
+            "+-*/%^~0<>|",
+            Target::X86_64,
+            OverflowBehavior::Saturate,
+            "4831c948ffc1710948c1f93f480fbaf93f48ffc9710948c1f93f480fbaf93f486bc902710948c1f9\
+             3f480fbaf93f4889c849c7c002000000489949f7f84889c14889c849c7c002000000489949f7f848\
+             89d1480fafc9710a48b9ffffffffffffff7f48f7d9710948c1f93f480fbaf93f4831c948d1e17109\
+             48c1f93f480fbaf93f48d1f94889c848c1f83f4831c14829c1710948c1f93f480fbaf93f4889c8c3",
+        ),
+        (
+            "+-*/%^~0<>|",
+            Target::Aarch64,
+            OverflowBehavior::Wrap,
+            "000080d200040091000400d100f87fd301fc408b20fc4193420080d2010cc29a2080029b007c009b\
+             e00300cb000080d200f87fd300fc41931f0000f1005480dac0035fd6",
+        ),
+        (
+            "+-*/%^~0<>|",
+            Target::Aarch64,
+            OverflowBehavior::Checked,
+            "000080d2000400b167000054210080d2c0035fd6000400f167000054410080d2c0035fd6000000ab\
+             67000054610080d2c0035fd601fc408b20fc4193420080d2010cc29a2080029b027c409b007c009b\
+             5ffc80eb60000054c10080d2c0035fd6e00300eb67000054e10080d2c0035fd6000080d2000000ab\
+             67000054210180d2c0035fd600fc41931f0000f1005480da1f0000f165000054610180d2c0035fd6\
+             010080d2c0035fd6",
+        ),
+        (
+            "+-*/%^~0<>|",
+            Target::Aarch64,
+            OverflowBehavior::Saturate,
+            "000080d2000400b16700005400fc7f93000041d2000400f16700005400fc7f93000041d2000000ab\
+             6700005400fc7f93000041d201fc408b20fc4193420080d2010cc29a2080029b027c409b007c009b\
+             5ffc80eb400000540000f092e00300eb6700005400fc7f93000041d2000080d2000000ab67000054\
+             00fc7f93000041d200fc41931f0000f1005480da1f0000f16500005400fc7f93000041d2c0035fd6",
+        ),
+        (
+            "+5-7*3/3%3=-7",
+            Target::X86_64,
+            OverflowBehavior::Wrap,
+            "4831c94883c1054883e907486bc9034889c849c7c003000000489949f7f84889c14889c849c7c003\
+             000000489949f7f84889d148c7c1f9ffffff4889c8c3",
+        ),
+        (
+            "+5-7*3/3%3=-7",
+            Target::X86_64,
+            OverflowBehavior::Checked,
+            "4831c94883c1057106ba01000000c34883e9077106ba02000000c3486bc9037106ba03000000c348\
+             89c849c7c003000000489949f7f84889c14889c849c7c003000000489949f7f84889d148c7c1f9ff\
+             ffff4889c831d2c3",
+        ),
+        (
+            "+5-7*3/3%3=-7",
+            Target::X86_64,
+            OverflowBehavior::Saturate,
+            "4831c94883c105710948c1f93f480fbaf93f4883e907710948c1f93f480fbaf93f4889ca486bc903\
+             710f4889d148c1f93f480fbaf93f48f7d14889c849c7c003000000489949f7f84889c14889c849c7\
+             c003000000489949f7f84889d148c7c1f9ffffff4889c8c3",
+        ),
+        (
+            "+5-7*3/3%3=-7",
+            Target::Aarch64,
+            OverflowBehavior::Wrap,
+            "000080d200140091001c00d1610080d2007c019b610080d2000cc19a620080d2010cc29a2080029b\
+             20ff9fd2e0ffbff2e0ffdff2e0fffff2c0035fd6",
+        ),
+        (
+            "+5-7*3/3%3=-7",
+            Target::Aarch64,
+            OverflowBehavior::Checked,
+            "000080d2001400b167000054210080d2c0035fd6001c00f167000054410080d2c0035fd6610080d2\
+             027c419b007c019b5ffc80eb60000054610080d2c0035fd6610080d2000cc19a620080d2010cc29a\
+             2080029b20ff9fd2e0ffbff2e0ffdff2e0fffff2010080d2c0035fd6",
+        ),
+        (
+            "+5-7*3/3%3=-7",
+            Target::Aarch64,
+            OverflowBehavior::Saturate,
+            "000080d2001400b16700005400fc7f93000041d2001c00f16700005400fc7f93000041d2610080d2\
+             030001ca027c419b007c019b5ffc80eb6000005460fc7f9300f840d2610080d2000cc19a620080d2\
+             010cc29a2080029b20ff9fd2e0ffbff2e0ffdff2e0fffff2c0035fd6",
+        ),
+        (
+            "3[+?-]2[3[*]-]",
+            Target::X86_64,
+            OverflowBehavior::Wrap,
+            "4831c949c7c10300000048ffc14885c90f840300000048ffc949ffc975ec49c7c10200000049c7c2\
+             03000000486bc90249ffca75f748ffc949ffc975e84889c8c3",
+        ),
+        (
+            "3[+?-]2[3[*]-]",
+            Target::X86_64,
+            OverflowBehavior::Checked,
+            "4831c949c7c10300000048ffc17106ba02000000c34885c90f840b00000048ffc97106ba04000000\
+             c349ffc975dc49c7c10200000049c7c203000000486bc9027106ba08000000c349ffca75ef48ffc9\
+             7106ba0a000000c349ffc975d84889c831d2c3",
+        ),
+        (
+            "3[+?-]2[3[*]-]",
+            Target::X86_64,
+            OverflowBehavior::Saturate,
+            "4831c949c7c10300000048ffc1710948c1f93f480fbaf93f4885c90f840e00000048ffc9710948c1\
+             f93f480fbaf93f49ffc975d649c7c10200000049c7c203000000486bc902710948c1f93f480fbaf9\
+             3f49ffca75ec48ffc9710948c1f93f480fbaf93f49ffc975d24889c8c3",
+        ),
+        (
+            "3[+?-]2[3[*]-]",
+            Target::Aarch64,
+            OverflowBehavior::Wrap,
+            "000080d2690080d200040091400000b502000014000400d1290500f161ffff54490080d26a0080d2\
+             00f87fd34a0500f1c1ffff54000400d1290500f141ffff54c0035fd6",
+        ),
+        (
+            "3[+?-]2[3[*]-]",
+            Target::Aarch64,
+            OverflowBehavior::Checked,
+            "000080d2690080d2000400b167000054410080d2c0035fd6400000b505000014000400f167000054\
+             810080d2c0035fd6290500f1a1feff54490080d26a0080d2000000ab67000054010180d2c0035fd6\
+             4a0500f161ffff54000400f167000054410180d2c0035fd6290500f181feff54010080d2c0035fd6",
+        ),
+        (
+            "3[+?-]2[3[*]-]",
+            Target::Aarch64,
+            OverflowBehavior::Saturate,
+            "000080d2690080d2000400b16700005400fc7f93000041d2400000b505000014000400f167000054\
+             00fc7f93000041d2290500f1a1feff54490080d26a0080d2000000ab6700005400fc7f93000041d2\
+             4a0500f161ffff54000400f16700005400fc7f93000041d2290500f181feff54c0035fd6",
+        ),
+        (
+            "??+?3[+]",
+            Target::X86_64,
+            OverflowBehavior::Wrap,
+            "4831c94885c90f84090000004885c90f840300000048ffc14885c90f840f00000049c7c103000000\
+             48ffc149ffc975f84889c8c3",
+        ),
+        (
+            "??+?3[+]",
+            Target::X86_64,
+            OverflowBehavior::Checked,
+            "4831c94885c90f84090000004885c90f840b00000048ffc17106ba03000000c34885c90f84170000\
+             0049c7c10300000048ffc17106ba06000000c349ffc975f04889c831d2c3",
+        ),
+        (
+            "??+?3[+]",
+            Target::X86_64,
+            OverflowBehavior::Saturate,
+            "4831c94885c90f84090000004885c90f840e00000048ffc1710948c1f93f480fbaf93f4885c90f84\
+             1a00000049c7c10300000048ffc1710948c1f93f480fbaf93f49ffc975ed4889c8c3",
+        ),
+        (
+            "??+?3[+]",
+            Target::Aarch64,
+            OverflowBehavior::Wrap,
+            "000080d2400000b503000014400000b50200001400040091400000b505000014690080d200040091\
+             290500f1c1ffff54c0035fd6",
+        ),
+        (
+            "??+?3[+]",
+            Target::Aarch64,
+            OverflowBehavior::Checked,
+            "000080d2400000b503000014400000b505000014000400b167000054610080d2c0035fd6400000b5\
+             08000014690080d2000400b167000054c10080d2c0035fd6290500f161ffff54010080d2c0035fd6",
+        ),
+        (
+            "??+?3[+]",
+            Target::Aarch64,
+            OverflowBehavior::Saturate,
+            "000080d2400000b503000014400000b505000014000400b16700005400fc7f93000041d2400000b5\
+             08000014690080d2000400b16700005400fc7f93000041d2290500f161ffff54c0035fd6",
+        ),
+        (
+            "=5d+s*d-s/",
+            Target::X86_64,
+            OverflowBehavior::Wrap,
+            "4831c9554889e548c7c1050000005148ffc148870c24486bc9025148ffc948870c244889c849c7c0\
+             02000000489949f7f84889c1c94889c8c3",
+        ),
+        (
+            "=5d+s*d-s/",
+            Target::X86_64,
+            OverflowBehavior::Checked,
+            "4831c9554889e548c7c1050000005148ffc17107ba03000000c9c348870c24486bc9027107ba0500\
+             0000c9c35148ffc97107ba07000000c9c348870c244889c849c7c002000000489949f7f84889c1c9\
+             4889c831d2c3",
+        ),
+        (
+            "=5d+s*d-s/",
+            Target::X86_64,
+            OverflowBehavior::Saturate,
+            "4831c9554889e548c7c1050000005148ffc1710948c1f93f480fbaf93f48870c24486bc902710948\
+             c1f93f480fbaf93f5148ffc9710948c1f93f480fbaf93f48870c244889c849c7c002000000489949\
+             f7f84889c1c94889c8c3",
+        ),
+        (
+            "=5d+s*d-s/",
+            Target::Aarch64,
+            OverflowBehavior::Wrap,
+            "000080d2f0030091a00080d2e00f1ff800040091e10340f9e00300f9e00301aa00f87fd3e00f1ff8\
+             000400d1e10340f9e00300f9e00301aa01fc408b20fc41931f020091c0035fd6",
+        ),
+        (
+            "=5d+s*d-s/",
+            Target::Aarch64,
+            OverflowBehavior::Checked,
+            "000080d2f0030091a00080d2e00f1ff8000400b187000054610080d21f020091c0035fd6e10340f9\
+             e00300f9e00301aa000000ab87000054a10080d21f020091c0035fd6e00f1ff8000400f187000054\
+             e10080d21f020091c0035fd6e10340f9e00300f9e00301aa01fc408b20fc41931f020091010080d2\
+             c0035fd6",
+        ),
+        (
+            "=5d+s*d-s/",
+            Target::Aarch64,
+            OverflowBehavior::Saturate,
+            "000080d2f0030091a00080d2e00f1ff8000400b16700005400fc7f93000041d2e10340f9e00300f9\
+             e00301aa000000ab6700005400fc7f93000041d2e00f1ff8000400f16700005400fc7f93000041d2\
+             e10340f9e00300f9e00301aa01fc408b20fc41931f020091c0035fd6",
+        ),
+    ];
+
+    /// The same for floating point code
+    const FLOAT_CODE: [(&str, Target, &str); 10] = [
+        (
+            "+-*/%^~0<>|",
+            Target::X86_64,
+            "660f57c0f20f100d8c000000f20f58c1f20f100d80000000f20f5cc1f20f100d7c000000f20f59c1\
+             f20f100d70000000f20f5ec1f20f100d64000000660f28d0f20f5ed1660f3a0bd203f20f59d1f20f\
+             5cc2f20f59c0f20f100d4a000000660f57c1660f57c0f20f100d32000000f20f59c1f20f100d2600\
+             0000f20f5ec1660f3a0bc001f20f100d24000000660f54c1c300000000000000000000000000f03f\
+             00000000000000400000000000000080ffffffffffffff7f",
+        ),
+        (
+            "+-*/%^~0<>|",
+            Target::Aarch64,
+            "e003679ee102005c0028611ea102005c0038611ea102005c0008611e6102005c0018611e2102005c\
+             0218611e42c0651e4080411f0008601e0040611ee003679e4101005c0008611e0101005c0018611e\
+             0040651e00c0601ec0035fd600000000000000000000f03f0000000000000040",
+        ),
+        (
+            "+5-7*3/3%3=-7",
+            Target::X86_64,
+            "660f57c0f20f100d54000000f20f58c1f20f100d50000000f20f5cc1f20f100d4c000000f20f59c1\
+             f20f100d40000000f20f5ec1f20f100d34000000660f28d0f20f5ed1660f3a0bd203f20f59d1f20f\
+             5cc2f20f10051e000000c3000000000000000000000014400000000000001c400000000000000840\
+             0000000000001cc0",
+        ),
+        (
+            "+5-7*3/3%3=-7",
+            Target::Aarch64,
+            "e003679ee101005c0028611ee101005c0038611ee101005c0008611ea101005c0018611e6101005c\
+             0218611e42c0651e4080411f2001005cc0035fd60000000000000000000014400000000000001c40\
+             00000000000008400000000000001cc0",
+        ),
+        (
+            "3[+?-]2[3[*]-]",
+            Target::X86_64,
+            "660f57c049c7c103000000f20f100d55000000f20f58c166480f7ec148d1e10f840c000000f20f10\
+             0d3b000000f20f5cc149ffc975d549c7c10200000049c7c203000000f20f100d24000000f20f59c1\
+             49ffca75eff20f100d0b000000f20f5cc149ffc975d7c300000000000000f03f0000000000000040",
+        ),
+        (
+            "3[+?-]2[3[*]-]",
+            Target::Aarch64,
+            "e003679e690080d2c102005c0028611e0100669e21f87fd3410000b5030000140102005c0038611e\
+             290500f1e1feff54490080d26a0080d28101005c0008611e4a0500f1a1ffff54c100005c0038611e\
+             290500f101ffff54c0035fd600000000000000000000f03f0000000000000040",
+        ),
+        (
+            "??+?3[+]",
+            Target::X86_64,
+            "660f57c066480f7ec148d1e10f840e00000066480f7ec148d1e10f840c000000f20f100d30000000\
+             f20f58c166480f7ec148d1e10f841800000049c7c103000000f20f100d0f000000f20f58c149ffc9\
+             75efc30000000000000000000000f03f",
+        ),
+        (
+            "??+?3[+]",
+            Target::Aarch64,
+            "e003679e0100669e21f87fd3410000b5050000140100669e21f87fd3410000b503000014a101005c\
+             0028611e0100669e21f87fd3410000b506000014690080d2c100005c0028611e290500f1a1ffff54\
+             c0035fd600000000000000000000f03f",
+        ),
+        (
+            "=5d+s*d-s/",
+            Target::X86_64,
+            "660f57c0554889e5f20f10056000000066480f7ec050f20f100d5a000000f20f58c166480f7ec048\
+             87042466480f6ec0f20f100d48000000f20f59c166480f7ec050f20f100d2e000000f20f5cc16648\
+             0f7ec04887042466480f6ec0f20f100d1c000000f20f5ec1c9c30000000000000000000000001440\
+             000000000000f03f0000000000000040",
+        ),
+        (
+            "=5d+s*d-s/",
+            Target::Aarch64,
+            "e003679ef00300918002005ce00f1ffc8102005c0028611ee10340fde00300fd2040601e2102005c\
+             0008611ee00f1ffc8101005c0038611ee10340fde00300fd2040601e2101005c0018611e1f020091\
+             c0035fd6000000000000000000001440000000000000f03f0000000000000040",
+        ),
+    ];
+
+    /// Check the machine code for `program` is `expected`, written in hex
+    fn check_code(machine_code: &[u8], expected: &str, program: &str) {
+        let hex: String = machine_code.iter().map(|b| format!("{b:02x}")).collect();
+        let at = hex
+            .bytes()
+            .zip(expected.bytes())
+            .position(|(a, b)| a != b)
+            .unwrap_or(hex.len().min(expected.len()));
+        assert_eq!(hex, expected, "{program} differs from byte {}", at / 2);
+    }
+
+    #[test]
+    fn test_code_unchanged() {
+        for (program, target, overflow, expected) in CODE {
+            let options = Options {
+                target,
+                overflow,
+                ..Options::default()
+            };
+            let machine_code =
+                codegen(&tokenize(program).unwrap(), Start::Constant(0), &options).unwrap();
+            check_code(
+                &machine_code,
+                expected,
+                &format!("{program} ({target:?}, {overflow:?})"),
+            );
+        }
+        for (program, target, expected) in FLOAT_CODE {
+            let machine_code = codegen_float(&tokenize(program).unwrap(), target).unwrap();
+            check_code(
+                &machine_code,
+                expected,
+                &format!("{program} (float, {target:?})"),
+            );
+        }
+    }
+
+    #[test]
+    fn test_assembly_unchanged() {
+        assert_eq!(
+            to_assembly("??+?3[+]").unwrap(),
+            "    xor %rcx, %rcx
+    # ?
+    test %rcx, %rcx
+    {disp32} jz .Lskip0
+    # ?
+    test %rcx, %rcx
+    {disp32} jz .Lskip1
+.Lskip0:
+    # +
+    inc %rcx
+.Lskip1:
+    # ?
+    test %rcx, %rcx
+    {disp32} jz .Lskip2
+    # 3[
+    mov $3, %r9
+0:
+    # +
+    inc %rcx
+    # ]
+    dec %r9
+    jnz 0b
+.Lskip2:
+    mov %rcx, %rax
+    ret
+"
+        );
+    }
+}
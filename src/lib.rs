@@ -0,0 +1,4058 @@
+// Created: 2025/1/5
+//  Edited: 2025/1/6
+//  Author: Alexander Bass
+//
+// The program which follows acts as a simple JIT compiler.
+// A sequence of arithmetic operations is fed to a jit function which converts
+// them into executable x86_64 (or AArch64) machine code. Then, the machine code is sent
+// to a run function which executes the machine code and receives a return
+// value from it. The operations are very simple and all act upon one variable.
+//
+// "+": Increment variable
+// "-": Decrement variable
+// "*": Double variable
+// "/": Halve variable
+// "%": Remainder of halving variable
+// "^": Square variable
+// "~": Negate variable
+// "0": Reset variable to zero
+// "<": Shift variable left by one bit
+// ">": Shift variable right by one bit, rounding down
+// "|": Absolute value of variable
+// "=n": Set variable to n
+// "#": Comment, running to the end of the line
+// "n(...)": Repeat the operations in parentheses n times
+// "n[...]": Loop over the operations in square brackets n times
+// "?": Skip the next operation if the variable is zero
+// "d": Push a copy of variable onto the stack
+// "s": Swap variable with the value on top of the stack
+//
+// Some example sequences and their outputs include:
+// "+":  1
+// "-": -1
+// "++*": 4
+// "++*-/": 1
+//
+// This has been tested and works on x86_64 Linux. It should work on Windows
+// and other OSes, as the x86_64 backend follows the Microsoft x64 calling convention
+// when built for Windows. There is also an AArch64 backend which is picked when building
+// for ARM64 machines. Code for either can be generated on any machine through
+// Options. Other CPU architectures can still evaluate programs with the interpreter.
+//
+// Compiling happens in stages. `tokenize` turns the program string into a list of `Op`s,
+// unrolling groups along the way. The interpreter and `stats` work on the operations, as
+// do optimizations such as `coalesce`, which merges runs like `+++` into a single
+// `Add(3)`. `codegen` lowers them to the instructions in `ir`, where loops and skips have
+// become labels and jumps, and has the backend for the target emit each instruction. The
+// x86_64 backend lowers them further to machine instructions before encoding those,
+// which `to_assembly` prints instead.
+//
+// The region library is used as a cross-platform way to allocate executable memory.
+//
+// This was inspired by a the blog post <https://ochagavia.nl/blog/the-jit-calculator-challenge/>
+//
+//
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::iter::Peekable;
+use core::ops::Range;
+use core::str::CharIndices;
+
+/// Reasons a program string can fail to compile
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JitError {
+    /// A character which is not an operation or whitespace.
+    /// `index` is the byte offset of the character within the program string,
+    /// and `line` and `column` (both counting from 1) give the same position for people.
+    /// The column counts characters, not bytes.
+    UnknownCharacter {
+        character: char,
+        index: usize,
+        line: usize,
+        column: usize,
+    },
+    /// The program contains no operations, or [`jit_ops`] was given none
+    EmptyProgram,
+    /// The number following the operator at byte offset `index` is larger than `i32::MAX`,
+    /// or for [`jit_expr`], the number starting there
+    OperandOutOfRange { index: usize },
+    /// The operator at byte offset `index` divides by zero
+    DivisionByZero { index: usize },
+    /// The `=` at byte offset `index` isn't followed by a number
+    MissingOperand { index: usize },
+    /// The parenthesis or square bracket at byte offset `index` has no partner
+    UnbalancedBracket { index: usize },
+    /// The repeat count at byte offset `index` isn't followed by a group or a loop
+    MissingGroup { index: usize },
+    /// The loop at byte offset `index` is nested inside [`MAX_LOOP_DEPTH`] other loops
+    LoopTooDeep { index: usize },
+    /// The `?` at byte offset `index` is the last operation of the program or of a loop,
+    /// so there is nothing for it to skip. This is an error rather than doing nothing,
+    /// as it's most likely a mistake.
+    NothingToSkip { index: usize },
+    /// The repeat count at byte offset `index` is zero
+    ZeroRepeat { index: usize },
+    /// Unrolling the group with its repeat count at byte offset `index` would make the
+    /// program longer than [`MAX_OPERATIONS`]
+    TooManyOperations { index: usize },
+    /// The `s` at index `at_op` of the operations (see [`parse`]) may run with nothing
+    /// on the stack for it to swap with
+    EmptyStack { at_op: usize },
+    /// The operation at index `at_op` of the operations (see [`parse`]), or the token at
+    /// that index of an expression for [`jit_rpn`], may push more than [`MAX_STACK_DEPTH`]
+    /// values onto the stack
+    StackTooDeep { at_op: usize },
+    /// The expression given to [`jit_expr`] needs a number, a unary `-` or a `(` at byte
+    /// offset `index`, which may be the end of the expression
+    ExpectedOperand { index: usize },
+    /// The expression given to [`jit_expr`] needs an operator at byte offset `index`
+    ExpectedOperator { index: usize },
+    /// The parenthesis at byte offset `index` of an expression is nested inside
+    /// [`MAX_PAREN_DEPTH`] others
+    ParenTooDeep { index: usize },
+    /// The operator at index `token` of the tokens of an expression for [`jit_rpn`]
+    /// (counting from 0) has fewer than two values on the stack to work on
+    MissingOperands { token: usize },
+    /// An expression for [`jit_rpn`] leaves more than one value on the stack. `token` is
+    /// the index of the token which gave the value just under the result.
+    LeftoverOperands { token: usize },
+    /// The machine code for the program is `size` bytes, more than the `limit` set by
+    /// [`Options::max_code_size`]. Floating point code for AArch64 is also limited to a
+    /// megabyte, as that's as far as its instructions can load constants from. Jumps
+    /// are limited to 128 megabytes on AArch64 and 2 GiB on x86_64, which no program
+    /// [`parse`] accepts comes close to.
+    CodeTooLarge { size: usize, limit: usize },
+    /// The operation at index `at_op` of the operations given to [`jit_ops`] can't be
+    /// compiled, which [`parse`] never produces. It's a loop without an end, an end
+    /// without a loop, a loop nested inside [`MAX_LOOP_DEPTH`] others or run fewer than
+    /// once, a skip with nothing after it to skip, or a division by a number less than
+    /// one, as [`parse`] only gives positive operands. `at_op` is
+    /// [`MAX_OPERATIONS`] when there are more operations than that.
+    InvalidOperation { at_op: usize },
+}
+
+impl fmt::Display for JitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JitError::UnknownCharacter {
+                character,
+                line,
+                column,
+                ..
+            } => {
+                write!(
+                    f,
+                    "Unknown character in program string: {character} (at line {line}, column {column})"
+                )
+            }
+            JitError::EmptyProgram => write!(f, "Program string contains no operations"),
+            JitError::OperandOutOfRange { index } => {
+                write!(f, "Operand is too large (at byte {index})")
+            }
+            JitError::DivisionByZero { index } => write!(f, "Division by zero (at byte {index})"),
+            JitError::MissingOperand { index } => {
+                write!(f, "Expected a number after = (at byte {index})")
+            }
+            JitError::UnbalancedBracket { index } => {
+                write!(f, "Unbalanced brackets (at byte {index})")
+            }
+            JitError::MissingGroup { index } => {
+                write!(
+                    f,
+                    "Expected ( or [ after the repeat count (at byte {index})"
+                )
+            }
+            JitError::LoopTooDeep { index } => write!(
+                f,
+                "Loops can only be nested {MAX_LOOP_DEPTH} deep (at byte {index})"
+            ),
+            JitError::NothingToSkip { index } => {
+                write!(f, "Nothing follows ? for it to skip (at byte {index})")
+            }
+            JitError::ZeroRepeat { index } => {
+                write!(f, "Group repeated zero times (at byte {index})")
+            }
+            JitError::TooManyOperations { index } => write!(
+                f,
+                "Program is longer than {MAX_OPERATIONS} operations once repeated (at byte {index})"
+            ),
+            JitError::EmptyStack { at_op } => {
+                write!(
+                    f,
+                    "Nothing on the stack to swap with (at operation {at_op})"
+                )
+            }
+            JitError::StackTooDeep { at_op } => write!(
+                f,
+                "The stack can only hold {MAX_STACK_DEPTH} values (at operation {at_op})"
+            ),
+            JitError::ExpectedOperand { index } => {
+                write!(f, "Expected a number, - or ( (at byte {index})")
+            }
+            JitError::ExpectedOperator { index } => {
+                write!(f, "Expected +, -, * or / (at byte {index})")
+            }
+            JitError::ParenTooDeep { index } => write!(
+                f,
+                "Parentheses can only be nested {MAX_PAREN_DEPTH} deep (at byte {index})"
+            ),
+            JitError::MissingOperands { token } => {
+                write!(f, "Operator needs two values to work on (at token {token})")
+            }
+            JitError::LeftoverOperands { token } => write!(
+                f,
+                "More than one value is left on the stack (at token {token})"
+            ),
+            JitError::CodeTooLarge { size, limit } => write!(
+                f,
+                "Machine code is {size} bytes, more than the limit of {limit} bytes"
+            ),
+            JitError::InvalidOperation { at_op } => write!(
+                f,
+                "Operation is out of place, or its operand is out of range (at operation {at_op})"
+            ),
+        }
+    }
+}
+
+impl JitError {
+    /// The byte offset within the program string the error was found at, if any
+    pub fn index(&self) -> Option<usize> {
+        match self {
+            JitError::UnknownCharacter { index, .. }
+            | JitError::OperandOutOfRange { index }
+            | JitError::DivisionByZero { index }
+            | JitError::MissingOperand { index }
+            | JitError::UnbalancedBracket { index }
+            | JitError::MissingGroup { index }
+            | JitError::LoopTooDeep { index }
+            | JitError::NothingToSkip { index }
+            | JitError::ZeroRepeat { index }
+            | JitError::TooManyOperations { index }
+            | JitError::ExpectedOperand { index }
+            | JitError::ExpectedOperator { index }
+            | JitError::ParenTooDeep { index } => Some(*index),
+            JitError::EmptyProgram
+            | JitError::EmptyStack { .. }
+            | JitError::StackTooDeep { .. }
+            | JitError::MissingOperands { .. }
+            | JitError::LeftoverOperands { .. }
+            | JitError::CodeTooLarge { .. }
+            | JitError::InvalidOperation { .. } => None,
+        }
+    }
+
+    /// The bytes of `source`, the program string which failed to compile, that the error
+    /// is about. This is the character at [`index`](JitError::index) for most errors, but a
+    /// run of unknown characters such as the `abc` in `*abc` is taken as a whole, and an
+    /// operand which is out of range covers its operator and all of its digits. The range
+    /// is empty when the error is at the end of the program.
+    pub fn span(&self, source: &str) -> Option<Range<usize>> {
+        let index = self.index()?;
+        let mut chars = source[index..]
+            .char_indices()
+            .map(|(i, c)| (index + i, c))
+            .peekable();
+        let Some((_, first)) = chars.next() else {
+            return Some(index..index);
+        };
+        match self {
+            JitError::UnknownCharacter { .. } => {
+                let unknown = |c: char| {
+                    Op::try_from(c).is_err()
+                        && !c.is_whitespace()
+                        && !c.is_ascii_digit()
+                        && !"=#()[]".contains(c)
+                };
+                while chars.next_if(|&(_, c)| unknown(c)).is_some() {}
+            }
+            JitError::OperandOutOfRange { .. } => {
+                if first == '=' {
+                    chars.next_if(|&(_, c)| c == '-');
+                }
+                while chars.next_if(|(_, c)| c.is_ascii_digit()).is_some() {}
+            }
+            _ => {}
+        }
+        Some(index..chars.peek().map_or(source.len(), |&(i, _)| i))
+    }
+
+    /// Describe the error the way rustc does, with the line of `source` it was found on
+    /// and its [`span`](JitError::span) underlined. This is the same as [`render`].
+    pub fn render(&self, source: &str) -> String {
+        render(self, source)
+    }
+}
+
+impl core::error::Error for JitError {}
+
+/// The working register overflowed while running code from [`jit_checked`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overflow {
+    /// The index of the operation which overflowed, in the operations from [`parse`].
+    /// Inside a loop, this is the same on every time around.
+    pub at_op: usize,
+}
+
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The result overflowed a 64-bit integer at operation {}",
+            self.at_op
+        )
+    }
+}
+
+impl core::error::Error for Overflow {}
+
+#[cfg(feature = "std")]
+/// Reasons machine code can fail to be loaded into executable memory
+#[derive(Debug)]
+pub enum RunError {
+    /// The memory to hold the code couldn't be allocated
+    Alloc(region::Error),
+    /// The memory holding the code couldn't be made executable
+    Protect(region::Error),
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunError::Alloc(e) => write!(f, "Failed to allocate memory for the code: {e}"),
+            RunError::Protect(e) => write!(f, "Failed to make memory executable: {e}"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl core::error::Error for RunError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            RunError::Alloc(e) | RunError::Protect(e) => Some(e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+/// Any error which can happen when going from a program string to a callable function
+#[derive(Debug)]
+pub enum CalcError {
+    Jit(JitError),
+    Run(RunError),
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for CalcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CalcError::Jit(e) => e.fmt(f),
+            CalcError::Run(e) => e.fmt(f),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl core::error::Error for CalcError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            CalcError::Jit(e) => Some(e),
+            CalcError::Run(e) => Some(e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<JitError> for CalcError {
+    fn from(e: JitError) -> Self {
+        CalcError::Jit(e)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<RunError> for CalcError {
+    fn from(e: RunError) -> Self {
+        CalcError::Run(e)
+    }
+}
+
+mod aarch64;
+#[cfg(feature = "arbitrary")]
+mod arbitrary;
+#[cfg(all(feature = "std", any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod bench;
+mod blob;
+#[cfg(feature = "std")]
+mod cache;
+mod dump;
+mod elf;
+mod encoding;
+#[cfg(feature = "std")]
+mod exec;
+mod expr;
+mod ir;
+#[cfg(all(feature = "std", target_os = "macos", target_arch = "aarch64"))]
+mod macos;
+mod render;
+mod stats;
+mod wasm;
+mod x86_64;
+
+#[cfg(all(feature = "std", any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub use bench::{benchmark, BenchReport};
+#[cfg(feature = "std")]
+pub use blob::run_blob;
+pub use blob::{load_blob, save_blob, BlobError, BLOB_MAGIC, BLOB_VERSION};
+#[cfg(feature = "std")]
+pub use cache::{CacheStats, JitCache};
+#[cfg(feature = "std")]
+pub use dump::write_code;
+pub use dump::{annotate, disassemble, format_hex, hex_dump, Span};
+pub use elf::emit_object;
+pub use encoding::{
+    EPILOGUE, OP_BAR, OP_CARET, OP_CLAMP, OP_GREATER, OP_LESS, OP_MINUS, OP_PERCENT, OP_PLUS,
+    OP_SLASH, OP_STAR, OP_TILDE, OP_ZERO, PROLOGUE,
+};
+pub use expr::{jit_expr, jit_rpn};
+pub use render::render;
+pub use stats::{stats, ProgramStats};
+pub use wasm::emit_wasm;
+
+#[cfg(feature = "std")]
+use exec::{load, refill, Memory};
+use expr::Step;
+use ir::{Instruction, Label};
+
+/// The most operations a program may contain once its repeat groups are unrolled
+pub const MAX_OPERATIONS: usize = 1 << 20;
+
+/// The most values a program may push onto the stack with `d`. Each takes 8 bytes of
+/// the machine stack on x86_64, and 16 on AArch64.
+pub const MAX_STACK_DEPTH: usize = 256;
+
+/// How deeply parentheses can be nested in an expression for [`jit_expr`]. Each level
+/// can leave two values on the machine stack while the innermost one is worked out.
+pub const MAX_PAREN_DEPTH: usize = 64;
+
+/// How deeply loops can be nested. Each loop keeps its counter in a register of its own.
+pub const MAX_LOOP_DEPTH: usize = 3;
+
+/// The operations a program string is made of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Op {
+    /// `+`: Increment the working register
+    Plus,
+    /// `-`: Decrement the working register
+    Minus,
+    /// `*`: Double the working register
+    Star,
+    /// `/`: Halve the working register, rounding towards zero
+    Slash,
+    /// `%`: Replace the working register with its remainder after dividing by 2.
+    /// The remainder has the same sign as the working register, like `%` in C.
+    Percent,
+    /// `^`: Square the working register
+    Caret,
+    /// `~`: Negate the working register. Like `neg`, negating `i64::MIN` wraps
+    /// around to `i64::MIN` again.
+    Tilde,
+    /// `0`: Reset the working register to zero, the value it starts at
+    Zero,
+    /// `<`: Shift the working register left by one bit, which is the same as `*`
+    Less,
+    /// `>`: Shift the working register right by one bit, keeping its sign. Unlike `/`,
+    /// this rounds towards negative infinity, so `--->` is -2 where `---/` is -1.
+    Greater,
+    /// `|`: Replace the working register with its absolute value. Like
+    /// [`i64::wrapping_abs`], `i64::MIN` stays as it is, having no positive counterpart.
+    Bar,
+    /// `_`: Replace the working register with zero if it's negative, so it never goes
+    /// below zero
+    Clamp,
+    /// `+n`: Add n to the working register.
+    /// This is also produced by optimizations which merge several operations together.
+    Add(i64),
+    /// `-n`: Subtract n from the working register
+    Sub(i64),
+    /// `*n`: Multiply the working register by n
+    Mul(i64),
+    /// `/n`: Divide the working register by n, rounding towards zero
+    Div(i64),
+    /// `%n`: Replace the working register with its remainder after dividing by n,
+    /// which has the same sign as the working register
+    Rem(i64),
+    /// `=n` or `=-n`: Set the working register to n, discarding its old value
+    Set(i64),
+    /// `n[`: Run the operations up to the matching [`Op::EndLoop`] n times.
+    /// n must be positive.
+    Loop(i64),
+    /// `]`: The end of a loop
+    EndLoop,
+    /// `?`: Skip the next operation if the working register is zero.
+    /// When the next operation starts a loop, the whole loop is skipped.
+    Skip,
+    /// `d`: Push a copy of the working register onto the stack. Whatever is left on the
+    /// stack when the program ends is thrown away.
+    Dup,
+    /// `s`: Swap the working register with the value on top of the stack. A program is
+    /// rejected if this could happen with nothing on the stack.
+    Swap,
+}
+
+/// The operation a single character stands for.
+/// Fails with the character itself if it isn't an operation.
+impl TryFrom<char> for Op {
+    type Error = char;
+
+    fn try_from(c: char) -> Result<Self, Self::Error> {
+        match c {
+            '+' => Ok(Op::Plus),
+            '-' => Ok(Op::Minus),
+            '*' => Ok(Op::Star),
+            '/' => Ok(Op::Slash),
+            '%' => Ok(Op::Percent),
+            '^' => Ok(Op::Caret),
+            '~' => Ok(Op::Tilde),
+            '0' => Ok(Op::Zero),
+            '<' => Ok(Op::Less),
+            '>' => Ok(Op::Greater),
+            '|' => Ok(Op::Bar),
+            '_' => Ok(Op::Clamp),
+            '?' => Ok(Op::Skip),
+            'd' => Ok(Op::Dup),
+            's' => Ok(Op::Swap),
+            c => Err(c),
+        }
+    }
+}
+
+/// Writes the operation the way it appears in a program string
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Op::Plus => write!(f, "+"),
+            Op::Minus => write!(f, "-"),
+            Op::Star => write!(f, "*"),
+            Op::Slash => write!(f, "/"),
+            Op::Percent => write!(f, "%"),
+            Op::Caret => write!(f, "^"),
+            Op::Tilde => write!(f, "~"),
+            Op::Zero => write!(f, "0"),
+            Op::Less => write!(f, "<"),
+            Op::Greater => write!(f, ">"),
+            Op::Bar => write!(f, "|"),
+            Op::Clamp => write!(f, "_"),
+            // Negative additions only come out of optimizations, and are the same as subtracting
+            Op::Add(n) if *n < 0 => write!(f, "-{}", n.unsigned_abs()),
+            Op::Add(n) => write!(f, "+{n}"),
+            Op::Sub(n) => write!(f, "-{n}"),
+            Op::Mul(n) => write!(f, "*{n}"),
+            Op::Div(n) => write!(f, "/{n}"),
+            Op::Rem(n) => write!(f, "%{n}"),
+            Op::Set(n) => write!(f, "={n}"),
+            Op::Loop(n) => write!(f, "{n}["),
+            Op::EndLoop => write!(f, "]"),
+            Op::Skip => write!(f, "?"),
+            Op::Dup => write!(f, "d"),
+            Op::Swap => write!(f, "s"),
+        }
+    }
+}
+
+/// A program as a list of operations, for building programs some other way than writing
+/// a program string. With the `serde` feature it's a list of operations by name, so
+/// `["plus", "plus", "star"]` is the same program as `++*`, and `{"add": 5}` is `+5`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct Program {
+    pub ops: Vec<Op>,
+}
+
+impl Program {
+    /// Parse a program string into its operations, like [`parse`]
+    pub fn parse(program: &str) -> Result<Program, JitError> {
+        Ok(Program {
+            ops: parse(program)?,
+        })
+    }
+
+    /// Compile the operations with [`jit_ops`], which fails in the same cases
+    pub fn jit(&self) -> Result<Vec<u8>, JitError> {
+        jit_ops(&self.ops)
+    }
+
+    #[cfg(feature = "std")]
+    /// Compile the operations with [`jit_ops`] and load them into executable memory,
+    /// like [`compile`]
+    pub fn compile(&self) -> Result<CompiledProgram, CalcError> {
+        let machine_code = self.jit()?;
+        // Safety: `jit_ops` produces code of the form `int64_t f()`
+        Ok(unsafe { CompiledProgram::new(&machine_code) }?)
+    }
+}
+
+impl From<Vec<Op>> for Program {
+    fn from(ops: Vec<Op>) -> Self {
+        Program { ops }
+    }
+}
+
+/// Settings which control how a program is compiled.
+/// The default compiles every operation exactly as it is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Options {
+    /// Merge runs of `+` and `-` into a single addition or subtraction,
+    /// and drop the operations before a `0` or `=n` whose result is thrown away
+    pub coalesce: bool,
+    /// Evaluate the whole program at compile time, so the machine code only returns the result.
+    /// This makes every other optimization irrelevant.
+    pub fold_constants: bool,
+    /// Replace multiplication and division by a power of two with cheaper shifts
+    pub strength_reduce: bool,
+    /// The architecture to generate machine code for
+    pub target: Target,
+    /// The type of integer the function returns
+    pub return_type: ReturnType,
+    /// Whether the working register is signed or unsigned, which changes how it's
+    /// divided and shifted right
+    pub signedness: Signedness,
+    /// What happens when an operation overflows the working register
+    pub overflow: OverflowBehavior,
+    /// Which way `/` rounds odd negative values
+    pub div_rounding: DivRounding,
+    /// Put a breakpoint at the start of the body, after the prologue, to stop in a
+    /// debugger there. Constant folding leaves no body to put it in.
+    pub breakpoint: bool,
+    /// The most bytes of machine code the program may compile to. Longer code is a
+    /// [`JitError::CodeTooLarge`] rather than being returned, so it's never loaded.
+    /// `None` allows code of any size.
+    pub max_code_size: Option<usize>,
+}
+
+impl Options {
+    /// Every optimization which keeps one piece of machine code per operation.
+    /// Constant folding is left off, as it throws the operations away entirely.
+    pub fn optimized() -> Self {
+        Options {
+            coalesce: true,
+            strength_reduce: true,
+            ..Options::default()
+        }
+    }
+}
+
+/// The type of integer a compiled function returns. The arithmetic is always done on
+/// 64-bit integers, and only converted to this type at the end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ReturnType {
+    /// `int64_t`
+    #[default]
+    I64,
+    /// `uint64_t`, the same bits as `I64` read as unsigned
+    U64,
+    /// `int32_t`, keeping the bottom 32 bits of the result
+    I32,
+    /// `uint32_t`, keeping the bottom 32 bits of the result
+    U32,
+}
+
+impl ReturnType {
+    /// Convert a 64-bit result to this type, the same way the compiled function does.
+    /// The result is widened back to 64 bits, sign extended for `I32` and zero extended
+    /// for `U32`, which is also what the compiled function leaves in the full register.
+    pub fn convert(self, value: i64) -> i64 {
+        match self {
+            ReturnType::I64 | ReturnType::U64 => value,
+            ReturnType::I32 => i64::from(value as i32),
+            ReturnType::U32 => i64::from(value as u32),
+        }
+    }
+}
+
+/// How the bits of the working register are read by the operations which care.
+/// Adding, subtracting and multiplying wrap around to the same bits either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Signedness {
+    /// Two's complement: `/` rounds towards zero, `>` copies the sign bit and `|` negates
+    /// negative values
+    #[default]
+    Signed,
+    /// `-` from zero wraps around to `u64::MAX`, `/` and `%` divide unsigned, `>` shifts
+    /// in a zero bit and `|` does nothing
+    Unsigned,
+}
+
+/// What happens when an operation's result doesn't fit in the working register.
+/// Other than with `Wrap`, the working register is always signed, and `coalesce` and
+/// `strength_reduce` are ignored, as they would change which operations overflow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum OverflowBehavior {
+    /// Wrap around, keeping the bottom 64 bits of the result
+    #[default]
+    Wrap,
+    /// Return as soon as an operation overflows. The function returns the pair of
+    /// integers described by [`jit_checked`], and `fold_constants` and `return_type`
+    /// are ignored.
+    Checked,
+    /// Clamp the result to `i64::MIN` or `i64::MAX`, whichever is closer
+    Saturate,
+}
+
+/// How halving with `/` rounds when the value is odd. The two only differ for negative
+/// values, and not at all when the working register is unsigned. Dividing by an operand,
+/// as in `/4`, always rounds towards zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum DivRounding {
+    /// Like `/` in Rust and C, so `---/` gives -1
+    #[default]
+    TruncateTowardZero,
+    /// Towards negative infinity, the same as `>`, so `---/` gives -2
+    Floor,
+}
+
+/// A value returned by a compiled function, of the function's [`ReturnType`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value {
+    I64(i64),
+    U64(u64),
+    I32(i32),
+    U32(u32),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::I64(n) => n.fmt(f),
+            Value::U64(n) => n.fmt(f),
+            Value::I32(n) => n.fmt(f),
+            Value::U32(n) => n.fmt(f),
+        }
+    }
+}
+
+/// The 64 bits of the working register, read both as a signed and as an unsigned integer.
+/// A program which overflows into the top half of the unsigned range, such as `-`, is
+/// easier to read one way than the other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bits {
+    pub signed: i64,
+    pub unsigned: u64,
+}
+
+impl From<i64> for Bits {
+    fn from(value: i64) -> Self {
+        Bits {
+            signed: value,
+            unsigned: value as u64,
+        }
+    }
+}
+
+/// The CPU architectures machine code can be generated for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Target {
+    /// x86_64, following the System V calling convention
+    X86_64,
+    /// AArch64 (ARM64), following the AAPCS64 calling convention
+    Aarch64,
+}
+
+impl Target {
+    /// The architecture this program is running on, if there is a backend for it
+    pub const fn host() -> Option<Target> {
+        if cfg!(target_arch = "x86_64") {
+            Some(Target::X86_64)
+        } else if cfg!(target_arch = "aarch64") {
+            Some(Target::Aarch64)
+        } else {
+            None
+        }
+    }
+
+    fn prologue(self, machine_code: &mut Vec<u8>, start: Start) {
+        match self {
+            Target::X86_64 => x86_64::prologue(machine_code, start),
+            Target::Aarch64 => aarch64::prologue(machine_code, start),
+        }
+    }
+
+    fn emit(self, machine_code: &mut Vec<u8>, instruction: &Instruction, options: &Options) {
+        match self {
+            Target::X86_64 => x86_64::emit(machine_code, instruction, options),
+            Target::Aarch64 => aarch64::emit(machine_code, instruction, options),
+        }
+    }
+
+    /// Start a loop running `n` times, nested inside `depth` other loops
+    fn loop_start(self, machine_code: &mut Vec<u8>, depth: usize, n: i64) {
+        match self {
+            Target::X86_64 => x86_64::loop_start(machine_code, depth, n),
+            Target::Aarch64 => aarch64::loop_start(machine_code, depth, n),
+        }
+    }
+
+    /// End a loop nested inside `depth` other loops, whose body starts at byte offset `body`.
+    /// Fails with [`JitError::CodeTooLarge`] if the body is too far back to jump to.
+    fn loop_end(
+        self,
+        machine_code: &mut Vec<u8>,
+        depth: usize,
+        body: usize,
+    ) -> Result<(), JitError> {
+        match self {
+            Target::X86_64 => x86_64::loop_end(machine_code, depth, body),
+            Target::Aarch64 => aarch64::loop_end(machine_code, depth, body),
+        }
+    }
+
+    /// Start skipping the next operation. Returns where the jump over it has to be
+    /// patched by [`Target::patch_skip`] once the operation has been emitted.
+    fn skip(self, machine_code: &mut Vec<u8>) -> usize {
+        match self {
+            Target::X86_64 => x86_64::skip(machine_code),
+            Target::Aarch64 => aarch64::skip(machine_code),
+        }
+    }
+
+    /// Make the jump at `fixup` (from [`Target::skip`]) go to the end of the machine code.
+    /// Fails with [`JitError::CodeTooLarge`] if the end is too far away to jump to.
+    fn patch_skip(self, machine_code: &mut [u8], fixup: usize) -> Result<(), JitError> {
+        match self {
+            Target::X86_64 => x86_64::patch_skip(machine_code, fixup),
+            Target::Aarch64 => aarch64::patch_skip(machine_code, fixup),
+        }
+    }
+
+    fn epilogue(self, machine_code: &mut Vec<u8>, return_type: ReturnType) {
+        match self {
+            Target::X86_64 => x86_64::epilogue(machine_code, return_type),
+            Target::Aarch64 => aarch64::epilogue(machine_code, return_type),
+        }
+    }
+
+    fn emit_saturating(
+        self,
+        machine_code: &mut Vec<u8>,
+        instruction: &Instruction,
+        options: &Options,
+    ) {
+        match self {
+            Target::X86_64 => x86_64::emit_saturating(machine_code, instruction, options),
+            Target::Aarch64 => aarch64::emit_saturating(machine_code, instruction, options),
+        }
+    }
+
+    /// `frame` is whether the stack pointer has to be restored by [`Target::frame_end`]
+    /// before returning early
+    fn emit_checked(
+        self,
+        machine_code: &mut Vec<u8>,
+        index: usize,
+        instruction: &Instruction,
+        options: &Options,
+        frame: bool,
+    ) {
+        match self {
+            Target::X86_64 => {
+                x86_64::emit_checked(machine_code, index, instruction, options, frame)
+            }
+            Target::Aarch64 => {
+                aarch64::emit_checked(machine_code, index, instruction, options, frame)
+            }
+        }
+    }
+
+    /// Save the stack pointer, so the values pushed onto the stack can all be thrown away
+    /// by [`Target::frame_end`]
+    fn frame_start(self, machine_code: &mut Vec<u8>) {
+        match self {
+            Target::X86_64 => x86_64::frame_start(machine_code),
+            Target::Aarch64 => aarch64::frame_start(machine_code),
+        }
+    }
+
+    fn breakpoint(self, machine_code: &mut Vec<u8>) {
+        match self {
+            Target::X86_64 => x86_64::breakpoint(machine_code),
+            Target::Aarch64 => aarch64::breakpoint(machine_code),
+        }
+    }
+
+    fn frame_end(self, machine_code: &mut Vec<u8>) {
+        match self {
+            Target::X86_64 => x86_64::frame_end(machine_code),
+            Target::Aarch64 => aarch64::frame_end(machine_code),
+        }
+    }
+
+    fn epilogue_checked(self, machine_code: &mut Vec<u8>) {
+        match self {
+            Target::X86_64 => x86_64::epilogue_checked(machine_code),
+            Target::Aarch64 => aarch64::epilogue_checked(machine_code),
+        }
+    }
+
+    fn prologue_float(self, machine_code: &mut Vec<u8>) {
+        match self {
+            Target::X86_64 => x86_64::prologue_float(machine_code),
+            Target::Aarch64 => aarch64::prologue_float(machine_code),
+        }
+    }
+
+    fn emit_float(
+        self,
+        machine_code: &mut Vec<u8>,
+        instruction: &Instruction,
+        constants: &mut Constants,
+    ) {
+        match self {
+            Target::X86_64 => x86_64::emit_float(machine_code, instruction, constants),
+            Target::Aarch64 => aarch64::emit_float(machine_code, instruction, constants),
+        }
+    }
+
+    /// Like [`Target::skip`], for the floating point working register
+    fn skip_float(self, machine_code: &mut Vec<u8>) -> usize {
+        match self {
+            Target::X86_64 => x86_64::skip_float(machine_code),
+            Target::Aarch64 => aarch64::skip_float(machine_code),
+        }
+    }
+
+    /// Fails if the constants are placed too far away for the code to load them
+    fn epilogue_float(
+        self,
+        machine_code: &mut Vec<u8>,
+        constants: &Constants,
+    ) -> Result<(), JitError> {
+        match self {
+            Target::X86_64 => {
+                x86_64::epilogue_float(machine_code, constants);
+                Ok(())
+            }
+            Target::Aarch64 => aarch64::epilogue_float(machine_code, constants),
+        }
+    }
+
+    /// Emit a step of an expression compiled by [`jit_expr`]
+    fn emit_step(self, machine_code: &mut Vec<u8>, step: &Step) {
+        match self {
+            Target::X86_64 => x86_64::emit_step(machine_code, step),
+            Target::Aarch64 => aarch64::emit_step(machine_code, step),
+        }
+    }
+
+    fn return_constant(self, value: i64) -> Vec<u8> {
+        match self {
+            Target::X86_64 => x86_64::return_constant(value),
+            Target::Aarch64 => aarch64::return_constant(value),
+        }
+    }
+}
+
+/// The host architecture. Architectures without a backend get x86_64 code,
+/// which can be inspected but not run; use [`interpret`] or [`evaluate`] there instead.
+impl Default for Target {
+    fn default() -> Self {
+        Target::host().unwrap_or(Target::X86_64)
+    }
+}
+
+/// Where the working register gets its initial value from
+#[derive(Clone, Copy)]
+pub(crate) enum Start {
+    /// The working register starts at a value fixed at compile time
+    Constant(i64),
+    /// The working register starts at the value of the first integer argument
+    Input,
+}
+
+/// Compile the sequence of instructions into working machine code for the host
+/// architecture following the C calling convention. The type of the function
+/// produced (in C notation) is: `int64_t f()`
+pub fn jit(program: &str) -> Result<Vec<u8>, JitError> {
+    jit_from(program, 0)
+}
+
+/// Like [`jit`], but the working register starts at `start` instead of zero.
+/// Unlike [`jit_with_input`], the starting value is fixed when the program is compiled.
+/// The type of the function produced (in C notation) is: `int64_t f()`
+pub fn jit_from(program: &str, start: i64) -> Result<Vec<u8>, JitError> {
+    let tokens = tokenize(program)?;
+    codegen(&tokens, Start::Constant(start), &Options::default())
+}
+
+/// Parse a program string into the operations it's made of, the same way [`jit`] does.
+/// Groups are unrolled, and comments and whitespace dropped.
+/// The result can be inspected or rewritten, and then compiled with [`jit_ops`].
+pub fn parse(program: &str) -> Result<Vec<Op>, JitError> {
+    tokenize(program)
+}
+
+/// Like [`jit`], but compiles a sequence of operations directly instead of parsing
+/// a program string. The operations are checked the way [`parse`] checks the ones it
+/// produces: it's a [`JitError::InvalidOperation`] for them not to be in the form
+/// [`parse`] gives, and a [`JitError::EmptyStack`] or [`JitError::StackTooDeep`] for the
+/// stack to be misused. Like an empty program string, an empty list of operations is a
+/// [`JitError::EmptyProgram`].
+/// The type of the function produced (in C notation) is: `int64_t f()`
+pub fn jit_ops(ops: &[Op]) -> Result<Vec<u8>, JitError> {
+    check_ops(ops)?;
+    codegen(ops, Start::Constant(0), &Options::default())
+}
+
+/// Like [`jit`], but optimizations can be turned on and another architecture
+/// can be targeted through `options`.
+/// The type of the function produced (in C notation) is: `int64_t f()`
+pub fn jit_with_options(program: &str, options: Options) -> Result<Vec<u8>, JitError> {
+    let mut tokens = tokenize(program)?;
+    let machine_code = if options.fold_constants && options.overflow != OverflowBehavior::Checked {
+        let value = options.return_type.convert(fold(&tokens, 0, &options));
+        options.target.return_constant(value)
+    } else {
+        if options.coalesce && options.overflow == OverflowBehavior::Wrap {
+            tokens = coalesce(tokens);
+        }
+        codegen(&tokens, Start::Constant(0), &options)?
+    };
+    match options.max_code_size {
+        Some(limit) if machine_code.len() > limit => Err(JitError::CodeTooLarge {
+            size: machine_code.len(),
+            limit,
+        }),
+        _ => Ok(machine_code),
+    }
+}
+
+/// Like [`jit`], but the code stops at a breakpoint (`int3` on x86_64, `brk #0` on
+/// AArch64) once the prologue has run, for stepping through it in a debugger.
+/// This is [`jit_with_options`] with [`Options::breakpoint`] set.
+/// Running it with [`run`] outside a debugger kills the process with SIGTRAP.
+/// The type of the function produced (in C notation) is: `int64_t f()`
+pub fn jit_debug(program: &str) -> Result<Vec<u8>, JitError> {
+    let options = Options {
+        breakpoint: true,
+        ..Options::default()
+    };
+    jit_with_options(program, options)
+}
+
+/// Like [`jit`], but the working register is unsigned, so `-/` gives `u64::MAX / 2`
+/// rather than 0. This is [`jit_with_options`] with [`Signedness::Unsigned`] and
+/// [`ReturnType::U64`]. Use [`run_unsigned`] to run it.
+/// The type of the function produced (in C notation) is: `uint64_t f()`
+pub fn jit_unsigned(program: &str) -> Result<Vec<u8>, JitError> {
+    let options = Options {
+        signedness: Signedness::Unsigned,
+        return_type: ReturnType::U64,
+        ..Options::default()
+    };
+    jit_with_options(program, options)
+}
+
+/// Like [`jit`], but the function returns a `return_type` instead of an `int64_t`.
+/// The value is worked out with 64-bit arithmetic as usual, and only converted at the end,
+/// so `=2147483647+` returns `i32::MIN` as an `I32`. Use [`run_typed`] to run it.
+/// This is [`jit_with_options`] with [`Options::return_type`] set.
+pub fn jit_typed(program: &str, return_type: ReturnType) -> Result<Vec<u8>, JitError> {
+    let options = Options {
+        return_type,
+        ..Options::default()
+    };
+    jit_with_options(program, options)
+}
+
+/// Like [`jit`], but the working register starts at the value of the first argument
+/// instead of zero. The type of the function produced (in C notation) is:
+/// `int64_t f(int64_t x)`
+pub fn jit_with_input(program: &str) -> Result<Vec<u8>, JitError> {
+    let tokens = tokenize(program)?;
+    codegen(&tokens, Start::Input, &Options::default())
+}
+
+/// Like [`jit`], but the whole program is evaluated at compile time (constant folded),
+/// so the machine code produced only has to return the result.
+/// This is [`jit_with_options`] with [`Options::fold_constants`] set.
+/// The type of the function produced (in C notation) is: `int64_t f()`
+pub fn jit_folded(program: &str) -> Result<Vec<u8>, JitError> {
+    let options = Options {
+        fold_constants: true,
+        ..Options::default()
+    };
+    jit_with_options(program, options)
+}
+
+/// Like [`jit`], but the code produced detects when an operation overflows the
+/// 64-bit working register, instead of silently wrapping around.
+///
+/// The function returns a pair of integers. In C notation its type is
+/// `struct { int64_t value; int64_t overflowed; } f()`.
+/// `overflowed` is 0 when the program ran to completion, in which case `value` is the result.
+/// Otherwise `overflowed` is one more than the index of the operation which overflowed
+/// (see [`Overflow::at_op`]), and `value` is meaningless.
+/// Both are returned in registers (rax and rdx on x86_64, x0 and x1 on AArch64),
+/// so no result value has to be given up as a sentinel. Use [`run_checked`] to run it.
+/// On Windows the x86_64 code still returns the pair the System V way, so it has to be
+/// called as an `extern "sysv64"` function.
+/// This is [`jit_with_options`] with [`OverflowBehavior::Checked`].
+pub fn jit_checked(program: &str) -> Result<Vec<u8>, JitError> {
+    let options = Options {
+        overflow: OverflowBehavior::Checked,
+        ..Options::default()
+    };
+    jit_with_options(program, options)
+}
+
+/// Like [`jit`], but operations which overflow clamp the working register to `i64::MIN`
+/// or `i64::MAX` instead of wrapping around, so `+` does nothing once it reaches `i64::MAX`.
+/// This is [`jit_with_options`] with [`OverflowBehavior::Saturate`].
+/// The type of the function produced (in C notation) is: `int64_t f()`
+pub fn jit_saturating(program: &str) -> Result<Vec<u8>, JitError> {
+    let options = Options {
+        overflow: OverflowBehavior::Saturate,
+        ..Options::default()
+    };
+    jit_with_options(program, options)
+}
+
+/// Like [`jit`], but the working register holds a floating point number (an `f64`)
+/// instead of an integer, so `/` halves exactly and `+/` gives 0.5. `%` takes the
+/// remainder the same way `fmod` does in C, keeping the sign of the working register.
+/// Nothing wraps around; overflowing gives infinity instead.
+/// On x86_64 `%` and `>` need SSE4.1.
+///
+/// The constants the operations use are kept in a literal pool after the function's code.
+/// The type of the function produced (in C notation) is: `double f()`.
+/// Use [`run_float`] to run it.
+pub fn jit_float(program: &str) -> Result<Vec<u8>, JitError> {
+    let tokens = tokenize(program)?;
+    codegen_float(&tokens, Target::default())
+}
+
+/// Compile the tokens to floating point code for `target`, which is what [`jit_float`]
+/// does for the host
+fn codegen_float(tokens: &[Op], target: Target) -> Result<Vec<u8>, JitError> {
+    let instructions = ir::lower(tokens)?;
+    let mut machine_code: Vec<u8> = Vec::new();
+    let mut constants = Constants::new();
+    target.prologue_float(&mut machine_code);
+    let frame = uses_stack(tokens);
+    if frame {
+        target.frame_start(&mut machine_code);
+    }
+    emit_all(
+        &mut machine_code,
+        &instructions,
+        target,
+        |machine_code, _, token| target.emit_float(machine_code, token, &mut constants),
+        |machine_code| target.skip_float(machine_code),
+    )?;
+    if frame {
+        target.frame_end(&mut machine_code);
+    }
+    target.epilogue_float(&mut machine_code, &constants)?;
+    Ok(machine_code)
+}
+
+/// Like [`jit`], but also returns a side table recording which bytes of the
+/// machine code were emitted for each operation. See [`annotate`].
+pub fn jit_with_spans(program: &str) -> Result<(Vec<u8>, Vec<Span>), JitError> {
+    let tokens = tokenize(program)?;
+    let (machine_code, offsets) =
+        codegen_with_offsets(&tokens, Start::Constant(0), &Options::default())?;
+    let spans = offsets
+        .windows(2)
+        .zip(tokens)
+        .map(|(w, op)| Span {
+            bytes: w[0]..w[1],
+            op,
+        })
+        .collect();
+    Ok((machine_code, spans))
+}
+
+/// The x86_64 assembly for the code [`jit`] generates, in AT&T syntax. Assembling it
+/// with the GNU assembler (`as`) gives exactly the bytes [`jit`] returns on x86_64.
+/// The instructions for each operation are preceded by a comment naming it.
+pub fn to_assembly(program: &str) -> Result<String, JitError> {
+    let tokens = tokenize(program)?;
+    x86_64::assembly(&tokens, Start::Constant(0), &Options::default())
+}
+
+/// Step 1, tokenize the string into operations
+/// An operator may be followed by a decimal number, which replaces its implicit operand
+/// (1 for `+` and `-`, 2 for `*`, `/` and `%`). `=` must be followed by a number, which may be negative.
+/// Other than after `=`, a number can't start with `0`, so `+0` is `+` followed by `0`.
+/// Whitespace, and everything from a `#` to the end of the line, is ignored.
+///
+/// Operations can be grouped in parentheses, with a leading count to repeat the group
+/// that many times, so `3(+*)` is the same as `+*+*+*`. Groups can be nested, and are
+/// unrolled here, so the operations returned never contain any groups.
+/// Square brackets work the same way, except they become a loop in the generated code
+/// ([`Op::Loop`] and [`Op::EndLoop`]) rather than being unrolled.
+fn tokenize(program: &str) -> Result<Vec<Op>, JitError> {
+    let mut tokens: Vec<Op> = Vec::new();
+    let mut chars = program.char_indices().peekable();
+    // The groups and loops which are still open, innermost last
+    let mut open: Vec<Open> = Vec::new();
+    // Where the last `?` was. It's an error for one to be the last operation of a loop,
+    // or of the whole program.
+    let mut last_skip = 0;
+
+    while let Some((index, c)) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        // Comments run from `#` to the end of the line
+        if c == '#' {
+            while chars.next_if(|(_, c)| *c != '\n').is_some() {}
+            continue;
+        }
+        if c == '=' {
+            let negative = chars.next_if(|(_, c)| *c == '-').is_some();
+            let n = operand(&mut chars, index)?.ok_or(JitError::MissingOperand { index })?;
+            tokens.push(Op::Set(if negative { -n } else { n }));
+            continue;
+        }
+        // A lone `0` resets the working register, rather than being a repeat count
+        if c == '0'
+            && !chars
+                .peek()
+                .is_some_and(|(_, c)| c.is_ascii_digit() || *c == '(' || *c == '[')
+        {
+            tokens.push(Op::Zero);
+            continue;
+        }
+        if let Some(digit) = c.to_digit(10) {
+            let count = repeat_count(&mut chars, index, digit)?;
+            let Some((_, bracket)) = chars.next_if(|(_, c)| *c == '(' || *c == '[') else {
+                return Err(JitError::MissingGroup { index });
+            };
+            if count == 0 {
+                return Err(JitError::ZeroRepeat { index });
+            }
+            open_bracket(&mut open, &mut tokens, bracket, index, count)?;
+            continue;
+        }
+        // A group or loop without a count is run once
+        if c == '(' || c == '[' {
+            open_bracket(&mut open, &mut tokens, c, index, 1)?;
+            continue;
+        }
+        if c == ')' {
+            let Some(Open::Group {
+                index: start,
+                count,
+                before,
+            }) = open.pop()
+            else {
+                return Err(JitError::UnbalancedBracket { index });
+            };
+            let group = core::mem::replace(&mut tokens, before);
+            let length = group.len().checked_mul(count).map(|n| n + tokens.len());
+            if length.is_none_or(|n| n > MAX_OPERATIONS) {
+                return Err(JitError::TooManyOperations { index: start });
+            }
+            for _ in 0..count {
+                tokens.extend_from_slice(&group);
+            }
+            continue;
+        }
+        if c == ']' {
+            let Some(Open::Loop { .. }) = open.pop() else {
+                return Err(JitError::UnbalancedBracket { index });
+            };
+            if tokens.last() == Some(&Op::Skip) {
+                return Err(JitError::NothingToSkip { index: last_skip });
+            }
+            tokens.push(Op::EndLoop);
+            continue;
+        }
+        let op = Op::try_from(c).map_err(|character| {
+            let (line, column) = line_column(program, index);
+            JitError::UnknownCharacter {
+                character,
+                index,
+                line,
+                column,
+            }
+        })?;
+        if op == Op::Skip {
+            last_skip = index;
+        }
+        // Numbers can't start with a zero, so a `0` straight after an operator is the
+        // operation which resets the working register. Except after `/` and `%`, where
+        // it's far more likely to be a mistaken division by zero.
+        let zero_next = chars.peek().is_some_and(|(_, c)| *c == '0');
+        let operand = match op {
+            Op::Slash | Op::Percent if zero_next => return Err(JitError::DivisionByZero { index }),
+            Op::Plus | Op::Minus | Op::Star | Op::Slash | Op::Percent if !zero_next => {
+                operand(&mut chars, index)?
+            }
+            _ => None,
+        };
+        let t = match (op, operand) {
+            (op, None) => op,
+            (Op::Plus, Some(n)) => Op::Add(n),
+            (Op::Minus, Some(n)) => Op::Sub(n),
+            (Op::Star, Some(n)) => Op::Mul(n),
+            (Op::Slash, Some(n)) => Op::Div(n),
+            (Op::Percent, Some(n)) => Op::Rem(n),
+            _ => unreachable!(),
+        };
+        tokens.push(t);
+    }
+    if let Some(Open::Group { index, .. } | Open::Loop { index }) = open.pop() {
+        return Err(JitError::UnbalancedBracket { index });
+    }
+    if tokens.last() == Some(&Op::Skip) {
+        return Err(JitError::NothingToSkip { index: last_skip });
+    }
+    if tokens.is_empty() {
+        return Err(JitError::EmptyProgram);
+    }
+    check_stack(&tokens)?;
+    Ok(tokens)
+}
+
+/// Check that operations which didn't come from [`tokenize`] are in the form it gives,
+/// so none of the steps after it have to check again
+fn check_ops(ops: &[Op]) -> Result<(), JitError> {
+    if ops.is_empty() {
+        return Err(JitError::EmptyProgram);
+    }
+    if ops.len() > MAX_OPERATIONS {
+        return Err(JitError::InvalidOperation {
+            at_op: MAX_OPERATIONS,
+        });
+    }
+    // The loops which are still open, innermost last
+    let mut loops: Vec<usize> = Vec::new();
+    for (at_op, op) in ops.iter().enumerate() {
+        let valid = match op {
+            Op::Loop(n) => {
+                loops.push(at_op);
+                *n >= 1 && loops.len() <= MAX_LOOP_DEPTH
+            }
+            Op::EndLoop => loops.pop().is_some(),
+            Op::Skip => !matches!(ops.get(at_op + 1), None | Some(Op::EndLoop)),
+            // Dividing `i64::MIN` by -1 overflows, which traps on x86_64
+            Op::Div(n) | Op::Rem(n) => *n >= 1,
+            _ => true,
+        };
+        if !valid {
+            return Err(JitError::InvalidOperation { at_op });
+        }
+    }
+    if let Some(&at_op) = loops.last() {
+        return Err(JitError::InvalidOperation { at_op });
+    }
+    check_stack(ops)
+}
+
+/// Check that every `s` has something on the stack to swap with, and that no more than
+/// [`MAX_STACK_DEPTH`] values are ever pushed. Nothing is ever popped, so this only
+/// needs to know how many values there are at least when each `s` runs, which is the
+/// number of `d`s before it that are sure to run, and how many there can be at most.
+fn check_stack(tokens: &[Op]) -> Result<(), JitError> {
+    let mut depth = StackDepth { fewest: 0, most: 0 };
+    stack_depth(tokens, 0, &mut depth)
+}
+
+/// The fewest and the most values there can be on the stack at some point in a program
+#[derive(Clone, Copy)]
+struct StackDepth {
+    fewest: usize,
+    most: usize,
+}
+
+/// Update `depth` by the operations in `tokens`, the first of which is at index `start`
+/// of the whole program
+fn stack_depth(tokens: &[Op], start: usize, depth: &mut StackDepth) -> Result<(), JitError> {
+    let mut i = 0;
+    while let Some(token) = tokens.get(i) {
+        let at_op = start + i;
+        i += 1;
+        match token {
+            Op::Dup => {
+                depth.fewest += 1;
+                depth.most += 1;
+            }
+            Op::Swap if depth.fewest == 0 => return Err(JitError::EmptyStack { at_op }),
+            Op::Loop(n) => {
+                let length = loop_length(&tokens[i..]);
+                let before = *depth;
+                // Later runs of the body start with at least as many values on the stack
+                // as the first, so checking the first is enough. Each pushes as many again.
+                stack_depth(&tokens[i..i + length], start + i, depth)?;
+                // Loops always run at least once, which `check_ops` makes sure of
+                let again = usize::try_from(*n).map_or(0, |n| n.saturating_sub(1));
+                let fewest = (depth.fewest - before.fewest).saturating_mul(again);
+                let most = (depth.most - before.most).saturating_mul(again);
+                depth.fewest = depth.fewest.saturating_add(fewest);
+                depth.most = depth.most.saturating_add(most);
+                i += length + 1;
+            }
+            // The next operation might not run, so the values it pushes can't be counted on
+            Op::Skip => {
+                let length = match tokens.get(i) {
+                    Some(Op::Loop(_)) => loop_length(&tokens[i + 1..]) + 2,
+                    Some(_) => 1,
+                    None => 0,
+                };
+                let fewest = depth.fewest;
+                stack_depth(&tokens[i..i + length], start + i, depth)?;
+                depth.fewest = fewest;
+                i += length;
+            }
+            _ => {}
+        }
+        if depth.most > MAX_STACK_DEPTH {
+            return Err(JitError::StackTooDeep { at_op });
+        }
+    }
+    Ok(())
+}
+
+/// A group or loop which has been opened, but not closed yet
+enum Open {
+    /// A group starting at byte offset `index`, repeated `count` times.
+    /// `before` holds the operations which came before the group.
+    Group {
+        index: usize,
+        count: usize,
+        before: Vec<Op>,
+    },
+    /// A loop starting at byte offset `index`. Its operations are added to the others
+    /// as they're read.
+    Loop { index: usize },
+}
+
+/// Open a group (`bracket` is `(`) or a loop (`bracket` is `[`) repeated `count` times
+fn open_bracket(
+    open: &mut Vec<Open>,
+    tokens: &mut Vec<Op>,
+    bracket: char,
+    index: usize,
+    count: usize,
+) -> Result<(), JitError> {
+    if bracket == '(' {
+        let before = core::mem::take(tokens);
+        open.push(Open::Group {
+            index,
+            count,
+            before,
+        });
+        return Ok(());
+    }
+    let depth = open
+        .iter()
+        .filter(|o| matches!(o, Open::Loop { .. }))
+        .count();
+    if depth == MAX_LOOP_DEPTH {
+        return Err(JitError::LoopTooDeep { index });
+    }
+    open.push(Open::Loop { index });
+    tokens.push(Op::Loop(count as i64));
+    Ok(())
+}
+
+/// The line and column (both counting from 1) of the character at byte offset `index`
+pub(crate) fn line_column(program: &str, index: usize) -> (usize, usize) {
+    let before = &program[..index];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let column = before[line_start..].chars().count() + 1;
+    (line, column)
+}
+
+/// Read the decimal number directly following the operator at byte offset `index`, if any.
+/// Operands are limited to `i32::MAX` so they fit in an instruction's immediate.
+/// A number followed by `(` or `[` is the repeat count of a group or loop, so it isn't read.
+fn operand(chars: &mut Peekable<CharIndices>, index: usize) -> Result<Option<i64>, JitError> {
+    let mut ahead = chars.clone();
+    while ahead.next_if(|(_, c)| c.is_ascii_digit()).is_some() {}
+    if ahead.peek().is_some_and(|(_, c)| *c == '(' || *c == '[') {
+        return Ok(None);
+    }
+    let mut operand: Option<i64> = None;
+    while let Some(digit) = chars.peek().and_then(|(_, c)| c.to_digit(10)) {
+        chars.next();
+        let n = operand.unwrap_or(0) * 10 + i64::from(digit);
+        if n > i64::from(i32::MAX) {
+            return Err(JitError::OperandOutOfRange { index });
+        }
+        operand = Some(n);
+    }
+    Ok(operand)
+}
+
+/// Read the rest of the repeat count at byte offset `index`, whose first digit has
+/// already been read. Counts are limited to `i32::MAX` like operands.
+fn repeat_count(
+    chars: &mut Peekable<CharIndices>,
+    index: usize,
+    first: u32,
+) -> Result<usize, JitError> {
+    let mut count = first as usize;
+    while let Some(digit) = chars.peek().and_then(|(_, c)| c.to_digit(10)) {
+        chars.next();
+        count = count * 10 + digit as usize;
+        if count > i32::MAX as usize {
+            return Err(JitError::OperandOutOfRange { index });
+        }
+    }
+    Ok(count)
+}
+
+/// Replace each run of `+` and `-` with a single addition of their net effect.
+/// A run which cancels itself out is removed entirely. Nothing else is cancelled, as
+/// `*/` and `/*` only undo each other when no bits are lost off either end.
+///
+/// Everything before a `0` or `=n` outside of any loop is removed too, as nothing
+/// but the final value can be observed, and it no longer depends on those operations.
+fn coalesce(tokens: Vec<Op>) -> Vec<Op> {
+    let mut optimized: Vec<Op> = Vec::new();
+    let mut net: i64 = 0;
+    // The operation after a skip can't be merged with others, or they'd be skipped too
+    let mut after_skip = false;
+    // How many loops the current operation is inside
+    let mut depth = 0;
+    // How many of the optimized operations have to be kept, as they push values onto the
+    // stack, which a `0` or `=n` doesn't throw away
+    let mut kept = 0;
+    // Whether there has been a stack operation since `kept` was last moved up to the end.
+    // Inside a loop, the whole loop has to be kept.
+    let mut uses_stack = false;
+
+    for token in tokens {
+        match token {
+            Op::Loop(_) => depth += 1,
+            Op::EndLoop => depth -= 1,
+            Op::Dup | Op::Swap => uses_stack = true,
+            _ => {}
+        }
+        if after_skip {
+            after_skip = token == Op::Skip;
+            optimized.push(token);
+            if depth == 0 && uses_stack {
+                kept = optimized.len();
+                uses_stack = false;
+            }
+            continue;
+        }
+        let delta = match token {
+            Op::Plus => 1,
+            Op::Minus => -1,
+            Op::Add(n) => n,
+            Op::Sub(n) => n.wrapping_neg(),
+            Op::Zero | Op::Set(_) if depth == 0 => {
+                optimized.truncate(kept);
+                net = 0;
+                optimized.push(token);
+                continue;
+            }
+            token => {
+                push_addition(&mut optimized, net);
+                net = 0;
+                after_skip = token == Op::Skip;
+                optimized.push(token);
+                if depth == 0 && uses_stack {
+                    kept = optimized.len();
+                    uses_stack = false;
+                }
+                continue;
+            }
+        };
+        net = net.wrapping_add(delta);
+    }
+    push_addition(&mut optimized, net);
+    optimized
+}
+
+/// Push the shortest operation which adds `n` to the working register
+fn push_addition(tokens: &mut Vec<Op>, n: i64) {
+    match n {
+        0 => {}
+        1 => tokens.push(Op::Plus),
+        -1 => tokens.push(Op::Minus),
+        n => tokens.push(Op::Add(n)),
+    }
+}
+
+/// Step 2: Compile
+/// The tokens are lowered to [`ir::Instruction`]s, which the backend for the target
+/// compiles to machine code.
+/// Fails if the tokens aren't in the form [`tokenize`] gives, or if the code jumps
+/// further than the target can, as described at [`emit_all`].
+fn codegen(tokens: &[Op], start: Start, options: &Options) -> Result<Vec<u8>, JitError> {
+    Ok(codegen_with_offsets(tokens, start, options)?.0)
+}
+
+/// Like [`codegen`], but also returns the byte offset at which each token's code starts,
+/// followed by the offset of the epilogue.
+fn codegen_with_offsets(
+    tokens: &[Op],
+    start: Start,
+    options: &Options,
+) -> Result<(Vec<u8>, Vec<usize>), JitError> {
+    let instructions = ir::lower(tokens)?;
+    let target = options.target;
+    let mut machine_code: Vec<u8> = Vec::new();
+    target.prologue(&mut machine_code, start);
+    let frame = uses_stack(tokens);
+    if frame {
+        target.frame_start(&mut machine_code);
+    }
+    if options.breakpoint {
+        target.breakpoint(&mut machine_code);
+    }
+    let skip = |machine_code: &mut Vec<u8>| target.skip(machine_code);
+    // Checked and saturating code only keeps the options which don't change which
+    // operations overflow
+    let plain = Options {
+        div_rounding: options.div_rounding,
+        ..Options::default()
+    };
+    let mut offsets = match options.overflow {
+        OverflowBehavior::Wrap => emit_all(
+            &mut machine_code,
+            &instructions,
+            target,
+            |machine_code, _, token| target.emit(machine_code, token, options),
+            skip,
+        ),
+        OverflowBehavior::Checked => emit_all(
+            &mut machine_code,
+            &instructions,
+            target,
+            |machine_code, index, token| {
+                target.emit_checked(machine_code, index, token, &plain, frame)
+            },
+            skip,
+        ),
+        OverflowBehavior::Saturate => emit_all(
+            &mut machine_code,
+            &instructions,
+            target,
+            |machine_code, _, token| target.emit_saturating(machine_code, token, &plain),
+            skip,
+        ),
+    }?;
+    offsets.push(machine_code.len());
+    if frame {
+        target.frame_end(&mut machine_code);
+    }
+    match options.overflow {
+        OverflowBehavior::Checked => target.epilogue_checked(&mut machine_code),
+        _ => target.epilogue(&mut machine_code, options.return_type),
+    }
+    Ok((machine_code, offsets))
+}
+
+/// Whether a program pushes anything onto the stack, so it needs a call frame to restore
+/// the stack pointer from when it returns
+pub(crate) fn uses_stack(tokens: &[Op]) -> bool {
+    tokens
+        .iter()
+        .any(|token| matches!(token, Op::Dup | Op::Swap))
+}
+
+/// Emit the instructions lowered by [`ir::lower`] with `emit`, which is also given the
+/// index of the operation each is for, except for loops and skips, which are handled here
+/// as they jump to other places in the code. The jump of a skip comes from `skip`, which
+/// works like [`Target::skip`]. Returns the byte offset the instructions for each
+/// operation start at.
+/// Fails with [`JitError::InvalidOperation`] for a jump to a label which is never placed,
+/// or a loop which counts down to a body that hasn't been placed before it, naming the
+/// operation the jump is for. Fails with [`JitError::CodeTooLarge`] for a jump further
+/// than the target's branches reach.
+fn emit_all(
+    machine_code: &mut Vec<u8>,
+    instructions: &[(usize, Instruction)],
+    target: Target,
+    mut emit: impl FnMut(&mut Vec<u8>, usize, &Instruction),
+    mut skip: impl FnMut(&mut Vec<u8>) -> usize,
+) -> Result<Vec<usize>, JitError> {
+    let mut offsets: Vec<usize> = Vec::new();
+    // Where each label which has been placed is
+    let mut labels: Vec<Option<usize>> = Vec::new();
+    // The jumps to labels which haven't been placed yet, where their offsets are, and the
+    // index of the operation each is for
+    let mut fixups: Vec<(Label, usize, usize)> = Vec::new();
+    for &(index, ref instruction) in instructions {
+        if index == offsets.len() {
+            offsets.push(machine_code.len());
+        }
+        match *instruction {
+            Instruction::SetCounter { depth, n } => target.loop_start(machine_code, depth, n),
+            Instruction::Label(label) => {
+                if labels.len() <= label.0 {
+                    labels.resize(label.0 + 1, None);
+                }
+                labels[label.0] = Some(machine_code.len());
+                if let Some(i) = fixups.iter().position(|&(to, _, _)| to == label) {
+                    target.patch_skip(machine_code, fixups.swap_remove(i).1)?;
+                }
+            }
+            Instruction::CountDown { depth, body } => {
+                let Some(&Some(body)) = labels.get(body.0) else {
+                    return Err(JitError::InvalidOperation { at_op: index });
+                };
+                target.loop_end(machine_code, depth, body)?;
+            }
+            Instruction::JumpIfZero(label) => fixups.push((label, skip(machine_code), index)),
+            ref instruction => emit(machine_code, index, instruction),
+        }
+    }
+    if let Some(&(_, _, at_op)) = fixups.first() {
+        return Err(JitError::InvalidOperation { at_op });
+    }
+    Ok(offsets)
+}
+
+/// The floating point constants which floating point code loads, each with where in the
+/// code it's loaded. The meaning of the position depends on the backend.
+pub(crate) type Constants = Vec<(usize, f64)>;
+
+/// Place a literal pool holding each distinct constant after the code, aligned to 8 bytes.
+/// Returns each position from `constants`, along with the byte offset of its constant.
+pub(crate) fn literal_pool(
+    machine_code: &mut Vec<u8>,
+    constants: &Constants,
+) -> Vec<(usize, usize)> {
+    machine_code.resize(machine_code.len().next_multiple_of(8), 0);
+    let mut pool: Vec<u64> = Vec::new();
+    let start = machine_code.len();
+    let mut addresses = Vec::new();
+    for &(position, value) in constants {
+        let bits = value.to_bits();
+        let index = pool.iter().position(|b| *b == bits).unwrap_or_else(|| {
+            pool.push(bits);
+            machine_code.extend_from_slice(&bits.to_le_bytes());
+            pool.len() - 1
+        });
+        addresses.push((position, start + index * 8));
+    }
+    addresses
+}
+
+/// If `n` is a power of two larger than 1, the power it raises 2 to
+pub(crate) fn exact_log2(n: i64) -> Option<u32> {
+    (n > 1 && n.count_ones() == 1).then(|| n.trailing_zeros())
+}
+
+/// Evaluate the tokens in Rust, with the working register starting at `value`.
+/// This matches the semantics of the generated machine code exactly:
+/// arithmetic wraps on overflow and halving rounds towards zero.
+/// With [`Signedness::Unsigned`] the bits of `value` are divided and shifted as a `u64`,
+/// and with [`OverflowBehavior::Saturate`] results are clamped rather than wrapped.
+fn fold(tokens: &[Op], value: i64, options: &Options) -> i64 {
+    fold_with_stack(tokens, value, &mut Vec::new(), options)
+}
+
+/// Like [`fold`], starting with the values already on the stack
+fn fold_with_stack(tokens: &[Op], mut value: i64, stack: &mut Vec<i64>, options: &Options) -> i64 {
+    let saturate = options.overflow == OverflowBehavior::Saturate;
+    let unsigned = options.signedness == Signedness::Unsigned && !saturate;
+    let floor = options.div_rounding == DivRounding::Floor;
+    let mut i = 0;
+    while let Some(token) = tokens.get(i) {
+        i += 1;
+        value = match token {
+            Op::Loop(n) => {
+                let body = &tokens[i..i + loop_length(&tokens[i..])];
+                i += body.len() + 1;
+                for _ in 0..*n {
+                    value = fold_with_stack(body, value, stack, options);
+                }
+                value
+            }
+            Op::EndLoop => unreachable!("unbalanced loops"),
+            Op::Skip if value == 0 => {
+                i += match tokens.get(i) {
+                    Some(Op::Loop(_)) => loop_length(&tokens[i + 1..]) + 2,
+                    _ => 1,
+                };
+                value
+            }
+            Op::Skip => value,
+            Op::Dup => {
+                stack.push(value);
+                value
+            }
+            Op::Swap => {
+                core::mem::replace(stack.last_mut().expect("checked by check_stack"), value)
+            }
+            Op::Plus if saturate => value.saturating_add(1),
+            Op::Minus if saturate => value.saturating_sub(1),
+            Op::Star | Op::Less if saturate => value.saturating_mul(2),
+            Op::Caret if saturate => value.saturating_mul(value),
+            Op::Tilde if saturate => value.saturating_neg(),
+            Op::Bar if saturate => value.saturating_abs(),
+            Op::Add(n) if saturate => value.saturating_add(*n),
+            Op::Sub(n) if saturate => value.saturating_sub(*n),
+            Op::Mul(n) if saturate => value.saturating_mul(*n),
+            Op::Slash if unsigned => (value as u64 / 2) as i64,
+            Op::Percent if unsigned => (value as u64 % 2) as i64,
+            Op::Greater if unsigned => (value as u64 >> 1) as i64,
+            Op::Bar | Op::Clamp if unsigned => value,
+            Op::Slash if floor => value >> 1,
+            Op::Div(n) if unsigned => (value as u64 / *n as u64) as i64,
+            Op::Rem(n) if unsigned => (value as u64 % *n as u64) as i64,
+            Op::Plus => value.wrapping_add(1),
+            Op::Minus => value.wrapping_sub(1),
+            Op::Star => value.wrapping_mul(2),
+            Op::Slash => value / 2,
+            Op::Percent => value % 2,
+            Op::Caret => value.wrapping_mul(value),
+            Op::Tilde => value.wrapping_neg(),
+            Op::Zero => 0,
+            Op::Less => value.wrapping_shl(1),
+            Op::Greater => value >> 1,
+            Op::Bar => value.wrapping_abs(),
+            Op::Clamp => value.max(0),
+            Op::Add(n) => value.wrapping_add(*n),
+            Op::Sub(n) => value.wrapping_sub(*n),
+            Op::Mul(n) => value.wrapping_mul(*n),
+            Op::Div(n) => value.wrapping_div(*n),
+            Op::Rem(n) => value.wrapping_rem(*n),
+            Op::Set(n) => *n,
+        };
+    }
+    value
+}
+
+/// How many operations the body of a loop has. `tokens` starts just after the loop's
+/// [`Op::Loop`], and the body ends at the matching [`Op::EndLoop`].
+fn loop_length(tokens: &[Op]) -> usize {
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            Op::Loop(_) => depth += 1,
+            Op::EndLoop if depth == 0 => return i,
+            Op::EndLoop => depth -= 1,
+            _ => {}
+        }
+    }
+    panic!("unbalanced loops")
+}
+
+/// Evaluate a program directly in Rust, without generating any machine code.
+/// The result is always the same as running the output of [`jit`].
+pub fn interpret(program: &str) -> Result<i64, JitError> {
+    let tokens = tokenize(program)?;
+    Ok(fold(&tokens, 0, &Options::default()))
+}
+
+/// Like [`interpret`], but the working register is unsigned.
+/// The result is always the same as running the output of [`jit_unsigned`].
+pub fn interpret_unsigned(program: &str) -> Result<u64, JitError> {
+    let tokens = tokenize(program)?;
+    let options = Options {
+        signedness: Signedness::Unsigned,
+        ..Options::default()
+    };
+    Ok(fold(&tokens, 0, &options) as u64)
+}
+
+#[cfg(feature = "std")]
+/// Evaluate a program in one call: it is compiled, loaded into executable memory and run,
+/// and the memory is released again, without any `unsafe` needed. This uses the JIT
+/// compiler when there is a backend for the host architecture, and falls back to
+/// [`interpret`] otherwise.
+///
+/// To run a program more than once, [`compile`] it instead. The machine code itself
+/// comes from [`jit`], which [`run`] can execute.
+pub fn evaluate(program: &str) -> Result<i64, CalcError> {
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    {
+        Ok(compile(program)?.call())
+    }
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        Ok(interpret(program)?)
+    }
+}
+
+#[cfg(feature = "std")]
+/// Evaluate many programs, returning their results in the same order.
+/// All of them are compiled into a single region of executable memory, one after the
+/// other, so allocating it and making it executable happens once for the whole batch
+/// rather than once per program. Like [`evaluate`], the programs are interpreted instead
+/// where there is no backend for the host architecture.
+///
+/// If any of the programs doesn't compile, its error is returned and none of them run.
+pub fn run_batch(programs: &[&str]) -> Result<Vec<i64>, CalcError> {
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    {
+        // The byte offset each program's function starts at
+        let mut entries = Vec::with_capacity(programs.len());
+        let mut machine_code = Vec::new();
+        for program in programs {
+            entries.push(machine_code.len());
+            machine_code.extend_from_slice(&jit(program)?);
+        }
+        if machine_code.is_empty() {
+            return Ok(Vec::new());
+        }
+        let memory = load(&machine_code)?;
+        type Executable = unsafe extern "C" fn() -> i64;
+        Ok(entries
+            .into_iter()
+            // Safety: each entry is the start of a function from `jit`, which all have
+            // this type
+            .map(|entry| unsafe {
+                let f: Executable = std::mem::transmute(memory.as_ptr::<u8>().add(entry));
+                f()
+            })
+            .collect())
+    }
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        Ok(programs
+            .iter()
+            .map(|program| interpret(program))
+            .collect::<Result<_, _>>()?)
+    }
+}
+
+#[cfg(feature = "std")]
+/// The same as [`evaluate`]
+pub fn run_auto(program: &str) -> Result<i64, CalcError> {
+    evaluate(program)
+}
+
+#[cfg(feature = "std")]
+/// Compile a program with [`jit`] and load it into executable memory,
+/// so it can be called as many times as needed.
+pub fn compile(program: &str) -> Result<CompiledProgram, CalcError> {
+    let machine_code = jit(program)?;
+    // Safety: `jit` produces code of the form `int64_t f()`
+    Ok(unsafe { CompiledProgram::new(&machine_code) }?)
+}
+
+#[cfg(feature = "std")]
+/// Compile a program with [`jit_with_input`] and load it into executable memory.
+/// Use [`CompiledProgram::call_with`] to choose the starting value.
+pub fn compile_with_input(program: &str) -> Result<CompiledProgram, CalcError> {
+    let machine_code = jit_with_input(program)?;
+    // Safety: `jit_with_input` produces code of the form `int64_t f(int64_t x)`
+    Ok(unsafe { CompiledProgram::new_with_input(&machine_code) }?)
+}
+
+#[cfg(feature = "std")]
+/// Machine code which has been loaded into executable memory.
+/// The memory is released when the `CompiledProgram` is dropped.
+pub struct CompiledProgram {
+    memory: Memory,
+    /// Whether the function takes an argument
+    input: bool,
+}
+
+#[cfg(feature = "std")]
+impl CompiledProgram {
+    /// Copy a sequence of bytes into executable memory.
+    ///
+    /// # Safety
+    /// `machine_code` must be valid machine code for the host architecture, forming a
+    /// function of type `int64_t f()` which follows the C calling convention.
+    /// Code produced by [`jit`] satisfies this.
+    pub unsafe fn new(machine_code: &[u8]) -> Result<CompiledProgram, RunError> {
+        Ok(CompiledProgram {
+            memory: load(machine_code)?,
+            input: false,
+        })
+    }
+
+    /// Copy a sequence of bytes into executable memory.
+    ///
+    /// # Safety
+    /// `machine_code` must be valid machine code for the host architecture, forming a
+    /// function of type `int64_t f(int64_t x)` which follows the C calling convention.
+    /// Code produced by [`jit_with_input`] satisfies this.
+    pub unsafe fn new_with_input(machine_code: &[u8]) -> Result<CompiledProgram, RunError> {
+        Ok(CompiledProgram {
+            memory: load(machine_code)?,
+            input: true,
+        })
+    }
+
+    /// Call the compiled function and return its return value.
+    /// A function which takes an input is passed zero.
+    pub fn call(&self) -> i64 {
+        if self.input {
+            return self.call_with(0);
+        }
+        // Rust doesn't have a stable ABI. It's safe to assume the calling convention
+        // used with C functions won't change. We'll use that instead.
+        type Executable = unsafe extern "C" fn() -> i64;
+
+        // Safety: the constructors require the memory to hold a function of this type
+        unsafe {
+            let f: Executable = std::mem::transmute(self.memory.as_ptr::<u8>());
+            f()
+        }
+    }
+
+    /// Call the compiled function with `x` as its input and return its return value.
+    /// A function which doesn't take an input ignores `x`.
+    pub fn call_with(&self, x: i64) -> i64 {
+        if !self.input {
+            return self.call();
+        }
+        type Executable = unsafe extern "C" fn(i64) -> i64;
+
+        // Safety: the constructors require the memory to hold a function of this type
+        unsafe {
+            let f: Executable = std::mem::transmute(self.memory.as_ptr::<u8>());
+            f(x)
+        }
+    }
+
+    /// Call the compiled function once for each seed, passing it as the input like
+    /// [`call_with`](CompiledProgram::call_with), and return the results in order
+    pub fn run_batch(&self, seeds: &[i64]) -> Vec<i64> {
+        seeds.iter().map(|&seed| self.call_with(seed)).collect()
+    }
+
+    /// Like [`run_batch`](CompiledProgram::run_batch), but replaces each seed with its
+    /// result instead of allocating a new vector for them
+    pub fn run_batch_in_place(&self, values: &mut [i64]) {
+        for value in values {
+            *value = self.call_with(*value);
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+/// Execute a sequence of bytes as machine code for the host architecture
+/// Returns the return value of the passed function
+/// Panics if the code can't be loaded into executable memory; see [`try_run`].
+///
+/// # Safety
+/// An arbitrary string of bytes is not guaranteed to be valid machine code,
+/// neither is it guaranteed to follow the calling convention used.
+/// The same requirements as [`CompiledProgram::new`] apply.
+pub unsafe fn run(machine_code: &[u8]) -> i64 {
+    try_run(machine_code).unwrap()
+}
+
+#[cfg(feature = "std")]
+/// Execute machine code produced by [`jit_unsigned`], returning the result as a `u64`.
+/// This is the same as [`run_typed`] with [`ReturnType::U64`].
+/// Panics if the code can't be loaded into executable memory.
+///
+/// # Safety
+/// The same requirements as [`run`] apply.
+pub unsafe fn run_unsigned(machine_code: &[u8]) -> u64 {
+    run(machine_code) as u64
+}
+
+#[cfg(feature = "std")]
+/// Execute machine code produced by [`jit`] or [`jit_unsigned`], returning the result
+/// both as an `i64` and as a `u64`. The same code is run once, and its result is only
+/// read two ways, so this works whichever [`Signedness`] the code was compiled with.
+/// Panics if the code can't be loaded into executable memory.
+///
+/// # Safety
+/// The same requirements as [`run`] apply.
+pub unsafe fn run_bits(machine_code: &[u8]) -> Bits {
+    Bits::from(run(machine_code))
+}
+
+#[cfg(feature = "std")]
+/// Like [`run`], but returns an error instead of panicking when the code can't be
+/// loaded into executable memory, such as when the OS refuses to allocate it.
+///
+/// # Safety
+/// The same requirements as [`run`] apply.
+pub unsafe fn try_run(machine_code: &[u8]) -> Result<i64, RunError> {
+    Ok(CompiledProgram::new(machine_code)?.call())
+}
+
+#[cfg(feature = "std")]
+/// Runs machine code in a single block of executable memory, reused from one call to the
+/// next, where [`run`] allocates a new block each time. The block grows to fit the
+/// largest code run so far. It's made writable again to copy in the next code, so it's
+/// never writable and executable at the same time.
+#[derive(Default)]
+pub struct Runner {
+    memory: Option<Memory>,
+}
+
+#[cfg(feature = "std")]
+impl Runner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Copy machine code into the runner's memory and call it, returning its return value.
+    /// Only allocates when the code doesn't fit in the memory from earlier calls.
+    ///
+    /// # Safety
+    /// The same requirements as [`run`] apply.
+    pub unsafe fn run(&mut self, machine_code: &[u8]) -> Result<i64, RunError> {
+        match &mut self.memory {
+            Some(memory) if memory.len() >= machine_code.len() => refill(memory, machine_code)?,
+            memory => {
+                // Free the old block before allocating its replacement
+                *memory = None;
+                *memory = Some(load(machine_code)?);
+            }
+        }
+        // Safety: the caller guarantees the code is a function of this type
+        type Executable = unsafe extern "C" fn() -> i64;
+        let memory = self.memory.as_ref().unwrap();
+        let f: Executable = std::mem::transmute(memory.as_ptr::<u8>());
+        Ok(f())
+    }
+
+    /// How many bytes of code can be run without allocating more memory
+    pub fn capacity(&self) -> usize {
+        self.memory.as_ref().map_or(0, Memory::len)
+    }
+}
+
+#[cfg(feature = "std")]
+/// Execute a sequence of bytes as machine code for the host architecture, passing `x` as the first argument.
+/// Returns the return value of the passed function
+/// Panics if the code can't be loaded into executable memory.
+///
+/// # Safety
+/// The same requirements as [`CompiledProgram::new_with_input`] apply.
+pub unsafe fn run_with_input(machine_code: &[u8], x: i64) -> i64 {
+    CompiledProgram::new_with_input(machine_code)
+        .unwrap()
+        .call_with(x)
+}
+
+#[cfg(feature = "std")]
+/// Execute machine code produced by [`jit_checked`]
+/// Returns the result of the program, or [`Overflow`] if any operation overflowed.
+/// Panics if the code can't be loaded into executable memory.
+///
+/// # Safety
+/// `machine_code` must be valid machine code for the host architecture, forming a
+/// function of the type described by [`jit_checked`]. Code produced by it satisfies this.
+pub unsafe fn run_checked(machine_code: &[u8]) -> Result<i64, Overflow> {
+    /// The pair of integers checked code returns
+    #[repr(C)]
+    struct Checked {
+        value: i64,
+        overflowed: i64,
+    }
+    // The Microsoft x64 convention returns structs larger than 8 bytes through memory
+    // rather than in rax and rdx, so the code is called the System V way there instead
+    #[cfg(all(windows, target_arch = "x86_64"))]
+    type Executable = unsafe extern "sysv64" fn() -> Checked;
+    #[cfg(not(all(windows, target_arch = "x86_64")))]
+    type Executable = unsafe extern "C" fn() -> Checked;
+
+    let memory = load(machine_code).unwrap();
+    let f: Executable = std::mem::transmute(memory.as_ptr::<u8>());
+    let result = f();
+    match result.overflowed {
+        0 => Ok(result.value),
+        n => Err(Overflow {
+            at_op: n as usize - 1,
+        }),
+    }
+}
+
+#[cfg(feature = "std")]
+/// Execute machine code produced by [`jit_float`]
+/// Returns the return value of the passed function
+/// Panics if the code can't be loaded into executable memory.
+///
+/// # Safety
+/// `machine_code` must be valid machine code for the host architecture, forming a
+/// function of the type described by [`jit_float`]. Code produced by it satisfies this.
+pub unsafe fn run_float(machine_code: &[u8]) -> f64 {
+    type Executable = unsafe extern "C" fn() -> f64;
+
+    let memory = load(machine_code).unwrap();
+    let f: Executable = std::mem::transmute(memory.as_ptr::<u8>());
+    f()
+}
+
+#[cfg(feature = "std")]
+/// Execute machine code produced by [`jit_typed`], which returns a `return_type`
+/// Panics if the code can't be loaded into executable memory.
+///
+/// # Safety
+/// `machine_code` must be valid machine code for the host architecture, forming a
+/// function which takes no arguments and returns a `return_type`, following the C
+/// calling convention. Code produced by [`jit_typed`] satisfies this.
+pub unsafe fn run_typed(machine_code: &[u8], return_type: ReturnType) -> Value {
+    let memory = load(machine_code).unwrap();
+    let code = memory.as_ptr::<u8>();
+    match return_type {
+        ReturnType::I64 => {
+            let f: unsafe extern "C" fn() -> i64 = std::mem::transmute(code);
+            Value::I64(f())
+        }
+        ReturnType::U64 => {
+            let f: unsafe extern "C" fn() -> u64 = std::mem::transmute(code);
+            Value::U64(f())
+        }
+        ReturnType::I32 => {
+            let f: unsafe extern "C" fn() -> i32 = std::mem::transmute(code);
+            Value::I32(f())
+        }
+        ReturnType::U32 => {
+            let f: unsafe extern "C" fn() -> u32 = std::mem::transmute(code);
+            Value::U32(f())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        codegen, compile, compile_with_input, evaluate, fold, interpret, interpret_unsigned, jit,
+        jit_checked, jit_debug, jit_float, jit_folded, jit_from, jit_ops, jit_saturating,
+        jit_typed, jit_unsigned, jit_with_input, jit_with_options, literal_pool, parse, run,
+        run_auto, run_batch, run_bits, run_checked, run_float, run_typed, run_unsigned,
+        run_with_input, tokenize, try_run, Bits, CalcError, CompiledProgram, DivRounding, JitError,
+        Op, Options, Overflow, OverflowBehavior, ReturnType, RunError, Runner, Signedness, Start,
+        Target, Value, MAX_OPERATIONS, MAX_STACK_DEPTH,
+    };
+
+    /// A small xorshift generator, so random programs can be made without extra dependencies
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        /// A random program of up to 64 operations, some of which have operands
+        fn program(&mut self) -> String {
+            let mut program = String::new();
+            for _ in 0..self.next() % 64 {
+                let op = ['+', '-', '*', '/', '%', '^'][(self.next() % 6) as usize];
+                program.push(op);
+                if !matches!(op, '%' | '^') && self.next().is_multiple_of(4) {
+                    program.push_str(&(1 + self.next() % 1000).to_string());
+                }
+            }
+            // Programs must contain at least one operation
+            program.push('-');
+            program
+        }
+    }
+
+    #[test]
+    fn test_execution() {
+        /// Tester function, which checks the JIT and the interpreter agree
+        fn t(p: &str) -> i64 {
+            let result = compile(p).unwrap().call();
+            assert_eq!(interpret(p), Ok(result), "{p}");
+            result
+        }
+
+        assert_eq!(t("+"), 1);
+        assert_eq!(t("++"), 2);
+        assert_eq!(t("++/"), 1);
+        assert_eq!(t("-"), -1);
+        assert_eq!(t("--*"), -4);
+        assert_eq!(t("*"), 0);
+        assert_eq!(t("/"), 0);
+        assert_eq!(t("++*******"), 256);
+        assert_eq!(t("--**++"), -6);
+        assert_eq!(t("---/"), -1);
+    }
+
+    #[test]
+    fn test_evaluate() {
+        /// Tester function, which checks evaluating agrees with the interpreter
+        fn t(p: &str) -> i64 {
+            let result = evaluate(p).unwrap();
+            assert_eq!(interpret(p), Ok(result), "{p}");
+            result
+        }
+
+        assert_eq!(t("+"), 1);
+        assert_eq!(t("++"), 2);
+        assert_eq!(t("++/"), 1);
+        assert_eq!(t("-"), -1);
+        assert_eq!(t("--*"), -4);
+        assert_eq!(t("*"), 0);
+        assert_eq!(t("/"), 0);
+        assert_eq!(t("++*******"), 256);
+        assert_eq!(t("--**++"), -6);
+        assert_eq!(t("---/"), -1);
+        assert_eq!(t("+8(*)"), 256);
+        assert_eq!(t("1000[1000[+]]"), 1000000);
+        assert!(matches!(
+            evaluate("++x"),
+            Err(CalcError::Jit(JitError::UnknownCharacter { index: 2, .. }))
+        ));
+        // Programs with nothing in them are an error everywhere rather than a panic
+        for empty in ["", "   "] {
+            assert!(matches!(
+                evaluate(empty),
+                Err(CalcError::Jit(JitError::EmptyProgram))
+            ));
+            assert!(matches!(
+                compile(empty),
+                Err(CalcError::Jit(JitError::EmptyProgram))
+            ));
+            assert_eq!(interpret(empty), Err(JitError::EmptyProgram));
+            assert_eq!(jit(empty), Err(JitError::EmptyProgram));
+        }
+        // The same goes for programs built from no operations at all
+        assert_eq!(jit_ops(&[]), Err(JitError::EmptyProgram));
+        assert!(matches!(
+            crate::Program::default().compile(),
+            Err(CalcError::Jit(JitError::EmptyProgram))
+        ));
+        assert!(matches!(
+            evaluate("+/0"),
+            Err(CalcError::Jit(JitError::DivisionByZero { index: 1 }))
+        ));
+        assert_eq!(
+            evaluate("++x").unwrap_err().to_string(),
+            jit("++x").unwrap_err().to_string()
+        );
+        assert_eq!(run_auto("++*").unwrap(), evaluate("++*").unwrap());
+    }
+
+    #[test]
+    fn test_run_batch() {
+        assert_eq!(run_batch(&["+", "++", "--*"]).unwrap(), [1, 2, -4]);
+        assert_eq!(run_batch(&[]).unwrap(), Vec::<i64>::new());
+        assert_eq!(run_batch(&["1000[+]", "+d*s-"]).unwrap(), [1000, 0]);
+        assert!(matches!(
+            run_batch(&["+", "++x"]),
+            Err(CalcError::Jit(JitError::UnknownCharacter { index: 2, .. }))
+        ));
+
+        let mut random = Random(0x5eed);
+        let programs: Vec<String> = (0..1000).map(|_| random.program()).collect();
+        let programs: Vec<&str> = programs.iter().map(String::as_str).collect();
+        let expected: Vec<i64> = programs.iter().map(|p| interpret(p).unwrap()).collect();
+        assert_eq!(run_batch(&programs).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_compiled_program() {
+        let program = compile("++*-").unwrap();
+        for _ in 0..1000 {
+            assert_eq!(program.call(), 3);
+        }
+        let programs: Vec<_> = ["+", "--*", "++*******"]
+            .into_iter()
+            .map(|p| compile(p).unwrap())
+            .collect();
+        for _ in 0..10 {
+            let results: Vec<i64> = programs.iter().map(|p| p.call()).collect();
+            assert_eq!(results, [1, -4, 256]);
+        }
+    }
+
+    #[test]
+    fn test_compiled_program_owns_memory() {
+        // The machine code is copied, so the program outlives the buffer it came from
+        let machine_code = jit("+++").unwrap();
+        let program = unsafe { CompiledProgram::new(&machine_code) }.unwrap();
+        drop(machine_code);
+        // Moving the handle doesn't move the executable memory it owns
+        let moved = Box::new(program);
+        assert_eq!(moved.call(), 3);
+        let programs = [*moved, compile("+").unwrap()];
+        assert_eq!(programs[0].call(), 3);
+        assert_eq!(programs[1].call(), 1);
+    }
+
+    #[test]
+    fn test_try_run() {
+        assert_eq!(unsafe { try_run(&jit("++*").unwrap()) }.unwrap(), 4);
+        // Nothing can be allocated for no code at all
+        assert!(matches!(unsafe { try_run(&[]) }, Err(RunError::Alloc(_))));
+    }
+
+    #[test]
+    fn test_runner() {
+        let mut runner = Runner::new();
+        assert_eq!(runner.capacity(), 0);
+        for p in ["++*", "-", "+8(*)", "3[+?-]", "++d*s-", "=-7%3"] {
+            let result = unsafe { runner.run(&jit(p).unwrap()) }.unwrap();
+            assert_eq!(result, evaluate(p).unwrap(), "{p}");
+        }
+        // Every program so far fit in the first block
+        let capacity = runner.capacity();
+        assert!(capacity >= jit("+8(*)").unwrap().len());
+        let memory = runner.memory.as_ref().unwrap().as_ptr::<u8>();
+        assert_eq!(unsafe { runner.run(&jit("+").unwrap()) }.unwrap(), 1);
+        assert_eq!(runner.memory.as_ref().unwrap().as_ptr::<u8>(), memory);
+        // `MAP_JIT` memory is always mapped both writable and executable
+        #[cfg(not(all(target_os = "macos", target_arch = "aarch64")))]
+        {
+            let region = region::query(memory).unwrap();
+            assert_eq!(region.protection(), region::Protection::READ_EXECUTE);
+        }
+
+        // A longer program grows it
+        let long = "+".repeat(capacity);
+        assert_eq!(
+            unsafe { runner.run(&jit(&long).unwrap()) }.unwrap(),
+            capacity as i64
+        );
+        assert!(runner.capacity() > capacity);
+        assert_eq!(unsafe { runner.run(&jit("--*").unwrap()) }.unwrap(), -4);
+    }
+
+    #[test]
+    #[cfg(not(all(target_os = "macos", target_arch = "aarch64")))]
+    fn test_memory_not_writable() {
+        let program = compile("++").unwrap();
+        let region = region::query(program.memory.as_ptr::<u8>()).unwrap();
+        assert_eq!(region.protection(), region::Protection::READ_EXECUTE);
+        assert_eq!(program.call(), 2);
+    }
+
+    #[test]
+    fn test_folded() {
+        let programs = [
+            "+",
+            "++",
+            "++/",
+            "-",
+            "--*",
+            "*",
+            "/",
+            "++*******",
+            "--**++",
+            "---/",
+            "--/",
+            "+**************************************************************",
+            "+10*3/2",
+        ];
+        let options = Options {
+            fold_constants: true,
+            ..Options::default()
+        };
+        for p in programs {
+            let folded = unsafe { run(&jit_folded(p).unwrap()) };
+            assert_eq!(folded, compile(p).unwrap().call(), "{p}");
+            assert_eq!(jit_with_options(p, options), jit_folded(p));
+        }
+        assert_eq!(unsafe { run(&jit_folded("--/").unwrap()) }, -1);
+        // Folding can be turned off again
+        assert_eq!(jit_with_options("++", Options::default()), jit("++"));
+        let long = "+".repeat(100);
+        assert!(jit_folded(&long).unwrap().len() < jit(&long).unwrap().len());
+    }
+
+    #[test]
+    fn test_strength_reduce() {
+        let options = Options {
+            strength_reduce: true,
+            ..Options::default()
+        };
+        let programs = [
+            "-/",
+            "--/",
+            "---/",
+            "/",
+            "+/",
+            "+++/",
+            "-----/4",
+            "----/4",
+            "-/8",
+            "------/8",
+            "+/8",
+            "-17/16",
+            "-16/16",
+            "-15/16",
+            "+17/16",
+            "-1000/1024",
+            "-1025/1024",
+            "+2147483647/1073741824",
+            "-2147483647/1073741824",
+            "-100/3",
+            "+100/1",
+        ];
+        for p in programs {
+            let reduced = unsafe { run(&jit_with_options(p, options).unwrap()) };
+            assert_eq!(reduced, compile(p).unwrap().call(), "{p}");
+        }
+        assert_eq!(unsafe { run(&jit_with_options("-/", options).unwrap()) }, 0);
+        assert_eq!(
+            unsafe { run(&jit_with_options("---/", options).unwrap()) },
+            -1
+        );
+        // AArch64 always halves with shifts, so only x86_64 code gets any shorter
+        #[cfg(target_arch = "x86_64")]
+        {
+            assert!(jit_with_options("/", options).unwrap().len() < jit("/").unwrap().len());
+            // No `idivq %r8`
+            assert!(!jit_with_options("/8", options)
+                .unwrap()
+                .windows(3)
+                .any(|w| w == [0x49, 0xf7, 0xf8]));
+        }
+    }
+
+    #[test]
+    fn test_strength_reduce_multiply() {
+        let options = Options {
+            strength_reduce: true,
+            ..Options::default()
+        };
+        let programs = [
+            "+*",
+            "-*",
+            "---*",
+            "*",
+            "+***",
+            "---*4",
+            "+*1024",
+            "-*1073741824",
+            "+*3",
+            "-*1",
+            "+2147483647*2*2*1073741824",
+            "---*/*/*",
+        ];
+        for p in programs {
+            let reduced = unsafe { run(&jit_with_options(p, options).unwrap()) };
+            assert_eq!(reduced, compile(p).unwrap().call(), "{p}");
+        }
+        // AArch64 always doubles with a shift, so only x86_64 code gets any shorter
+        #[cfg(target_arch = "x86_64")]
+        {
+            assert!(jit_with_options("*", options).unwrap().len() < jit("*").unwrap().len());
+            // `imul` with an 8-bit immediate is the same length as `shl`, but larger
+            // multipliers need a 32-bit immediate
+            assert_eq!(
+                jit_with_options("*8", options).unwrap().len(),
+                jit("*8").unwrap().len()
+            );
+            assert!(
+                jit_with_options("*1024", options).unwrap().len() < jit("*1024").unwrap().len()
+            );
+        }
+    }
+
+    #[test]
+    fn test_optimized() {
+        let program = format!(
+            "{}*{}/-{}*8/4",
+            "-".repeat(99),
+            "+".repeat(7),
+            "+".repeat(3)
+        );
+        let optimized = jit_with_options(&program, Options::optimized()).unwrap();
+        assert!(optimized.len() < jit(&program).unwrap().len());
+        assert_eq!(
+            unsafe { run(&optimized) },
+            compile(&program).unwrap().call()
+        );
+        assert!(!Options::optimized().fold_constants);
+    }
+
+    #[test]
+    fn test_coalesce() {
+        let options = Options {
+            coalesce: true,
+            ..Options::default()
+        };
+        let programs = [
+            "+",
+            "++/",
+            "--*",
+            "++*******",
+            "--**++",
+            "+++-+",
+            "+-",
+            "-+-+*",
+        ];
+        let long = format!("*{}*{}", "+".repeat(200), "-".repeat(70));
+        for p in programs.into_iter().chain([long.as_str()]) {
+            let optimized = unsafe { run(&jit_with_options(p, options).unwrap()) };
+            assert_eq!(optimized, compile(p).unwrap().call(), "{p}");
+        }
+        assert!(jit_with_options("++++++", options).unwrap().len() < jit("++++++").unwrap().len());
+        assert!(jit_with_options("+-+-", options).unwrap().len() < jit("+-+-").unwrap().len());
+        assert_eq!(jit_with_options("+", options), jit("+"));
+        assert_eq!(jit_with_options("+", Options::default()), jit("+"));
+    }
+
+    #[test]
+    fn test_coalesce_boundaries() {
+        let options = Options {
+            coalesce: true,
+            ..Options::default()
+        };
+        // 500 increments become a single addition
+        let long = "+".repeat(500);
+        assert_eq!(jit_with_options(&long, options), jit("+500"));
+        // Runs which cancel out disappear entirely
+        assert_eq!(jit_with_options("*+-+-*", options), jit("**"));
+        assert_eq!(
+            jit_with_options("+5-3--", options),
+            jit_with_options("+-", options)
+        );
+        // Nothing is merged across `*` or `/`
+        assert_eq!(jit_with_options("+*+/+", options), jit("+*+/+"));
+        assert_eq!(jit_with_options("++*--/+++", options), jit("+2*-2/+3"));
+
+        let programs = [
+            format!("{}*{}/{}", "+".repeat(300), "-".repeat(299), "+".repeat(41)),
+            format!("-{}*3{}/4", "-".repeat(1000), "+".repeat(10)),
+            format!("+7{}+7*{}", "-".repeat(14), "--+".repeat(100)),
+        ];
+        for p in &programs {
+            let optimized = jit_with_options(p, options).unwrap();
+            assert!(optimized.len() < jit(p).unwrap().len(), "{p}");
+            assert_eq!(
+                unsafe { run(&optimized) },
+                compile(p).unwrap().call(),
+                "{p}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_coalesce_keeps_rounding() {
+        // Halving and doubling only cancel out for some values, so they're never removed,
+        // unlike runs of `+` and `-`
+        for options in [
+            Options {
+                coalesce: true,
+                ..Options::default()
+            },
+            Options::optimized(),
+        ] {
+            for (p, expected) in [
+                // `/*` loses the bottom bit of odd values
+                ("+++/*", 2),
+                ("---/*", -2),
+                // `*/` loses the top bit when doubling overflows
+                ("+62(*)*/", -(1 << 62)),
+                ("+-*/+-/*", 0),
+            ] {
+                let optimized = jit_with_options(p, options).unwrap();
+                assert_eq!(unsafe { run(&optimized) }, expected, "{p}");
+                assert_eq!(evaluate(p).unwrap(), expected, "{p}");
+            }
+        }
+        let options = Options {
+            coalesce: true,
+            ..Options::default()
+        };
+        assert_eq!(jit_with_options("+++/*", options), jit("+3/*"));
+        assert_eq!(jit_with_options("+-*/-+", options), jit("*/"));
+    }
+
+    #[test]
+    fn test_input() {
+        /// Tester function
+        fn t(p: &str, x: i64) -> i64 {
+            compile_with_input(p).unwrap().call_with(x)
+        }
+
+        assert_eq!(t("++*", 5), 14);
+        assert_eq!(t("+", 0), 1);
+        assert_eq!(t("/", -7), -3);
+        assert_eq!(t("*-", -3), -7);
+        assert_eq!(t("*+", 10), 21);
+        assert_eq!(t("*+", 0), 1);
+        assert_eq!(t("*+", -10), -19);
+        assert_eq!(t("/", 0), 0);
+        assert_eq!(t("-", i64::MIN + 1), i64::MIN);
+        assert_eq!(t("+10", -10), 0);
+
+        assert_eq!(compile_with_input("++").unwrap().call(), 2);
+        assert_eq!(compile("++").unwrap().call_with(10), 2);
+        assert_eq!(
+            unsafe { run_with_input(&jit_with_input("+").unwrap(), 7) },
+            8
+        );
+        assert_eq!(
+            unsafe { run_with_input(&jit_with_input("*+").unwrap(), 10) },
+            21
+        );
+    }
+
+    #[test]
+    fn test_compiled_run_batch() {
+        let program = compile_with_input("*+3[+?-]^").unwrap();
+        let seeds: Vec<i64> = (-100_000..100_000).step_by(7).collect();
+        let one_at_a_time: Vec<i64> = seeds.iter().map(|&x| program.call_with(x)).collect();
+        assert_eq!(program.run_batch(&seeds), one_at_a_time);
+        let mut values = seeds.clone();
+        program.run_batch_in_place(&mut values);
+        assert_eq!(values, one_at_a_time);
+        assert_eq!(program.run_batch(&[4]), [81]);
+
+        assert_eq!(program.run_batch(&[]), Vec::<i64>::new());
+        program.run_batch_in_place(&mut []);
+        // A program without an input gives the same result for every seed
+        assert_eq!(compile("++*").unwrap().run_batch(&[1, 2, 3]), [4, 4, 4]);
+    }
+
+    #[test]
+    fn test_targets() {
+        assert_eq!(jit_with_options("++*", Options::default()), jit("++*"));
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(Target::host(), Some(Target::X86_64));
+        let options = Options {
+            target: Target::X86_64,
+            ..Options::default()
+        };
+        assert_eq!(
+            jit_with_options("+", options).unwrap(),
+            [0x48, 0x31, 0xc9, 0x48, 0xff, 0xc1, 0x48, 0x89, 0xc8, 0xc3]
+        );
+    }
+
+    #[test]
+    fn test_jit_debug() {
+        // `int3` is 0xcc, which the rest of the x86_64 code never contains
+        if Target::default() == Target::X86_64 {
+            for p in ["++*", "+8(*)", "++d*s-", "3[+?-]"] {
+                let debug = jit_debug(p).unwrap();
+                let normal = jit(p).unwrap();
+                assert!(debug.contains(&0xcc), "{p}");
+                assert!(!normal.contains(&0xcc), "{p}");
+            }
+        }
+        // The breakpoint comes straight after the prologue, `xor %rcx, %rcx`
+        let options = Options {
+            target: Target::X86_64,
+            breakpoint: true,
+            ..Options::default()
+        };
+        assert_eq!(
+            jit_with_options("+", options).unwrap(),
+            [0x48, 0x31, 0xc9, 0xcc, 0x48, 0xff, 0xc1, 0x48, 0x89, 0xc8, 0xc3]
+        );
+        // and after the frame is set up, when there is one
+        assert_eq!(
+            jit_with_options("d", options).unwrap()[3..8],
+            [0x55, 0x48, 0x89, 0xe5, 0xcc]
+        );
+    }
+
+    #[test]
+    fn test_checked() {
+        /// Tester function
+        fn t(p: &str) -> Result<i64, Overflow> {
+            unsafe { run_checked(&jit_checked(p).unwrap()) }
+        }
+
+        assert_eq!(t("++*"), Ok(4));
+        assert_eq!(t("---/"), Ok(-1));
+        assert_eq!(t("+10*3/2-20"), Ok(-5));
+        // 2^62 is fine, 2^63 is not
+        assert_eq!(t(&format!("+{}", "*".repeat(62))), Ok(1 << 62));
+        assert_eq!(
+            t(&format!("+{}", "*".repeat(63))),
+            Err(Overflow { at_op: 63 })
+        );
+        assert_eq!(t(&format!("-{}", "*".repeat(63))), Ok(i64::MIN));
+        assert_eq!(
+            t(&format!("-{}-", "*".repeat(63))),
+            Err(Overflow { at_op: 64 })
+        );
+        assert_eq!(t(&"+*".repeat(64)), Err(Overflow { at_op: 125 }));
+        assert_eq!(
+            t("+2147483647*2147483647*2147483647"),
+            Err(Overflow { at_op: 2 })
+        );
+        assert_eq!(t("+2147483647*2147483647*2"), Ok(9223372028264841218));
+        assert_eq!(t(&format!("+{}-1+1", "*".repeat(62))), Ok(1 << 62));
+        assert_eq!(
+            t(&format!("+{}-1+1+2147483647*2", "*".repeat(62))),
+            Err(Overflow { at_op: 66 })
+        );
+        // Operations in groups count once per repeat, but those in loops only once
+        assert_eq!(t("+++70(*)"), Err(Overflow { at_op: 64 }));
+        assert_eq!(t("++2[+]70[*]"), Err(Overflow { at_op: 6 }));
+        assert_eq!(
+            Overflow { at_op: 6 }.to_string(),
+            "The result overflowed a 64-bit integer at operation 6"
+        );
+        // The unchecked code wraps around instead
+        assert_eq!(
+            compile("+2147483647*2147483647*2147483647").unwrap().call(),
+            2147483647i64.wrapping_pow(3)
+        );
+    }
+
+    #[test]
+    fn test_jit_from() {
+        /// Tester function
+        fn t(p: &str, start: i64) -> i64 {
+            unsafe { run(&jit_from(p, start).unwrap()) }
+        }
+
+        assert_eq!(t("+", 100), 101);
+        assert_eq!(t("++*", -3), -2);
+        assert_eq!(t("=5", 100), 5);
+        assert_eq!(t("2[*]", 1 << 40), 1 << 42);
+        assert_eq!(t("-", i64::MIN), i64::MAX);
+        assert_eq!(t("?+", 0), 0);
+        assert_eq!(t("?+", 7), 8);
+        assert_eq!(jit_from("++*", 0), jit("++*"));
+        assert!(jit_from("x", 1).is_err());
+    }
+
+    #[test]
+    fn test_interpret() {
+        assert_eq!(interpret("++*"), Ok(4));
+        assert_eq!(interpret("-/"), Ok(0));
+        assert_eq!(interpret("---/"), Ok(-1));
+        assert_eq!(
+            interpret("x"),
+            Err(JitError::UnknownCharacter {
+                character: 'x',
+                index: 0,
+                line: 1,
+                column: 1
+            })
+        );
+        assert_eq!(run_auto("++*").unwrap(), 4);
+        assert!(run_auto("++x").is_err());
+    }
+
+    #[test]
+    fn test_interpret_matches_jit() {
+        let mut random = Random(0x2545f4914f6cdd1d);
+        for _ in 0..1000 {
+            let program = random.program();
+            assert_eq!(
+                interpret(&program).unwrap(),
+                compile(&program).unwrap().call(),
+                "{program}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_error_positions() {
+        /// Tester function, returning the character, byte offset, line, and column of the error
+        fn t(p: &str) -> (char, usize, usize, usize) {
+            match jit(p) {
+                Err(JitError::UnknownCharacter {
+                    character,
+                    index,
+                    line,
+                    column,
+                }) => (character, index, line, column),
+                result => panic!("{p:?} gave {result:?}"),
+            }
+        }
+
+        assert_eq!(t("x++\n**\n--"), ('x', 0, 1, 1));
+        assert_eq!(t("++\n*x*\n--"), ('x', 4, 2, 2));
+        assert_eq!(t("++\n**\n--x"), ('x', 8, 3, 3));
+        assert_eq!(t("++\n**\n--\nx"), ('x', 9, 4, 1));
+        assert_eq!(t("\n\n\n  y"), ('y', 5, 4, 3));
+        assert_eq!(t("++\n\n+ + +  !"), ('!', 11, 3, 8));
+        // Columns count characters, while the offset counts bytes
+        assert_eq!(t("++\n é"), ('é', 4, 2, 2));
+        assert_eq!(t("++\n+é ë"), ('é', 4, 2, 2));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            jit("++x"),
+            Err(JitError::UnknownCharacter {
+                character: 'x',
+                index: 2,
+                line: 1,
+                column: 3
+            })
+        );
+        assert_eq!(
+            jit("+a+"),
+            Err(JitError::UnknownCharacter {
+                character: 'a',
+                index: 1,
+                line: 1,
+                column: 2
+            })
+        );
+        assert_eq!(jit(""), Err(JitError::EmptyProgram));
+        assert_eq!(jit(" \n "), Err(JitError::EmptyProgram));
+        assert_eq!(jit("   "), Err(JitError::EmptyProgram));
+        assert!(jit("+ + * - /").is_ok());
+        assert!(jit("+2147483647").is_ok());
+        assert_eq!(
+            jit("++ +2147483648"),
+            Err(JitError::OperandOutOfRange { index: 3 })
+        );
+        assert_eq!(jit("+/0"), Err(JitError::DivisionByZero { index: 1 }));
+    }
+
+    #[test]
+    fn test_max_code_size() {
+        let limit = |max_code_size| Options {
+            max_code_size: Some(max_code_size),
+            ..Options::default()
+        };
+        let program = "+".repeat(10000);
+        let size = jit(&program).unwrap().len();
+        assert_eq!(
+            jit_with_options(&program, limit(1024)),
+            Err(JitError::CodeTooLarge { size, limit: 1024 })
+        );
+        assert_eq!(
+            jit_with_options("1000(+)", limit(1024)),
+            Err(JitError::CodeTooLarge {
+                size: jit("1000(+)").unwrap().len(),
+                limit: 1024
+            })
+        );
+        // Code exactly as long as the limit is allowed
+        assert_eq!(jit_with_options(&program, limit(size)), jit(&program));
+        assert_eq!(jit_with_options("++*", limit(1024)), jit("++*"));
+        // The limit applies to the code generated, so folding can bring a program under it
+        let folded = Options {
+            fold_constants: true,
+            ..limit(1024)
+        };
+        assert!(jit_with_options(&program, folded).unwrap().len() <= 1024);
+        assert_eq!(
+            jit_with_options(&program, limit(1024)).unwrap_err().index(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_error_spans() {
+        /// Tester function
+        fn t(p: &str) -> Option<&str> {
+            jit(p).unwrap_err().span(p).map(|span| &p[span])
+        }
+
+        assert_eq!(t("++x*"), Some("x"));
+        assert_eq!(t("*abc-"), Some("abc"));
+        assert_eq!(t("+xyz"), Some("xyz"));
+        // `d` and `s` are operations, and digits and brackets are read separately
+        assert_eq!(t("+xds"), Some("x"));
+        assert_eq!(t("+ab3(+)"), Some("ab"));
+        assert_eq!(t("+é ë"), Some("é"));
+        assert_eq!(t("*3000000000-1"), Some("*3000000000"));
+        assert_eq!(t("+=-3000000000"), Some("=-3000000000"));
+        assert_eq!(t("++/0"), Some("/"));
+        assert_eq!(t("+(+"), Some("("));
+        assert_eq!(t("="), Some("="));
+        assert_eq!(t(""), None);
+        assert_eq!(t("+?ds"), None);
+        let error = crate::jit_expr("1+").unwrap_err();
+        assert_eq!(error.span("1+"), Some(2..2));
+    }
+
+    #[test]
+    fn test_repeat() {
+        assert_eq!(
+            compile("+8(*)").unwrap().call(),
+            compile("++*******").unwrap().call()
+        );
+        assert_eq!(jit("+8(*)"), jit("+********"));
+        assert_eq!(jit("2(3(+)-)"), jit("+++-+++-"));
+        assert_eq!(jit("1(+)"), jit("+"));
+        assert_eq!(jit("(+*)"), jit("+*"));
+        // A number before a group is its count, not an operand
+        assert_eq!(jit("+2(+)"), jit("+++"));
+        assert_eq!(jit("+22(+)"), jit(&"+".repeat(23)));
+        assert_eq!(jit("+5 2(+)"), jit("+5++"));
+        assert_eq!(interpret("+8(*)"), Ok(256));
+        assert_eq!(interpret("3(+ # comment\n*)"), Ok(14));
+        assert_eq!(interpret("+1024(*)"), Ok(0));
+        assert_eq!(tokenize("2(3(+)-)").unwrap().len(), 8);
+    }
+
+    #[test]
+    fn test_repeat_errors() {
+        assert_eq!(jit("3(+"), Err(JitError::UnbalancedBracket { index: 0 }));
+        assert_eq!(jit("2(3(+)"), Err(JitError::UnbalancedBracket { index: 0 }));
+        assert_eq!(jit("+(+"), Err(JitError::UnbalancedBracket { index: 1 }));
+        assert_eq!(jit("+)"), Err(JitError::UnbalancedBracket { index: 1 }));
+        assert_eq!(jit("2(+))"), Err(JitError::UnbalancedBracket { index: 4 }));
+        assert_eq!(jit("3+"), Err(JitError::MissingGroup { index: 0 }));
+        assert_eq!(jit("++ 12"), Err(JitError::MissingGroup { index: 3 }));
+        assert_eq!(jit("0(+)"), Err(JitError::ZeroRepeat { index: 0 }));
+        assert_eq!(jit("2(00(+))"), Err(JitError::ZeroRepeat { index: 2 }));
+        assert_eq!(jit("3()"), Err(JitError::EmptyProgram));
+        assert_eq!(
+            jit("2147483648(+)"),
+            Err(JitError::OperandOutOfRange { index: 0 })
+        );
+        assert_eq!(
+            jit("2147483647(2147483647(+))"),
+            Err(JitError::TooManyOperations { index: 11 })
+        );
+        assert_eq!(
+            tokenize("1024(1024(+))").unwrap().len(),
+            crate::MAX_OPERATIONS
+        );
+        assert_eq!(
+            jit("+1024(1024(+))"),
+            Err(JitError::TooManyOperations { index: 1 })
+        );
+    }
+
+    #[test]
+    fn test_loops() {
+        /// Tester function
+        fn t(p: &str) -> i64 {
+            let result = compile(p).unwrap().call();
+            assert_eq!(interpret(p), Ok(result), "{p}");
+            assert_eq!(jit_folded(p).map(|m| unsafe { run(&m) }), Ok(result), "{p}");
+            result
+        }
+
+        assert_eq!(t("3[+]"), 3);
+        assert_eq!(t("+8[*]"), 256);
+        assert_eq!(t("[+]+"), 2);
+        assert_eq!(t("3[2[+]*]"), 28);
+        assert_eq!(t("2[3[4[+]]]"), 24);
+        assert_eq!(t("2[+]3[-]"), -1);
+        assert_eq!(t("2(3[+])"), 6);
+        assert_eq!(t("5[]+"), 1);
+        // Bodies longer than a conditional branch reaches on AArch64
+        assert_eq!(t(&format!("2[{}]", "+".repeat(300_000))), 600_000);
+        assert_eq!(t("1000[1000[+]]"), 1_000_000);
+        // Large bodies need the long form of the backwards jump
+        assert_eq!(t("2[100(+)]"), 200);
+        assert_eq!(
+            t("3[+2147483647*3]"),
+            interpret("+2147483647*3+2147483647*3+2147483647*3").unwrap()
+        );
+
+        // The size of a loop doesn't depend on how many times it runs
+        assert_eq!(t("1000000[+]"), 1_000_000);
+        assert_eq!(
+            jit("1000000[+]").unwrap().len(),
+            jit("1000001[+]").unwrap().len()
+        );
+        assert!(jit("1000000[+]").unwrap().len() < 64);
+
+        assert_eq!(
+            unsafe { run_checked(&jit_checked("1000[+]").unwrap()) },
+            Ok(1000)
+        );
+        assert_eq!(
+            unsafe { run_checked(&jit_checked("+2[2[2[*]]]").unwrap()) },
+            Ok(256)
+        );
+        assert_eq!(
+            unsafe { run_checked(&jit_checked("+100[*]").unwrap()) },
+            Err(Overflow { at_op: 2 })
+        );
+        assert_eq!(
+            tokenize("2[+]").unwrap(),
+            [Op::Loop(2), Op::Plus, Op::EndLoop]
+        );
+    }
+
+    #[test]
+    fn test_loop_errors() {
+        assert_eq!(jit("3[+"), Err(JitError::UnbalancedBracket { index: 0 }));
+        assert_eq!(jit("+]"), Err(JitError::UnbalancedBracket { index: 1 }));
+        assert_eq!(jit("2[+)"), Err(JitError::UnbalancedBracket { index: 3 }));
+        assert_eq!(jit("2(+]"), Err(JitError::UnbalancedBracket { index: 3 }));
+        assert_eq!(jit("0[+]"), Err(JitError::ZeroRepeat { index: 0 }));
+        assert!(jit("2[2[2[+]]]").is_ok());
+        assert!(jit("2[2[2[+]]2[+]]").is_ok());
+        assert_eq!(
+            jit("2[2[2[2[+]]]]"),
+            Err(JitError::LoopTooDeep { index: 6 })
+        );
+    }
+
+    #[test]
+    fn test_skip() {
+        /// Tester function
+        fn t(p: &str) -> i64 {
+            let result = compile(p).unwrap().call();
+            assert_eq!(interpret(p), Ok(result), "{p}");
+            assert_eq!(jit_folded(p).map(|m| unsafe { run(&m) }), Ok(result), "{p}");
+            let optimized = jit_with_options(p, Options::optimized()).unwrap();
+            assert_eq!(unsafe { run(&optimized) }, result, "{p}");
+            assert_eq!(
+                unsafe { run_checked(&jit_checked(p).unwrap()) },
+                Ok(result),
+                "{p}"
+            );
+            result
+        }
+
+        assert_eq!(t("?+"), 0);
+        assert_eq!(t("+?*"), 2);
+        // Only the operation directly after is skipped
+        assert_eq!(t("?++"), 1);
+        assert_eq!(t("?+-"), -1);
+        // The second `?` is skipped, so the `+` isn't
+        assert_eq!(t("??+"), 1);
+        assert_eq!(t("+??+"), 2);
+        // A whole loop is skipped
+        assert_eq!(t("?2[+]+"), 1);
+        assert_eq!(t("+?2[+]"), 3);
+        assert_eq!(t("3[?+]"), 0);
+        assert_eq!(t("-3[?+]"), 0);
+        assert_eq!(t("-2[+?2[+]]"), 3);
+        assert_eq!(t("?2[100(+)]+"), 1);
+        assert_eq!(t("?100(+)"), 99);
+        assert_eq!(t("+2(-?+)"), 0);
+    }
+
+    #[test]
+    fn test_skip_errors() {
+        assert_eq!(jit("?"), Err(JitError::NothingToSkip { index: 0 }));
+        assert_eq!(jit("+ ? "), Err(JitError::NothingToSkip { index: 2 }));
+        assert_eq!(jit("2[+?]"), Err(JitError::NothingToSkip { index: 3 }));
+        assert_eq!(jit("+2(+?)"), Err(JitError::NothingToSkip { index: 4 }));
+        assert!(jit("2(?+)").is_ok());
+        assert!(jit("?2[+]").is_ok());
+    }
+
+    #[test]
+    fn test_typed() {
+        /// Tester function
+        fn t(p: &str, return_type: ReturnType) -> Value {
+            let machine_code = jit_typed(p, return_type).unwrap();
+            let value = unsafe { run_typed(&machine_code, return_type) };
+            // The whole return register holds the value, widened back to 64 bits
+            let widened = unsafe { run(&machine_code) };
+            assert_eq!(widened, return_type.convert(interpret(p).unwrap()), "{p}");
+            let options = Options {
+                return_type,
+                fold_constants: true,
+                ..Options::default()
+            };
+            let folded = jit_with_options(p, options).unwrap();
+            assert_eq!(unsafe { run_typed(&folded, return_type) }, value, "{p}");
+            value
+        }
+
+        assert_eq!(t("++*", ReturnType::I64), Value::I64(4));
+        assert_eq!(t("=2147483647", ReturnType::I32), Value::I32(i32::MAX));
+        // Values over `i32::MAX` wrap around when truncated
+        assert_eq!(t("=2147483647+", ReturnType::I32), Value::I32(i32::MIN));
+        assert_eq!(t("=2147483647*2+2", ReturnType::I32), Value::I32(0));
+        assert_eq!(t("=2147483647*3", ReturnType::I32), Value::I32(2147483645));
+        assert_eq!(t("=2147483647+", ReturnType::U32), Value::U32(1 << 31));
+        assert_eq!(t("-", ReturnType::U32), Value::U32(u32::MAX));
+        assert_eq!(t("-", ReturnType::U64), Value::U64(u64::MAX));
+        assert_eq!(t("-", ReturnType::I32), Value::I32(-1));
+        assert_eq!(Value::U64(u64::MAX).to_string(), "18446744073709551615");
+        assert_eq!(Value::I32(-3).to_string(), "-3");
+    }
+
+    #[test]
+    fn test_float() {
+        /// Tester function
+        fn t(p: &str) -> f64 {
+            unsafe { run_float(&jit_float(p).unwrap()) }
+        }
+
+        assert_eq!(t("+/"), 0.5);
+        assert_eq!(t("+++d/s-"), 2.0);
+        assert_eq!(t("+d/d/ss"), 0.25);
+        assert_eq!(t("+2[d/]s*s"), 0.25);
+        assert_eq!(t("-/"), -0.5);
+        assert_eq!(t("+++/"), 1.5);
+        assert_eq!(t("+//"), 0.25);
+        assert_eq!(t("+/~"), -0.5);
+        assert_eq!(t("++^/"), 2.0);
+        assert_eq!(t("=-7/2"), -3.5);
+        assert_eq!(t("+/3"), 1.0 / 3.0);
+        assert_eq!(t("3[+/]"), 0.875);
+        assert_eq!(t("+/ 0+"), 1.0);
+        // The remainder keeps the sign of the working register
+        assert_eq!(t("+++%"), 1.0);
+        assert_eq!(t("---%"), -1.0);
+        assert_eq!(t("+++/%"), 1.5);
+        assert_eq!(t("+7%3"), 1.0);
+        assert_eq!(t("=-15/2%4"), -3.5);
+        // Only zero is skipped, whichever sign it has
+        assert_eq!(t("+/?+"), 1.5);
+        assert_eq!(t("?+"), 0.0);
+        assert_eq!(t("~?+").to_bits(), (-0.0f64).to_bits());
+        // Instead of wrapping around, large values become infinite
+        assert_eq!(t("+62(*)"), 2.0f64.powi(62));
+        assert_eq!(t("+64(*)"), 2.0f64.powi(64));
+        assert_eq!(t("+1100[*]"), f64::INFINITY);
+        assert_eq!(t("-1100[*]"), f64::NEG_INFINITY);
+        // Without halving, small results are the same as with integers
+        for p in ["++*", "+++^-", "=-7*3+1000", "+10%3", "---~", "+5[+?-]"] {
+            assert_eq!(t(p), interpret(p).unwrap() as f64, "{p}");
+        }
+        // The same as doing the arithmetic in Rust
+        assert_eq!(t("=7/3*5-%4"), ((7.0f64 / 3.0 * 5.0) - 1.0) % 4.0);
+        assert_eq!(t("=1000/7/7/7^"), (1000.0f64 / 7.0 / 7.0 / 7.0).powi(2));
+        assert_eq!(t("=-9/4|"), (-9.0f64 / 4.0).abs());
+        assert_eq!(t("=-9/4>"), (-9.0f64 / 4.0 / 2.0).floor());
+        assert!(jit_float("+x").is_err());
+    }
+
+    #[test]
+    fn test_unsigned() {
+        /// Tester function, which checks the JIT and the interpreter agree
+        fn t(p: &str) -> u64 {
+            let result = unsafe { run_unsigned(&jit_unsigned(p).unwrap()) };
+            assert_eq!(interpret_unsigned(p), Ok(result), "{p}");
+            for options in [Options::optimized(), Options::default()] {
+                let options = Options {
+                    signedness: Signedness::Unsigned,
+                    ..options
+                };
+                let machine_code = jit_with_options(p, options).unwrap();
+                assert_eq!(unsafe { run(&machine_code) } as u64, result, "{p}");
+            }
+            result
+        }
+
+        assert_eq!(interpret("-/"), Ok(0));
+        assert_eq!(t("-/"), u64::MAX / 2);
+        assert_eq!(t("-"), u64::MAX);
+        assert_eq!(t("-%"), 1);
+        assert_eq!(t("-%10"), u64::MAX % 10);
+        assert_eq!(t("->"), u64::MAX >> 1);
+        assert_eq!(t("-|"), u64::MAX);
+        assert_eq!(t("-/4"), u64::MAX / 4);
+        assert_eq!(t("-/3"), u64::MAX / 3);
+        assert_eq!(t("=-7/2"), (-7i64 as u64) / 2);
+        // Everything else is the same as signed
+        for p in ["++*", "+++^~", "=-7*3+1000", "-<", "3[+?-]"] {
+            assert_eq!(t(p), interpret(p).unwrap() as u64, "{p}");
+        }
+        let mut random = Random(0x9e3779b97f4a7c15);
+        for _ in 0..200 {
+            t(&random.program());
+        }
+    }
+
+    #[test]
+    fn test_bits() {
+        let bits = unsafe { run_bits(&jit("-").unwrap()) };
+        assert_eq!(bits.signed, -1);
+        assert_eq!(bits.unsigned, u64::MAX);
+        assert_eq!(
+            unsafe { run_bits(&jit("+70(*)").unwrap()) },
+            Bits {
+                signed: 0,
+                unsigned: 0
+            }
+        );
+        assert_eq!(
+            unsafe { run_bits(&jit("+63(*)").unwrap()) },
+            Bits {
+                signed: i64::MIN,
+                unsigned: 1 << 63
+            }
+        );
+        // Both views agree with the signed and unsigned runs of the same program
+        for p in ["++*", "-/", "=-7*3+1000", "-<", "3[+?-]", "--_"] {
+            let machine_code = jit(p).unwrap();
+            let bits = unsafe { run_bits(&machine_code) };
+            assert_eq!(bits.signed, unsafe { run(&machine_code) }, "{p}");
+            assert_eq!(bits.unsigned, unsafe { run_unsigned(&machine_code) }, "{p}");
+            let unsigned = jit_unsigned(p).unwrap();
+            assert_eq!(
+                unsafe { run_bits(&unsigned) }.unsigned,
+                unsafe { run_unsigned(&unsigned) },
+                "{p}"
+            );
+        }
+        assert_eq!(Bits::from(-2).unsigned, u64::MAX - 1);
+    }
+
+    #[test]
+    fn test_stack() {
+        /// Tester function, which checks the JIT and the interpreter agree
+        fn t(p: &str) -> i64 {
+            let result = compile(p).unwrap().call();
+            assert_eq!(interpret(p), Ok(result), "{p}");
+            let optimized = jit_with_options(p, Options::optimized()).unwrap();
+            assert_eq!(unsafe { run(&optimized) }, result, "{p}");
+            result
+        }
+
+        // 2 is pushed, doubled to 4, then swapped back for the 4, which is left on the stack
+        assert_eq!(t("++d*s-"), 1);
+        assert_eq!(t("+d+d+sss"), 2);
+        assert_eq!(t("+d+d+ss"), 3);
+        assert_eq!(t("+++d3[*s]"), 6);
+        assert_eq!(t("2[+d]s*s"), 2);
+        assert_eq!(t("=7d=-3s"), 7);
+        assert_eq!(t("=7d0s"), 7);
+        assert_eq!(t("+d2[+++d]0s"), 7);
+        assert_eq!(t("?d+ds"), 1);
+        assert_eq!(t("+?d+ds"), 2);
+        assert_eq!(t("??ds"), 0);
+        assert_eq!(t("+dddd+10s+s"), 11);
+        assert_eq!(t(&format!("+{}s", "d".repeat(MAX_STACK_DEPTH))), 1);
+        assert_eq!(t("+100[d]s"), 1);
+        assert_eq!(compile_with_input("d+s").unwrap().call_with(5), 5);
+        // Whether nothing was pushed isn't known until the program runs
+        assert_eq!(parse("s"), Err(JitError::EmptyStack { at_op: 0 }));
+        assert_eq!(parse("+-s"), Err(JitError::EmptyStack { at_op: 2 }));
+        assert_eq!(parse("?ds"), Err(JitError::EmptyStack { at_op: 2 }));
+        assert_eq!(parse("?2[d]s"), Err(JitError::EmptyStack { at_op: 4 }));
+        assert_eq!(parse("2[s]d"), Err(JitError::EmptyStack { at_op: 1 }));
+        assert_eq!(parse("2[sd]"), Err(JitError::EmptyStack { at_op: 1 }));
+        assert_eq!(
+            parse(&"d".repeat(MAX_STACK_DEPTH + 1)),
+            Err(JitError::StackTooDeep {
+                at_op: MAX_STACK_DEPTH
+            })
+        );
+        assert_eq!(parse("1000[d]"), Err(JitError::StackTooDeep { at_op: 0 }));
+        assert_eq!(
+            parse("2[?2[+100[d]]]"),
+            Err(JitError::StackTooDeep { at_op: 0 })
+        );
+        assert_eq!(
+            parse("2147483647[d]"),
+            Err(JitError::StackTooDeep { at_op: 0 })
+        );
+        assert!(parse("2147483647[s]").is_err());
+        assert_eq!(
+            JitError::EmptyStack { at_op: 3 }.to_string(),
+            "Nothing on the stack to swap with (at operation 3)"
+        );
+
+        // Returning early still throws away the stack
+        assert_eq!(
+            unsafe { run_checked(&jit_checked("+d70(*)").unwrap()) },
+            Err(Overflow { at_op: 64 })
+        );
+        assert_eq!(
+            unsafe { run_checked(&jit_checked("+30[d]s+70(*)").unwrap()) },
+            Err(Overflow { at_op: 67 })
+        );
+        assert_eq!(unsafe { run_checked(&jit_checked("+d*s").unwrap()) }, Ok(1));
+        assert_eq!(
+            unsafe { run(&jit_saturating("+d70(*)s+s").unwrap()) },
+            i64::MAX
+        );
+        assert_eq!(unsafe { run(&jit_saturating("+d70(*)s+").unwrap()) }, 2);
+        let folded = Options {
+            fold_constants: true,
+            ..Options::default()
+        };
+        assert_eq!(
+            jit_with_options("++d*s-", folded),
+            jit_with_options("+", folded)
+        );
+    }
+
+    #[test]
+    fn test_jit_ops_errors() {
+        use Op::*;
+        let invalid = |at_op| Err(JitError::InvalidOperation { at_op });
+        assert_eq!(
+            jit_ops(&[Plus, Swap]),
+            Err(JitError::EmptyStack { at_op: 1 })
+        );
+        assert_eq!(
+            jit_ops(&[Loop(300), Dup, EndLoop]),
+            Err(JitError::StackTooDeep { at_op: 0 })
+        );
+        assert_eq!(jit_ops(&[Plus, Loop(2), Plus]), invalid(1));
+        assert_eq!(jit_ops(&[Loop(2), Loop(2), EndLoop]), invalid(0));
+        assert_eq!(jit_ops(&[Plus, EndLoop]), invalid(1));
+        assert_eq!(jit_ops(&[Loop(2), EndLoop, EndLoop]), invalid(2));
+        assert_eq!(jit_ops(&[Loop(0), Plus, EndLoop]), invalid(0));
+        assert_eq!(jit_ops(&[Loop(-3), Dup, EndLoop]), invalid(0));
+        let deep = [Loop(2), Loop(2), Loop(2), Loop(2), Plus];
+        assert_eq!(jit_ops(&[&deep[..], &[EndLoop; 4]].concat()), invalid(3));
+        assert_eq!(jit_ops(&[Plus, Skip]), invalid(1));
+        assert_eq!(jit_ops(&[Loop(2), Skip, EndLoop, Plus]), invalid(1));
+        assert_eq!(jit_ops(&[Plus, Div(0)]), invalid(1));
+        assert_eq!(jit_ops(&[Rem(0)]), invalid(0));
+        assert_eq!(jit_ops(&[Set(i64::MIN), Div(-1)]), invalid(1));
+        assert_eq!(jit_ops(&[Set(i64::MIN), Rem(-1)]), invalid(1));
+        assert_eq!(jit_ops(&[Div(i64::MIN)]), invalid(0));
+        assert_eq!(
+            jit_ops(&vec![Plus; MAX_OPERATIONS + 1]),
+            invalid(MAX_OPERATIONS)
+        );
+        assert_eq!(jit_ops(&[Plus]).unwrap(), jit("+").unwrap());
+        // Everything `parse` gives is valid
+        for p in ["3[2[+]*]", "?+", "?3[+]", "??+", "++d*s-", "3[d]s", "%7/3"] {
+            assert_eq!(jit_ops(&parse(p).unwrap()), jit(p), "{p}");
+        }
+        assert_eq!(invalid(0).unwrap_err().index(), None);
+    }
+
+    #[test]
+    fn test_saturating() {
+        let options = Options {
+            overflow: OverflowBehavior::Saturate,
+            ..Options::default()
+        };
+        /// Tester function, which checks the JIT and the interpreter agree
+        fn t(p: &str) -> i64 {
+            let options = Options {
+                overflow: OverflowBehavior::Saturate,
+                ..Options::default()
+            };
+            let result = unsafe { run(&jit_saturating(p).unwrap()) };
+            assert_eq!(fold(&tokenize(p).unwrap(), 0, &options), result, "{p}");
+            result
+        }
+
+        // Once at a limit, the working register stays there until something moves it back
+        assert_eq!(t("+70(*)"), i64::MAX);
+        assert_eq!(t("+70(*)+++"), i64::MAX);
+        assert_eq!(t("+70(*)+++-"), i64::MAX - 1);
+        assert_eq!(t("-70(*)---"), i64::MIN);
+        assert_eq!(t("-70(*)---+"), i64::MIN + 1);
+        assert_eq!(t("+100[*]*3+2147483647"), i64::MAX);
+        assert_eq!(t("-100[*]*3-2147483647"), i64::MIN);
+        assert_eq!(t("+63(<)"), i64::MAX);
+        assert_eq!(t("-64(<)<"), i64::MIN);
+        assert_eq!(t("=2147483647*2147483647*2147483647"), i64::MAX);
+        assert_eq!(t("=-2147483647*2147483647*2147483647"), i64::MIN);
+        assert_eq!(t("=-2147483647*2147483647*2147483647^"), i64::MAX);
+        let min = "=-2147483647-1*65536*65536";
+        assert_eq!(t(&format!("{min}~")), i64::MAX);
+        assert_eq!(t(&format!("{min}|")), i64::MAX);
+        assert_eq!(t(&format!("{min}-")), i64::MIN);
+        assert_eq!(t(&format!("{min}+~")), i64::MAX);
+        // Nothing changes for programs which don't overflow
+        assert_eq!(t("++*-/"), 1);
+        assert_eq!(t("=-7*3+1000/7%5"), interpret("=-7*3+1000/7%5").unwrap());
+        // Multiplying by a negative number flips which limit is closer
+        let ops = [Op::Set(1 << 62), Op::Mul(-3)];
+        let machine_code = codegen(&ops, Start::Constant(0), &options).unwrap();
+        assert_eq!(unsafe { run(&machine_code) }, i64::MIN);
+        let ops = [Op::Set(-(1 << 62)), Op::Mul(-3), Op::Mul(-3)];
+        let machine_code = codegen(&ops, Start::Constant(0), &options).unwrap();
+        assert_eq!(unsafe { run(&machine_code) }, i64::MIN);
+        let mut random = Random(0xd1b54a32d192ed03);
+        for _ in 0..500 {
+            let program = random.program();
+            t(&program);
+            // Folding saturates too, whereas coalescing and strength reduction are ignored
+            for options in [Options::optimized(), Options::default()] {
+                let options = Options {
+                    overflow: OverflowBehavior::Saturate,
+                    ..options
+                };
+                let machine_code = jit_with_options(&program, options).unwrap();
+                assert_eq!(unsafe { run(&machine_code) }, t(&program), "{program}");
+            }
+        }
+        let folded = Options {
+            fold_constants: true,
+            ..options
+        };
+        assert_eq!(
+            unsafe { run(&jit_with_options("+70(*)-", folded).unwrap()) },
+            i64::MAX - 1
+        );
+    }
+
+    #[test]
+    fn test_div_rounding() {
+        // Every combination of sign and parity, including the extremes
+        let values = [
+            -7,
+            -6,
+            -1,
+            0,
+            1,
+            6,
+            7,
+            i64::MIN,
+            i64::MIN + 1,
+            i64::MAX - 1,
+            i64::MAX,
+        ];
+        for rounding in [DivRounding::TruncateTowardZero, DivRounding::Floor] {
+            for value in values {
+                let expected = match rounding {
+                    DivRounding::TruncateTowardZero => value / 2,
+                    DivRounding::Floor => value.div_euclid(2),
+                };
+                let ops = [Op::Set(value), Op::Slash];
+                for options in [Options::default(), Options::optimized()] {
+                    let options = Options {
+                        div_rounding: rounding,
+                        ..options
+                    };
+                    assert_eq!(fold(&ops, 0, &options), expected, "{value} {rounding:?}");
+                    let machine_code = codegen(&ops, Start::Constant(0), &options).unwrap();
+                    assert_eq!(
+                        unsafe { run(&machine_code) },
+                        expected,
+                        "{value} {rounding:?}"
+                    );
+                    let saturating = Options {
+                        overflow: OverflowBehavior::Saturate,
+                        ..options
+                    };
+                    let machine_code = codegen(&ops, Start::Constant(0), &saturating).unwrap();
+                    assert_eq!(
+                        unsafe { run(&machine_code) },
+                        expected,
+                        "{value} {rounding:?}"
+                    );
+                    let checked = Options {
+                        overflow: OverflowBehavior::Checked,
+                        ..options
+                    };
+                    let machine_code = codegen(&ops, Start::Constant(0), &checked).unwrap();
+                    assert_eq!(unsafe { run_checked(&machine_code) }, Ok(expected));
+                }
+            }
+        }
+        let floor = Options {
+            div_rounding: DivRounding::Floor,
+            ..Options::default()
+        };
+        assert_eq!(
+            unsafe { run(&jit_with_options("---/", floor).unwrap()) },
+            -2
+        );
+        assert_eq!(unsafe { run(&jit("---/").unwrap()) }, -1);
+        // Dividing by an operand still rounds towards zero
+        assert_eq!(
+            unsafe { run(&jit_with_options("---/2", floor).unwrap()) },
+            -1
+        );
+        // Unsigned halving is the same either way
+        let unsigned = Options {
+            signedness: Signedness::Unsigned,
+            ..floor
+        };
+        assert_eq!(
+            unsafe { run(&jit_with_options("---/", unsigned).unwrap()) },
+            (-3i64 as u64 / 2) as i64
+        );
+        assert_eq!(
+            fold(&parse("---/").unwrap(), 0, &unsigned),
+            (-3i64 as u64 / 2) as i64
+        );
+    }
+
+    #[test]
+    fn test_checked_option() {
+        let options = Options {
+            overflow: OverflowBehavior::Checked,
+            ..Options::optimized()
+        };
+        for p in ["++*", "+70(*)", "+++--"] {
+            assert_eq!(jit_with_options(p, options), jit_checked(p), "{p}");
+        }
+        assert_eq!(
+            unsafe { run_checked(&jit_with_options("++++70(*)", options).unwrap()) },
+            Err(Overflow { at_op: 64 })
+        );
+    }
+
+    #[test]
+    fn test_literal_pool() {
+        let mut machine_code = vec![0xc3];
+        let constants = vec![(0, 1.0), (1, 2.0), (2, 1.0)];
+        let addresses = literal_pool(&mut machine_code, &constants);
+        // Repeated constants are only stored once
+        assert_eq!(addresses, [(0, 8), (1, 16), (2, 8)]);
+        assert_eq!(machine_code.len(), 24);
+        assert_eq!(machine_code[1..8], [0; 7]);
+        assert_eq!(machine_code[8..16], 1.0f64.to_le_bytes());
+        assert_eq!(machine_code[16..], 2.0f64.to_le_bytes());
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("++*"), Ok(vec![Op::Plus, Op::Plus, Op::Star]));
+        assert_eq!(
+            parse("=5 2(-) # done"),
+            Ok(vec![Op::Set(5), Op::Minus, Op::Minus])
+        );
+        assert_eq!(parse("+x"), tokenize("+x"));
+        for p in ["++*", "=-7*3+1000/7", "3[+?-]", "+8(*)"] {
+            assert_eq!(jit_ops(&parse(p).unwrap()), jit(p), "{p}");
+        }
+    }
+
+    #[test]
+    fn test_comments() {
+        assert_eq!(interpret("++*   # start at 2, double"), Ok(4));
+        assert_eq!(interpret("# start at 2\n++\n# double\n*\n"), Ok(4));
+        // Anything can go in a comment, including operators
+        assert_eq!(interpret("+ #x+++\n+"), Ok(2));
+        assert_eq!(interpret("+#"), Ok(1));
+        assert_eq!(jit("# nothing\n  # here\n"), Err(JitError::EmptyProgram));
+        assert_eq!(jit("#"), Err(JitError::EmptyProgram));
+        // Tabs, carriage returns and other whitespace are skipped like spaces
+        assert_eq!(interpret("+\t+\r\n*\t# double\r\n+ # trailing"), Ok(5));
+        assert_eq!(interpret("\u{a0}+\u{2003}+\x0c+"), Ok(3));
+        let annotated = "# Start at 2\r\n++\r\n\r\n\t# Cube it\r\n\t*2*2 # that's 8\r\n";
+        assert_eq!(interpret(annotated), Ok(8));
+        assert_eq!(compile(annotated).unwrap().call(), 8);
+        assert_eq!(
+            jit("+ # fine\nx"),
+            Err(JitError::UnknownCharacter {
+                character: 'x',
+                index: 9,
+                line: 2,
+                column: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_remainder() {
+        /// Tester function
+        fn t(p: &str) -> i64 {
+            let result = compile(p).unwrap().call();
+            assert_eq!(interpret(p), Ok(result), "{p}");
+            result
+        }
+
+        // The quotient and the remainder of the same division
+        assert_eq!(t("+++/"), 1);
+        assert_eq!(t("+++%"), 1);
+        assert_eq!(t("++%"), 0);
+        assert_eq!(t("%"), 0);
+        assert_eq!(t("---%"), -1);
+        assert_eq!(t("----%"), 0);
+        assert_eq!(t("+++%+"), 2);
+        assert_eq!(t("-2147483647*2147483647*2%"), 0);
+        assert_eq!(t("-2147483647*2147483647%"), -1);
+        assert_eq!(unsafe { run_checked(&jit_checked("+++%").unwrap()) }, Ok(1));
+
+        // An operand replaces the divisor
+        assert_eq!(t("+7%3"), 1);
+        assert_eq!(t("+9%3"), 0);
+        assert_eq!(t("-7%3"), -1);
+        assert_eq!(t("-9%3"), 0);
+        assert_eq!(t("+7%2"), 1);
+        assert_eq!(t("%5"), 0);
+        assert_eq!(t("+4%1"), 0);
+        assert_eq!(t("+1000%7+"), 7);
+        assert_eq!(t("-2147483647*2147483647%2147483647"), 0);
+        assert_eq!(t("-2147483647*2147483647-%1000"), -610);
+        assert_eq!(tokenize("%3").unwrap(), [Op::Rem(3)]);
+        assert_eq!(
+            unsafe { run_checked(&jit_checked("-8%5").unwrap()) },
+            Ok(-3)
+        );
+        assert_eq!(jit("+%0"), Err(JitError::DivisionByZero { index: 1 }));
+        assert_eq!(
+            jit("%2147483648"),
+            Err(JitError::OperandOutOfRange { index: 0 })
+        );
+    }
+
+    #[test]
+    fn test_square() {
+        /// Tester function
+        fn t(p: &str) -> i64 {
+            let result = compile(p).unwrap().call();
+            assert_eq!(interpret(p), Ok(result), "{p}");
+            result
+        }
+
+        assert_eq!(t("+++^"), 9);
+        assert_eq!(t("--^"), 4);
+        assert_eq!(t("^"), 0);
+        assert_eq!(t("++^^"), 16);
+        assert_eq!(t("++^^^"), 256);
+        assert_eq!(t("---^"), 9);
+        assert_eq!(t("=-5^^"), 625);
+        assert_eq!(t("-2147483647^"), 4611686014132420609);
+        // Overflow wraps, just like `*`
+        assert_eq!(
+            t("+2147483647*4^"),
+            2147483647i64.wrapping_mul(4).wrapping_pow(2)
+        );
+        assert_eq!(t("++^^^^^^"), 0);
+        assert_eq!(
+            unsafe { run_checked(&jit_checked("+2147483647*2^").unwrap()) },
+            Err(Overflow { at_op: 2 })
+        );
+        assert_eq!(unsafe { run_checked(&jit_checked("---^").unwrap()) }, Ok(9));
+    }
+
+    #[test]
+    fn test_negate() {
+        /// Tester function
+        fn t(p: &str) -> i64 {
+            let result = compile(p).unwrap().call();
+            assert_eq!(interpret(p), Ok(result), "{p}");
+            result
+        }
+
+        assert_eq!(t("++~"), -2);
+        assert_eq!(t("~"), 0);
+        assert_eq!(t("--~"), 2);
+        assert_eq!(t("+++~"), -3);
+        assert_eq!(t("+++~~"), 3);
+        assert_eq!(t("+~+"), 0);
+        assert_eq!(t("=2147483647~"), -2147483647);
+        // i64::MIN has no positive counterpart, so negating it wraps around to itself
+        let min = "=-2147483647-1*65536*65536";
+        assert_eq!(t(min), i64::MIN);
+        assert_eq!(t(&format!("{min}~")), i64::MIN);
+        assert_eq!(t(&format!("{min}+~")), i64::MAX);
+        assert_eq!(
+            unsafe { run_checked(&jit_checked(&format!("{min}~")).unwrap()) },
+            Err(Overflow { at_op: 4 })
+        );
+        assert_eq!(unsafe { run_checked(&jit_checked("++~").unwrap()) }, Ok(-2));
+    }
+
+    #[test]
+    fn test_reset() {
+        /// Tester function
+        fn t(p: &str) -> i64 {
+            let result = compile(p).unwrap().call();
+            assert_eq!(interpret(p), Ok(result), "{p}");
+            result
+        }
+
+        assert_eq!(t("+++0+"), 1);
+        assert_eq!(t("0"), 0);
+        assert_eq!(t("0--"), -2);
+        assert_eq!(t("++*0"), 0);
+        assert_eq!(t("=5 0"), 0);
+        assert_eq!(t("-0+0-"), -1);
+        assert_eq!(t("3[++0+]"), 1);
+        assert_eq!(t("+?0+"), 1);
+        assert_eq!(t("?0+"), 1);
+        // Numbers can't start with a zero, so these aren't operands
+        assert_eq!(tokenize("+0").unwrap(), [Op::Plus, Op::Zero]);
+        assert_eq!(tokenize("*0").unwrap(), [Op::Star, Op::Zero]);
+        assert_eq!(t("+10"), 10);
+        assert_eq!(t("=0+"), 1);
+        assert_eq!(jit("/0"), Err(JitError::DivisionByZero { index: 0 }));
+        assert_eq!(jit("0(+)"), Err(JitError::ZeroRepeat { index: 0 }));
+        assert_eq!(
+            unsafe { run_checked(&jit_checked("+2147483647*2147483647*2147483647*0+").unwrap()) },
+            Err(Overflow { at_op: 2 })
+        );
+        assert_eq!(unsafe { run_checked(&jit_checked("--0+").unwrap()) }, Ok(1));
+    }
+
+    #[test]
+    fn test_reset_removes_dead_code() {
+        let options = Options {
+            coalesce: true,
+            ..Options::default()
+        };
+        assert_eq!(jit_with_options("+++*0+", options), jit("0+"));
+        assert_eq!(jit_with_options("+^2[*]~0", options), jit("0"));
+        assert_eq!(jit_with_options("++*=5-", options), jit("=5-"));
+        assert_eq!(jit_with_options("+0*0+", options), jit("0+"));
+        // A skipped reset might not happen, and a reset inside a loop only clears the
+        // value in that loop
+        for p in ["+?0+", "-*?0*", "+2[*0+]*", "+2[*?0]*"] {
+            assert_eq!(jit_with_options(p, options), jit(p), "{p}");
+        }
+    }
+
+    #[test]
+    fn test_shifts() {
+        /// Tester function
+        fn t(p: &str) -> i64 {
+            let result = compile(p).unwrap().call();
+            assert_eq!(interpret(p), Ok(result), "{p}");
+            result
+        }
+
+        // Shifting right rounds down, where halving rounds towards zero
+        assert_eq!(t("---/"), -1);
+        assert_eq!(t("--->"), -2);
+        assert_eq!(t("+++>"), 1);
+        assert_eq!(t("->"), -1);
+        assert_eq!(t("-/"), 0);
+        assert_eq!(t("->>>"), -1);
+        assert_eq!(t("+>"), 0);
+        assert_eq!(t(">"), 0);
+        assert_eq!(t("+<<<"), 8);
+        assert_eq!(t("---<"), -6);
+        assert_eq!(t("+5<>"), 5);
+        assert_eq!(t("+63(<)"), i64::MIN);
+        assert_eq!(t("+64(<)"), 0);
+        assert_eq!(t("-63(<)>"), i64::MIN >> 1);
+        assert_eq!(
+            unsafe { run_checked(&jit_checked("+62(<)").unwrap()) },
+            Ok(1 << 62)
+        );
+        assert_eq!(
+            unsafe { run_checked(&jit_checked("+63(<)").unwrap()) },
+            Err(Overflow { at_op: 63 })
+        );
+        assert_eq!(
+            unsafe { run_checked(&jit_checked("-63(<)>").unwrap()) },
+            Ok(i64::MIN >> 1)
+        );
+        assert_eq!(unsafe { run_float(&jit_float("--->").unwrap()) }, -2.0);
+        assert_eq!(unsafe { run_float(&jit_float("+++/<").unwrap()) }, 3.0);
+    }
+
+    #[test]
+    fn test_abs() {
+        /// Tester function
+        fn t(p: &str) -> i64 {
+            let result = compile(p).unwrap().call();
+            assert_eq!(interpret(p), Ok(result), "{p}");
+            result
+        }
+
+        assert_eq!(t("---|"), 3);
+        assert_eq!(t("++|"), 2);
+        assert_eq!(t("|"), 0);
+        assert_eq!(t("-|-|"), 0);
+        assert_eq!(t("=-2147483647*2147483647|"), 4611686014132420609);
+        // i64::MIN has no positive counterpart, so it stays as it is
+        let min = "=-2147483647-1*65536*65536";
+        assert_eq!(t(&format!("{min}|")), i64::MIN);
+        assert_eq!(t(&format!("{min}+|")), i64::MAX);
+        assert_eq!(
+            unsafe { run_checked(&jit_checked(&format!("{min}|")).unwrap()) },
+            Err(Overflow { at_op: 4 })
+        );
+        assert_eq!(
+            unsafe { run_checked(&jit_checked(&format!("{min}+|")).unwrap()) },
+            Ok(i64::MAX)
+        );
+        assert_eq!(unsafe { run_checked(&jit_checked("---|").unwrap()) }, Ok(3));
+        assert_eq!(unsafe { run_checked(&jit_checked("++|").unwrap()) }, Ok(2));
+        assert_eq!(unsafe { run_float(&jit_float("---/|").unwrap()) }, 1.5);
+    }
+
+    #[test]
+    fn test_clamp() {
+        /// Tester function
+        fn t(p: &str) -> i64 {
+            let result = compile(p).unwrap().call();
+            assert_eq!(interpret(p), Ok(result), "{p}");
+            result
+        }
+
+        assert_eq!(t("--_"), 0);
+        assert_eq!(t("++_"), 2);
+        assert_eq!(t("_"), 0);
+        assert_eq!(t("--_+"), 1);
+        assert_eq!(t("=-5_~_"), 0);
+        assert_eq!(t("+62(*)*_"), 0);
+        assert_eq!(t("+62(*)-*_"), i64::MAX - 1);
+        assert_eq!(t("3[-_]"), 0);
+        assert_eq!(unsafe { run_checked(&jit_checked("--_").unwrap()) }, Ok(0));
+        assert_eq!(
+            unsafe { run(&jit_saturating("+62(*)*_").unwrap()) },
+            i64::MAX
+        );
+        assert_eq!(unsafe { run(&jit_saturating("-62(*)*_").unwrap()) }, 0);
+        // Unsigned values are never negative
+        assert_eq!(
+            unsafe { run_unsigned(&jit_unsigned("--_").unwrap()) },
+            u64::MAX - 1
+        );
+        assert_eq!(interpret_unsigned("--_"), Ok(u64::MAX - 1));
+        assert_eq!(unsafe { run_float(&jit_float("---/_").unwrap()) }, 0.0);
+        assert_eq!(unsafe { run_float(&jit_float("+++/_").unwrap()) }, 1.5);
+    }
+
+    #[test]
+    fn test_op_chars() {
+        for c in ['+', '-', '*', '/', '%', '^', '~', '<', '>', '|', '_'] {
+            let op = Op::try_from(c).unwrap();
+            assert_eq!(op.to_string(), c.to_string());
+        }
+        assert_eq!(Op::try_from('x'), Err('x'));
+        assert_eq!(Op::try_from(' '), Err(' '));
+        assert_eq!(Op::try_from('1'), Err('1'));
+        assert_eq!(Op::Add(5).to_string(), "+5");
+        assert_eq!(Op::Add(-5).to_string(), "-5");
+        assert_eq!(Op::Sub(3).to_string(), "-3");
+        assert_eq!(Op::Mul(4).to_string(), "*4");
+        assert_eq!(Op::Div(2).to_string(), "/2");
+        assert_eq!(Op::Rem(3).to_string(), "%3");
+        assert_eq!(Op::Set(-7).to_string(), "=-7");
+        assert_eq!(Op::try_from('='), Err('='));
+        let program = "++*3-/4%^+~=-7*=9^2[+?-~]";
+        let ops = tokenize(program).unwrap();
+        assert_eq!(ops.iter().map(Op::to_string).collect::<String>(), program);
+    }
+
+    #[test]
+    fn test_jit_ops() {
+        /// Tester function
+        fn t(ops: &[Op]) -> i64 {
+            unsafe { run(&jit_ops(ops).unwrap()) }
+        }
+        use Op::*;
+
+        assert_eq!(t(&[Plus]), 1);
+        assert_eq!(t(&[Plus, Plus]), 2);
+        assert_eq!(t(&[Plus, Plus, Slash]), 1);
+        assert_eq!(t(&[Minus]), -1);
+        assert_eq!(t(&[Minus, Minus, Star]), -4);
+        assert_eq!(t(&[Star]), 0);
+        assert_eq!(t(&[Slash]), 0);
+        assert_eq!(
+            t(&[Plus, Plus, Star, Star, Star, Star, Star, Star, Star]),
+            256
+        );
+        assert_eq!(t(&[Minus, Minus, Star, Star, Plus, Plus]), -6);
+        assert_eq!(t(&[Minus, Minus, Minus, Slash]), -1);
+        assert_eq!(t(&[Add(-7), Mul(3), Div(5)]), -4);
+        assert_eq!(jit_ops(&tokenize("++*").unwrap()), jit("++*"));
+    }
+
+    #[test]
+    fn test_program() {
+        let program = crate::Program::parse("++*").unwrap();
+        assert_eq!(program.ops, [Op::Plus, Op::Plus, Op::Star]);
+        assert_eq!(program.jit(), jit("++*"));
+        assert_eq!(program.compile().unwrap().call(), 4);
+        let program = crate::Program::from(vec![Op::Set(7), Op::Rem(4)]);
+        assert_eq!(program.compile().unwrap().call(), 3);
+        assert!(crate::Program::parse("+x").is_err());
+        for op in [Op::Div(-1), Op::Rem(-1), Op::Div(i64::MIN)] {
+            let program = crate::Program::from(vec![Op::Set(i64::MIN), op]);
+            assert!(
+                matches!(
+                    program.compile(),
+                    Err(CalcError::Jit(JitError::InvalidOperation { at_op: 1 }))
+                ),
+                "{op}"
+            );
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        use crate::{OverflowBehavior, Program, Target};
+
+        let program: Program = serde_json::from_str(r#"["plus","plus","star"]"#).unwrap();
+        assert_eq!(program, Program::parse("++*").unwrap());
+        assert_eq!(program.compile().unwrap().call(), 4);
+
+        let program = Program::parse("+5-3*2/4%3=-7 2[+?-]~|^<>0ds").unwrap();
+        let json = serde_json::to_string(&program).unwrap();
+        assert!(
+            json.starts_with(r#"[{"add":5},{"sub":3},{"mul":2}"#),
+            "{json}"
+        );
+        assert!(json.contains(r#"{"loop":2},"plus","skip","minus","end_loop""#));
+        assert_eq!(serde_json::from_str::<Program>(&json).unwrap(), program);
+        assert!(serde_json::from_str::<Program>(r#"["plus","twice"]"#).is_err());
+
+        let options = Options {
+            coalesce: true,
+            target: Target::Aarch64,
+            overflow: OverflowBehavior::Saturate,
+            ..Options::default()
+        };
+        let json = serde_json::to_string(&options).unwrap();
+        assert!(json.contains(r#""target":"aarch64""#), "{json}");
+        assert_eq!(serde_json::from_str::<Options>(&json).unwrap(), options);
+        // Settings which are left out keep their defaults
+        assert_eq!(
+            serde_json::from_str::<Options>(r#"{"coalesce":true}"#).unwrap(),
+            Options {
+                coalesce: true,
+                ..Options::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_set() {
+        /// Tester function
+        fn t(p: &str) -> i64 {
+            let result = compile(p).unwrap().call();
+            assert_eq!(interpret(p), Ok(result), "{p}");
+            result
+        }
+
+        assert_eq!(t("=42"), 42);
+        assert_eq!(t("=42++"), 44);
+        assert_eq!(t("=10*"), 20);
+        assert_eq!(t("=-7"), -7);
+        assert_eq!(t("=-7/"), -3);
+        assert_eq!(t("+++=0"), 0);
+        assert_eq!(t("--*=5-"), 4);
+        assert_eq!(t("=2147483647*2"), 4294967294);
+        assert_eq!(t("=-2147483647-"), -2147483648);
+        assert_eq!(jit("=").unwrap_err(), JitError::MissingOperand { index: 0 });
+        assert_eq!(
+            jit("++=-").unwrap_err(),
+            JitError::MissingOperand { index: 2 }
+        );
+        assert_eq!(
+            jit("= 5").unwrap_err(),
+            JitError::MissingOperand { index: 0 }
+        );
+        assert_eq!(
+            jit("=-2147483648").unwrap_err(),
+            JitError::OperandOutOfRange { index: 0 }
+        );
+        assert_eq!(
+            unsafe { run_checked(&jit_checked("+2147483647*2147483647*2147483647=3").unwrap()) },
+            Err(Overflow { at_op: 2 })
+        );
+        assert_eq!(
+            unsafe { run_checked(&jit_checked("=-2147483647*2147483647*2").unwrap()) },
+            Ok(-9223372028264841218)
+        );
+    }
+
+    #[test]
+    fn test_operands() {
+        /// Tester function
+        fn t(p: &str) -> i64 {
+            compile(p).unwrap().call()
+        }
+
+        assert_eq!(t("+10*3/2"), 15);
+        assert_eq!(t("+5"), 5);
+        assert_eq!(t("-3"), -3);
+        assert_eq!(t("+*4"), 4);
+        assert_eq!(t("+100/3"), 33);
+        assert_eq!(t("-100/3"), -33);
+        assert_eq!(t("+7 -7"), 0);
+        assert_eq!(t("+2147483647*2147483647"), 4611686014132420609);
+        assert_eq!(t("-2147483647-2147483647"), -4294967294);
+        assert_eq!(t("+1000/1"), 1000);
+        assert_eq!(t("++*3"), 6);
+        assert_eq!(t("+*10*10"), 100);
+        assert_eq!(t("--*3"), -6);
+        assert_eq!(t("+*0"), 0);
+        assert_eq!(t("++*1000"), 2000);
+        // A bare `*` still doubles
+        assert_eq!(t("++*"), 4);
+    }
+}
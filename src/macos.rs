@@ -0,0 +1,142 @@
+// Executable memory on macOS on Apple Silicon. There a process with the hardened runtime
+// can't make memory executable once it's been written to, so the code is instead written
+// to memory mapped with `MAP_JIT`. That memory is always mapped writable and executable,
+// but each thread sees it as only one or the other at a time, which
+// `pthread_jit_write_protect_np` switches between. The code is still never writable and
+// executable at once for the thread running it. Binaries signed with the hardened runtime
+// need the `com.apple.security.cs.allow-jit` entitlement to map it.
+
+use crate::exec::flush_icache;
+use crate::RunError;
+use core::ffi::{c_int, c_void};
+
+extern "C" {
+    fn mmap(
+        addr: *mut c_void,
+        len: usize,
+        prot: c_int,
+        flags: c_int,
+        fd: c_int,
+        offset: i64,
+    ) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    fn pthread_jit_write_protect_np(enabled: c_int);
+}
+
+const PROT_READ: c_int = 0x1;
+const PROT_WRITE: c_int = 0x2;
+const PROT_EXEC: c_int = 0x4;
+const MAP_PRIVATE: c_int = 0x2;
+const MAP_JIT: c_int = 0x800;
+const MAP_ANON: c_int = 0x1000;
+const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+/// A `MAP_JIT` mapping, which is unmapped when dropped
+pub(crate) struct Allocation {
+    base: *mut u8,
+    len: usize,
+}
+
+impl Allocation {
+    pub(crate) fn as_ptr<T>(&self) -> *const T {
+        self.base as *const T
+    }
+
+    /// How many bytes are mapped, which is a whole number of pages
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+}
+
+impl Drop for Allocation {
+    fn drop(&mut self) {
+        // Safety: the mapping came from `mmap` with this length, and is only unmapped here
+        unsafe { munmap(self.base as *mut c_void, self.len) };
+    }
+}
+
+/// Map at least `len` bytes of memory for code. Like `region::alloc`, nothing can be
+/// mapped for no code at all.
+pub(crate) fn alloc(len: usize) -> Result<Allocation, RunError> {
+    if len == 0 {
+        return Err(RunError::Alloc(region::Error::InvalidParameter("size")));
+    }
+    let len = len.next_multiple_of(region::page::size());
+    let prot = PROT_READ | PROT_WRITE | PROT_EXEC;
+    // Safety: a new anonymous mapping doesn't alias any existing memory
+    let base = unsafe {
+        mmap(
+            core::ptr::null_mut(),
+            len,
+            prot,
+            MAP_PRIVATE | MAP_ANON | MAP_JIT,
+            -1,
+            0,
+        )
+    };
+    if base == MAP_FAILED {
+        let error = std::io::Error::last_os_error();
+        return Err(RunError::Alloc(region::Error::SystemCall(error)));
+    }
+    Ok(Allocation {
+        base: base as *mut u8,
+        len,
+    })
+}
+
+/// Copy machine code to the start of the mapping, which must be at least as long as the
+/// code, and make sure the CPU fetches the new code rather than whatever was there before
+pub(crate) fn fill(memory: &mut Allocation, machine_code: &[u8]) {
+    assert!(machine_code.len() <= memory.len);
+    // Safety: the mapping is writable for this thread between the two calls, and the
+    // code is only called after the second, from this same thread
+    unsafe {
+        pthread_jit_write_protect_np(0);
+        core::ptr::copy_nonoverlapping(machine_code.as_ptr(), memory.base, machine_code.len());
+        pthread_jit_write_protect_np(1);
+        flush_icache(memory.base, machine_code.len());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::macos::alloc;
+    use crate::{compile, evaluate, jit, jit_checked, run_checked, try_run, RunError, Runner};
+
+    #[test]
+    fn test_map_jit() {
+        for p in ["++*", "-", "+8(*)", "3[+?-]", "++d*s-", "=-7%3", "--_"] {
+            assert_eq!(compile(p).unwrap().call(), evaluate(p).unwrap(), "{p}");
+        }
+        assert_eq!(
+            unsafe { run_checked(&jit_checked("+62(*)*").unwrap()) }
+                .unwrap_err()
+                .at_op,
+            63
+        );
+    }
+
+    /// An empty mapping would leave nothing but zeros to run, which aren't instructions
+    #[test]
+    fn test_map_jit_empty() {
+        assert!(matches!(
+            alloc(0),
+            Err(RunError::Alloc(region::Error::InvalidParameter(_)))
+        ));
+        assert!(matches!(unsafe { try_run(&[]) }, Err(RunError::Alloc(_))));
+        assert_eq!(alloc(1).unwrap().len(), region::page::size());
+    }
+
+    /// Rewriting the same memory again and again needs the instruction cache to be
+    /// invalidated each time, or the CPU could run the previous program instead
+    #[test]
+    fn test_map_jit_reuse() {
+        let mut runner = Runner::new();
+        for n in 0..100 {
+            let program = format!("+{n}*");
+            let result = unsafe { runner.run(&jit(&program).unwrap()) }.unwrap();
+            assert_eq!(result, 2 * n, "{program}");
+        }
+        assert_eq!(runner.capacity(), region::page::size());
+    }
+}
@@ -1,163 +1,753 @@
-// Created: 2025/1/5
-//  Edited: 2025/1/6
-//  Author: Alexander Bass
-//
-// The program which follows acts as a simple JIT compiler.
-// A sequence of arithmetic operations is fed to a jit function which converts
-// them into executable x86_64 machine code. Then, the machine code is sent
-// to a run function which executes the machine code and receives a return
-// value from it. The operations are very simple and all act upon one variable.
-//
-// "+": Increment variable
-// "-": Decrement variable
-// "*": Double variable
-// "/": Halve variable
-//
-// Some example sequences and their outputs include:
-// "+":  1
-// "-": -1
-// "++*": 4
-// "++*-/": 1
-//
-// This has been tested and works on x86_64 Linux. It should work on Windows
-// and other OSes, but will certainly not work on other CPU architectures.
-//
-// The region library is used as a cross-platform way to allocate executable memory.
-//
-// This was inspired by a the blog post <https://ochagavia.nl/blog/the-jit-calculator-challenge/>
-//
-//
-use region::Protection;
-
-fn main() {
-    let p = jit("+ + * - /");
-    let r = run(&p);
-    println!("{r}");
-}
-
-/// Compile the sequence of instructions into working x86_64 machine code
-/// following the C calling convention. The type of the function
-/// produced (in C notation) is: `int64_t f()`
-fn jit(program: &str) -> Vec<u8> {
-    // Step 1, tokenize the string into operations
-    enum Op {
-        Plus,
-        Minus,
-        Star,
-        Slash,
-    }
-
-    let mut tokens: Vec<Op> = Vec::new();
-
-    for c in program.chars() {
-        let t = match c {
-            '+' => Op::Plus,
-            '-' => Op::Minus,
-            '*' => Op::Star,
-            '/' => Op::Slash,
-            ' ' | '\n' => continue,
-            e => panic!("Unknown character in program string: {e}"),
-        };
-        tokens.push(t);
-    }
-    assert!(!tokens.is_empty());
-
-    // Step 2: Compile
-    // The tokens are compiled to a sequence of instructions.
-
-    let mut machine_code: Vec<u8> = Vec::new();
-    // Set working 64-bit register (rcx) to zero by xoring it with itself
-    // `xor %rcx, %rcx`
-    machine_code.extend_from_slice(&[0x48, 0x31, 0xc9]);
-
-    for token in tokens {
-        let m: &[u8] = match token {
-            // Increment the working register by 1
-            // `inc %rcx`
-            Op::Plus => &[0x48, 0xff, 0xc1],
-            // Decrement the working register by 1
-            // `dec %rcx`
-            Op::Minus => &[0x48, 0xff, 0xc9],
-            // Multiply the working register by 2
-            // `imul $0x02, %rcx`
-            Op::Star => &[0x48, 0x6b, 0xc9, 0x02],
-            // Copy the value in the working register (rcx) to rax
-            // `mov  %rcx, %rax`
-            // Copy the divisor (2) into register r8
-            // `mov $0x02, %r8`
-            // Just google this one
-            // `cqto`
-            // Divide the value in rax by the value in r8, store result to rax.
-            // `idivq %r8`
-            // Move result (currently in rax) back into working register (rcx)
-            // `mov %rax, %rcx`
-            Op::Slash => &[
-                0x48, 0x89, 0xC8, 0x49, 0xc7, 0xc0, 0x02, 0x00, 0x00, 0x00, 0x48, 0x99, 0x49, 0xF7,
-                0xF8, 0x48, 0x89, 0xC1,
-            ],
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+use simple_jit_calculator::benchmark;
+use simple_jit_calculator::{
+    disassemble, emit_object, evaluate, format_hex, interpret_unsigned, jit, jit_expr, jit_float,
+    jit_rpn, jit_unsigned, jit_with_options, load_blob, render, run, run_blob, run_float,
+    run_unsigned, save_blob, CalcError, JitError, Options, Target,
+};
+use std::io::{BufRead, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+const USAGE: &str =
+    "Usage: simple_jit_calculator [--float | --unsigned | --expr | --rpn] [--dump-hex] <program>
+       simple_jit_calculator [--float | --unsigned | --expr | --rpn] [--dump-hex] -f <path>
+Example: simple_jit_calculator \"++*\"
+
+Options:
+  -f, --file <path>  Read the program from a file, or from stdin if the path is -
+  --float            Work on floating point numbers instead of integers, so +/ gives 0.5
+  --unsigned         Work on unsigned integers, so -/ gives 9223372036854775807
+  --expr             Read an infix expression instead, such as (1+2)*3-4/2
+  --rpn              Read an expression in reverse Polish notation instead, such as 3 4 + 2 *
+  --dump-asm         Print the x86_64 machine code for the program instead of running it
+  --dump-hex         Also print a hex dump of the machine code for the program to stderr
+  --emit-obj <path>  Write the program to an ELF object file exporting `int64_t calc(void)`,
+                     instead of running it
+  --emit-bin <path>  Write the machine code for the program to a file instead of running it
+  --run-bin <path>   Run machine code written by --emit-bin, rather than a program
+  --bench <n>        Time running the program n times compiled against interpreting it
+Options can be given before or after the program.
+When no arguments are given, an interactive prompt is started, or the program is read
+from stdin if it is not a terminal.";
+
+/// The flags choosing a [`Mode`], only one of which can be given
+const MODE_FLAGS: [&str; 5] = ["--float", "--unsigned", "--expr", "--rpn", "--dump-asm"];
+
+/// What the command line arguments ask for
+#[derive(Debug, PartialEq)]
+struct Args {
+    source: Source,
+    mode: Mode,
+    /// Whether to print a hex dump of the machine code to stderr before the result
+    dump_hex: bool,
+    /// Where to write the compiled program, if it isn't being run
+    emit: Option<Emit>,
+    /// How many times to run the program for a benchmark, instead of running it once
+    bench: Option<u32>,
+}
+
+/// A file to write the compiled program to, rather than running it
+#[derive(Debug, PartialEq)]
+enum Emit {
+    /// An ELF object file from [`emit_object`], exporting the program as `calc`
+    Object(PathBuf),
+    /// The machine code from `jit` behind the header from [`save_blob`], for `--run-bin`
+    Binary(PathBuf),
+}
+
+impl Emit {
+    /// The flag asking for this kind of file
+    fn flag(&self) -> &'static str {
+        match self {
+            Emit::Object(_) => "--emit-obj",
+            Emit::Binary(_) => "--emit-bin",
+        }
+    }
+
+    /// Compile the program, returning the path to write to and what to write there
+    fn contents(&self, program: &str) -> Result<(&Path, Vec<u8>), JitError> {
+        match self {
+            Emit::Object(path) => Ok((path, emit_object(program, "calc")?)),
+            Emit::Binary(path) => Ok((path, save_blob(&jit(program)?, Target::default()))),
+        }
+    }
+}
+
+/// What kind of number the program works on
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    /// Signed 64-bit integers, as compiled by `jit`
+    Integer,
+    /// Unsigned 64-bit integers, as compiled by [`jit_unsigned`]
+    Unsigned,
+    /// Floating point numbers, as compiled by [`jit_float`]
+    Float,
+    /// Signed 64-bit integers, with the program written as an infix expression
+    /// compiled by [`jit_expr`]
+    Expr,
+    /// Signed 64-bit integers, with the program written in reverse Polish notation
+    /// compiled by [`jit_rpn`]
+    Rpn,
+    /// Signed 64-bit integers, but the x86_64 code for the program is printed through
+    /// [`disassemble`] rather than being run. This works whatever the host is.
+    DumpAsm,
+}
+
+/// Where the program string comes from
+#[derive(Debug, PartialEq)]
+enum Source {
+    /// The program was given directly on the command line
+    Inline(String),
+    /// The program is stored in a file
+    File(PathBuf),
+    /// The program is piped in through standard input
+    Stdin,
+    /// Programs are typed in one line at a time at an interactive prompt
+    Repl,
+    /// Machine code saved by `--emit-bin` is run instead of a program
+    Blob(PathBuf),
+}
+
+impl Source {
+    /// Get the program string out of its source
+    fn read(self) -> std::io::Result<String> {
+        match self {
+            Source::Inline(program) => Ok(program),
+            Source::File(path) => std::fs::read_to_string(path),
+            Source::Stdin => {
+                let mut program = String::new();
+                std::io::stdin().read_to_string(&mut program)?;
+                Ok(program)
+            }
+            Source::Repl => unreachable!("the REPL reads its own input"),
+            Source::Blob(_) => unreachable!("saved machine code isn't a program string"),
+        }
+    }
+}
+
+/// Work out where to read the program from, given the command line arguments
+/// (excluding the binary name). Multiple program arguments are joined with a space,
+/// which the tokenizer skips over.
+fn parse_args(args: impl Iterator<Item = String>, stdin_is_terminal: bool) -> Result<Args, String> {
+    let mut args: Vec<String> = args.collect();
+    let mut mode = Mode::Integer;
+    let mut dump_hex = false;
+    let mut emit = None;
+    let mut bench = None;
+    // Flags can come before or after the program, so they're taken out wherever they are,
+    // leaving the arguments saying where the program comes from
+    let mut i = 0;
+    while let Some(flag) = args.get(i) {
+        let flag_mode = match flag.as_str() {
+            "--dump-hex" => {
+                dump_hex = true;
+                args.remove(i);
+                continue;
+            }
+            flag @ ("--emit-obj" | "--emit-bin") => {
+                let Some(path) = args.get(i + 1) else {
+                    return Err(format!("Missing path after {flag}\n\n{USAGE}"));
+                };
+                let path = PathBuf::from(path);
+                emit = Some(match flag {
+                    "--emit-obj" => Emit::Object(path),
+                    _ => Emit::Binary(path),
+                });
+                args.drain(i..i + 2);
+                continue;
+            }
+            // The benchmark needs something to compare the interpreter against
+            "--bench" if Target::host().is_none() => {
+                return Err("--bench is only supported on x86_64 and AArch64".to_string());
+            }
+            "--bench" => {
+                let iterations = args
+                    .get(i + 1)
+                    .and_then(|n| n.parse().ok())
+                    .filter(|&n| n > 0);
+                let Some(iterations) = iterations else {
+                    return Err(format!(
+                        "Expected a positive number of iterations after --bench\n\n{USAGE}"
+                    ));
+                };
+                bench = Some(iterations);
+                args.drain(i..i + 2);
+                continue;
+            }
+            // The path after these is left for `parse_source`, even if it looks like a flag
+            "-f" | "--file" | "--run-bin" => {
+                i += 2;
+                continue;
+            }
+            // There's no interpreter for floating point programs or expressions to fall back on
+            flag @ ("--float" | "--expr" | "--rpn") if Target::host().is_none() => {
+                return Err(format!("{flag} is only supported on x86_64 and AArch64"));
+            }
+            "--float" => Mode::Float,
+            "--unsigned" => Mode::Unsigned,
+            "--expr" => Mode::Expr,
+            "--rpn" => Mode::Rpn,
+            "--dump-asm" => Mode::DumpAsm,
+            _ => {
+                i += 1;
+                continue;
+            }
         };
-        machine_code.extend_from_slice(m);
+        if mode != Mode::Integer {
+            return Err(format!(
+                "{} can't be combined\n\n{USAGE}",
+                MODE_FLAGS.join(", ")
+            ));
+        }
+        mode = flag_mode;
+        args.remove(i);
+    }
+    let source = parse_source(&args, stdin_is_terminal)?;
+    if let Some(emit) = &emit {
+        // Both kinds of file hold the code from `jit`, which works on signed integers
+        if mode != Mode::Integer {
+            return Err(format!(
+                "{} can't be combined with {}\n\n{USAGE}",
+                emit.flag(),
+                MODE_FLAGS.join(", ")
+            ));
+        }
+        if matches!(source, Source::Repl | Source::Blob(_)) {
+            return Err(format!(
+                "{} needs a program to compile\n\n{USAGE}",
+                emit.flag()
+            ));
+        }
+    }
+    if matches!(source, Source::Blob(_)) && mode != Mode::Integer {
+        return Err(format!(
+            "--run-bin can't be combined with {}\n\n{USAGE}",
+            MODE_FLAGS.join(", ")
+        ));
+    }
+    if bench.is_some() {
+        // The interpreter only works on signed integers
+        if mode != Mode::Integer || emit.is_some() {
+            return Err(format!(
+                "--bench can't be combined with {}, --emit-obj, --emit-bin\n\n{USAGE}",
+                MODE_FLAGS.join(", ")
+            ));
+        }
+        if matches!(source, Source::Repl | Source::Blob(_)) {
+            return Err(format!("--bench needs a program to run\n\n{USAGE}"));
+        }
+    }
+    Ok(Args {
+        source,
+        mode,
+        dump_hex,
+        emit,
+        bench,
+    })
+}
+
+/// Work out where to read the program from, given the arguments other than flags
+fn parse_source(args: &[String], stdin_is_terminal: bool) -> Result<Source, String> {
+    match args.first().map(String::as_str) {
+        None if stdin_is_terminal => Ok(Source::Repl),
+        None => Ok(Source::Stdin),
+        Some("-f" | "--file") => match &args[1..] {
+            [path] if path == "-" => Ok(Source::Stdin),
+            [path] => Ok(Source::File(PathBuf::from(path))),
+            [] => Err(format!("Missing path after {}\n\n{USAGE}", args[0])),
+            _ => Err(format!("Unexpected arguments after file path\n\n{USAGE}")),
+        },
+        Some("--run-bin") => match &args[1..] {
+            [path] => Ok(Source::Blob(PathBuf::from(path))),
+            [] => Err(format!("Missing path after --run-bin\n\n{USAGE}")),
+            _ => Err(format!("Unexpected arguments after file path\n\n{USAGE}")),
+        },
+        Some(_) => Ok(Source::Inline(args.join(" "))),
     }
-    // Move the value of the working register (rcx) into the return register (rcx)
-    // `mov %rcx, %rax`
-    // Return
-    // `ret`
-    machine_code.extend_from_slice(&[0x48, 0x89, 0xc8, 0xc3]);
-    machine_code
 }
 
-/// Execute a sequence of bytes as x86_64 machine code
-/// Expect code to be of the form of a C function with type `int64_t f()`
-/// Returns the return value of the passed function
-fn run(machine_code: &[u8]) -> i64 {
-    // In all probability, this function should be considered unsafe.
-    // An arbitrary string of bytes is not guaranteed to be valid x86_64 machine code,
-    // Neither is it guaranteed to follow the calling convention used.
+/// Compile and run a program, working on the kind of number `mode` says.
+/// Returns the result as it should be printed.
+fn calculate(program: &str, mode: Mode, dump_hex: bool) -> Result<String, CalcError> {
+    if dump_hex {
+        eprint!("{}", format_hex(&machine_code(program, mode)?));
+    }
+    // Programs are JIT compiled where there is a backend for this CPU, and interpreted
+    // otherwise
+    match mode {
+        Mode::Integer => Ok(evaluate(program)?.to_string()),
+        Mode::Unsigned if Target::host().is_none() => Ok(interpret_unsigned(program)?.to_string()),
+        Mode::Unsigned => {
+            let machine_code = machine_code(program, mode)?;
+            // Safety: there is a backend for this CPU, so the code is for this CPU
+            Ok(unsafe { run_unsigned(&machine_code) }.to_string())
+        }
+        Mode::Float => {
+            let machine_code = machine_code(program, mode)?;
+            // Safety: `parse_args` only allows `--float` on CPUs with a backend, so the
+            // code from `jit_float` is for this CPU
+            Ok(unsafe { run_float(&machine_code) }.to_string())
+        }
+        Mode::Expr => {
+            let machine_code = machine_code(program, mode)?;
+            // Safety: `parse_args` only allows `--expr` on CPUs with a backend, so the
+            // code from `jit_expr` is for this CPU
+            Ok(unsafe { run(&machine_code) }.to_string())
+        }
+        Mode::Rpn => {
+            let machine_code = machine_code(program, mode)?;
+            // Safety: as for `--expr`
+            Ok(unsafe { run(&machine_code) }.to_string())
+        }
+        Mode::DumpAsm => {
+            let listing = disassemble(&machine_code(program, mode)?);
+            Ok(listing.trim_end().to_string())
+        }
+    }
+}
+
+/// Compile the program to the machine code `mode` runs, or for [`Mode::DumpAsm`] prints
+fn machine_code(program: &str, mode: Mode) -> Result<Vec<u8>, JitError> {
+    match mode {
+        Mode::Integer => jit(program),
+        Mode::Unsigned => jit_unsigned(program),
+        Mode::Float => jit_float(program),
+        Mode::Expr => jit_expr(program),
+        Mode::Rpn => jit_rpn(program),
+        Mode::DumpAsm => {
+            let options = Options {
+                target: Target::X86_64,
+                ..Options::default()
+            };
+            jit_with_options(program, options)
+        }
+    }
+}
 
-    // Rust doesn't have a stable ABI. It's safe to assume the calling convention
-    // used with C functions won't change. We'll use that instead.
-    type Executable = unsafe extern "C" fn() -> i64;
+/// Read programs from `input` one line at a time, writing the result of each to `output`.
+/// Invalid programs print their error and the loop carries on.
+/// Stops at the end of the input or when a line says `quit`.
+fn repl(
+    input: impl BufRead,
+    mut output: impl Write,
+    mode: Mode,
+    dump_hex: bool,
+) -> std::io::Result<()> {
+    write!(output, "> ")?;
+    output.flush()?;
+    for line in input.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line == "quit" {
+            break;
+        }
+        if !line.is_empty() {
+            match calculate(line, mode, dump_hex) {
+                Ok(result) => writeln!(output, "{result}")?,
+                Err(e) => writeln!(output, "{e}")?,
+            }
+        }
+        write!(output, "> ")?;
+        output.flush()?;
+    }
+    writeln!(output)
+}
 
-    let code_len = machine_code.len();
+fn main() -> ExitCode {
+    let Args {
+        source,
+        mode,
+        dump_hex,
+        emit,
+        bench,
+    } = match parse_args(std::env::args().skip(1), std::io::stdin().is_terminal()) {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{message}");
+            return ExitCode::from(2);
+        }
+    };
+    if source == Source::Repl {
+        if let Err(e) = repl(std::io::stdin().lock(), std::io::stdout(), mode, dump_hex) {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+        return ExitCode::SUCCESS;
+    }
+    if let Source::Blob(path) = &source {
+        return run_saved(path, dump_hex);
+    }
+    let program = match source.read() {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("Failed to read program: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Some(iterations) = bench {
+        return run_benchmark(&program, iterations);
+    }
+    if let Some(emit) = emit {
+        let (path, contents) = match emit.contents(&program) {
+            Ok(contents) => contents,
+            Err(e) => {
+                print_error(CalcError::Jit(e), &program);
+                return ExitCode::FAILURE;
+            }
+        };
+        if dump_hex {
+            if let Ok(machine_code) = machine_code(&program, mode) {
+                eprint!("{}", format_hex(&machine_code));
+            }
+        }
+        if let Err(e) = std::fs::write(path, contents) {
+            eprintln!("Failed to write {}: {e}", path.display());
+            return ExitCode::FAILURE;
+        }
+        return ExitCode::SUCCESS;
+    }
+    let result = match calculate(&program, mode, dump_hex) {
+        Ok(result) => result,
+        Err(e) => {
+            print_error(e, &program);
+            return ExitCode::FAILURE;
+        }
+    };
+    println!("{result}");
+    ExitCode::SUCCESS
+}
 
-    // Memory allocated by a structure like Vec<u8> is almost certainly not executable.
-    // Thus, we can't simply interpret the machine_code slice as a function and run it.
-    // First: allocate executable memory
-    let memory = region::alloc(code_len, Protection::READ_WRITE_EXECUTE).unwrap();
+/// Print why a program failed, pointing at where in the program it went wrong
+fn print_error(error: CalcError, program: &str) {
+    match error {
+        CalcError::Jit(e) => eprint!("{}", render(&e, program)),
+        e => eprintln!("{e}"),
+    }
+}
 
-    let slice =
-        unsafe { std::slice::from_raw_parts_mut(memory.as_ptr::<u8>() as *mut u8, memory.len()) };
+/// Time running the program through the JIT against the interpreter, printing the report
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+fn run_benchmark(program: &str, iterations: u32) -> ExitCode {
+    match benchmark(program, iterations) {
+        Ok(report) => {
+            println!("{report}");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            print_error(e, program);
+            ExitCode::FAILURE
+        }
+    }
+}
 
-    // Then: copy the data in machine_code into the memory
-    // This is essentially copying a function from non-executable memory to executable memory.
-    slice[..code_len].copy_from_slice(machine_code);
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn run_benchmark(_: &str, _: u32) -> ExitCode {
+    unreachable!("`parse_args` only allows --bench on CPUs with a backend")
+}
 
-    unsafe {
-        let ptr = slice.as_ptr();
-        let f: Executable = std::mem::transmute(ptr);
-        f()
+/// Run machine code saved by `--emit-bin`, printing the result
+fn run_saved(path: &Path, dump_hex: bool) -> ExitCode {
+    let blob = match std::fs::read(path) {
+        Ok(blob) => blob,
+        Err(e) => {
+            eprintln!("Failed to read {}: {e}", path.display());
+            return ExitCode::FAILURE;
+        }
+    };
+    if dump_hex {
+        if let Ok((_, machine_code)) = load_blob(&blob) {
+            eprint!("{}", format_hex(machine_code));
+        }
+    }
+    // Safety: `run_blob` checks the header says the code is for this CPU. Past that, the
+    // file is trusted to have been written by `--emit-bin`.
+    match unsafe { run_blob(&blob) } {
+        Ok(result) => {
+            println!("{result}");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Failed to run {}: {e}", path.display());
+            ExitCode::FAILURE
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::{jit, run};
+    use crate::{parse_args, repl, Args, Emit, Mode, Source};
+    use std::path::PathBuf;
+
+    /// Tester function, for where the program comes from
+    fn t(args: &[&str], stdin_is_terminal: bool) -> Result<Source, String> {
+        parse_args(args.iter().map(|a| a.to_string()), stdin_is_terminal).map(|a| a.source)
+    }
+
+    #[test]
+    fn test_args() {
+        assert_eq!(t(&[], true), Ok(Source::Repl));
+        assert_eq!(t(&["++*"], true), Ok(Source::Inline("++*".to_string())));
+        assert_eq!(
+            t(&["+ +\n*"], true),
+            Ok(Source::Inline("+ +\n*".to_string()))
+        );
+        assert_eq!(
+            t(&["++", "*"], true),
+            Ok(Source::Inline("++ *".to_string()))
+        );
+        assert_eq!(t(&["-"], true), Ok(Source::Inline("-".to_string())));
+    }
+
+    #[test]
+    fn test_file_args() {
+        assert_eq!(
+            t(&["-f", "prog.txt"], true),
+            Ok(Source::File(PathBuf::from("prog.txt")))
+        );
+        assert_eq!(
+            t(&["--file", "prog.txt"], true),
+            Ok(Source::File(PathBuf::from("prog.txt")))
+        );
+        assert_eq!(t(&["-f", "-"], true), Ok(Source::Stdin));
+        assert_eq!(t(&[], false), Ok(Source::Stdin));
+        assert!(t(&["-f"], true).is_err());
+        assert!(t(&["-f", "a", "b"], true).is_err());
+    }
 
     #[test]
-    fn test_execution() {
+    fn test_mode_args() {
         /// Tester function
-        fn t(p: &str) -> i64 {
-            run(&jit(p))
-        }
-
-        assert_eq!(t("+"), 1);
-        assert_eq!(t("++"), 2);
-        assert_eq!(t("++/"), 1);
-        assert_eq!(t("-"), -1);
-        assert_eq!(t("--*"), -4);
-        assert_eq!(t("*"), 0);
-        assert_eq!(t("/"), 0);
-        assert_eq!(t("++*******"), 256);
-        assert_eq!(t("--**++"), -6);
+        fn t(args: &[&str]) -> Result<Args, String> {
+            parse_args(args.iter().map(|a| a.to_string()), true)
+        }
+
+        assert_eq!(
+            t(&["--float", "+/"]),
+            Ok(Args {
+                source: Source::Inline("+/".to_string()),
+                mode: Mode::Float,
+                dump_hex: false,
+                emit: None,
+                bench: None
+            })
+        );
+        assert_eq!(
+            t(&["--float", "-f", "prog.txt"]),
+            Ok(Args {
+                source: Source::File(PathBuf::from("prog.txt")),
+                mode: Mode::Float,
+                dump_hex: false,
+                emit: None,
+                bench: None
+            })
+        );
+        assert_eq!(
+            t(&["+/"]),
+            Ok(Args {
+                source: Source::Inline("+/".to_string()),
+                mode: Mode::Integer,
+                dump_hex: false,
+                emit: None,
+                bench: None
+            })
+        );
+        assert_eq!(
+            t(&["--float"]),
+            Ok(Args {
+                source: Source::Repl,
+                mode: Mode::Float,
+                dump_hex: false,
+                emit: None,
+                bench: None
+            })
+        );
+        assert_eq!(
+            t(&["--unsigned", "-/"]),
+            Ok(Args {
+                source: Source::Inline("-/".to_string()),
+                mode: Mode::Unsigned,
+                dump_hex: false,
+                emit: None,
+                bench: None
+            })
+        );
+        assert_eq!(
+            t(&["--expr", "(1+2)*3"]),
+            Ok(Args {
+                source: Source::Inline("(1+2)*3".to_string()),
+                mode: Mode::Expr,
+                dump_hex: false,
+                emit: None,
+                bench: None
+            })
+        );
+        assert!(t(&["--unsigned", "--float", "+"]).is_err());
+        assert_eq!(
+            t(&["--rpn", "3 4 + 2 *"]),
+            Ok(Args {
+                source: Source::Inline("3 4 + 2 *".to_string()),
+                mode: Mode::Rpn,
+                dump_hex: false,
+                emit: None,
+                bench: None
+            })
+        );
+        assert!(t(&["--expr", "--unsigned", "1"]).is_err());
+        assert!(t(&["--rpn", "--expr", "1"]).is_err());
+        assert_eq!(t(&["--dump-asm", "+"]).map(|a| a.mode), Ok(Mode::DumpAsm));
+        assert!(t(&["--float", "--float", "+"]).is_err());
+        assert_eq!(
+            t(&["--dump-hex", "+"]),
+            Ok(Args {
+                source: Source::Inline("+".to_string()),
+                mode: Mode::Integer,
+                dump_hex: true,
+                emit: None,
+                bench: None
+            })
+        );
+        assert_eq!(
+            t(&["--unsigned", "--dump-hex", "-f", "prog.txt"]),
+            Ok(Args {
+                source: Source::File(PathBuf::from("prog.txt")),
+                mode: Mode::Unsigned,
+                dump_hex: true,
+                emit: None,
+                bench: None
+            })
+        );
+        // Flags can also come after the program
+        assert_eq!(
+            t(&["-f", "prog.txt", "--unsigned", "--dump-hex"]),
+            t(&["--unsigned", "--dump-hex", "-f", "prog.txt"])
+        );
+        assert_eq!(
+            t(&["+", "--dump-hex", "+"]),
+            Ok(Args {
+                source: Source::Inline("+ +".to_string()),
+                mode: Mode::Integer,
+                dump_hex: true,
+                emit: None,
+                bench: None
+            })
+        );
+        assert_eq!(
+            t(&["-f", "--dump-hex"]).map(|a| (a.source, a.dump_hex)),
+            Ok((Source::File(PathBuf::from("--dump-hex")), false))
+        );
+        assert_eq!(
+            t(&["--dump-hex", "--dump-asm", "+"]).map(|a| (a.mode, a.dump_hex)),
+            Ok((Mode::DumpAsm, true))
+        );
+        assert!(t(&["--float", "--dump-hex", "--expr", "1"]).is_err());
+        assert_eq!(
+            t(&["--emit-obj", "calc.o", "++*"]),
+            Ok(Args {
+                source: Source::Inline("++*".to_string()),
+                mode: Mode::Integer,
+                dump_hex: false,
+                emit: Some(Emit::Object(PathBuf::from("calc.o"))),
+                bench: None
+            })
+        );
+        assert_eq!(
+            t(&["--emit-obj", "calc.o", "-f", "prog.txt"]).map(|a| a.source),
+            Ok(Source::File(PathBuf::from("prog.txt")))
+        );
+        assert!(t(&["--emit-obj"]).is_err());
+        assert!(t(&["--emit-obj", "calc.o"]).is_err());
+        assert!(t(&["--unsigned", "--emit-obj", "calc.o", "-/"]).is_err());
+        assert_eq!(
+            t(&["--emit-bin", "calc.bin", "++*"]).map(|a| a.emit),
+            Ok(Some(Emit::Binary(PathBuf::from("calc.bin"))))
+        );
+        assert_eq!(
+            t(&["--run-bin", "calc.bin"]).map(|a| a.source),
+            Ok(Source::Blob(PathBuf::from("calc.bin")))
+        );
+        assert_eq!(
+            t(&["--dump-hex", "--run-bin", "calc.bin"]).map(|a| a.dump_hex),
+            Ok(true)
+        );
+        assert!(t(&["--run-bin"]).is_err());
+        assert!(t(&["--run-bin", "calc.bin", "+"]).is_err());
+        assert!(t(&["--float", "--run-bin", "calc.bin"]).is_err());
+        assert!(t(&["--emit-bin", "out.bin", "--run-bin", "calc.bin"]).is_err());
+        #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+        {
+            assert_eq!(
+                t(&["--bench", "1000", "++*"]),
+                Ok(Args {
+                    source: Source::Inline("++*".to_string()),
+                    mode: Mode::Integer,
+                    dump_hex: false,
+                    emit: None,
+                    bench: Some(1000)
+                })
+            );
+            assert_eq!(
+                t(&["--bench", "5", "-f", "prog.txt"]).map(|a| a.bench),
+                Ok(Some(5))
+            );
+        }
+        for args in [
+            &["--bench", "0", "+"][..],
+            &["--bench", "many", "+"],
+            &["--bench", "-5", "+"],
+            &["--bench"],
+            &["--bench", "10"],
+            &["--float", "--bench", "10", "+"],
+            &["--bench", "10", "--emit-obj", "calc.o", "+"],
+        ] {
+            assert!(t(args).is_err(), "{args:?}");
+        }
+    }
+
+    #[test]
+    fn test_repl() {
+        /// Tester function
+        fn t(input: &str) -> String {
+            let mut output = Vec::new();
+            repl(input.as_bytes(), &mut output, Mode::Integer, false).unwrap();
+            String::from_utf8(output).unwrap()
+        }
+
+        assert_eq!(t(""), "> \n");
+        assert_eq!(t("++*\n"), "> 4\n> \n");
+        assert_eq!(t("+\n\n--\n"), "> 1\n> > -2\n> \n");
+        assert_eq!(
+            t("+x\n++\n"),
+            "> Unknown character in program string: x (at line 1, column 2)\n> 2\n> \n"
+        );
+        assert_eq!(t("+\nquit\n++\n"), "> 1\n> \n");
+
+        let mut output = Vec::new();
+        repl("+++/\n+\n".as_bytes(), &mut output, Mode::Float, false).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "> 1.5\n> 1\n> \n");
+        let mut output = Vec::new();
+        repl("-/\n-\n".as_bytes(), &mut output, Mode::Unsigned, false).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!("> {}\n> {}\n> \n", u64::MAX / 2, u64::MAX)
+        );
+        let mut output = Vec::new();
+        repl(
+            "(1+2)*3-4/2\n1/0\n".as_bytes(),
+            &mut output,
+            Mode::Expr,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "> 7\n> Division by zero (at byte 1)\n> \n"
+        );
+        let mut output = Vec::new();
+        repl("3 4 + 2 *\n1 +\n".as_bytes(), &mut output, Mode::Rpn, false).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "> 14\n> Operator needs two values to work on (at token 1)\n> \n"
+        );
     }
 }
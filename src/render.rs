@@ -0,0 +1,295 @@
+// Rendering errors the way rustc does: the message, followed by the offending line
+// of the program with carets under the characters at fault.
+
+use crate::{
+    line_column, JitError, MAX_LOOP_DEPTH, MAX_OPERATIONS, MAX_PAREN_DEPTH, MAX_STACK_DEPTH,
+};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+/// How many columns of a line are shown before it gets cut short
+const MAX_WIDTH: usize = 60;
+/// Tabs are expanded to this many spaces, so the caret lines up however the terminal shows tabs
+const TAB_WIDTH: usize = 4;
+
+/// Describe an error found in `source`, underlining its [`span`](JitError::span).
+/// For example:
+///
+/// ```text
+/// error: unknown character `a`
+///  --> 1:3
+///   |
+/// 1 | ++abc*
+///   |   ^^^
+/// ```
+pub fn render(error: &JitError, source: &str) -> String {
+    let message = match error {
+        JitError::UnknownCharacter { character, .. } => {
+            format!("unknown character `{}`", character.escape_default())
+        }
+        JitError::EmptyProgram => "program contains no operations".to_string(),
+        JitError::OperandOutOfRange { .. } => format!("operand is larger than {}", i32::MAX),
+        JitError::DivisionByZero { .. } => "division by zero".to_string(),
+        JitError::MissingOperand { .. } => "expected a number after `=`".to_string(),
+        JitError::UnbalancedBracket { .. } => "unbalanced brackets".to_string(),
+        JitError::MissingGroup { .. } => "expected `(` or `[` after the repeat count".to_string(),
+        JitError::NothingToSkip { .. } => "nothing follows `?` for it to skip".to_string(),
+        JitError::LoopTooDeep { .. } => {
+            format!("loops can only be nested {MAX_LOOP_DEPTH} deep")
+        }
+        JitError::ZeroRepeat { .. } => "group repeated zero times".to_string(),
+        JitError::TooManyOperations { .. } => {
+            format!("program is longer than {MAX_OPERATIONS} operations once repeated")
+        }
+        JitError::EmptyStack { at_op } => {
+            format!("operation {at_op} swaps with the stack when there may be nothing on it")
+        }
+        JitError::StackTooDeep { at_op } => {
+            format!("operation {at_op} may push more than {MAX_STACK_DEPTH} values onto the stack")
+        }
+        JitError::ExpectedOperand { .. } => "expected a number, `-` or `(`".to_string(),
+        JitError::ExpectedOperator { .. } => "expected `+`, `-`, `*` or `/`".to_string(),
+        JitError::ParenTooDeep { .. } => {
+            format!("parentheses can only be nested {MAX_PAREN_DEPTH} deep")
+        }
+        JitError::MissingOperands { token } => {
+            format!("token {token} is an operator with fewer than two values to work on")
+        }
+        JitError::LeftoverOperands { token } => {
+            format!("the value from token {token} is left on the stack unused")
+        }
+        JitError::CodeTooLarge { size, limit } => {
+            format!("machine code is {size} bytes, more than the limit of {limit}")
+        }
+        JitError::InvalidOperation { at_op } => {
+            format!("operation {at_op} is out of place, or its operand is out of range")
+        }
+    };
+    let mut out = format!("error: {message}\n");
+    let Some(span) = error.span(source) else {
+        return out;
+    };
+
+    let (line, column) = line_column(source, span.start);
+    let text = source.lines().nth(line - 1).unwrap_or("");
+    let (text, caret, end) = clip(&expand_tabs(text, column));
+    // Spans never run onto another line, but can run past the part of a long line shown
+    let width = source[span]
+        .chars()
+        .count()
+        .min(end.saturating_sub(caret))
+        .max(1);
+    let gutter = " ".repeat(line.to_string().len());
+    writeln!(out, "{gutter}--> {line}:{column}").unwrap();
+    writeln!(out, "{gutter} |").unwrap();
+    writeln!(out, "{line} | {text}").unwrap();
+    writeln!(out, "{gutter} | {}{}", " ".repeat(caret), "^".repeat(width)).unwrap();
+    out
+}
+
+/// Replace tabs in `text` with spaces. Returns the new text, and how many characters
+/// come before the one at `column` (counting from 1) once tabs are expanded.
+fn expand_tabs(text: &str, column: usize) -> (String, usize) {
+    let mut expanded = String::new();
+    let mut caret = 0;
+    for (i, c) in text.chars().enumerate() {
+        if i + 1 == column {
+            caret = expanded.chars().count();
+        }
+        if c == '\t' {
+            expanded.push_str(&" ".repeat(TAB_WIDTH - expanded.chars().count() % TAB_WIDTH));
+        } else {
+            expanded.push(c);
+        }
+    }
+    if column > text.chars().count() {
+        caret = expanded.chars().count();
+    }
+    (expanded, caret)
+}
+
+/// Cut a long line down to a window around the caret, marking cut off ends with `...`.
+/// Returns the text to show, the new position of the caret in it, and where the part of
+/// the line shown ends, before any `...` after it.
+fn clip((text, caret): &(String, usize)) -> (String, usize, usize) {
+    let chars: Vec<char> = text.chars().collect();
+    if chars.len() <= MAX_WIDTH {
+        return (text.clone(), *caret, chars.len());
+    }
+    let start = caret
+        .saturating_sub(MAX_WIDTH / 2)
+        .min(chars.len() - MAX_WIDTH);
+    let end = start + MAX_WIDTH;
+    let mut clipped = String::new();
+    let mut caret = caret - start;
+    if start > 0 {
+        clipped.push_str("...");
+        caret += 3;
+    }
+    clipped.extend(&chars[start..end]);
+    let shown = clipped.chars().count();
+    if end < chars.len() {
+        clipped.push_str("...");
+    }
+    (clipped, caret, shown)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::render::expand_tabs;
+    use crate::{jit, render};
+
+    /// Tester function
+    fn t(source: &str) -> String {
+        render(&jit(source).unwrap_err(), source)
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            t("++x*"),
+            "error: unknown character `x`
+ --> 1:3
+  |
+1 | ++x*
+  |   ^
+"
+        );
+        assert_eq!(
+            t("++\n**\n\n+/0"),
+            "error: division by zero
+ --> 4:2
+  |
+4 | +/0
+  |  ^
+"
+        );
+        // The last line has no newline after it
+        assert_eq!(
+            t("+\n+\n+\n+\n+\n+\n+\n+\n+\n==="),
+            "error: expected a number after `=`
+  --> 10:1
+   |
+10 | ===
+   | ^
+"
+        );
+        assert_eq!(t(""), "error: program contains no operations\n");
+        assert_eq!(
+            t("+?ds"),
+            "error: operation 3 swaps with the stack when there may be nothing on it\n"
+        );
+    }
+
+    #[test]
+    fn test_render_spans() {
+        assert_eq!(
+            t("++*abc-"),
+            "error: unknown character `a`
+ --> 1:4
+  |
+1 | ++*abc-
+  |    ^^^
+"
+        );
+        assert_eq!(
+            t("+\n=-99999999999*2"),
+            "error: operand is larger than 2147483647
+ --> 2:1
+  |
+2 | =-99999999999*2
+  | ^^^^^^^^^^^^^
+"
+        );
+        // The `-` after the operand isn't part of it
+        assert_eq!(
+            t("*3000000000-1"),
+            "error: operand is larger than 2147483647
+ --> 1:1
+  |
+1 | *3000000000-1
+  | ^^^^^^^^^^^
+"
+        );
+        // Only the part of the span on the part of the line shown is underlined
+        let long = format!("{}*{}", "+".repeat(100), "x".repeat(100));
+        let rendered = t(&long);
+        let carets = rendered.lines().last().unwrap();
+        assert_eq!(carets.trim_start_matches([' ', '|']), "^".repeat(30));
+        assert_eq!(jit("++*abc").unwrap_err().render("++*abc"), t("++*abc"));
+    }
+
+    #[test]
+    fn test_render_tabs() {
+        assert_eq!(expand_tabs("+\t+\t?", 5), ("+   +   ?".to_string(), 8));
+        assert_eq!(expand_tabs("\t\tx", 3), ("        x".to_string(), 8));
+        assert_eq!(expand_tabs("++\t", 4), ("++  ".to_string(), 4));
+        assert_eq!(
+            t("++*\tx"),
+            "error: unknown character `x`
+ --> 1:5
+  |
+1 | ++* x
+  |     ^
+"
+        );
+        // Carriage returns aren't part of the line shown
+        assert_eq!(
+            t("++\r\n\tx\r\n"),
+            "error: unknown character `x`
+ --> 2:2
+  |
+2 |     x
+  |     ^
+"
+        );
+    }
+
+    #[test]
+    fn test_render_long_lines() {
+        let start = format!("x{}", "+".repeat(100));
+        assert_eq!(
+            t(&start),
+            format!(
+                "error: unknown character `x`
+ --> 1:1
+  |
+1 | x{}...
+  | ^
+",
+                "+".repeat(59)
+            )
+        );
+        let middle = format!("{}x{}", "+".repeat(100), "+".repeat(100));
+        assert_eq!(
+            t(&middle),
+            format!(
+                "error: unknown character `x`
+ --> 1:101
+  |
+1 | ...{}x{}...
+  | {}^
+",
+                "+".repeat(30),
+                "+".repeat(29),
+                " ".repeat(33)
+            )
+        );
+        let end = format!("{}x", "+".repeat(100));
+        assert_eq!(
+            t(&end),
+            format!(
+                "error: unknown character `x`
+ --> 1:101
+  |
+1 | ...{}x
+  | {}^
+",
+                "+".repeat(59),
+                " ".repeat(62)
+            )
+        );
+    }
+}
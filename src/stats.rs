@@ -0,0 +1,101 @@
+// Summarizing a program before compiling it: what it's made of, and how much machine
+// code it turns into.
+
+use crate::{codegen, tokenize, JitError, Op, Options, Start};
+use alloc::collections::BTreeMap;
+
+/// What a program is made of, from [`stats`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgramStats {
+    /// How many operations the program holds once its repeat groups are unrolled.
+    /// A loop counts as one operation, plus however many its body holds.
+    pub operations: usize,
+    /// How many times each operation appears, keyed by the character it's written with.
+    /// Operations with an operand count towards their operator, so `+5` is a `+`,
+    /// `=5` is an `=` and `5[` is a `[`.
+    pub counts: BTreeMap<char, usize>,
+    /// The size in bytes of the machine code [`jit`](crate::jit) generates for the program
+    pub code_size: usize,
+}
+
+/// Count the operations in a program and work out the size of its machine code, to see
+/// what compiling it costs. The code is generated to measure it, but never run.
+pub fn stats(program: &str) -> Result<ProgramStats, JitError> {
+    let tokens = tokenize(program)?;
+    let mut counts = BTreeMap::new();
+    for token in &tokens {
+        if let Some(symbol) = symbol(token) {
+            *counts.entry(symbol).or_insert(0) += 1;
+        }
+    }
+    Ok(ProgramStats {
+        operations: counts.values().sum(),
+        counts,
+        code_size: codegen(&tokens, Start::Constant(0), &Options::default())?.len(),
+    })
+}
+
+/// The character an operation is written with. `None` for the end of a loop, which is
+/// part of the loop rather than an operation of its own.
+fn symbol(token: &Op) -> Option<char> {
+    Some(match token {
+        Op::Plus | Op::Add(_) => '+',
+        Op::Minus | Op::Sub(_) => '-',
+        Op::Star | Op::Mul(_) => '*',
+        Op::Slash | Op::Div(_) => '/',
+        Op::Percent | Op::Rem(_) => '%',
+        Op::Caret => '^',
+        Op::Tilde => '~',
+        Op::Zero => '0',
+        Op::Less => '<',
+        Op::Greater => '>',
+        Op::Bar => '|',
+        Op::Clamp => '_',
+        Op::Set(_) => '=',
+        Op::Loop(_) => '[',
+        Op::Skip => '?',
+        Op::Dup => 'd',
+        Op::Swap => 's',
+        Op::EndLoop => return None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{jit, stats, JitError};
+    use alloc::collections::BTreeMap;
+
+    #[test]
+    fn test_stats() {
+        let program = stats("++*-/").unwrap();
+        assert_eq!(program.operations, 5);
+        assert_eq!(
+            program.counts,
+            BTreeMap::from([('+', 2), ('*', 1), ('-', 1), ('/', 1)])
+        );
+        assert_eq!(program.code_size, jit("++*-/").unwrap().len());
+
+        let program = stats("+5 =3 4(*) 2[?-] # comment").unwrap();
+        assert_eq!(program.operations, 9);
+        assert_eq!(
+            program.counts,
+            BTreeMap::from([('+', 1), ('=', 1), ('*', 4), ('[', 1), ('?', 1), ('-', 1)])
+        );
+        let program = stats("++d*s-").unwrap();
+        assert_eq!(program.counts[&'d'], 1);
+        assert_eq!(program.counts[&'s'], 1);
+        for p in [
+            "+",
+            "++*-/",
+            "+8(*)",
+            "3[2[+]*]",
+            "?+",
+            "=-7*3+1000/7%5",
+            "1000[+]",
+            "++d*s-",
+        ] {
+            assert_eq!(stats(p).unwrap().code_size, jit(p).unwrap().len(), "{p}");
+        }
+        assert_eq!(stats(""), Err(JitError::EmptyProgram));
+    }
+}
@@ -0,0 +1,333 @@
+// A WebAssembly backend, so programs can run in a browser (or anywhere else with a
+// WebAssembly runtime) instead of only on x86_64 and AArch64.
+//
+// The module holds a single function, `calc`, of type `() -> i64`. The working value
+// lives in local 0, and the counters of nested loops in locals 1 to MAX_LOOP_DEPTH.
+// Programs which use the stack also get a memory to keep it in, with the address of the
+// value on top of it in the local after the loop counters.
+
+use crate::{tokenize, uses_stack, JitError, Op, MAX_LOOP_DEPTH};
+use alloc::vec;
+use alloc::vec::Vec;
+
+// Value types
+const I32: u8 = 0x7f;
+const I64: u8 = 0x7e;
+/// The block type of blocks which take and leave nothing on the stack
+const EMPTY: u8 = 0x40;
+
+// Instructions
+const IF: u8 = 0x04;
+const LOOP: u8 = 0x03;
+const END: u8 = 0x0b;
+const BR_IF: u8 = 0x0d;
+const SELECT: u8 = 0x1b;
+const LOCAL_GET: u8 = 0x20;
+const LOCAL_SET: u8 = 0x21;
+const LOCAL_TEE: u8 = 0x22;
+const I64_LOAD: u8 = 0x29;
+const I64_STORE: u8 = 0x37;
+const I32_CONST: u8 = 0x41;
+const I64_CONST: u8 = 0x42;
+const I32_EQZ: u8 = 0x45;
+const I64_EQZ: u8 = 0x50;
+const I64_LT_S: u8 = 0x53;
+const I32_ADD: u8 = 0x6a;
+const I32_SUB: u8 = 0x6b;
+const I64_ADD: u8 = 0x7c;
+const I64_SUB: u8 = 0x7d;
+const I64_MUL: u8 = 0x7e;
+const I64_DIV_S: u8 = 0x7f;
+const I64_REM_S: u8 = 0x81;
+const I64_SHL: u8 = 0x86;
+const I64_SHR_S: u8 = 0x87;
+
+/// The local holding the address of the value on top of the stack. The stack starts
+/// at address 0, so the first value pushed goes at address 8.
+const STACK_POINTER: u8 = MAX_LOOP_DEPTH as u8 + 1;
+/// The alignment (as a power of two) and offset of 64-bit loads and stores
+const MEMARG: [u8; 2] = [3, 0];
+
+/// Compile a program to a WebAssembly module (a `.wasm` file), which exports one
+/// function named `calc` taking nothing and returning an `i64`.
+/// The arithmetic wraps around on overflow, the same as the code from [`jit`](crate::jit).
+pub fn emit_wasm(program: &str) -> Result<Vec<u8>, JitError> {
+    let tokens = tokenize(program)?;
+    Ok(module(&body(&tokens), uses_stack(&tokens)))
+}
+
+/// The instructions of the `calc` function
+fn body(tokens: &[Op]) -> Vec<u8> {
+    let mut code = Vec::new();
+    // How many loops the current operation is inside
+    let mut depth = 0;
+    // The `if` blocks wrapping a skipped operation which are still open, and the depth
+    // the operation they wrap is at
+    let mut skips: Vec<usize> = Vec::new();
+    let mut i = 0;
+    while let Some(token) = tokens.get(i) {
+        i += 1;
+        match token {
+            Op::Loop(n) => {
+                code.push(I32_CONST);
+                signed(&mut code, *n);
+                code.extend_from_slice(&[LOCAL_SET, counter(depth), LOOP, EMPTY]);
+                depth += 1;
+                continue;
+            }
+            Op::EndLoop => {
+                depth -= 1;
+                // Count down, and go back to the start of the loop until reaching zero
+                let c = counter(depth);
+                code.extend_from_slice(&[LOCAL_GET, c, I32_CONST, 1, I32_SUB, LOCAL_TEE, c]);
+                code.extend_from_slice(&[BR_IF, 0, END]);
+            }
+            Op::Skip => {
+                // A `?` which is itself skipped doesn't get to skip anything, and one which
+                // isn't skipped never skips, as the value can't be zero. So in a run of
+                // them, only whether there's an odd number matters.
+                let mut run = 1;
+                while tokens.get(i) == Some(&Op::Skip) {
+                    run += 1;
+                    i += 1;
+                }
+                if run % 2 == 1 {
+                    // Only run the next operation if the value isn't zero
+                    code.extend_from_slice(&[LOCAL_GET, 0, I64_EQZ, I32_EQZ, IF, EMPTY]);
+                    skips.push(depth);
+                }
+                continue;
+            }
+            token => operation(&mut code, token),
+        }
+        if skips.last() == Some(&depth) {
+            code.push(END);
+            skips.pop();
+        }
+    }
+    code.extend_from_slice(&[LOCAL_GET, 0, END]);
+    code
+}
+
+/// Emit the instructions for an operation, which update the working value in local 0
+fn operation(code: &mut Vec<u8>, token: &Op) {
+    let (op, n) = match token {
+        Op::Plus => (I64_ADD, 1),
+        Op::Minus => (I64_SUB, 1),
+        Op::Star => (I64_MUL, 2),
+        // `div_s` and `rem_s` round towards zero, just like the other backends
+        Op::Slash => (I64_DIV_S, 2),
+        Op::Percent => (I64_REM_S, 2),
+        Op::Add(n) => (I64_ADD, *n),
+        Op::Sub(n) => (I64_SUB, *n),
+        Op::Mul(n) => (I64_MUL, *n),
+        Op::Div(n) => (I64_DIV_S, *n),
+        Op::Rem(n) => (I64_REM_S, *n),
+        Op::Less => (I64_SHL, 1),
+        Op::Greater => (I64_SHR_S, 1),
+        Op::Caret => {
+            code.extend_from_slice(&[LOCAL_GET, 0, LOCAL_GET, 0, I64_MUL, LOCAL_SET, 0]);
+            return;
+        }
+        // There's no `neg` for integers, so subtract from zero instead
+        Op::Tilde => {
+            code.extend_from_slice(&[I64_CONST, 0, LOCAL_GET, 0, I64_SUB, LOCAL_SET, 0]);
+            return;
+        }
+        // Pick between the value and its negation, depending on whether it's negative
+        Op::Bar => {
+            code.extend_from_slice(&[I64_CONST, 0, LOCAL_GET, 0, I64_SUB, LOCAL_GET, 0]);
+            code.extend_from_slice(&[LOCAL_GET, 0, I64_CONST, 0, I64_LT_S, SELECT, LOCAL_SET, 0]);
+            return;
+        }
+        // Pick between zero and the value, depending on whether it's negative
+        Op::Clamp => {
+            code.extend_from_slice(&[I64_CONST, 0, LOCAL_GET, 0, LOCAL_GET, 0, I64_CONST, 0]);
+            code.extend_from_slice(&[I64_LT_S, SELECT, LOCAL_SET, 0]);
+            return;
+        }
+        Op::Zero => {
+            code.extend_from_slice(&[I64_CONST, 0, LOCAL_SET, 0]);
+            return;
+        }
+        Op::Set(n) => {
+            code.push(I64_CONST);
+            signed(code, *n);
+            code.extend_from_slice(&[LOCAL_SET, 0]);
+            return;
+        }
+        // Move the stack pointer up, and store the value there
+        Op::Dup => {
+            code.extend_from_slice(&[LOCAL_GET, STACK_POINTER, I32_CONST, 8, I32_ADD]);
+            code.extend_from_slice(&[LOCAL_TEE, STACK_POINTER, LOCAL_GET, 0, I64_STORE]);
+            code.extend_from_slice(&MEMARG);
+            return;
+        }
+        // Load the value on top of the stack, store the working value in its place, and
+        // then make the loaded value the working value
+        Op::Swap => {
+            code.extend_from_slice(&[LOCAL_GET, STACK_POINTER, I64_LOAD]);
+            code.extend_from_slice(&MEMARG);
+            code.extend_from_slice(&[LOCAL_GET, STACK_POINTER, LOCAL_GET, 0, I64_STORE]);
+            code.extend_from_slice(&MEMARG);
+            code.extend_from_slice(&[LOCAL_SET, 0]);
+            return;
+        }
+        Op::Loop(_) | Op::EndLoop | Op::Skip => unreachable!("handled by body"),
+    };
+    code.extend_from_slice(&[LOCAL_GET, 0, I64_CONST]);
+    signed(code, n);
+    code.extend_from_slice(&[op, LOCAL_SET, 0]);
+}
+
+/// The local holding the counter of a loop nested inside `depth` others
+fn counter(depth: usize) -> u8 {
+    depth as u8 + 1
+}
+
+/// Wrap the body of the `calc` function in a module exporting it, along with a memory
+/// for the stack if the function uses it (`stack`)
+fn module(body: &[u8], stack: bool) -> Vec<u8> {
+    // The only type is `() -> i64`
+    let types = [1, 0x60, 0, 1, I64];
+    // One function, of type 0
+    let functions = [1, 0];
+    // One memory, of at least one 64KiB page, which is plenty for the stack
+    let memories = [1, 0x00, 1];
+    // Export function 0 as `calc`
+    let exports = [1, 4, b'c', b'a', b'l', b'c', 0x00, 0];
+
+    // The locals: the working value, a counter for each level of loop nesting, and
+    // the stack pointer
+    let i32_locals = MAX_LOOP_DEPTH as u8 + u8::from(stack);
+    let mut function = vec![2, 1, I64, i32_locals, I32];
+    function.extend_from_slice(body);
+    let mut code = vec![1];
+    unsigned(&mut code, function.len() as u64);
+    code.extend_from_slice(&function);
+
+    // The magic number and version
+    let mut wasm = b"\0asm".to_vec();
+    wasm.extend_from_slice(&1u32.to_le_bytes());
+    section(&mut wasm, 1, &types);
+    section(&mut wasm, 3, &functions);
+    if stack {
+        section(&mut wasm, 5, &memories);
+    }
+    section(&mut wasm, 7, &exports);
+    section(&mut wasm, 10, &code);
+    wasm
+}
+
+/// Append a section with the given id
+fn section(wasm: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    wasm.push(id);
+    unsigned(wasm, contents.len() as u64);
+    wasm.extend_from_slice(contents);
+}
+
+/// Append a number in the unsigned LEB128 encoding, 7 bits at a time
+fn unsigned(out: &mut Vec<u8>, mut n: u64) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Append a number in the signed LEB128 encoding, which stops once the rest of the
+/// number is just copies of the sign bit
+fn signed(out: &mut Vec<u8>, mut n: i64) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        let sign = byte & 0x40 != 0;
+        if (n == 0 && !sign) || (n == -1 && sign) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::wasm::signed;
+    use crate::{emit_wasm, interpret};
+
+    /// Run the `calc` function of a module
+    fn run(wasm: &[u8]) -> i64 {
+        let engine = wasmi::Engine::default();
+        let module = wasmi::Module::new(&engine, wasm).unwrap();
+        let mut store = wasmi::Store::new(&engine, ());
+        let linker = wasmi::Linker::<()>::new(&engine);
+        let instance = linker.instantiate_and_start(&mut store, &module).unwrap();
+        let calc = instance.get_typed_func::<(), i64>(&store, "calc").unwrap();
+        calc.call(&mut store, ()).unwrap()
+    }
+
+    #[test]
+    fn test_emit_wasm() {
+        for program in [
+            "+",
+            "++*-/",
+            "---/",
+            "+++%",
+            "---%",
+            "+++^",
+            "++~",
+            "+++0+",
+            "-100%7",
+            "---><<",
+            "---|",
+            "++|",
+            "+2[+0+]",
+            "=-2147483647-1*65536*65536~",
+            "=-7*3+1000/7",
+            "=2147483647*2147483647*2147483647",
+            "+8(*)",
+            "3[2[+]*]",
+            "2[3[4[+]]]",
+            "1000[1000[+]]",
+            "?+",
+            "+?*",
+            "??+",
+            "???+",
+            "?2[+]+",
+            "-3[?+]",
+            "-2[+?2[+]]",
+            "++d*s-",
+            "+d+d+sss",
+            "+++d3[*s]",
+            "+?d+d|s",
+            "2[+d]s*s",
+        ] {
+            let wasm = emit_wasm(program).unwrap();
+            wasmparser::validate(&wasm).unwrap();
+            assert_eq!(Ok(run(&wasm)), interpret(program), "{program}");
+        }
+        assert!(emit_wasm("+x").is_err());
+    }
+
+    #[test]
+    fn test_leb128() {
+        /// Tester function
+        fn t(n: i64) -> Vec<u8> {
+            let mut out = Vec::new();
+            signed(&mut out, n);
+            out
+        }
+
+        assert_eq!(t(0), [0x00]);
+        assert_eq!(t(63), [0x3f]);
+        assert_eq!(t(64), [0xc0, 0x00]);
+        assert_eq!(t(-1), [0x7f]);
+        assert_eq!(t(-64), [0x40]);
+        assert_eq!(t(-65), [0xbf, 0x7f]);
+        assert_eq!(t(i64::MIN).len(), 10);
+    }
+}
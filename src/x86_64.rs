@@ -0,0 +1,1821 @@
+// The x86_64 backend, following the System V calling convention, or the Microsoft x64
+// one when built for Windows. The working register is rcx, and the result is returned
+// in rax, which both conventions return integers in.
+//
+// The conventions differ in where arguments are passed: System V passes the first
+// integer argument in rdi, whereas Microsoft x64 passes it in rcx. Microsoft x64 also
+// makes rdi a callee-saved register, so the only time rdi is touched is to read the
+// argument on System V. Every other register the backend uses (rcx, rdx, r8 and the
+// loop counters r9 to r11) is caller-saved in both, so nothing has to be saved or restored.
+// The exception is programs which push values onto the stack, which use rbp as a frame
+// pointer to restore the stack pointer from, so they save it like a C function would.
+//
+// Operations are first lowered to a list of instructions, which can then either be
+// encoded as machine code or written out as AT&T assembly, so the two can't disagree.
+
+use crate::expr::{BinOp, Step};
+use crate::ir::{self, Instruction};
+use crate::{
+    exact_log2, literal_pool, uses_stack, Constants, DivRounding, JitError, Op, Options,
+    ReturnType, Signedness, Start, MAX_LOOP_DEPTH,
+};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// The registers the backend uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Reg {
+    Rax,
+    Rcx,
+    Rdx,
+    // Only the System V convention passes anything in rdi
+    #[cfg_attr(windows, allow(dead_code))]
+    Rdi,
+    R8,
+    R9,
+    R10,
+    R11,
+}
+
+impl Reg {
+    /// The register's number. The bottom 3 bits go in the ModRM byte (or the opcode),
+    /// and the 4th bit in the REX prefix.
+    fn number(self) -> u8 {
+        match self {
+            Reg::Rax => 0,
+            Reg::Rcx => 1,
+            Reg::Rdx => 2,
+            Reg::Rdi => 7,
+            Reg::R8 => 8,
+            Reg::R9 => 9,
+            Reg::R10 => 10,
+            Reg::R11 => 11,
+        }
+    }
+
+    /// The REX.W prefix, with REX.B set if this register is encoded in the ModRM r/m field
+    fn rex_b(self) -> u8 {
+        0x48 | (self.number() >> 3)
+    }
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Reg::Rax => "rax",
+            Reg::Rcx => "rcx",
+            Reg::Rdx => "rdx",
+            Reg::Rdi => "rdi",
+            Reg::R8 => "r8",
+            Reg::R9 => "r9",
+            Reg::R10 => "r10",
+            Reg::R11 => "r11",
+        };
+        write!(f, "%{name}")
+    }
+}
+
+/// The SSE registers the floating point code uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Xmm {
+    Xmm0,
+    Xmm1,
+    Xmm2,
+}
+
+impl fmt::Display for Xmm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "%xmm{}", *self as u8)
+    }
+}
+
+/// The instructions the backend uses. All of them operate on 64-bit registers unless noted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Inst {
+    /// `xor %rcx, %rcx`
+    ZeroRcx,
+    /// `xor %edx, %edx`, which also clears the top half of rdx
+    ZeroEdx,
+    /// `xor %rax, %rax`
+    ZeroRax,
+    /// `mov %src, %dst`
+    Mov(Reg, Reg),
+    /// `mov $imm, %dst`, with the immediate sign extended to 64 bits
+    MovImm(i32, Reg),
+    /// `movabs $imm, %dst`
+    Movabs(i64, Reg),
+    /// `mov $imm, %edx`
+    MovEdx(i32),
+    /// `mov %ecx, %eax`, which also clears the top half of rax
+    MovEcxEax,
+    /// `movslq %ecx, %rax`, sign extending the bottom half of rcx into rax
+    Movslq,
+    /// `inc %rcx`
+    IncRcx,
+    /// `dec %dst`
+    Dec(Reg),
+    /// `add $imm, %rcx`
+    AddImm(i32),
+    /// `sub $imm, %rcx`
+    SubImm(i32),
+    /// `add %rax, %rcx`
+    AddRax,
+    /// `sub %rax, %rcx`
+    SubRax,
+    /// `xor %rax, %rcx`
+    XorRax,
+    /// `cmovs %rax, %rcx`, moving only if the sign flag is set
+    CmovsRax,
+    /// `imul $imm, %rcx, %rcx`
+    ImulImm(i32),
+    /// `imul %src, %rcx`
+    Imul(Reg),
+    /// `neg %dst`
+    Neg(Reg),
+    /// `shl $n, %dst`
+    Shl(u8, Reg),
+    /// `shr $n, %dst`
+    Shr(u8, Reg),
+    /// `sar $n, %dst`
+    Sar(u8, Reg),
+    /// `btc $n, %dst`, flipping bit `n`
+    Btc(u8, Reg),
+    /// `not %dst`
+    Not(Reg),
+    /// `movsd disp(%rip), %dst`, loading a double from `disp` bytes after the end of the
+    /// instruction
+    MovsdRip(i32, Xmm),
+    /// `movq %src, %dst`, copying the bits into a general purpose register
+    MovqFromXmm(Xmm, Reg),
+    /// `movq %src, %dst`, copying the bits of a general purpose register
+    MovqToXmm(Reg, Xmm),
+    /// `movapd %src, %dst`
+    Movapd(Xmm, Xmm),
+    /// `xorpd %src, %dst`
+    Xorpd(Xmm, Xmm),
+    /// `andpd %src, %dst`
+    Andpd(Xmm, Xmm),
+    /// `addsd %src, %dst`
+    Addsd(Xmm, Xmm),
+    /// `subsd %src, %dst`
+    Subsd(Xmm, Xmm),
+    /// `mulsd %src, %dst`
+    Mulsd(Xmm, Xmm),
+    /// `divsd %src, %dst`
+    Divsd(Xmm, Xmm),
+    /// `maxsd %src, %dst`, which gives `src` when either is NaN
+    Maxsd(Xmm, Xmm),
+    /// `roundsd $mode, %src, %dst`, where mode 1 rounds down and mode 3 rounds towards
+    /// zero. This needs SSE4.1.
+    Roundsd(u8, Xmm, Xmm),
+    /// `test %rcx, %rcx`
+    TestRcx,
+    /// `cqto`
+    Cqto,
+    /// `idivq %r8`
+    IdivR8,
+    /// `divq %r8`
+    DivR8,
+    /// `jno .+n`, jumping `n` bytes from the start of this instruction
+    Jno(i8),
+    /// `jnz .+n`, jumping `n` bytes from the start of this instruction
+    Jnz(i32),
+    /// `jz .+n`, jumping `n` bytes from the start of this instruction.
+    /// This always uses the long form, so the offset can be patched in afterwards.
+    Jz(i32),
+    /// `push %src`
+    Push(Reg),
+    /// `pop %dst`
+    Pop(Reg),
+    /// `xchg %src, (%rsp)`, swapping a register with the value on top of the stack
+    XchgStack(Reg),
+    /// `push %rbp`
+    PushRbp,
+    /// `mov %rsp, %rbp`
+    MovRspRbp,
+    /// `leave`, which restores rsp from rbp and then pops rbp
+    Leave,
+    /// `ret`
+    Ret,
+    /// `int3`, a breakpoint
+    Int3,
+}
+
+impl Inst {
+    /// Append the machine code for the instruction
+    pub(crate) fn encode(self, machine_code: &mut Vec<u8>) {
+        /// A ModRM byte addressing two registers directly
+        fn modrm(reg: u8, rm: Reg) -> u8 {
+            0xc0 | (reg & 7) << 3 | (rm.number() & 7)
+        }
+        /// A shift of `dst` by `n`, where `ext` selects the kind of shift
+        fn shift(machine_code: &mut Vec<u8>, ext: u8, n: u8, dst: Reg) {
+            if n == 1 {
+                machine_code.extend_from_slice(&[dst.rex_b(), 0xd1, modrm(ext, dst)]);
+            } else {
+                machine_code.extend_from_slice(&[dst.rex_b(), 0xc1, modrm(ext, dst), n]);
+            }
+        }
+        /// An SSE instruction from `src` to `dst` with a mandatory `prefix`
+        fn sse(machine_code: &mut Vec<u8>, prefix: u8, opcode: u8, src: Xmm, dst: Xmm) {
+            let modrm = 0xc0 | (dst as u8) << 3 | src as u8;
+            machine_code.extend_from_slice(&[prefix, 0x0f, opcode, modrm]);
+        }
+        /// An instruction with either an 8-bit or a 32-bit immediate, whichever fits
+        fn with_imm(machine_code: &mut Vec<u8>, short: u8, long: u8, modrm: u8, imm: i32) {
+            match i8::try_from(imm) {
+                Ok(imm) => machine_code.extend_from_slice(&[0x48, short, modrm, imm as u8]),
+                Err(_) => {
+                    machine_code.extend_from_slice(&[0x48, long, modrm]);
+                    machine_code.extend_from_slice(&imm.to_le_bytes());
+                }
+            }
+        }
+
+        match self {
+            Inst::ZeroRcx => machine_code.extend_from_slice(&[0x48, 0x31, 0xc9]),
+            Inst::ZeroEdx => machine_code.extend_from_slice(&[0x31, 0xd2]),
+            Inst::ZeroRax => machine_code.extend_from_slice(&[0x48, 0x31, 0xc0]),
+            Inst::Mov(src, dst) => {
+                let rex = dst.rex_b() | (src.number() >> 3) << 2;
+                machine_code.extend_from_slice(&[rex, 0x89, modrm(src.number(), dst)]);
+            }
+            Inst::MovImm(value, dst) => {
+                machine_code.extend_from_slice(&[dst.rex_b(), 0xc7, modrm(0, dst)]);
+                machine_code.extend_from_slice(&value.to_le_bytes());
+            }
+            Inst::Movabs(value, dst) => {
+                machine_code.extend_from_slice(&[dst.rex_b(), 0xb8 + (dst.number() & 7)]);
+                machine_code.extend_from_slice(&value.to_le_bytes());
+            }
+            Inst::MovEdx(value) => {
+                machine_code.push(0xba);
+                machine_code.extend_from_slice(&value.to_le_bytes());
+            }
+            Inst::MovEcxEax => machine_code.extend_from_slice(&[0x89, 0xc8]),
+            Inst::Movslq => machine_code.extend_from_slice(&[0x48, 0x63, 0xc1]),
+            Inst::IncRcx => machine_code.extend_from_slice(&[0x48, 0xff, 0xc1]),
+            Inst::Dec(dst) => machine_code.extend_from_slice(&[dst.rex_b(), 0xff, modrm(1, dst)]),
+            // The ModRM byte selects between `add` (0xc1) and `sub` (0xe9) on rcx
+            Inst::AddImm(value) => with_imm(machine_code, 0x83, 0x81, 0xc1, value),
+            Inst::SubImm(value) => with_imm(machine_code, 0x83, 0x81, 0xe9, value),
+            Inst::AddRax => machine_code.extend_from_slice(&[0x48, 0x01, 0xc1]),
+            Inst::SubRax => machine_code.extend_from_slice(&[0x48, 0x29, 0xc1]),
+            Inst::XorRax => machine_code.extend_from_slice(&[0x48, 0x31, 0xc1]),
+            Inst::CmovsRax => machine_code.extend_from_slice(&[0x48, 0x0f, 0x48, 0xc8]),
+            Inst::ImulImm(value) => with_imm(machine_code, 0x6b, 0x69, 0xc9, value),
+            Inst::Imul(src) => {
+                machine_code.extend_from_slice(&[src.rex_b(), 0x0f, 0xaf, modrm(1, src)]);
+            }
+            Inst::Neg(dst) => machine_code.extend_from_slice(&[dst.rex_b(), 0xf7, modrm(3, dst)]),
+            Inst::Shl(n, dst) => shift(machine_code, 4, n, dst),
+            Inst::Shr(n, dst) => shift(machine_code, 5, n, dst),
+            Inst::Sar(n, dst) => shift(machine_code, 7, n, dst),
+            Inst::Btc(n, dst) => {
+                machine_code.extend_from_slice(&[dst.rex_b(), 0x0f, 0xba, modrm(7, dst), n]);
+            }
+            Inst::Not(dst) => machine_code.extend_from_slice(&[dst.rex_b(), 0xf7, modrm(2, dst)]),
+            Inst::MovsdRip(disp, dst) => {
+                // A ModRM byte with mod 0 and r/m 5 addresses relative to rip
+                machine_code.extend_from_slice(&[0xf2, 0x0f, 0x10, 0x05 | (dst as u8) << 3]);
+                machine_code.extend_from_slice(&disp.to_le_bytes());
+            }
+            Inst::MovqFromXmm(src, dst) => {
+                machine_code.extend_from_slice(&[0x66, dst.rex_b(), 0x0f, 0x7e]);
+                machine_code.push(modrm(src as u8, dst));
+            }
+            Inst::MovqToXmm(src, dst) => {
+                machine_code.extend_from_slice(&[0x66, src.rex_b(), 0x0f, 0x6e]);
+                machine_code.push(modrm(dst as u8, src));
+            }
+            Inst::Movapd(src, dst) => sse(machine_code, 0x66, 0x28, src, dst),
+            Inst::Xorpd(src, dst) => sse(machine_code, 0x66, 0x57, src, dst),
+            Inst::Andpd(src, dst) => sse(machine_code, 0x66, 0x54, src, dst),
+            Inst::Addsd(src, dst) => sse(machine_code, 0xf2, 0x58, src, dst),
+            Inst::Subsd(src, dst) => sse(machine_code, 0xf2, 0x5c, src, dst),
+            Inst::Mulsd(src, dst) => sse(machine_code, 0xf2, 0x59, src, dst),
+            Inst::Divsd(src, dst) => sse(machine_code, 0xf2, 0x5e, src, dst),
+            Inst::Maxsd(src, dst) => sse(machine_code, 0xf2, 0x5f, src, dst),
+            Inst::Roundsd(mode, src, dst) => {
+                let modrm = 0xc0 | (dst as u8) << 3 | src as u8;
+                machine_code.extend_from_slice(&[0x66, 0x0f, 0x3a, 0x0b, modrm, mode]);
+            }
+            Inst::TestRcx => machine_code.extend_from_slice(&[0x48, 0x85, 0xc9]),
+            Inst::Cqto => machine_code.extend_from_slice(&[0x48, 0x99]),
+            Inst::IdivR8 => machine_code.extend_from_slice(&[0x49, 0xf7, 0xf8]),
+            Inst::DivR8 => machine_code.extend_from_slice(&[0x49, 0xf7, 0xf0]),
+            // The encoded offset is relative to the end of this 2 byte instruction
+            Inst::Jno(n) => machine_code.extend_from_slice(&[0x71, (n - 2) as u8]),
+            // Like the assembler, the short form is used when the offset fits in a byte
+            Inst::Jnz(n) => match i8::try_from(n - 2) {
+                Ok(rel8) => machine_code.extend_from_slice(&[0x75, rel8 as u8]),
+                Err(_) => {
+                    machine_code.extend_from_slice(&[0x0f, 0x85]);
+                    machine_code.extend_from_slice(&(n - 6).to_le_bytes());
+                }
+            },
+            Inst::Jz(n) => {
+                machine_code.extend_from_slice(&[0x0f, 0x84]);
+                machine_code.extend_from_slice(&(n - 6).to_le_bytes());
+            }
+            Inst::Push(src) => {
+                if src.number() >= 8 {
+                    machine_code.push(0x41);
+                }
+                machine_code.push(0x50 + (src.number() & 7));
+            }
+            Inst::Pop(dst) => {
+                if dst.number() >= 8 {
+                    machine_code.push(0x41);
+                }
+                machine_code.push(0x58 + (dst.number() & 7));
+            }
+            Inst::XchgStack(src) => {
+                // A ModRM byte with r/m 4 is followed by a SIB byte, which addresses rsp
+                let rex = 0x48 | (src.number() >> 3) << 2;
+                let modrm = 0x04 | (src.number() & 7) << 3;
+                machine_code.extend_from_slice(&[rex, 0x87, modrm, 0x24]);
+            }
+            Inst::PushRbp => machine_code.push(0x55),
+            Inst::MovRspRbp => machine_code.extend_from_slice(&[0x48, 0x89, 0xe5]),
+            Inst::Leave => machine_code.push(0xc9),
+            Inst::Int3 => machine_code.push(0xcc),
+            Inst::Ret => machine_code.push(0xc3),
+        }
+    }
+}
+
+/// The instruction in AT&T syntax, as accepted by the GNU assembler
+impl fmt::Display for Inst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        /// Shifts by 1 have their own encoding, which is written without the count
+        fn shift(f: &mut fmt::Formatter<'_>, name: &str, n: u8, dst: Reg) -> fmt::Result {
+            match n {
+                1 => write!(f, "{name} {dst}"),
+                n => write!(f, "{name} ${n}, {dst}"),
+            }
+        }
+
+        match self {
+            Inst::ZeroRcx => write!(f, "xor %rcx, %rcx"),
+            Inst::ZeroEdx => write!(f, "xor %edx, %edx"),
+            Inst::ZeroRax => write!(f, "xor %rax, %rax"),
+            Inst::Mov(src, dst) => write!(f, "mov {src}, {dst}"),
+            Inst::MovImm(value, dst) => write!(f, "mov ${value}, {dst}"),
+            Inst::Movabs(value, dst) => write!(f, "movabs ${value}, {dst}"),
+            Inst::MovEdx(value) => write!(f, "mov ${value}, %edx"),
+            Inst::MovEcxEax => write!(f, "mov %ecx, %eax"),
+            Inst::Movslq => write!(f, "movslq %ecx, %rax"),
+            Inst::IncRcx => write!(f, "inc %rcx"),
+            Inst::Dec(dst) => write!(f, "dec {dst}"),
+            Inst::AddImm(value) => write!(f, "add ${value}, %rcx"),
+            Inst::SubImm(value) => write!(f, "sub ${value}, %rcx"),
+            Inst::AddRax => write!(f, "add %rax, %rcx"),
+            Inst::SubRax => write!(f, "sub %rax, %rcx"),
+            Inst::XorRax => write!(f, "xor %rax, %rcx"),
+            Inst::CmovsRax => write!(f, "cmovs %rax, %rcx"),
+            Inst::ImulImm(value) => write!(f, "imul ${value}, %rcx, %rcx"),
+            Inst::Imul(src) => write!(f, "imul {src}, %rcx"),
+            Inst::Neg(dst) => write!(f, "neg {dst}"),
+            Inst::Shl(n, dst) => shift(f, "shl", *n, *dst),
+            Inst::Shr(n, dst) => shift(f, "shr", *n, *dst),
+            Inst::Sar(n, dst) => shift(f, "sar", *n, *dst),
+            Inst::Btc(n, dst) => write!(f, "btc ${n}, {dst}"),
+            Inst::Not(dst) => write!(f, "not {dst}"),
+            Inst::MovsdRip(disp, dst) => write!(f, "movsd {disp}(%rip), {dst}"),
+            Inst::MovqFromXmm(src, dst) => write!(f, "movq {src}, {dst}"),
+            Inst::MovqToXmm(src, dst) => write!(f, "movq {src}, {dst}"),
+            Inst::Movapd(src, dst) => write!(f, "movapd {src}, {dst}"),
+            Inst::Xorpd(src, dst) => write!(f, "xorpd {src}, {dst}"),
+            Inst::Andpd(src, dst) => write!(f, "andpd {src}, {dst}"),
+            Inst::Addsd(src, dst) => write!(f, "addsd {src}, {dst}"),
+            Inst::Subsd(src, dst) => write!(f, "subsd {src}, {dst}"),
+            Inst::Mulsd(src, dst) => write!(f, "mulsd {src}, {dst}"),
+            Inst::Divsd(src, dst) => write!(f, "divsd {src}, {dst}"),
+            Inst::Maxsd(src, dst) => write!(f, "maxsd {src}, {dst}"),
+            Inst::Roundsd(mode, src, dst) => write!(f, "roundsd ${mode}, {src}, {dst}"),
+            Inst::TestRcx => write!(f, "test %rcx, %rcx"),
+            Inst::Cqto => write!(f, "cqto"),
+            Inst::IdivR8 => write!(f, "idivq %r8"),
+            Inst::DivR8 => write!(f, "divq %r8"),
+            Inst::Jno(n) => write!(f, "jno .+{n}"),
+            Inst::Jnz(n) => write!(f, "jnz .{n:+}"),
+            // The assembler would pick the short form if the offset fits, unless told not to
+            Inst::Jz(n) => write!(f, "{{disp32}} jz .{n:+}"),
+            Inst::Push(src) => write!(f, "push {src}"),
+            Inst::Pop(dst) => write!(f, "pop {dst}"),
+            Inst::XchgStack(src) => write!(f, "xchg {src}, (%rsp)"),
+            Inst::PushRbp => write!(f, "push %rbp"),
+            Inst::MovRspRbp => write!(f, "mov %rsp, %rbp"),
+            Inst::Leave => write!(f, "leave"),
+            Inst::Int3 => write!(f, "int3"),
+            Inst::Ret => write!(f, "ret"),
+        }
+    }
+}
+
+/// Every general purpose register the backend uses, for [`decode`] to try
+const REGS: [Reg; 8] = [
+    Reg::Rax,
+    Reg::Rcx,
+    Reg::Rdx,
+    Reg::Rdi,
+    Reg::R8,
+    Reg::R9,
+    Reg::R10,
+    Reg::R11,
+];
+/// Every SSE register the backend uses, for [`decode`] to try
+const XMMS: [Xmm; 3] = [Xmm::Xmm0, Xmm::Xmm1, Xmm::Xmm2];
+
+/// Decode the instruction at the start of `machine_code`, returning it and its length.
+/// Only the instructions the backend emits are understood, and only in the encodings
+/// [`Inst::encode`] gives them. Rather than taking the bytes apart, every instruction
+/// is tried in turn, with its operands read from where they would be, and the one
+/// which encodes back to the same bytes is kept. This way decoding can't disagree
+/// with encoding.
+pub(crate) fn decode(machine_code: &[u8]) -> Option<(Inst, usize)> {
+    /// The `N` bytes at `at`, or zeros if the code is too short
+    fn bytes<const N: usize>(machine_code: &[u8], at: usize) -> [u8; N] {
+        machine_code
+            .get(at..at + N)
+            .and_then(|b| b.try_into().ok())
+            .unwrap_or([0; N])
+    }
+    let u8_at = |at| bytes::<1>(machine_code, at)[0];
+    let i8_at = |at| u8_at(at) as i8;
+    let i32_at = |at| i32::from_le_bytes(bytes(machine_code, at));
+    let i64_at = |at| i64::from_le_bytes(bytes(machine_code, at));
+
+    let mut candidates = vec![
+        Inst::ZeroRcx,
+        Inst::ZeroEdx,
+        Inst::ZeroRax,
+        Inst::MovEdx(i32_at(1)),
+        Inst::MovEcxEax,
+        Inst::Movslq,
+        Inst::IncRcx,
+        // Immediates come after an opcode and a ModRM byte, in one byte if they fit
+        Inst::AddImm(i8_at(3) as i32),
+        Inst::AddImm(i32_at(3)),
+        Inst::SubImm(i8_at(3) as i32),
+        Inst::SubImm(i32_at(3)),
+        Inst::AddRax,
+        Inst::SubRax,
+        Inst::XorRax,
+        Inst::CmovsRax,
+        Inst::ImulImm(i8_at(3) as i32),
+        Inst::ImulImm(i32_at(3)),
+        Inst::TestRcx,
+        Inst::Cqto,
+        Inst::IdivR8,
+        Inst::DivR8,
+        // Jumps count from the start of the instruction, whereas the encoded offset
+        // counts from the end
+        Inst::Jnz(i8_at(1) as i32 + 2),
+        Inst::PushRbp,
+        Inst::MovRspRbp,
+        Inst::Leave,
+        Inst::Ret,
+        Inst::Int3,
+    ];
+    candidates.extend(i8_at(1).checked_add(2).map(Inst::Jno));
+    candidates.extend(i32_at(2).checked_add(6).map(Inst::Jnz));
+    candidates.extend(i32_at(2).checked_add(6).map(Inst::Jz));
+    for reg in REGS {
+        candidates.extend([
+            Inst::MovImm(i32_at(3), reg),
+            Inst::Movabs(i64_at(2), reg),
+            Inst::Dec(reg),
+            Inst::Imul(reg),
+            Inst::Neg(reg),
+            Inst::Not(reg),
+            Inst::Shl(1, reg),
+            Inst::Shl(u8_at(3), reg),
+            Inst::Shr(1, reg),
+            Inst::Shr(u8_at(3), reg),
+            Inst::Sar(1, reg),
+            Inst::Sar(u8_at(3), reg),
+            Inst::Btc(u8_at(4), reg),
+            Inst::Push(reg),
+            Inst::Pop(reg),
+            Inst::XchgStack(reg),
+        ]);
+        candidates.extend(REGS.map(|src| Inst::Mov(src, reg)));
+        candidates.extend(XMMS.map(|xmm| Inst::MovqFromXmm(xmm, reg)));
+        candidates.extend(XMMS.map(|xmm| Inst::MovqToXmm(reg, xmm)));
+    }
+    for dst in XMMS {
+        candidates.push(Inst::MovsdRip(i32_at(4), dst));
+        for src in XMMS {
+            candidates.extend([
+                Inst::Movapd(src, dst),
+                Inst::Xorpd(src, dst),
+                Inst::Andpd(src, dst),
+                Inst::Addsd(src, dst),
+                Inst::Subsd(src, dst),
+                Inst::Mulsd(src, dst),
+                Inst::Divsd(src, dst),
+                Inst::Maxsd(src, dst),
+                Inst::Roundsd(u8_at(5), src, dst),
+            ]);
+        }
+    }
+
+    let mut encoded = Vec::new();
+    candidates.into_iter().find_map(|inst| {
+        encoded.clear();
+        inst.encode(&mut encoded);
+        machine_code
+            .starts_with(&encoded)
+            .then_some((inst, encoded.len()))
+    })
+}
+
+/// Append the machine code for a list of instructions
+fn encode(machine_code: &mut Vec<u8>, instructions: &[Inst]) {
+    for inst in instructions {
+        inst.encode(machine_code);
+    }
+}
+
+/// Initialize the working register (rcx)
+pub(crate) fn prologue(machine_code: &mut Vec<u8>, start: Start) {
+    encode(machine_code, &lower_prologue(start));
+}
+
+/// Emit the instructions for a single operation
+pub(crate) fn emit(machine_code: &mut Vec<u8>, token: &Instruction, options: &Options) {
+    encode(machine_code, &lower(token, options));
+}
+
+/// Emit the instructions for a step of an expression
+pub(crate) fn emit_step(machine_code: &mut Vec<u8>, step: &Step) {
+    encode(machine_code, &lower_step(step));
+}
+
+/// The registers holding the counters of nested loops, outermost first
+const COUNTERS: [Reg; MAX_LOOP_DEPTH] = [Reg::R9, Reg::R10, Reg::R11];
+
+/// Start a loop running `n` times, nested inside `depth` other loops.
+/// The loop's counter counts down to zero.
+pub(crate) fn loop_start(machine_code: &mut Vec<u8>, depth: usize, n: i64) {
+    encode(machine_code, &[load(n, COUNTERS[depth])]);
+}
+
+/// End a loop nested inside `depth` other loops, jumping back to the start of its body
+/// (at byte offset `body`) until its counter reaches zero.
+/// Fails with [`JitError::CodeTooLarge`] if the body is more than 2 GiB back.
+pub(crate) fn loop_end(
+    machine_code: &mut Vec<u8>,
+    depth: usize,
+    body: usize,
+) -> Result<(), JitError> {
+    let length = machine_code.len() - body;
+    if length + 3 > JUMP_RANGE {
+        return Err(JitError::CodeTooLarge {
+            size: machine_code.len(),
+            limit: JUMP_RANGE,
+        });
+    }
+    encode(machine_code, &lower_loop_end(depth, length));
+    Ok(())
+}
+
+/// The instructions ending a loop, whose body started `length` bytes before them
+fn lower_loop_end(depth: usize, length: usize) -> [Inst; 2] {
+    let counter = COUNTERS[depth];
+    // The jump comes after the 3 byte `dec`. Even [`MAX_OPERATIONS`](crate::MAX_OPERATIONS)
+    // of the longest operations come nowhere near 2 GiB of code, and [`loop_end`] checks
+    // the offset fits anyway.
+    let offset = -((length + 3) as i32);
+    [Inst::Dec(counter), Inst::Jnz(offset)]
+}
+
+/// How many bytes a jump reaches, with its signed 32-bit offset
+const JUMP_RANGE: usize = i32::MAX as usize;
+
+/// Jump over the next operation if the working register is zero. Returns where the
+/// jump's offset is, as it can't be known until the operation has been emitted.
+pub(crate) fn skip(machine_code: &mut Vec<u8>) -> usize {
+    encode(machine_code, &[Inst::TestRcx, Inst::Jz(0)]);
+    machine_code.len() - 4
+}
+
+/// Fill in the offset at `fixup` (from [`skip`]), so the jump goes to the end of the
+/// machine code so far.
+/// Fails with [`JitError::CodeTooLarge`] if the end is more than 2 GiB away.
+pub(crate) fn patch_skip(machine_code: &mut [u8], fixup: usize) -> Result<(), JitError> {
+    // The offset is relative to the end of the jump, which is where the offset ends
+    let offset = machine_code.len() - (fixup + 4);
+    if offset > JUMP_RANGE {
+        return Err(JitError::CodeTooLarge {
+            size: machine_code.len(),
+            limit: JUMP_RANGE,
+        });
+    }
+    machine_code[fixup..fixup + 4].copy_from_slice(&(offset as i32).to_le_bytes());
+    Ok(())
+}
+
+/// Start a call frame, keeping the stack pointer in rbp
+pub(crate) fn frame_start(machine_code: &mut Vec<u8>) {
+    encode(machine_code, &FRAME_START);
+}
+
+/// End the call frame, throwing away whatever was pushed onto the stack since it started
+pub(crate) fn frame_end(machine_code: &mut Vec<u8>) {
+    encode(machine_code, &[Inst::Leave]);
+}
+
+/// Stop in the debugger, or raise SIGTRAP when there isn't one
+pub(crate) fn breakpoint(machine_code: &mut Vec<u8>) {
+    encode(machine_code, &[Inst::Int3]);
+}
+
+/// The instructions starting a call frame
+const FRAME_START: [Inst; 2] = [Inst::PushRbp, Inst::MovRspRbp];
+
+/// Return the working register, converted to `return_type`
+pub(crate) fn epilogue(machine_code: &mut Vec<u8>, return_type: ReturnType) {
+    encode(machine_code, &lower_epilogue(return_type));
+}
+
+/// Like [`emit`], but returns from the function early if the operation overflows.
+/// The overflow flag (OF) is set by `inc`, `dec`, `add`, `sub`, `imul` and `neg` when the
+/// signed result doesn't fit, and by `shl` by one when the sign bit changes. The `sub`
+/// which finishes taking the absolute value overflows only for `i64::MIN`. Division by
+/// a positive number, taking the remainder of it, shifting right, and setting or
+/// resetting the working register can't overflow.
+/// The early return reports which operation overflowed, as one more than its `index`,
+/// and ends the call frame first if there is one (`frame`).
+pub(crate) fn emit_checked(
+    machine_code: &mut Vec<u8>,
+    index: usize,
+    token: &Instruction,
+    options: &Options,
+    frame: bool,
+) {
+    emit(machine_code, token, options);
+    if !matches!(
+        token,
+        Instruction::Halve
+            | Instruction::DivImm(_)
+            | Instruction::RemTwo
+            | Instruction::RemImm(_)
+            | Instruction::LoadConst(_)
+            | Instruction::Reset
+            | Instruction::ShiftRight
+            | Instruction::ClampZero
+            | Instruction::Push
+            | Instruction::Swap
+    ) {
+        // Report the overflow in the second return register (rdx)
+        let mut early_return = vec![Inst::MovEdx(index as i32 + 1)];
+        if frame {
+            early_return.push(Inst::Leave);
+        }
+        early_return.push(Inst::Ret);
+        let mut length = Vec::new();
+        encode(&mut length, &early_return);
+        // Skip over the early return when there was no overflow
+        encode(machine_code, &[Inst::Jno(2 + length.len() as i8)]);
+        encode(machine_code, &early_return);
+    }
+}
+
+/// Like [`emit`], but clamps the working register to `i64::MIN` or `i64::MAX` when the
+/// operation overflows, instead of wrapping around. The overflow flag is set the same
+/// way as for [`emit_checked`].
+pub(crate) fn emit_saturating(machine_code: &mut Vec<u8>, token: &Instruction, options: &Options) {
+    encode(machine_code, &lower_saturating(token, options));
+}
+
+/// The instructions for a single operation which saturates instead of overflowing
+fn lower_saturating(token: &Instruction, options: &Options) -> Vec<Inst> {
+    let mut instructions = Vec::new();
+    let clamp = match token {
+        Instruction::Halve
+        | Instruction::DivImm(_)
+        | Instruction::RemTwo
+        | Instruction::RemImm(_)
+        | Instruction::LoadConst(_)
+        | Instruction::Reset
+        | Instruction::ShiftRight
+        | Instruction::ClampZero
+        | Instruction::Push
+        | Instruction::Swap => return lower(token, options),
+        // Squares are never negative
+        Instruction::Square => vec![Inst::Movabs(i64::MAX, Reg::Rcx)],
+        // The product is negative when exactly one of the working register and `n` is.
+        // The working register is kept in rdx, as loading `n` may need rax. Filling rcx
+        // with its sign bit and flipping the top bit gives i64::MAX for negative values
+        // and i64::MIN otherwise, which `not` swaps around when `n` is positive.
+        Instruction::MulImm(n) => {
+            instructions.push(Inst::Mov(Reg::Rcx, Reg::Rdx));
+            let mut clamp = vec![
+                Inst::Mov(Reg::Rdx, Reg::Rcx),
+                Inst::Sar(63, Reg::Rcx),
+                Inst::Btc(63, Reg::Rcx),
+            ];
+            if *n > 0 {
+                clamp.push(Inst::Not(Reg::Rcx));
+            }
+            clamp
+        }
+        // Otherwise a result which overflowed has the opposite sign from the real one, so
+        // negative results clamp to i64::MAX and others to i64::MIN, the same way as above
+        _ => vec![Inst::Sar(63, Reg::Rcx), Inst::Btc(63, Reg::Rcx)],
+    };
+    instructions.extend(lower(token, options));
+    let mut length = Vec::new();
+    encode(&mut length, &clamp);
+    // Skip over the clamp when there was no overflow
+    instructions.push(Inst::Jno(2 + length.len() as i8));
+    instructions.extend(clamp);
+    instructions
+}
+
+/// Like [`epilogue`], but also reports that nothing overflowed
+pub(crate) fn epilogue_checked(machine_code: &mut Vec<u8>) {
+    let instructions = [Inst::Mov(Reg::Rcx, Reg::Rax), Inst::ZeroEdx, Inst::Ret];
+    encode(machine_code, &instructions);
+}
+
+/// The assembly for a whole function, one instruction per line.
+/// Each operation's instructions are preceded by a comment naming it.
+/// The body of a loop nested inside `n` others starts at the local label `n:`.
+/// The `n`th skip in the program jumps to the label `.Lskip<n>`.
+/// Fails like [`ir::lower`] if the tokens aren't in the form [`tokenize`](crate::tokenize)
+/// gives.
+pub(crate) fn assembly(tokens: &[Op], start: Start, options: &Options) -> Result<String, JitError> {
+    let mut text = String::new();
+    list(&mut text, &lower_prologue(start));
+    let frame = uses_stack(tokens);
+    if frame {
+        list(&mut text, &FRAME_START);
+    }
+    if options.breakpoint {
+        list(&mut text, &[Inst::Int3]);
+    }
+    // The number of the skip jumping to each label, for those which are skips. The other
+    // labels start loop bodies, and are named after the depth of the loop, as the
+    // assembler works out the jumps back to them.
+    let mut skips: Vec<Option<usize>> = Vec::new();
+    let mut skip_count = 0;
+    let mut depth = 0;
+    let mut commented = 0;
+    for &(index, ref instruction) in &ir::lower(tokens)? {
+        if index == commented {
+            text.push_str(&format!("    # {}\n", tokens[index]));
+            commented += 1;
+        }
+        match *instruction {
+            Instruction::SetCounter { depth: d, n } => {
+                list(&mut text, &[load(n, COUNTERS[d])]);
+                depth = d;
+            }
+            Instruction::Label(label) => match skips.get(label.0) {
+                Some(&Some(n)) => text.push_str(&format!(".Lskip{n}:\n")),
+                _ => text.push_str(&format!("{depth}:\n")),
+            },
+            Instruction::CountDown { depth, .. } => {
+                let counter = COUNTERS[depth];
+                text.push_str(&format!("    dec {counter}\n    jnz {depth}b\n"));
+            }
+            Instruction::JumpIfZero(label) => {
+                list(&mut text, &[Inst::TestRcx]);
+                text.push_str(&format!("    {{disp32}} jz .Lskip{skip_count}\n"));
+                if skips.len() <= label.0 {
+                    skips.resize(label.0 + 1, None);
+                }
+                skips[label.0] = Some(skip_count);
+                skip_count += 1;
+            }
+            ref instruction => list(&mut text, &lower(instruction, options)),
+        }
+    }
+    if frame {
+        list(&mut text, &[Inst::Leave]);
+    }
+    list(&mut text, &lower_epilogue(options.return_type));
+    Ok(text)
+}
+
+/// Append the assembly for a list of instructions, one per line
+fn list(text: &mut String, instructions: &[Inst]) {
+    for inst in instructions {
+        text.push_str(&format!("    {inst}\n"));
+    }
+}
+
+fn lower_prologue(start: Start) -> Vec<Inst> {
+    match start {
+        // Set working 64-bit register (rcx) to zero by xoring it with itself
+        Start::Constant(0) => vec![Inst::ZeroRcx],
+        Start::Constant(n) => vec![load(n, Reg::Rcx)],
+        // The first integer argument is passed in rdi. Copy it into the working register (rcx)
+        #[cfg(not(windows))]
+        Start::Input => vec![Inst::Mov(Reg::Rdi, Reg::Rcx)],
+        // On Windows the first integer argument is passed in rcx, so it's already in place
+        #[cfg(windows)]
+        Start::Input => vec![],
+    }
+}
+
+fn lower_epilogue(return_type: ReturnType) -> Vec<Inst> {
+    // Move the value of the working register (rcx) into the return register (rax), and return
+    let mov = match return_type {
+        ReturnType::I64 | ReturnType::U64 => Inst::Mov(Reg::Rcx, Reg::Rax),
+        // Callers only look at eax for 32-bit results, but extending them to the whole
+        // of rax is just as short and leaves nothing undefined
+        ReturnType::I32 => Inst::Movslq,
+        ReturnType::U32 => Inst::MovEcxEax,
+    };
+    vec![mov, Inst::Ret]
+}
+
+/// The instructions for a single operation
+fn lower(token: &Instruction, options: &Options) -> Vec<Inst> {
+    let unsigned = options.signedness == Signedness::Unsigned;
+    let floor = options.div_rounding == DivRounding::Floor && !unsigned;
+    match token {
+        // Increment the working register by 1
+        Instruction::Inc => vec![Inst::IncRcx],
+        // Decrement the working register by 1
+        Instruction::Dec => vec![Inst::Dec(Reg::Rcx)],
+        // Multiply the working register by 2
+        Instruction::Double if options.strength_reduce => shift_multiply(1),
+        Instruction::Double => mul(2),
+        // Divide the working register by 2. An arithmetic shift rounds towards negative
+        // infinity by itself, whereas `idiv` rounds towards zero.
+        Instruction::Halve if floor => vec![Inst::Sar(1, Reg::Rcx)],
+        Instruction::Halve if options.strength_reduce && unsigned => vec![Inst::Shr(1, Reg::Rcx)],
+        Instruction::Halve if options.strength_reduce => shift_divide(1),
+        Instruction::Halve => div(2, unsigned),
+        // Take the remainder after dividing the working register by 2
+        Instruction::RemTwo => rem(2, unsigned),
+        // Multiply the working register by itself
+        Instruction::Square => vec![Inst::Imul(Reg::Rcx)],
+        // Negate the working register
+        Instruction::Negate => vec![Inst::Neg(Reg::Rcx)],
+        // Reset the working register to zero, the same way the prologue does
+        Instruction::Reset => vec![Inst::ZeroRcx],
+        // Shift the working register by one bit
+        Instruction::ShiftLeft => vec![Inst::Shl(1, Reg::Rcx)],
+        Instruction::ShiftRight if unsigned => vec![Inst::Shr(1, Reg::Rcx)],
+        Instruction::ShiftRight => vec![Inst::Sar(1, Reg::Rcx)],
+        // Unsigned values are never negative
+        Instruction::Abs if unsigned => vec![],
+        // Without branching: rax is all ones if the working register is negative, and
+        // zero otherwise. Flipping the bits and subtracting -1 negates it, whereas
+        // xoring with zero and subtracting zero leaves it alone.
+        Instruction::Abs => vec![
+            Inst::Mov(Reg::Rcx, Reg::Rax),
+            Inst::Sar(63, Reg::Rax),
+            Inst::XorRax,
+            Inst::SubRax,
+        ],
+        // Unsigned values are never negative
+        Instruction::ClampZero if unsigned => vec![],
+        // Without branching: `test` sets the sign flag when the working register is
+        // negative, in which case the zero in rax replaces it
+        Instruction::ClampZero => vec![Inst::ZeroRax, Inst::TestRcx, Inst::CmovsRax],
+        Instruction::AddImm(n) => add(*n),
+        Instruction::SubImm(n) => add(n.wrapping_neg()),
+        Instruction::MulImm(n) => match exact_log2(*n) {
+            Some(shift) if options.strength_reduce => shift_multiply(shift),
+            _ => mul(*n),
+        },
+        Instruction::DivImm(n) => match exact_log2(*n) {
+            Some(shift) if options.strength_reduce && unsigned => {
+                vec![Inst::Shr(shift as u8, Reg::Rcx)]
+            }
+            Some(shift) if options.strength_reduce => shift_divide(shift),
+            _ => div(*n, unsigned),
+        },
+        Instruction::RemImm(n) => rem(*n, unsigned),
+        // Set the working register to `n`
+        Instruction::LoadConst(n) => vec![load(*n, Reg::Rcx)],
+        // Push a copy of the working register onto the stack
+        Instruction::Push => vec![Inst::Push(Reg::Rcx)],
+        // Swap the working register with the value on top of the stack
+        Instruction::Swap => vec![Inst::XchgStack(Reg::Rcx)],
+        Instruction::SetCounter { .. } | Instruction::CountDown { .. } => {
+            unreachable!("loops are emitted by loop_start and loop_end")
+        }
+        Instruction::Label(_) | Instruction::JumpIfZero(_) => {
+            unreachable!("skips are emitted by skip and patch_skip")
+        }
+    }
+}
+
+/// The instructions for a step of an expression
+fn lower_step(step: &Step) -> Vec<Inst> {
+    match step {
+        Step::Load(n) => vec![load(*n, Reg::Rcx)],
+        Step::Push => vec![Inst::Push(Reg::Rcx)],
+        // The right operand moves to rax, making way for the left one
+        Step::Apply(BinOp::Add) => vec![
+            Inst::Mov(Reg::Rcx, Reg::Rax),
+            Inst::Pop(Reg::Rcx),
+            Inst::AddRax,
+        ],
+        Step::Apply(BinOp::Sub) => vec![
+            Inst::Mov(Reg::Rcx, Reg::Rax),
+            Inst::Pop(Reg::Rcx),
+            Inst::SubRax,
+        ],
+        Step::Apply(BinOp::Mul) => vec![
+            Inst::Mov(Reg::Rcx, Reg::Rax),
+            Inst::Pop(Reg::Rcx),
+            Inst::Imul(Reg::Rax),
+        ],
+        // `idiv` divides rdx:rax, so the left operand goes straight there instead
+        Step::Apply(BinOp::Div) => vec![
+            Inst::Mov(Reg::Rcx, Reg::R8),
+            Inst::Pop(Reg::Rax),
+            Inst::Cqto,
+            Inst::IdivR8,
+            Inst::Mov(Reg::Rax, Reg::Rcx),
+        ],
+        Step::Negate => vec![Inst::Neg(Reg::Rcx)],
+    }
+}
+
+/// Add `n` to the working register, using the shortest encoding available.
+/// Negative values are subtracted instead.
+fn add(n: i64) -> Vec<Inst> {
+    match i32::try_from(n.unsigned_abs()) {
+        Ok(imm) if n < 0 => vec![Inst::SubImm(imm)],
+        Ok(imm) => vec![Inst::AddImm(imm)],
+        // There is no 64-bit immediate form of `add`, so the value goes through rax
+        Err(_) => vec![Inst::Movabs(n, Reg::Rax), Inst::AddRax],
+    }
+}
+
+/// Load `value` into a register, using the shortest encoding available
+fn load(value: i64, dst: Reg) -> Inst {
+    match i32::try_from(value) {
+        // The immediate is sign extended to 64 bits
+        Ok(imm) => Inst::MovImm(imm, dst),
+        // Values which don't fit in 32 bits need the full 64-bit immediate form
+        Err(_) => Inst::Movabs(value, dst),
+    }
+}
+
+/// Multiply the working register by `n`, using the shortest encoding available
+fn mul(n: i64) -> Vec<Inst> {
+    match i32::try_from(n) {
+        Ok(imm) => vec![Inst::ImulImm(imm)],
+        Err(_) => vec![Inst::Movabs(n, Reg::Rax), Inst::Imul(Reg::Rax)],
+    }
+}
+
+/// Divide the working register by `n` with `idiv` (or `div` if `unsigned`), leaving the
+/// quotient in rax and the remainder in rdx
+fn idiv(n: i64, unsigned: bool) -> Vec<Inst> {
+    vec![
+        // Copy the value in the working register (rcx) to rax
+        Inst::Mov(Reg::Rcx, Reg::Rax),
+        // Copy the divisor into register r8
+        load(n, Reg::R8),
+        // Extend rax into rdx, as idiv and div divide the 128-bit value rdx:rax
+        if unsigned { Inst::ZeroEdx } else { Inst::Cqto },
+        // Divide the value in rax by the value in r8, store result to rax.
+        if unsigned { Inst::DivR8 } else { Inst::IdivR8 },
+    ]
+}
+
+/// Divide the working register by `n`, rounding towards zero
+fn div(n: i64, unsigned: bool) -> Vec<Inst> {
+    let mut instructions = idiv(n, unsigned);
+    // Move result (currently in rax) back into working register (rcx)
+    instructions.push(Inst::Mov(Reg::Rax, Reg::Rcx));
+    instructions
+}
+
+/// Replace the working register with its remainder after dividing by `n`.
+/// The remainder has the same sign as the working register, like `%` in C.
+fn rem(n: i64, unsigned: bool) -> Vec<Inst> {
+    let mut instructions = idiv(n, unsigned);
+    // `idiv` leaves the remainder in rdx. Move it into the working register (rcx)
+    instructions.push(Inst::Mov(Reg::Rdx, Reg::Rcx));
+    instructions
+}
+
+/// Multiply the working register by `2^shift`, without `imul`
+fn shift_multiply(shift: u32) -> Vec<Inst> {
+    vec![Inst::Shl(shift as u8, Reg::Rcx)]
+}
+
+/// Divide the working register by `2^shift`, rounding towards zero, without `idiv`.
+/// An arithmetic shift alone rounds towards negative infinity, so negative values
+/// are biased by `2^shift - 1` first.
+fn shift_divide(shift: u32) -> Vec<Inst> {
+    let mut instructions = vec![Inst::Mov(Reg::Rcx, Reg::Rax)];
+    if shift == 1 {
+        // The bias is just the sign bit
+        instructions.push(Inst::Shr(63, Reg::Rax));
+    } else {
+        // Fill rax with the sign bit, then keep the bottom `shift` bits of it
+        instructions.push(Inst::Sar(63, Reg::Rax));
+        instructions.push(Inst::Shr(64 - shift as u8, Reg::Rax));
+    }
+    instructions.push(Inst::AddRax);
+    instructions.push(Inst::Sar(shift as u8, Reg::Rcx));
+    instructions
+}
+
+/// Machine code for a function which returns `value` without doing anything else.
+pub(crate) fn return_constant(value: i64) -> Vec<u8> {
+    let mut machine_code: Vec<u8> = Vec::new();
+    encode(&mut machine_code, &[load(value, Reg::Rax), Inst::Ret]);
+    machine_code
+}
+
+// The floating point code keeps the working value in xmm0, which is also where both
+// calling conventions return a `double`. Operands are loaded into xmm1 from a literal
+// pool placed after the function's `ret`, addressed relative to rip. The loads of the
+// constants are recorded in `constants`, so their offsets can be filled in once the pool
+// is placed by `epilogue_float`.
+
+/// Initialize the floating point working register (xmm0) to zero
+pub(crate) fn prologue_float(machine_code: &mut Vec<u8>) {
+    encode(machine_code, &[Inst::Xorpd(Xmm::Xmm0, Xmm::Xmm0)]);
+}
+
+/// Emit the floating point instructions for a single operation
+pub(crate) fn emit_float(
+    machine_code: &mut Vec<u8>,
+    token: &Instruction,
+    constants: &mut Constants,
+) {
+    let start = machine_code.len();
+    let (instructions, constant) = lower_float(token);
+    encode(machine_code, &instructions);
+    if let Some(value) = constant {
+        // The constant is loaded first, and its offset starts 4 bytes into the load
+        constants.push((start + 4, value));
+    }
+}
+
+/// Like [`skip`], but for the floating point working register. Both zeros are skipped,
+/// which are the only values with no bits set other than the sign bit.
+pub(crate) fn skip_float(machine_code: &mut Vec<u8>) -> usize {
+    let sign_dropped = [
+        Inst::MovqFromXmm(Xmm::Xmm0, Reg::Rcx),
+        Inst::Shl(1, Reg::Rcx),
+    ];
+    encode(machine_code, &sign_dropped);
+    // `shl` sets the zero flag the same way `test` does
+    encode(machine_code, &[Inst::Jz(0)]);
+    machine_code.len() - 4
+}
+
+/// Return the floating point working register, which is already in the return register,
+/// followed by the literal pool
+pub(crate) fn epilogue_float(machine_code: &mut Vec<u8>, constants: &Constants) {
+    encode(machine_code, &[Inst::Ret]);
+    for (fixup, address) in literal_pool(machine_code, constants) {
+        // The offset is relative to the end of the load, which is where the offset ends
+        let offset = (address - (fixup + 4)) as i32;
+        machine_code[fixup..fixup + 4].copy_from_slice(&offset.to_le_bytes());
+    }
+}
+
+/// The floating point instructions for a single operation, along with the constant
+/// they load into xmm1 (or xmm0 for `=n`) first, if any. Apart from `%` and `>`, each
+/// takes one instruction once its operand is loaded.
+fn lower_float(token: &Instruction) -> (Vec<Inst>, Option<f64>) {
+    let (op, n): (fn(Xmm, Xmm) -> Inst, f64) = match token {
+        Instruction::Inc => (Inst::Addsd, 1.0),
+        Instruction::Dec => (Inst::Subsd, 1.0),
+        Instruction::Double | Instruction::ShiftLeft => (Inst::Mulsd, 2.0),
+        Instruction::Halve => (Inst::Divsd, 2.0),
+        Instruction::AddImm(n) => (Inst::Addsd, *n as f64),
+        Instruction::SubImm(n) => (Inst::Subsd, *n as f64),
+        Instruction::MulImm(n) => (Inst::Mulsd, *n as f64),
+        Instruction::DivImm(n) => (Inst::Divsd, *n as f64),
+        Instruction::RemTwo => return float_rem(2.0),
+        Instruction::RemImm(n) => return float_rem(*n as f64),
+        Instruction::Square => return (vec![Inst::Mulsd(Xmm::Xmm0, Xmm::Xmm0)], None),
+        // Flip the sign bit
+        Instruction::Negate => (Inst::Xorpd, -0.0),
+        // Clear the sign bit
+        Instruction::Abs => (Inst::Andpd, f64::from_bits(i64::MAX as u64)),
+        // NaN becomes zero too, as `maxsd` gives its source when either is NaN
+        Instruction::ClampZero => (Inst::Maxsd, 0.0),
+        // Halve, then round down like an arithmetic shift does (mode 1)
+        Instruction::ShiftRight => {
+            let instructions = vec![
+                Inst::MovsdRip(0, Xmm::Xmm1),
+                Inst::Divsd(Xmm::Xmm1, Xmm::Xmm0),
+                Inst::Roundsd(1, Xmm::Xmm0, Xmm::Xmm0),
+            ];
+            return (instructions, Some(2.0));
+        }
+        Instruction::Reset => return (vec![Inst::Xorpd(Xmm::Xmm0, Xmm::Xmm0)], None),
+        Instruction::LoadConst(n) => return (vec![Inst::MovsdRip(0, Xmm::Xmm0)], Some(*n as f64)),
+        // The stack holds the bits of the values, which go through rax
+        Instruction::Push => {
+            let instructions = vec![Inst::MovqFromXmm(Xmm::Xmm0, Reg::Rax), Inst::Push(Reg::Rax)];
+            return (instructions, None);
+        }
+        Instruction::Swap => {
+            let instructions = vec![
+                Inst::MovqFromXmm(Xmm::Xmm0, Reg::Rax),
+                Inst::XchgStack(Reg::Rax),
+                Inst::MovqToXmm(Reg::Rax, Xmm::Xmm0),
+            ];
+            return (instructions, None);
+        }
+        Instruction::SetCounter { .. } | Instruction::CountDown { .. } => {
+            unreachable!("loops are emitted by loop_start and loop_end")
+        }
+        Instruction::Label(_) | Instruction::JumpIfZero(_) => {
+            unreachable!("skips are emitted by skip_float and patch_skip")
+        }
+    };
+    let instructions = vec![Inst::MovsdRip(0, Xmm::Xmm1), op(Xmm::Xmm1, Xmm::Xmm0)];
+    (instructions, Some(n))
+}
+
+/// Replace the floating point working register (x) with its remainder after dividing
+/// by `n`, which is x - trunc(x / n) * n. This has the same sign as x, like `fmod` in C,
+/// though x / n is rounded, so the two can differ once x is many times larger than n.
+fn float_rem(n: f64) -> (Vec<Inst>, Option<f64>) {
+    let instructions = vec![
+        Inst::MovsdRip(0, Xmm::Xmm1),
+        Inst::Movapd(Xmm::Xmm0, Xmm::Xmm2),
+        Inst::Divsd(Xmm::Xmm1, Xmm::Xmm2),
+        Inst::Roundsd(3, Xmm::Xmm2, Xmm::Xmm2),
+        Inst::Mulsd(Xmm::Xmm1, Xmm::Xmm2),
+        Inst::Subsd(Xmm::Xmm2, Xmm::Xmm0),
+    ];
+    (instructions, Some(n))
+}
+
+#[cfg(all(test, target_arch = "x86_64"))]
+mod test {
+    use crate::expr::{BinOp, Step};
+    use crate::ir::Instruction;
+    use crate::x86_64::{
+        decode, lower, lower_epilogue, lower_loop_end, lower_prologue, lower_step, Inst, Reg, Xmm,
+    };
+    use crate::{
+        codegen, jit, jit_float, jit_with_options, run, to_assembly, Op, Options, ReturnType,
+        Signedness, Start, Target,
+    };
+    use object::{Object, ObjectSection};
+    use std::process::Command;
+
+    #[test]
+    fn test_add_encodings() {
+        for n in [
+            2,
+            -2,
+            127,
+            -128,
+            1000,
+            -1000,
+            i64::from(i32::MIN),
+            1 << 40,
+            -(1 << 40),
+        ] {
+            let result = unsafe {
+                run(&codegen(&[Op::Add(n)], Start::Constant(0), &Options::default()).unwrap())
+            };
+            assert_eq!(result, n, "{n}");
+        }
+    }
+
+    #[test]
+    fn test_mul_encodings() {
+        // `imul $3, %rcx, %rcx`
+        assert_eq!(jit("*3").unwrap()[3..7], [0x48, 0x6b, 0xc9, 0x03]);
+        // `imul $1000, %rcx, %rcx`
+        assert_eq!(
+            jit("*1000").unwrap()[3..10],
+            [0x48, 0x69, 0xc9, 0xe8, 0x03, 0x00, 0x00]
+        );
+        // Squaring uses the two operand form, `imul %rcx, %rcx`
+        assert_eq!(
+            jit("^").unwrap(),
+            [0x48, 0x31, 0xc9, 0x48, 0x0f, 0xaf, 0xc9, 0x48, 0x89, 0xc8, 0xc3]
+        );
+        for n in [0, 3, -3, 127, -128, 1000, 1 << 40] {
+            let result = unsafe {
+                run(&codegen(
+                    &[Op::Plus, Op::Mul(n)],
+                    Start::Constant(0),
+                    &Options::default(),
+                )
+                .unwrap())
+            };
+            assert_eq!(result, n, "{n}");
+        }
+    }
+
+    #[test]
+    fn test_abs_encoding() {
+        // `mov %rcx, %rax`
+        // `sar $63, %rax`
+        // `xor %rax, %rcx`
+        // `sub %rax, %rcx`
+        assert_eq!(
+            jit("|").unwrap()[3..16],
+            [0x48, 0x89, 0xc8, 0x48, 0xc1, 0xf8, 0x3f, 0x48, 0x31, 0xc1, 0x48, 0x29, 0xc1]
+        );
+    }
+
+    #[test]
+    fn test_literal_pool() {
+        let machine_code = jit_float("+").unwrap();
+        // `xorpd %xmm0, %xmm0`
+        // `movsd 12(%rip), %xmm1`
+        // `addsd %xmm1, %xmm0`
+        // `ret`
+        assert_eq!(
+            machine_code[..17],
+            [
+                0x66, 0x0f, 0x57, 0xc0, 0xf2, 0x0f, 0x10, 0x0d, 0x0c, 0x00, 0x00, 0x00, 0xf2, 0x0f,
+                0x58, 0xc1, 0xc3
+            ]
+        );
+        assert_eq!(machine_code[24..], 1.0f64.to_le_bytes());
+    }
+
+    #[test]
+    fn test_unsigned_lowering() {
+        let options = Options {
+            signedness: Signedness::Unsigned,
+            ..Options::default()
+        };
+        // The dividend is zero extended instead of sign extended
+        assert_eq!(
+            lower(&Instruction::DivImm(3), &options),
+            [
+                Inst::Mov(Reg::Rcx, Reg::Rax),
+                Inst::MovImm(3, Reg::R8),
+                Inst::ZeroEdx,
+                Inst::DivR8,
+                Inst::Mov(Reg::Rax, Reg::Rcx)
+            ]
+        );
+        assert_eq!(
+            lower(&Instruction::ShiftRight, &options),
+            [Inst::Shr(1, Reg::Rcx)]
+        );
+        assert_eq!(lower(&Instruction::Abs, &options), []);
+        assert_eq!(lower(&Instruction::ClampZero, &options), []);
+        let options = Options {
+            strength_reduce: true,
+            ..options
+        };
+        assert_eq!(
+            lower(&Instruction::DivImm(8), &options),
+            [Inst::Shr(3, Reg::Rcx)]
+        );
+    }
+
+    /// The complete machine code for programs of a single operation, prologue and epilogue
+    /// included, so any change to what gets emitted shows up here
+    #[test]
+    fn test_program_encoding() {
+        /// Tester function
+        fn t(p: &str) -> Vec<u8> {
+            let options = Options {
+                target: Target::X86_64,
+                ..Options::default()
+            };
+            jit_with_options(p, options).unwrap()
+        }
+
+        // xor %rcx, %rcx
+        // inc %rcx
+        // mov %rcx, %rax
+        // ret
+        assert_eq!(
+            t("+"),
+            [0x48, 0x31, 0xc9, 0x48, 0xff, 0xc1, 0x48, 0x89, 0xc8, 0xc3]
+        );
+        // dec %rcx
+        assert_eq!(
+            t("-"),
+            [0x48, 0x31, 0xc9, 0x48, 0xff, 0xc9, 0x48, 0x89, 0xc8, 0xc3]
+        );
+        // imul $2, %rcx, %rcx
+        assert_eq!(
+            t("*"),
+            [0x48, 0x31, 0xc9, 0x48, 0x6b, 0xc9, 0x02, 0x48, 0x89, 0xc8, 0xc3]
+        );
+        // mov %rcx, %rax
+        // mov $2, %r8
+        // cqto
+        // idivq %r8
+        // mov %rax, %rcx
+        assert_eq!(
+            t("/"),
+            [
+                0x48, 0x31, 0xc9, 0x48, 0x89, 0xc8, 0x49, 0xc7, 0xc0, 0x02, 0x00, 0x00, 0x00, 0x48,
+                0x99, 0x49, 0xf7, 0xf8, 0x48, 0x89, 0xc1, 0x48, 0x89, 0xc8, 0xc3
+            ]
+        );
+        // The same, keeping the remainder from rdx instead
+        // mov %rdx, %rcx
+        assert_eq!(
+            t("%"),
+            [
+                0x48, 0x31, 0xc9, 0x48, 0x89, 0xc8, 0x49, 0xc7, 0xc0, 0x02, 0x00, 0x00, 0x00, 0x48,
+                0x99, 0x49, 0xf7, 0xf8, 0x48, 0x89, 0xd1, 0x48, 0x89, 0xc8, 0xc3
+            ]
+        );
+        // imul %rcx, %rcx
+        assert_eq!(
+            t("^"),
+            [0x48, 0x31, 0xc9, 0x48, 0x0f, 0xaf, 0xc9, 0x48, 0x89, 0xc8, 0xc3]
+        );
+        // neg %rcx
+        assert_eq!(
+            t("~"),
+            [0x48, 0x31, 0xc9, 0x48, 0xf7, 0xd9, 0x48, 0x89, 0xc8, 0xc3]
+        );
+        // xor %rcx, %rcx
+        assert_eq!(
+            t("0"),
+            [0x48, 0x31, 0xc9, 0x48, 0x31, 0xc9, 0x48, 0x89, 0xc8, 0xc3]
+        );
+        // shl %rcx
+        assert_eq!(
+            t("<"),
+            [0x48, 0x31, 0xc9, 0x48, 0xd1, 0xe1, 0x48, 0x89, 0xc8, 0xc3]
+        );
+        // sar %rcx
+        assert_eq!(
+            t(">"),
+            [0x48, 0x31, 0xc9, 0x48, 0xd1, 0xf9, 0x48, 0x89, 0xc8, 0xc3]
+        );
+        // mov %rcx, %rax
+        // sar $63, %rax
+        // xor %rax, %rcx
+        // sub %rax, %rcx
+        assert_eq!(
+            t("|"),
+            [
+                0x48, 0x31, 0xc9, 0x48, 0x89, 0xc8, 0x48, 0xc1, 0xf8, 0x3f, 0x48, 0x31, 0xc1, 0x48,
+                0x29, 0xc1, 0x48, 0x89, 0xc8, 0xc3
+            ]
+        );
+        // mov $5, %rcx
+        assert_eq!(
+            t("=5"),
+            [0x48, 0x31, 0xc9, 0x48, 0xc7, 0xc1, 0x05, 0x00, 0x00, 0x00, 0x48, 0x89, 0xc8, 0xc3]
+        );
+        // push %rbp
+        // mov %rsp, %rbp
+        // push %rcx
+        // xchg %rcx, (%rsp)
+        // leave
+        assert_eq!(
+            t("ds"),
+            [
+                0x48, 0x31, 0xc9, 0x55, 0x48, 0x89, 0xe5, 0x51, 0x48, 0x87, 0x0c, 0x24, 0xc9, 0x48,
+                0x89, 0xc8, 0xc3
+            ]
+        );
+    }
+
+    /// Every kind of instruction decodes back to itself, including with the operands at
+    /// the edges of their encodings
+    #[test]
+    fn test_decode() {
+        let instructions = [
+            Inst::ZeroRcx,
+            Inst::ZeroEdx,
+            Inst::ZeroRax,
+            Inst::Mov(Reg::Rcx, Reg::Rax),
+            Inst::Mov(Reg::R8, Reg::Rdi),
+            Inst::Mov(Reg::Rdx, Reg::R11),
+            Inst::MovImm(-1, Reg::R8),
+            Inst::MovImm(i32::MAX, Reg::Rcx),
+            Inst::Movabs(i64::MIN, Reg::Rax),
+            Inst::Movabs(1 << 40, Reg::R10),
+            Inst::MovEdx(-7),
+            Inst::MovEcxEax,
+            Inst::Movslq,
+            Inst::IncRcx,
+            Inst::Dec(Reg::Rcx),
+            Inst::Dec(Reg::R9),
+            Inst::AddImm(127),
+            Inst::AddImm(128),
+            Inst::SubImm(-128),
+            Inst::SubImm(i32::MIN),
+            Inst::AddRax,
+            Inst::SubRax,
+            Inst::XorRax,
+            Inst::CmovsRax,
+            Inst::ImulImm(3),
+            Inst::ImulImm(1000),
+            Inst::Imul(Reg::Rcx),
+            Inst::Imul(Reg::R8),
+            Inst::Neg(Reg::Rcx),
+            Inst::Shl(1, Reg::Rcx),
+            Inst::Shl(3, Reg::Rax),
+            Inst::Shr(1, Reg::R11),
+            Inst::Shr(63, Reg::Rcx),
+            Inst::Sar(1, Reg::Rcx),
+            Inst::Sar(63, Reg::Rax),
+            Inst::Btc(63, Reg::Rcx),
+            Inst::Not(Reg::Rcx),
+            Inst::MovsdRip(16, Xmm::Xmm1),
+            Inst::MovsdRip(-4, Xmm::Xmm0),
+            Inst::MovqFromXmm(Xmm::Xmm0, Reg::Rax),
+            Inst::MovqToXmm(Reg::Rax, Xmm::Xmm0),
+            Inst::Movapd(Xmm::Xmm0, Xmm::Xmm2),
+            Inst::Xorpd(Xmm::Xmm0, Xmm::Xmm0),
+            Inst::Andpd(Xmm::Xmm1, Xmm::Xmm0),
+            Inst::Addsd(Xmm::Xmm1, Xmm::Xmm0),
+            Inst::Subsd(Xmm::Xmm2, Xmm::Xmm0),
+            Inst::Mulsd(Xmm::Xmm0, Xmm::Xmm0),
+            Inst::Maxsd(Xmm::Xmm1, Xmm::Xmm0),
+            Inst::Divsd(Xmm::Xmm1, Xmm::Xmm2),
+            Inst::Roundsd(3, Xmm::Xmm2, Xmm::Xmm2),
+            Inst::TestRcx,
+            Inst::Cqto,
+            Inst::IdivR8,
+            Inst::DivR8,
+            Inst::Jno(8),
+            Inst::Jno(-126),
+            Inst::Jnz(-18),
+            Inst::Jnz(129),
+            Inst::Jnz(-100_000),
+            Inst::Jz(9),
+            Inst::Jz(0),
+            Inst::Push(Reg::Rcx),
+            Inst::Push(Reg::R9),
+            Inst::Pop(Reg::Rax),
+            Inst::Pop(Reg::R8),
+            Inst::XchgStack(Reg::Rcx),
+            Inst::XchgStack(Reg::R8),
+            Inst::PushRbp,
+            Inst::MovRspRbp,
+            Inst::Leave,
+            Inst::Ret,
+            Inst::Int3,
+        ];
+        for inst in instructions {
+            let mut machine_code = Vec::new();
+            inst.encode(&mut machine_code);
+            assert_eq!(
+                decode(&machine_code),
+                Some((inst, machine_code.len())),
+                "{inst}"
+            );
+            // Cut short, the instruction isn't mistaken for another one
+            assert_eq!(
+                decode(&machine_code[..machine_code.len() - 1]),
+                None,
+                "{inst}"
+            );
+        }
+        assert_eq!(decode(&[]), None);
+        assert_eq!(decode(&[0x0f, 0x0b]), None);
+    }
+
+    #[test]
+    fn test_step_lowering() {
+        assert_eq!(
+            lower_step(&Step::Apply(BinOp::Sub)),
+            [
+                Inst::Mov(Reg::Rcx, Reg::Rax),
+                Inst::Pop(Reg::Rcx),
+                Inst::SubRax
+            ]
+        );
+        assert_eq!(
+            lower_step(&Step::Apply(BinOp::Div)),
+            [
+                Inst::Mov(Reg::Rcx, Reg::R8),
+                Inst::Pop(Reg::Rax),
+                Inst::Cqto,
+                Inst::IdivR8,
+                Inst::Mov(Reg::Rax, Reg::Rcx)
+            ]
+        );
+        assert_eq!(
+            lower_step(&Step::Load(1 << 40)),
+            [Inst::Movabs(1 << 40, Reg::Rcx)]
+        );
+    }
+
+    /// Whether an instruction addresses memory, rather than working only on registers,
+    /// immediates, and jumps relative to itself. Every instruction has to be listed, so
+    /// new ones have to be thought about here.
+    fn addresses_memory(inst: Inst) -> bool {
+        match inst {
+            Inst::MovsdRip(..) => true,
+            Inst::ZeroRcx
+            | Inst::ZeroEdx
+            | Inst::Mov(..)
+            | Inst::MovImm(..)
+            | Inst::Movabs(..)
+            | Inst::MovEdx(_)
+            | Inst::MovEcxEax
+            | Inst::Movslq
+            | Inst::IncRcx
+            | Inst::Dec(_)
+            | Inst::AddImm(_)
+            | Inst::SubImm(_)
+            | Inst::AddRax
+            | Inst::SubRax
+            | Inst::XorRax
+            | Inst::ZeroRax
+            | Inst::CmovsRax
+            | Inst::ImulImm(_)
+            | Inst::Imul(_)
+            | Inst::Neg(_)
+            | Inst::Shl(..)
+            | Inst::Shr(..)
+            | Inst::Sar(..)
+            | Inst::Btc(..)
+            | Inst::Not(_)
+            | Inst::MovqFromXmm(..)
+            | Inst::MovqToXmm(..)
+            | Inst::Movapd(..)
+            | Inst::Xorpd(..)
+            | Inst::Andpd(..)
+            | Inst::Addsd(..)
+            | Inst::Subsd(..)
+            | Inst::Mulsd(..)
+            | Inst::Divsd(..)
+            | Inst::Maxsd(..)
+            | Inst::Roundsd(..)
+            | Inst::TestRcx
+            | Inst::Cqto
+            | Inst::IdivR8
+            | Inst::DivR8
+            | Inst::Jno(_)
+            | Inst::Jnz(_)
+            | Inst::Jz(_)
+            | Inst::MovRspRbp
+            | Inst::Ret
+            | Inst::Int3 => false,
+            // The stack is addressed relative to rsp, which doesn't depend on where the
+            // code is either
+            Inst::Push(_) | Inst::Pop(_) | Inst::XchgStack(_) | Inst::PushRbp | Inst::Leave => {
+                false
+            }
+        }
+    }
+
+    /// The integer code can be copied anywhere and still run, such as into an object
+    /// file, as it never addresses memory. (The floating point code does, but only its
+    /// own literal pool, relative to rip, so it can still be copied as a whole.)
+    #[test]
+    fn test_position_independent() {
+        let ops = [
+            Op::Plus,
+            Op::Minus,
+            Op::Star,
+            Op::Slash,
+            Op::Percent,
+            Op::Caret,
+            Op::Tilde,
+            Op::Zero,
+            Op::Less,
+            Op::Greater,
+            Op::Bar,
+            Op::Clamp,
+            Op::Add(5),
+            Op::Add(1 << 40),
+            Op::Sub(5),
+            Op::Mul(3),
+            Op::Mul(8),
+            Op::Mul(1 << 40),
+            Op::Div(3),
+            Op::Div(8),
+            Op::Rem(3),
+            Op::Set(-7),
+            Op::Set(1 << 40),
+            Op::Dup,
+            Op::Swap,
+        ];
+        let unsigned = Options {
+            signedness: Signedness::Unsigned,
+            ..Options::optimized()
+        };
+        for options in [Options::default(), Options::optimized(), unsigned] {
+            for (_, op) in crate::ir::lower(&ops).unwrap() {
+                let instructions = lower(&op, &options);
+                assert!(!instructions.into_iter().any(addresses_memory), "{op:?}");
+            }
+        }
+        let mut instructions = lower_prologue(Start::Constant(1 << 40));
+        instructions.extend(lower_prologue(Start::Input));
+        instructions.extend(lower_loop_end(2, 100));
+        for return_type in [ReturnType::I64, ReturnType::I32, ReturnType::U32] {
+            instructions.extend(lower_epilogue(return_type));
+        }
+        assert!(!instructions.into_iter().any(addresses_memory));
+    }
+
+    #[test]
+    fn test_input_register() {
+        #[cfg(not(windows))]
+        assert_eq!(
+            lower_prologue(Start::Input),
+            [Inst::Mov(Reg::Rdi, Reg::Rcx)]
+        );
+        #[cfg(windows)]
+        assert_eq!(lower_prologue(Start::Input), []);
+        assert_eq!(lower_prologue(Start::Constant(0)), [Inst::ZeroRcx]);
+    }
+
+    #[test]
+    fn test_encode() {
+        /// Tester function
+        fn t(inst: Inst) -> Vec<u8> {
+            let mut machine_code = Vec::new();
+            inst.encode(&mut machine_code);
+            machine_code
+        }
+
+        assert_eq!(t(Inst::Mov(Reg::Rdi, Reg::Rcx)), [0x48, 0x89, 0xf9]);
+        assert_eq!(t(Inst::Mov(Reg::Rdx, Reg::Rcx)), [0x48, 0x89, 0xd1]);
+        assert_eq!(
+            t(Inst::MovImm(-1, Reg::R8)),
+            [0x49, 0xc7, 0xc0, 0xff, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(t(Inst::Movabs(1, Reg::R8))[..2], [0x49, 0xb8]);
+        assert_eq!(t(Inst::Imul(Reg::Rax)), [0x48, 0x0f, 0xaf, 0xc8]);
+        assert_eq!(t(Inst::Imul(Reg::R8)), [0x49, 0x0f, 0xaf, 0xc8]);
+        assert_eq!(t(Inst::Shl(1, Reg::Rcx)), [0x48, 0xd1, 0xe1]);
+        assert_eq!(t(Inst::Sar(63, Reg::Rax)), [0x48, 0xc1, 0xf8, 0x3f]);
+        assert_eq!(t(Inst::Jno(8)), [0x71, 0x06]);
+        assert_eq!(t(Inst::Dec(Reg::R11)), [0x49, 0xff, 0xcb]);
+        assert_eq!(t(Inst::Neg(Reg::Rcx)), [0x48, 0xf7, 0xd9]);
+        assert_eq!(t(Inst::Not(Reg::Rcx)), [0x48, 0xf7, 0xd1]);
+        assert_eq!(t(Inst::Push(Reg::Rcx)), [0x51]);
+        assert_eq!(t(Inst::Push(Reg::R9)), [0x41, 0x51]);
+        assert_eq!(t(Inst::Pop(Reg::Rcx)), [0x59]);
+        assert_eq!(t(Inst::Pop(Reg::R8)), [0x41, 0x58]);
+        assert_eq!(t(Inst::XchgStack(Reg::Rcx)), [0x48, 0x87, 0x0c, 0x24]);
+        assert_eq!(t(Inst::XchgStack(Reg::R8)), [0x4c, 0x87, 0x04, 0x24]);
+        assert_eq!(t(Inst::PushRbp), [0x55]);
+        assert_eq!(t(Inst::MovRspRbp), [0x48, 0x89, 0xe5]);
+        assert_eq!(t(Inst::Leave), [0xc9]);
+        assert_eq!(t(Inst::Int3), [0xcc]);
+        assert_eq!(
+            t(Inst::MovqToXmm(Reg::Rax, Xmm::Xmm0)),
+            [0x66, 0x48, 0x0f, 0x6e, 0xc0]
+        );
+        assert_eq!(t(Inst::Btc(63, Reg::Rcx)), [0x48, 0x0f, 0xba, 0xf9, 0x3f]);
+        assert_eq!(t(Inst::TestRcx), [0x48, 0x85, 0xc9]);
+        assert_eq!(t(Inst::ZeroRax), [0x48, 0x31, 0xc0]);
+        assert_eq!(t(Inst::CmovsRax), [0x48, 0x0f, 0x48, 0xc8]);
+        assert_eq!(
+            t(Inst::Maxsd(Xmm::Xmm1, Xmm::Xmm0)),
+            [0xf2, 0x0f, 0x5f, 0xc1]
+        );
+        assert_eq!(t(Inst::DivR8), [0x49, 0xf7, 0xf0]);
+        assert_eq!(t(Inst::Jz(9)), [0x0f, 0x84, 0x03, 0x00, 0x00, 0x00]);
+        assert_eq!(t(Inst::MovImm(5, Reg::R10))[..3], [0x49, 0xc7, 0xc2]);
+        assert_eq!(t(Inst::Jnz(-5)), [0x75, 0xf9]);
+        assert_eq!(t(Inst::Jnz(-300)), [0x0f, 0x85, 0xce, 0xfe, 0xff, 0xff]);
+        assert_eq!(
+            t(Inst::MovsdRip(12, Xmm::Xmm1)),
+            [0xf2, 0x0f, 0x10, 0x0d, 0x0c, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(t(Inst::MovsdRip(-1, Xmm::Xmm0))[3], 0x05);
+        assert_eq!(
+            t(Inst::MovqFromXmm(Xmm::Xmm0, Reg::Rcx)),
+            [0x66, 0x48, 0x0f, 0x7e, 0xc1]
+        );
+        assert_eq!(
+            t(Inst::Movapd(Xmm::Xmm0, Xmm::Xmm2)),
+            [0x66, 0x0f, 0x28, 0xd0]
+        );
+        assert_eq!(
+            t(Inst::Xorpd(Xmm::Xmm0, Xmm::Xmm0)),
+            [0x66, 0x0f, 0x57, 0xc0]
+        );
+        assert_eq!(
+            t(Inst::Andpd(Xmm::Xmm1, Xmm::Xmm0)),
+            [0x66, 0x0f, 0x54, 0xc1]
+        );
+        assert_eq!(
+            t(Inst::Addsd(Xmm::Xmm1, Xmm::Xmm0)),
+            [0xf2, 0x0f, 0x58, 0xc1]
+        );
+        assert_eq!(
+            t(Inst::Subsd(Xmm::Xmm2, Xmm::Xmm0)),
+            [0xf2, 0x0f, 0x5c, 0xc2]
+        );
+        assert_eq!(
+            t(Inst::Mulsd(Xmm::Xmm0, Xmm::Xmm0)),
+            [0xf2, 0x0f, 0x59, 0xc0]
+        );
+        assert_eq!(
+            t(Inst::Divsd(Xmm::Xmm1, Xmm::Xmm2)),
+            [0xf2, 0x0f, 0x5e, 0xd1]
+        );
+        assert_eq!(
+            t(Inst::Roundsd(3, Xmm::Xmm2, Xmm::Xmm2)),
+            [0x66, 0x0f, 0x3a, 0x0b, 0xd2, 0x03]
+        );
+    }
+
+    #[test]
+    fn test_to_assembly() {
+        assert_eq!(
+            to_assembly("++*").unwrap(),
+            "    xor %rcx, %rcx
+    # +
+    inc %rcx
+    # +
+    inc %rcx
+    # *
+    imul $2, %rcx, %rcx
+    mov %rcx, %rax
+    ret
+"
+        );
+        assert_eq!(
+            to_assembly("2[+]").unwrap(),
+            "    xor %rcx, %rcx
+    # 2[
+    mov $2, %r9
+0:
+    # +
+    inc %rcx
+    # ]
+    dec %r9
+    jnz 0b
+    mov %rcx, %rax
+    ret
+"
+        );
+        // Shifting by one has its own encoding, written without the count
+        // (`shl %rcx` is `shl $1, %rcx`)
+        let shifts = to_assembly("<>").unwrap();
+        assert!(shifts.contains("    # <\n    shl %rcx\n"), "{shifts}");
+        assert!(shifts.contains("    # >\n    sar %rcx\n"), "{shifts}");
+        assert!(to_assembly("+x").is_err());
+    }
+
+    /// Assemble the output of [`to_assembly`] with the GNU assembler, and check it
+    /// matches the machine code from [`jit`]. Skipped if `as` isn't installed.
+    #[test]
+    fn test_to_assembly_matches_jit() {
+        if Command::new("as").arg("--version").output().is_err() {
+            eprintln!("skipping, as isn't installed");
+            return;
+        }
+        let dir = std::env::temp_dir().join(format!("to_assembly_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (i, program) in [
+            "++*--",
+            "+/%^",
+            "+1000%7-%3",
+            "++~*~",
+            "---><<>",
+            "---|+|",
+            "=-7*3",
+            "+1000-2147483647",
+            "=2147483647*1000/7",
+            "=-2147483647-1000*100",
+            "=-9/3%",
+            "3[+*2[-]]2[+]",
+            "2[100(+)]",
+            "+2[3[4[*]]]",
+            "?+??+-?2[?+]",
+            "?2[100(+)]?*",
+            "++d*s-",
+            "+2[d+]?ss",
+        ]
+        .iter()
+        .enumerate()
+        {
+            let source = dir.join(format!("{i}.s"));
+            let output = dir.join(format!("{i}.o"));
+            std::fs::write(&source, to_assembly(program).unwrap()).unwrap();
+            let status = Command::new("as")
+                .arg(&source)
+                .arg("-o")
+                .arg(&output)
+                .status()
+                .unwrap();
+            assert!(status.success(), "{program}");
+
+            let elf = std::fs::read(&output).unwrap();
+            let file = object::File::parse(&*elf).unwrap();
+            let text = file.section_by_name(".text").unwrap();
+            assert_eq!(text.data().unwrap(), jit(program).unwrap(), "{program}");
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
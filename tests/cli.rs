@@ -0,0 +1,68 @@
+// Running the binary the way a user would, on programs kept in files
+
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Write `program` to a `.calc` file named `name` in a temporary directory
+fn write_program(name: &str, program: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("cli_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    std::fs::write(&path, program).unwrap();
+    path
+}
+
+/// Run the binary with `args`
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_simple_jit_calculator"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_file() {
+    let path = write_program(
+        "double.calc",
+        "# Start at two\n++\n\n# and double it three times\n* * *  # 16\n",
+    );
+    for flag in ["--file", "-f"] {
+        let output = run(&[flag, path.to_str().unwrap()]);
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "16\n");
+    }
+    let output = run(&["--unsigned", "--file", path.to_str().unwrap()]);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "16\n");
+    // Flags can come after the file too
+    let output = run(&["-f", path.to_str().unwrap(), "--dump-hex"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "16\n");
+    assert!(!output.stderr.is_empty());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_file_errors() {
+    let path = write_program("typo.calc", "# Halving\n++/\n+x\n");
+    let output = run(&["--file", path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "error: unknown character `x`
+ --> 3:2
+  |
+3 | +x
+  |  ^
+"
+    );
+    std::fs::remove_file(path).unwrap();
+
+    let output = run(&["--file", "does/not/exist.calc"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .starts_with("Failed to read program"));
+    let output = run(&["--file"]);
+    assert_eq!(output.status.code(), Some(2));
+}
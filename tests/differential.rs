@@ -0,0 +1,517 @@
+// Running random programs through the JIT compiler and through a reference interpreter
+// kept apart from the library, and checking they agree. A program they disagree on is
+// shrunk to a smaller one they still disagree on before it's reported. Each program is
+// checked with every combination of the options which change what it gives.
+#![cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+
+use simple_jit_calculator::{
+    compile, interpret, jit_with_options, parse, run, run_checked, DivRounding, Op, Options,
+    OverflowBehavior, Signedness, MAX_LOOP_DEPTH,
+};
+
+/// How many kinds of operation there are, which [`kind`] numbers from zero
+const OP_KINDS: usize = 23;
+
+/// A number for each kind of operation, ignoring operands. This is an exhaustive match
+/// so that adding an operation fails to compile until it's numbered here, after which
+/// `test_generator_covers_every_op` fails until [`Random::op`] generates it too.
+fn kind(op: &Op) -> usize {
+    match op {
+        Op::Plus => 0,
+        Op::Minus => 1,
+        Op::Star => 2,
+        Op::Slash => 3,
+        Op::Percent => 4,
+        Op::Caret => 5,
+        Op::Tilde => 6,
+        Op::Zero => 7,
+        Op::Less => 8,
+        Op::Greater => 9,
+        Op::Bar => 10,
+        Op::Clamp => 11,
+        Op::Add(_) => 12,
+        Op::Sub(_) => 13,
+        Op::Mul(_) => 14,
+        Op::Div(_) => 15,
+        Op::Rem(_) => 16,
+        Op::Set(_) => 17,
+        Op::Loop(_) => 18,
+        Op::EndLoop => 19,
+        Op::Skip => 20,
+        Op::Dup => 21,
+        Op::Swap => 22,
+    }
+}
+
+/// Every combination of overflow behavior, signedness and rounding, with the other
+/// options left as they are by default
+fn all_options() -> Vec<Options> {
+    let mut all = Vec::new();
+    for overflow in [
+        OverflowBehavior::Wrap,
+        OverflowBehavior::Checked,
+        OverflowBehavior::Saturate,
+    ] {
+        for signedness in [Signedness::Signed, Signedness::Unsigned] {
+            for div_rounding in [DivRounding::TruncateTowardZero, DivRounding::Floor] {
+                all.push(Options {
+                    overflow,
+                    signedness,
+                    div_rounding,
+                    ..Options::default()
+                });
+            }
+        }
+    }
+    all
+}
+
+/// Work out what a program gives the slow and obvious way, with the arithmetic `options`
+/// ask for done on 128-bit integers, which no operation overflows. A program which
+/// overflows with [`OverflowBehavior::Checked`] gives the index of the operation that
+/// overflowed. This doesn't share any code with the library.
+fn reference(ops: &[Op], options: &Options) -> Result<i64, usize> {
+    // The working register is only ever unsigned when it wraps around
+    let unsigned =
+        options.signedness == Signedness::Unsigned && options.overflow == OverflowBehavior::Wrap;
+    let floor = options.div_rounding == DivRounding::Floor;
+    let mut value: i64 = 0;
+    let mut stack: Vec<i64> = Vec::new();
+    // The index of the first operation of each loop being run, and how many more times
+    // its body runs after this one
+    let mut loops: Vec<(usize, i64)> = Vec::new();
+    let mut i = 0;
+    while let Some(op) = ops.get(i) {
+        i += 1;
+        let wide = i128::from(value);
+        let bits = value as u64;
+        // The result of an operation which can overflow, before it's made to fit
+        let exact = match op {
+            Op::Plus => wide + 1,
+            Op::Minus => wide - 1,
+            Op::Star | Op::Less => wide * 2,
+            Op::Caret => wide * wide,
+            Op::Tilde => -wide,
+            Op::Bar if !unsigned => wide.abs(),
+            Op::Add(n) => wide + i128::from(*n),
+            Op::Sub(n) => wide - i128::from(*n),
+            Op::Mul(n) => wide * i128::from(*n),
+            _ => wide,
+        };
+        if i64::try_from(exact).is_err() {
+            value = match options.overflow {
+                // Keep the bottom 64 bits
+                OverflowBehavior::Wrap => exact as i64,
+                OverflowBehavior::Checked => return Err(i - 1),
+                OverflowBehavior::Saturate if exact < 0 => i64::MIN,
+                OverflowBehavior::Saturate => i64::MAX,
+            };
+            continue;
+        }
+        value = match op {
+            Op::Slash if unsigned => (bits / 2) as i64,
+            Op::Slash if floor => wide.div_euclid(2) as i64,
+            Op::Slash => value / 2,
+            Op::Percent if unsigned => (bits % 2) as i64,
+            Op::Percent => value % 2,
+            Op::Zero => 0,
+            Op::Greater if unsigned => (bits >> 1) as i64,
+            Op::Greater => value >> 1,
+            Op::Clamp if !unsigned => value.max(0),
+            Op::Div(n) if unsigned => (bits / *n as u64) as i64,
+            Op::Div(n) => value / n,
+            Op::Rem(n) if unsigned => (bits % *n as u64) as i64,
+            Op::Rem(n) => value % n,
+            Op::Set(n) => *n,
+            Op::Loop(n) => {
+                loops.push((i, n - 1));
+                value
+            }
+            Op::EndLoop => {
+                let (start, again) = loops.last_mut().unwrap();
+                if *again > 0 {
+                    *again -= 1;
+                    i = *start;
+                } else {
+                    loops.pop();
+                }
+                value
+            }
+            Op::Skip if value == 0 => {
+                i = skipped(ops, i);
+                value
+            }
+            Op::Skip => value,
+            Op::Dup => {
+                stack.push(value);
+                value
+            }
+            Op::Swap => std::mem::replace(stack.last_mut().unwrap(), value),
+            _ => exact as i64,
+        };
+    }
+    Ok(value)
+}
+
+/// The index just after the operation at `i`, or after the whole loop if it starts one
+fn skipped(ops: &[Op], i: usize) -> usize {
+    match ops.get(i) {
+        Some(Op::Loop(_)) => matching_end(ops, i) + 1,
+        Some(_) => i + 1,
+        None => i,
+    }
+}
+
+/// The index of the end of the loop starting at `start`
+fn matching_end(ops: &[Op], start: usize) -> usize {
+    let mut depth = 0;
+    for (i, op) in ops.iter().enumerate().skip(start) {
+        match op {
+            Op::Loop(_) => depth += 1,
+            Op::EndLoop if depth == 1 => return i,
+            Op::EndLoop => depth -= 1,
+            _ => {}
+        }
+    }
+    panic!("unbalanced loops");
+}
+
+/// Write the operations as a program string, with spaces between them so that an
+/// operand is never read as part of the operation before it
+fn render(ops: &[Op]) -> String {
+    ops.iter().map(Op::to_string).collect::<Vec<_>>().join(" ")
+}
+
+/// Compile a program with `options` and run it, giving what [`reference`] does
+fn run_jit(program: &str, options: &Options) -> Result<i64, usize> {
+    let machine_code = jit_with_options(program, *options).unwrap();
+    // Safety: the code is from `jit_with_options` for the host
+    match options.overflow {
+        OverflowBehavior::Checked => {
+            unsafe { run_checked(&machine_code) }.map_err(|overflow| overflow.at_op)
+        }
+        _ => Ok(unsafe { run(&machine_code) }),
+    }
+}
+
+/// Whether the JIT and the reference disagree on a program compiled with `options`.
+/// With the default options, the interpreter and [`compile`] have to agree as well.
+/// Programs the library rejects, such as ones which swap on an empty stack, never count.
+fn diverges(ops: &[Op], options: &Options) -> bool {
+    let program = render(ops);
+    if parse(&program).is_err() {
+        return false;
+    }
+    let expected = reference(ops, options);
+    if run_jit(&program, options) != expected {
+        return true;
+    }
+    *options == Options::default()
+        && (compile(&program).map(|compiled| compiled.call()).ok() != expected.ok()
+            || interpret(&program).ok() != expected.ok())
+}
+
+/// Make a program `still_fails` on smaller, one operation or operand at a time, until
+/// none of the smaller ones fail
+fn shrink(mut ops: Vec<Op>, still_fails: impl Fn(&[Op]) -> bool) -> Vec<Op> {
+    'smaller: loop {
+        for candidate in candidates(&ops) {
+            if still_fails(&candidate) {
+                ops = candidate;
+                continue 'smaller;
+            }
+        }
+        return ops;
+    }
+}
+
+/// The programs one step smaller than `ops`: with a run of operations or a whole loop
+/// removed, with a loop's body kept but the loop itself removed, or with a smaller
+/// operand. Long runs come first, so large programs shrink quickly.
+fn candidates(ops: &[Op]) -> Vec<Vec<Op>> {
+    let without = |range: std::ops::Range<usize>| {
+        let mut ops = ops.to_vec();
+        ops.drain(range);
+        ops
+    };
+    let mut candidates = Vec::new();
+    let mut run = ops.len() / 2;
+    while run > 1 {
+        for start in (0..=ops.len() - run).step_by(run) {
+            if balanced(&ops[start..start + run]) {
+                candidates.push(without(start..start + run));
+            }
+        }
+        run /= 2;
+    }
+    for (i, op) in ops.iter().enumerate() {
+        match op {
+            Op::Loop(_) => {
+                let end = matching_end(ops, i);
+                candidates.push(without(i..end + 1));
+                let mut unwrapped = without(end..end + 1);
+                unwrapped.remove(i);
+                candidates.push(unwrapped);
+            }
+            Op::EndLoop => {}
+            _ => candidates.push(without(i..i + 1)),
+        }
+    }
+    for (i, op) in ops.iter().enumerate() {
+        let smaller = match *op {
+            Op::Add(n) if n > 1 => Op::Add(n / 2),
+            Op::Sub(n) if n > 1 => Op::Sub(n / 2),
+            Op::Mul(n) if n > 1 => Op::Mul(n / 2),
+            Op::Div(n) if n > 1 => Op::Div(n / 2),
+            Op::Rem(n) if n > 1 => Op::Rem(n / 2),
+            Op::Set(n) if n != 0 => Op::Set(n / 2),
+            Op::Loop(n) if n > 1 => Op::Loop(n - 1),
+            _ => continue,
+        };
+        let mut candidate = ops.to_vec();
+        candidate[i] = smaller;
+        candidates.push(candidate);
+    }
+    candidates
+}
+
+/// Whether every loop started in `ops` also ends there, and the other way around
+fn balanced(ops: &[Op]) -> bool {
+    let mut depth = 0;
+    for op in ops {
+        match op {
+            Op::Loop(_) => depth += 1,
+            Op::EndLoop if depth == 0 => return false,
+            Op::EndLoop => depth -= 1,
+            _ => {}
+        }
+    }
+    depth == 0
+}
+
+/// A small xorshift generator, so random programs can be made without extra dependencies
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// An operand, mostly small but sometimes as large as they go
+    fn operand(&mut self) -> i64 {
+        match self.next() % 8 {
+            0 => i64::from(i32::MAX) - (self.next() % 4) as i64,
+            1 => 1 << (self.next() % 31),
+            _ => 1 + (self.next() % 1000) as i64,
+        }
+    }
+
+    /// A random operation, which may start a loop if loops can still be nested
+    fn op(&mut self, can_loop: bool) -> Op {
+        match self.next() % if can_loop { 22 } else { 21 } {
+            0 => Op::Plus,
+            1 => Op::Minus,
+            2 => Op::Star,
+            3 => Op::Slash,
+            4 => Op::Percent,
+            5 => Op::Caret,
+            6 => Op::Tilde,
+            7 => Op::Zero,
+            8 => Op::Less,
+            9 => Op::Greater,
+            10 => Op::Bar,
+            11 => Op::Clamp,
+            12 => Op::Add(self.operand()),
+            13 => Op::Sub(self.operand()),
+            14 => Op::Mul(self.operand()),
+            15 => Op::Div(self.operand()),
+            16 => Op::Rem(self.operand()),
+            17 if self.next().is_multiple_of(2) => Op::Set(-self.operand()),
+            17 => Op::Set(self.operand()),
+            18 => Op::Skip,
+            19 => Op::Dup,
+            20 => Op::Swap,
+            _ => Op::Loop(1 + (self.next() % 4) as i64),
+        }
+    }
+
+    /// A random program of up to `length` operations which the library accepts.
+    /// Swaps with nothing to swap with, or too many values pushed, are tried again.
+    fn program(&mut self, length: usize) -> Vec<Op> {
+        loop {
+            let mut ops = Vec::new();
+            let mut depth = 0;
+            for _ in 0..1 + self.next() as usize % length {
+                if depth > 0 && self.next().is_multiple_of(8) {
+                    ops.push(Op::EndLoop);
+                    depth -= 1;
+                    continue;
+                }
+                let op = self.op(depth < MAX_LOOP_DEPTH);
+                if let Op::Loop(_) = op {
+                    depth += 1;
+                }
+                ops.push(op);
+            }
+            ops.extend((0..depth).map(|_| Op::EndLoop));
+            if parse(&render(&ops)).is_ok() {
+                return ops;
+            }
+        }
+    }
+}
+
+/// Check `count` random programs of up to `length` operations with each of
+/// [`all_options`], panicking with the shrunk program if the JIT and the reference
+/// disagree on one
+fn check(seed: u64, count: usize, length: usize) {
+    let mut random = Random(seed);
+    for _ in 0..count {
+        let ops = random.program(length);
+        for options in all_options() {
+            if diverges(&ops, &options) {
+                let shrunk = shrink(ops.clone(), |ops| diverges(ops, &options));
+                let program = render(&shrunk);
+                panic!(
+                    "the JIT gives {:?} and the reference {:?} for {program:?} with {options:?}, \
+                     shrunk from {:?}",
+                    run_jit(&program, &options),
+                    reference(&shrunk, &options),
+                    render(&ops),
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_differential() {
+    check(0xda942042e4dd58b5, 2000, 300);
+}
+
+/// Many more and longer programs, which take a while. Run with `cargo test -- --ignored`.
+#[test]
+#[ignore]
+fn test_differential_long() {
+    check(0x9e3779b97f4a7c15, 100_000, 1000);
+}
+
+/// Programs on the edges of what the operations do, where the JIT and the interpreter
+/// are most likely to disagree. Add any program the fuzzer in `fuzz/` finds them
+/// disagreeing on.
+const REGRESSIONS: &[&str] = &[
+    "=-2147483647 -1 *65536 *65536 /",
+    "=-2147483647 -1 *65536 *65536 %",
+    "=-2147483647 -1 *65536 *65536 |",
+    "=-2147483647 -1 *65536 *65536 ~ _",
+    "=-2147483647 -1 *65536 *65536 /2147483647 %2147483647",
+    "- - - / - - - % - - - >",
+    "+ 62(*) * _",
+    "? 3[ + ] d s",
+    "3[ 3[ 3[ d * ] ] ] s",
+    "=-7 %3 =-7 /3",
+];
+
+#[test]
+fn test_regressions() {
+    for program in REGRESSIONS {
+        let ops = parse(program).unwrap();
+        for options in all_options() {
+            assert!(!diverges(&ops, &options), "{program} {options:?}");
+        }
+    }
+}
+
+#[test]
+fn test_generator_covers_every_op() {
+    let mut random = Random(0x2545f4914f6cdd1d);
+    let mut seen = [false; OP_KINDS];
+    for _ in 0..100 {
+        for op in random.program(300) {
+            seen[kind(&op)] = true;
+        }
+    }
+    assert_eq!(seen, [true; OP_KINDS]);
+}
+
+#[test]
+fn test_reference() {
+    for (program, result) in [
+        ("+ + *", 4),
+        ("- - - /", -1),
+        ("- - - >", -2),
+        ("3[ + ]", 3),
+        ("2[ 3[ + ] * ]", 18),
+        ("? 5[ + ] +", 1),
+        ("+ ? 5[ + ]", 6),
+        ("+ + d * s -", 1),
+        ("- - _ + +", 2),
+        ("=-7 %3", -1),
+    ] {
+        let ops = parse(program).unwrap();
+        assert_eq!(
+            reference(&ops, &Options::default()),
+            Ok(result),
+            "{program}"
+        );
+    }
+}
+
+#[test]
+fn test_reference_options() {
+    use DivRounding::{Floor, TruncateTowardZero as Truncate};
+    use OverflowBehavior::{Checked, Saturate, Wrap};
+    use Signedness::{Signed, Unsigned};
+
+    let options = |overflow, signedness, div_rounding| Options {
+        overflow,
+        signedness,
+        div_rounding,
+        ..Options::default()
+    };
+    // Decrementing `i64::MIN`
+    let min = "=-2147483647 - *65536 *65536 -";
+    for (program, options, result) in [
+        (min, options(Wrap, Signed, Truncate), Ok(i64::MAX)),
+        (min, options(Checked, Signed, Truncate), Err(4)),
+        (min, options(Saturate, Signed, Truncate), Ok(i64::MIN)),
+        ("- - - /", options(Wrap, Signed, Floor), Ok(-2)),
+        ("- - - /", options(Saturate, Signed, Floor), Ok(-2)),
+        ("- - - /3", options(Wrap, Signed, Floor), Ok(-1)),
+        ("- /", options(Wrap, Unsigned, Truncate), Ok(i64::MAX)),
+        ("- /", options(Wrap, Unsigned, Floor), Ok(i64::MAX)),
+        ("- |", options(Wrap, Unsigned, Truncate), Ok(-1)),
+        ("- _", options(Wrap, Unsigned, Truncate), Ok(-1)),
+        // Only wrapping code is unsigned
+        ("- /", options(Saturate, Unsigned, Truncate), Ok(0)),
+        ("- _", options(Checked, Unsigned, Truncate), Ok(0)),
+        // An overflow inside a loop is at the operation in the loop
+        (
+            "=-2147483647 - *65536 *65536 + 3[ - ]",
+            options(Checked, Signed, Floor),
+            Err(6),
+        ),
+    ] {
+        let ops = parse(program).unwrap();
+        assert_eq!(reference(&ops, &options), result, "{program} {options:?}");
+    }
+}
+
+#[test]
+fn test_shrink() {
+    // Pretend squaring after a copy is pushed is broken
+    let fails = |ops: &[Op]| {
+        let dup = ops.iter().position(|op| *op == Op::Dup);
+        dup.is_some_and(|dup| ops[dup..].contains(&Op::Caret))
+    };
+    let ops = parse("+ 2[ - d * ] ^ +5 ~ 3[ ^ ]").unwrap();
+    assert!(fails(&ops));
+    assert_eq!(shrink(ops, fails), [Op::Dup, Op::Caret]);
+
+    let loops = |ops: &[Op]| ops.iter().any(|op| matches!(op, Op::Loop(_)));
+    let ops = parse("+ 4[ + 3[ * ] ] +100").unwrap();
+    assert_eq!(shrink(ops, loops), [Op::Loop(1), Op::EndLoop]);
+}